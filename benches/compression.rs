@@ -0,0 +1,67 @@
+//! Benchmarks of output compression. Run with `cargo bench`.
+//!
+//! Gzip on one thread uses flate2, and on several threads compresses blocks in parallel with
+//! libdeflate.
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use nohuman::compression::CompressionFormat;
+use std::io::Write;
+
+/// The size of the FASTQ file compressed.
+const INPUT_SIZE: usize = 32 * 1024 * 1024;
+
+/// Write `size` bytes of (pseudo-random) FASTQ to a temporary file.
+fn fastq_file(size: usize) -> tempfile::NamedTempFile {
+    let mut file = tempfile::NamedTempFile::new().unwrap();
+    let mut state: u64 = 42;
+    let mut written = 0;
+    let mut i = 0;
+    while written < size {
+        let seq: Vec<u8> = (0..150)
+            .map(|_| {
+                state = state.wrapping_mul(6364136223846793005).wrapping_add(1);
+                b"ACGT"[(state >> 62) as usize]
+            })
+            .collect();
+        let record = format!(
+            "@read{}\n{}\n+\n{}\n",
+            i,
+            String::from_utf8(seq).unwrap(),
+            "I".repeat(150)
+        );
+        file.write_all(record.as_bytes()).unwrap();
+        written += record.len();
+        i += 1;
+    }
+    file.flush().unwrap();
+    file
+}
+
+fn compress(c: &mut Criterion) {
+    let input = fastq_file(INPUT_SIZE);
+    let mut group = c.benchmark_group("compress");
+    group.sample_size(10);
+    group.throughput(Throughput::Bytes(INPUT_SIZE as u64));
+    for (format, threads) in [
+        (CompressionFormat::Gzip, 1),
+        (CompressionFormat::Gzip, 4),
+        (CompressionFormat::Bgzf, 4),
+        (CompressionFormat::Zstd, 4),
+        (CompressionFormat::Lz4, 1),
+    ] {
+        group.bench_with_input(
+            BenchmarkId::new(format.to_string(), threads),
+            &threads,
+            |b, &threads| {
+                b.iter(|| {
+                    format
+                        .compress_to(input.path(), std::io::sink(), threads)
+                        .unwrap()
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, compress);
+criterion_main!(benches);
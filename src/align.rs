@@ -0,0 +1,287 @@
+//! Finding host reads by aligning them to a host reference genome with minimap2, as an
+//! alternative to classifying them with kraken2.
+//!
+//! A read is a host read if it has an alignment to the reference that passes the
+//! [`AlignmentFilter`]. For paired reads, the pair is a host pair if either mate is.
+use crate::fastx::read_id;
+use crate::NohumanError;
+use anyhow::{bail, Context, Result};
+use log::debug;
+use std::collections::HashSet;
+use std::io::BufRead;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::str::FromStr;
+
+/// How host reads are identified.
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Default)]
+pub enum Aligner {
+    /// Classify reads with kraken2.
+    #[default]
+    Kraken2,
+    /// Align reads to a host reference with minimap2.
+    Minimap2,
+    /// Classify reads with nohuman's built-in classifier (see [`crate::native`]).
+    Native,
+    /// Mark a pseudo-random fraction of the reads as host, for testing (see [`crate::mock`]).
+    Mock,
+}
+
+impl FromStr for Aligner {
+    type Err = NohumanError;
+
+    /// Parse an aligner name. `s` is case-insensitive.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "kraken2" => Ok(Aligner::Kraken2),
+            "minimap2" => Ok(Aligner::Minimap2),
+            "native" => Ok(Aligner::Native),
+            "mock" => Ok(Aligner::Mock),
+            _ => Err(NohumanError::InvalidAligner(s.to_string())),
+        }
+    }
+}
+
+impl std::fmt::Display for Aligner {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let aligner = match self {
+            Aligner::Kraken2 => "kraken2",
+            Aligner::Minimap2 => "minimap2",
+            Aligner::Native => "native",
+            Aligner::Mock => "mock",
+        };
+        write!(f, "{}", aligner)
+    }
+}
+
+/// The backends used to find host reads, e.g. "kraken2+minimap2". With more than one, a read is a
+/// host read if enough of them flag it (see [`crate::pipeline::DehostPipeline::with_consensus`]).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Engines(Vec<Aligner>);
+
+impl Engines {
+    pub fn new(engines: Vec<Aligner>) -> Self {
+        Self(engines)
+    }
+
+    pub fn contains(&self, engine: Aligner) -> bool {
+        self.0.contains(&engine)
+    }
+
+    /// The engine, if only one is used.
+    pub fn single(&self) -> Option<Aligner> {
+        match self.0.as_slice() {
+            [engine] => Some(*engine),
+            _ => None,
+        }
+    }
+
+    pub fn as_slice(&self) -> &[Aligner] {
+        &self.0
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl Default for Engines {
+    fn default() -> Self {
+        Self(vec![Aligner::Kraken2])
+    }
+}
+
+impl FromStr for Engines {
+    type Err = NohumanError;
+
+    /// Parse engine names separated by `+`. Each engine can only be given once.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut engines = Vec::new();
+        for name in s.split('+') {
+            let engine: Aligner = name.trim().parse()?;
+            if engines.contains(&engine) {
+                return Err(NohumanError::InvalidAligner(s.to_string()));
+            }
+            engines.push(engine);
+        }
+        Ok(Self(engines))
+    }
+}
+
+impl std::fmt::Display for Engines {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let names: Vec<String> = self.0.iter().map(Aligner::to_string).collect();
+        write!(f, "{}", names.join("+"))
+    }
+}
+
+/// The alignments that make a read a host read.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AlignmentFilter {
+    /// The minimum fraction of the alignment's columns that are matches.
+    pub min_identity: f64,
+    /// The minimum length of the alignment (including gaps).
+    pub min_length: u64,
+}
+
+impl Default for AlignmentFilter {
+    fn default() -> Self {
+        Self {
+            min_identity: 0.9,
+            min_length: 50,
+        }
+    }
+}
+
+impl AlignmentFilter {
+    /// Whether an alignment with `matches` matching bases out of `length` alignment columns
+    /// passes the filter.
+    pub fn passes(&self, matches: u64, length: u64) -> bool {
+        length > 0
+            && length >= self.min_length
+            && matches as f64 / length as f64 >= self.min_identity
+    }
+}
+
+/// Parse a minimum alignment identity, which must be in [0, 1].
+pub fn parse_identity(s: &str) -> Result<f64, NohumanError> {
+    let identity: f64 = s
+        .parse()
+        .map_err(|_| NohumanError::InvalidIdentity(s.to_string()))?;
+    if !(0.0..=1.0).contains(&identity) {
+        return Err(NohumanError::InvalidIdentity(s.to_string()));
+    }
+    Ok(identity)
+}
+
+/// Collect the IDs of the reads with an alignment passing `filter` in minimap2's PAF output. IDs
+/// are normalised with [`read_id`] so they can be matched against FASTA/Q headers.
+pub fn host_read_ids<R: BufRead>(paf: R, filter: &AlignmentFilter) -> Result<HashSet<Vec<u8>>> {
+    let mut ids = HashSet::new();
+    for (i, line) in paf.lines().enumerate() {
+        let line = line?;
+        if line.is_empty() {
+            continue;
+        }
+        let fields: Vec<&str> = line.split('\t').collect();
+        if fields.len() < 12 {
+            bail!("PAF line {} has fewer than 12 columns", i + 1);
+        }
+        let matches: u64 = fields[9]
+            .parse()
+            .with_context(|| format!("Invalid number of matches on PAF line {}", i + 1))?;
+        let length: u64 = fields[10]
+            .parse()
+            .with_context(|| format!("Invalid alignment length on PAF line {}", i + 1))?;
+        if filter.passes(matches, length) {
+            ids.insert(read_id(fields[0].as_bytes()).to_vec());
+        }
+    }
+    Ok(ids)
+}
+
+/// Aligning reads to a host reference with minimap2.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Minimap2 {
+    /// The minimap2 executable.
+    pub command: String,
+    /// The host reference genome, as FASTA or a minimap2 index (.mmi).
+    pub reference: PathBuf,
+    /// The minimap2 preset (`-x`), e.g. "sr" for short reads. minimap2's default if `None`.
+    pub preset: Option<String>,
+    pub filter: AlignmentFilter,
+}
+
+impl Minimap2 {
+    pub fn new<P: Into<PathBuf>>(reference: P) -> Self {
+        Self {
+            command: "minimap2".to_string(),
+            reference: reference.into(),
+            preset: None,
+            filter: AlignmentFilter::default(),
+        }
+    }
+
+    /// Align the reads in `inputs` (two inputs are aligned as pairs) with `threads` threads,
+    /// writing the alignments to `paf`.
+    pub fn align(&self, inputs: &[PathBuf], threads: u32, paf: &Path) -> Result<()> {
+        let mut command = Command::new(&self.command);
+        // base-level alignment (-c) gives the exact number of matches the filter uses
+        command
+            .args(["-c", "--secondary=no", "-t", &threads.to_string(), "-o"])
+            .arg(paf);
+        if let Some(preset) = &self.preset {
+            command.args(["-x", preset]);
+        }
+        command.arg(&self.reference).args(inputs);
+        debug!("Running {:?}", command);
+        let output = command
+            .output()
+            .with_context(|| format!("Failed to run {}", self.command))?;
+        if !output.status.success() {
+            bail!(
+                "{} failed with stderr {}",
+                self.command,
+                String::from_utf8_lossy(&output.stderr)
+            );
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_aligner_from_str() {
+        assert_eq!("Minimap2".parse::<Aligner>().unwrap(), Aligner::Minimap2);
+        assert_eq!("kraken2".parse::<Aligner>().unwrap(), Aligner::Kraken2);
+        assert_eq!("native".parse::<Aligner>().unwrap(), Aligner::Native);
+        assert_eq!("MOCK".parse::<Aligner>().unwrap(), Aligner::Mock);
+        assert!("bwa".parse::<Aligner>().is_err());
+    }
+
+    #[test]
+    fn test_engines_from_str() {
+        let engines: Engines = "kraken2+Minimap2".parse().unwrap();
+        assert_eq!(engines.as_slice(), &[Aligner::Kraken2, Aligner::Minimap2]);
+        assert_eq!(engines.single(), None);
+        assert_eq!(engines.to_string(), "kraken2+minimap2");
+        assert_eq!(
+            "native".parse::<Engines>().unwrap().single(),
+            Some(Aligner::Native)
+        );
+        assert!("kraken2+kraken2".parse::<Engines>().is_err());
+        assert!("kraken2+".parse::<Engines>().is_err());
+    }
+
+    #[test]
+    fn test_parse_identity() {
+        assert_eq!(parse_identity("0.95").unwrap(), 0.95);
+        assert!(parse_identity("1.5").is_err());
+        assert!(parse_identity("high").is_err());
+    }
+
+    #[test]
+    fn test_host_read_ids() {
+        let paf = "\
+r1\t150\t0\t150\t+\tchr1\t1000\t10\t160\t148\t150\t60
+r2/1\t150\t0\t150\t+\tchr1\t1000\t10\t160\t100\t150\t60
+r3\t150\t0\t40\t+\tchr1\t1000\t10\t50\t40\t40\t60
+r4/2\t150\t0\t150\t-\tchr2\t1000\t10\t160\t145\t150\t60
+";
+        let ids = host_read_ids(Cursor::new(paf), &AlignmentFilter::default()).unwrap();
+        // r2 has low identity and r3 is too short
+        assert_eq!(ids.len(), 2);
+        assert!(ids.contains(b"r1".as_slice()));
+        assert!(ids.contains(b"r4".as_slice()));
+
+        assert!(host_read_ids(Cursor::new("r1\t150\n"), &AlignmentFilter::default()).is_err());
+    }
+}
@@ -0,0 +1,258 @@
+//! Writing reads as unaligned BAM (uBAM), which some nanopore and clinical workflows expect in
+//! place of FASTQ.
+//!
+//! BAM is a binary SAM file compressed with BGZF (see [`BlockWriter::bgzf`]). See the
+//! [SAM specification] for details.
+//!
+//! [SAM specification]: https://samtools.github.io/hts-specs/SAMv1.pdf
+use crate::compression::BlockWriter;
+use crate::fastx::{fastx_reader, read_id};
+use anyhow::{bail, Context, Result};
+use std::io::{Read, Write};
+
+/// The 4-bit codes of the bases in a BAM record.
+const BASES: &[u8; 16] = b"=ACMGRSVTWYHKDBN";
+/// The bin of an unmapped read (i.e. of position -1).
+const UNMAPPED_BIN: u16 = 4680;
+/// The flags of a single-end unmapped read.
+const FLAG_UNMAPPED: u16 = 0x4;
+/// The flags of the first and second reads of an unmapped pair.
+const FLAGS_MATE1: u16 = 0x1 | 0x4 | 0x8 | 0x40;
+const FLAGS_MATE2: u16 = 0x1 | 0x4 | 0x8 | 0x80;
+
+/// The format the dehosted reads are written in.
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Default)]
+pub enum OutputFormat {
+    /// FASTA/FASTQ, the same as the input.
+    #[default]
+    Fastx,
+    /// Unaligned BAM.
+    Bam,
+}
+
+impl std::str::FromStr for OutputFormat {
+    type Err = anyhow::Error;
+
+    /// Parse an output format. `s` is case-insensitive.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use nohuman::bam::OutputFormat;
+    ///
+    /// assert_eq!("BAM".parse::<OutputFormat>().unwrap(), OutputFormat::Bam);
+    /// assert_eq!("fastx".parse::<OutputFormat>().unwrap(), OutputFormat::Fastx);
+    /// assert!("sam".parse::<OutputFormat>().is_err());
+    /// ```
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "fastx" => Ok(Self::Fastx),
+            "bam" => Ok(Self::Bam),
+            _ => bail!("Invalid output format: {} (expected fastx or bam)", s),
+        }
+    }
+}
+
+impl std::fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Fastx => write!(f, "fastx"),
+            Self::Bam => write!(f, "bam"),
+        }
+    }
+}
+
+/// Writes unaligned reads to a BAM file.
+pub struct BamWriter<W: Write> {
+    writer: BlockWriter<W>,
+    /// A reusable buffer for encoding records.
+    record: Vec<u8>,
+}
+
+impl<W: Write> BamWriter<W> {
+    /// Create a writer, writing a header with no reference sequences, and `header_text` (SAM
+    /// header lines, e.g. `@PG`) after the `@HD` line.
+    pub fn new(writer: W, header_text: &str) -> Result<Self> {
+        let mut bam = Self {
+            writer: BlockWriter::bgzf(writer, 1),
+            record: Vec::new(),
+        };
+        let text = format!("@HD\tVN:1.6\tSO:unsorted\n{}", header_text);
+        let mut header = Vec::with_capacity(text.len() + 12);
+        header.extend_from_slice(b"BAM\x01");
+        header.extend_from_slice(&(text.len() as u32).to_le_bytes());
+        header.extend_from_slice(text.as_bytes());
+        // no reference sequences
+        header.extend_from_slice(&0u32.to_le_bytes());
+        bam.writer.write_all(&header)?;
+        Ok(bam)
+    }
+
+    /// Write an unmapped read. `name` is the read name (without any comment), and `qual` the
+    /// FASTQ (Phred+33) quality string, if there is one. `flag` is the SAM flag, e.g. 4 for a
+    /// single-end read.
+    pub fn write_read(
+        &mut self,
+        name: &[u8],
+        seq: &[u8],
+        qual: Option<&[u8]>,
+        flag: u16,
+    ) -> Result<()> {
+        if name.is_empty() || name.len() > 254 {
+            bail!(
+                "Read name {:?} can't be written to BAM - it must have 1 to 254 characters",
+                String::from_utf8_lossy(name)
+            );
+        }
+        if let Some(qual) = qual {
+            if qual.len() != seq.len() {
+                bail!(
+                    "Read {} has {} bases but {} quality scores",
+                    String::from_utf8_lossy(name),
+                    seq.len(),
+                    qual.len()
+                );
+            }
+        }
+
+        let mut record = std::mem::take(&mut self.record);
+        record.clear();
+        record.extend_from_slice(&(-1i32).to_le_bytes()); // refID
+        record.extend_from_slice(&(-1i32).to_le_bytes()); // pos
+        record.push(name.len() as u8 + 1); // l_read_name, including the NUL
+        record.push(255); // mapq (unavailable)
+        record.extend_from_slice(&UNMAPPED_BIN.to_le_bytes());
+        record.extend_from_slice(&0u16.to_le_bytes()); // n_cigar_op
+        record.extend_from_slice(&flag.to_le_bytes());
+        record.extend_from_slice(&(seq.len() as u32).to_le_bytes());
+        record.extend_from_slice(&(-1i32).to_le_bytes()); // next refID
+        record.extend_from_slice(&(-1i32).to_le_bytes()); // next pos
+        record.extend_from_slice(&0i32.to_le_bytes()); // tlen
+        record.extend_from_slice(name);
+        record.push(0);
+        for pair in seq.chunks(2) {
+            let high = base_code(pair[0]) << 4;
+            let low = pair.get(1).map_or(0, |&b| base_code(b));
+            record.push(high | low);
+        }
+        match qual {
+            Some(qual) => record.extend(qual.iter().map(|q| q.saturating_sub(33))),
+            // missing qualities are 0xff
+            None => record.extend(std::iter::repeat(0xff).take(seq.len())),
+        }
+
+        let result = self
+            .writer
+            .write_all(&(record.len() as u32).to_le_bytes())
+            .and_then(|_| self.writer.write_all(&record));
+        self.record = record;
+        Ok(result?)
+    }
+
+    /// Write the remaining data and the end-of-file block, and return the underlying writer.
+    pub fn finish(self) -> Result<W> {
+        Ok(self.writer.finish()?)
+    }
+}
+
+/// The 4-bit code of `base` in a BAM record. Bases that aren't IUPAC codes are written as N.
+fn base_code(base: u8) -> u8 {
+    let base = base.to_ascii_uppercase();
+    BASES
+        .iter()
+        .position(|&b| b == base)
+        .unwrap_or(BASES.len() - 1) as u8
+}
+
+/// Write the reads in `reader` to `writer` as unaligned BAM, with `header_text` in the header (see
+/// [`BamWriter::new`]). If `interleaved`, the reads are pairs with the mates one after the other,
+/// and are flagged as the first and second reads of a pair. Read names are the read IDs (see
+/// [`read_id`]). Returns the number of reads written.
+pub fn fastx_to_bam<R, W>(reader: R, writer: W, header_text: &str, interleaved: bool) -> Result<u64>
+where
+    R: Read + Send,
+    W: Write,
+{
+    let mut bam = BamWriter::new(writer, header_text)?;
+    let mut n_reads = 0;
+    if let Some(mut fastx_reader) = fastx_reader(reader)? {
+        while let Some(record) = fastx_reader.next() {
+            let record = record.context("Failed to parse read")?;
+            let flag = match (interleaved, n_reads % 2) {
+                (false, _) => FLAG_UNMAPPED,
+                (true, 0) => FLAGS_MATE1,
+                (true, _) => FLAGS_MATE2,
+            };
+            bam.write_read(read_id(record.id()), &record.seq(), record.qual(), flag)?;
+            n_reads += 1;
+        }
+    }
+    bam.finish()?;
+    Ok(n_reads)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    /// Decompress a BAM file.
+    fn decompress(bam: &[u8]) -> Vec<u8> {
+        let mut data = Vec::new();
+        flate2::read::MultiGzDecoder::new(bam)
+            .read_to_end(&mut data)
+            .unwrap();
+        data
+    }
+
+    #[test]
+    fn test_fastx_to_bam() {
+        let reads = b"@r1/1 comment\nACGTN\n+\nII#II\n@r1/2\nac\n+\nII\n";
+        let mut bam = Vec::new();
+        let n = fastx_to_bam(Cursor::new(reads), &mut bam, "", true).unwrap();
+        assert_eq!(n, 2);
+        assert!(bam.ends_with(&crate::compression::BGZF_EOF));
+
+        let data = decompress(&bam);
+        let text = "@HD\tVN:1.6\tSO:unsorted\n";
+        assert_eq!(&data[..4], b"BAM\x01");
+        assert_eq!(data[4..8], (text.len() as u32).to_le_bytes());
+        let record = &data[12 + text.len()..];
+        let block_size = u32::from_le_bytes(record[..4].try_into().unwrap()) as usize;
+        let record = &record[4..4 + block_size];
+        assert_eq!(record[8], 3); // "r1" plus NUL
+        assert_eq!(u16::from_le_bytes([record[14], record[15]]), FLAGS_MATE1);
+        assert_eq!(u32::from_le_bytes(record[16..20].try_into().unwrap()), 5);
+        assert_eq!(&record[32..35], b"r1\0");
+        // A=1 C=2, G=4 T=8, N=15
+        assert_eq!(&record[35..38], &[0x12, 0x48, 0xf0]);
+        assert_eq!(&record[38..], &[40, 40, 2, 40, 40]);
+    }
+
+    #[test]
+    fn test_bam_writer_blocks() {
+        let seq = vec![b'A'; 1000];
+        let mut bam = BamWriter::new(Vec::new(), "@PG\tID:nohuman\n").unwrap();
+        for i in 0..200 {
+            bam.write_read(format!("read{}", i).as_bytes(), &seq, None, FLAG_UNMAPPED)
+                .unwrap();
+        }
+        let bam = bam.finish().unwrap();
+
+        // every block stores its size, so the blocks can be walked
+        let mut offset = 0;
+        let mut n_blocks = 0;
+        while offset < bam.len() {
+            let bsize = u16::from_le_bytes([bam[offset + 16], bam[offset + 17]]) as usize;
+            offset += bsize + 1;
+            n_blocks += 1;
+        }
+        assert_eq!(offset, bam.len());
+        assert!(n_blocks > 3);
+        assert!(decompress(&bam).len() > 200 * 1000);
+
+        let mut bam = BamWriter::new(Vec::new(), "").unwrap();
+        assert!(bam.write_read(b"r1", b"ACGT", Some(b"II"), 4).is_err());
+        assert!(bam.write_read(b"", b"ACGT", None, 4).is_err());
+    }
+}
@@ -0,0 +1,901 @@
+use crate::compression::CompressionFormat;
+use crate::fastx::{fastx_reader, write_record};
+use crate::pipeline::{split_file_name, OutputTemplate};
+use crate::summary::Summary;
+use crate::{is_stdio, Host};
+use anyhow::{bail, Context, Result};
+use log::warn;
+use serde::Deserialize;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::ffi::OsString;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+/// Separates the sample tag from the original read ID in the combined batch files.
+const TAG_SEPARATOR: u8 = b'|';
+
+/// A single row of a batch sample sheet.
+#[derive(Debug, Deserialize, PartialEq)]
+pub struct Sample {
+    /// Name of the sample. Must be unique within the sample sheet.
+    pub sample: String,
+    /// The (first) input file of reads.
+    pub r1: PathBuf,
+    /// The second input file for paired-end reads.
+    #[serde(default)]
+    pub r2: Option<PathBuf>,
+    /// Prefix for the output file(s). Defaults to the sample name.
+    #[serde(default)]
+    pub output_prefix: Option<PathBuf>,
+}
+
+impl Sample {
+    pub fn is_paired(&self) -> bool {
+        self.r2.is_some()
+    }
+
+    /// The input files for this sample.
+    pub fn inputs(&self) -> Vec<&Path> {
+        let mut inputs = vec![self.r1.as_path()];
+        if let Some(r2) = &self.r2 {
+            inputs.push(r2.as_path());
+        }
+        inputs
+    }
+
+    /// The extension of the `n`th input, e.g. "fastq", or "fq" if it doesn't have one.
+    fn extension(&self, n: usize) -> String {
+        let (_, ext) = split_file_name(self.inputs()[n]);
+        ext.unwrap_or_else(|| "fq".to_string())
+    }
+
+    /// The (uncompressed) output path for the `n`th input of this sample. Paired samples get a
+    /// `_1`/`_2` suffix, and the input's extension is kept. e.g. prefix "out/s1" ->
+    /// "out/s1_1.nohuman.fq" and "out/s1_2.nohuman.fq".
+    pub fn output_path(&self, n: usize, host: &Host) -> PathBuf {
+        let mut path: OsString = match &self.output_prefix {
+            Some(prefix) => prefix.as_os_str().to_owned(),
+            None => OsString::from(&self.sample),
+        };
+        if self.is_paired() {
+            path.push(format!("_{}", n + 1));
+        }
+        path.push(format!(".{}.{}", host.output_suffix(), self.extension(n)));
+        PathBuf::from(path)
+    }
+
+    /// The output path for the `n`th input of this sample, named with `template`. `{stem}` is the
+    /// output prefix (or sample name), and `{ext}` is the extension of the input.
+    pub fn templated_output_path(
+        &self,
+        n: usize,
+        template: &OutputTemplate,
+        compression: CompressionFormat,
+        host: &Host,
+    ) -> PathBuf {
+        let prefix = match &self.output_prefix {
+            Some(prefix) => prefix.to_owned(),
+            None => PathBuf::from(&self.sample),
+        };
+        let stem = prefix.file_name().unwrap_or_default().to_string_lossy();
+        let ext = self.extension(n);
+        let name = template.render(&stem, n + 1, &ext, compression, host);
+        prefix.with_file_name(name)
+    }
+}
+
+/// Parse a CSV sample sheet with the header `sample,r1,r2,output_prefix`. The `r2` and
+/// `output_prefix` columns can be left empty (or omitted entirely). Relative `r1` and `r2` paths
+/// are relative to the sample sheet's directory, so the sheet can be used from anywhere.
+pub fn parse_sample_sheet(path: &Path) -> Result<Vec<Sample>> {
+    let dir = path.parent().unwrap_or(Path::new(""));
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_path(path)
+        .with_context(|| format!("Failed to open sample sheet {:?}", path))?;
+
+    let mut samples = Vec::new();
+    let mut names = HashSet::new();
+    for (i, row) in reader.deserialize().enumerate() {
+        let mut sample: Sample =
+            row.with_context(|| format!("Failed to parse row {} of the sample sheet", i + 1))?;
+        sample.r1 = dir.join(&sample.r1);
+        sample.r2 = sample.r2.map(|r2| dir.join(r2));
+        if !names.insert(sample.sample.clone()) {
+            bail!("Duplicate sample name in sample sheet: {}", sample.sample);
+        }
+        for input in sample.inputs() {
+            if !input.exists() {
+                bail!(
+                    "Input {:?} for sample {} does not exist",
+                    input,
+                    sample.sample
+                );
+            }
+        }
+        samples.push(sample);
+    }
+
+    if samples.is_empty() {
+        bail!("Sample sheet {:?} contains no samples", path);
+    }
+
+    Ok(samples)
+}
+
+/// The markers that tell the first and second files of a pair apart, e.g. "in_R1.fq" and
+/// "in_R2.fq", in the order they are looked for.
+const MATE_MARKERS: [(&str, &str); 4] =
+    [("_R1", "_R2"), (".R1", ".R2"), ("_1", "_2"), (".1", ".2")];
+
+/// A file's name split at its mate marker (see [`MATE_MARKERS`]).
+#[derive(Debug, PartialEq)]
+struct MateName<'a> {
+    /// The name with the marker removed, e.g. "in" for "in_R1" or "in_001" for "in_R1_001".
+    name: String,
+    /// 1 or 2.
+    mate: usize,
+    /// The part of the name before the marker, and the separator the marker starts with.
+    prefix: &'a str,
+    separator: char,
+    /// Whether the marker is just a number, e.g. "_1".
+    numeric: bool,
+}
+
+/// Split the file `stem` at its mate marker, if it has one. The marker must end the stem, except
+/// that "_R1"/"_R2" can be followed by a number, e.g. Illumina's "in_S1_L001_R1_001".
+fn split_mate(stem: &str) -> Option<MateName<'_>> {
+    for (marker1, marker2) in MATE_MARKERS {
+        for (mate, marker) in [(1, marker1), (2, marker2)] {
+            let Some(pos) = stem.rfind(marker) else {
+                continue;
+            };
+            let numeric = !marker.contains('R');
+            let rest = &stem[pos + marker.len()..];
+            let suffix_ok = rest.is_empty()
+                || (!numeric
+                    && rest.len() > 1
+                    && rest.starts_with('_')
+                    && rest[1..].bytes().all(|b| b.is_ascii_digit()));
+            if pos == 0 || !suffix_ok {
+                continue;
+            }
+            return Some(MateName {
+                name: format!("{}{}", &stem[..pos], rest),
+                mate,
+                prefix: &stem[..pos],
+                separator: marker.chars().next().unwrap_or('_'),
+                numeric,
+            });
+        }
+    }
+    None
+}
+
+/// Group sequence `files` into samples. Files whose names differ only by a mate marker (e.g.
+/// "in_R1.fq.gz" and "in_R2.fq.gz", or "_1"/"_2", ".R1"/".R2", and ".1"/".2") are a paired
+/// sample, and other files are single-end samples. Numbered chunks, e.g. nanopore's "reads_0.fq",
+/// "reads_1.fq", "reads_2.fq", aren't pairs. A sample is named after its path relative to `root`, without extensions or mate
+/// marker, and its outputs are written to the same relative path in `outdir`, or next to the
+/// inputs if `outdir` is `None`. Groupings that are ambiguous, e.g. two files that could be the
+/// first of a pair, are an error.
+pub fn group_samples(files: &[PathBuf], root: &Path, outdir: Option<&Path>) -> Result<Vec<Sample>> {
+    let stems: Vec<String> = files.iter().map(|path| split_file_name(path).0).collect();
+    // the stems of the files in each directory, to recognise numbered chunks
+    let mut siblings: HashMap<&Path, HashSet<&str>> = HashMap::new();
+    for (path, stem) in files.iter().zip(&stems) {
+        siblings
+            .entry(path.parent().unwrap_or(Path::new("")))
+            .or_default()
+            .insert(stem.as_str());
+    }
+    let is_chunk = |parent: &Path, mate_name: &MateName| {
+        siblings[parent].iter().any(|stem| {
+            stem.strip_prefix(mate_name.prefix)
+                .and_then(|rest| rest.strip_prefix(mate_name.separator))
+                .is_some_and(|n| {
+                    !n.is_empty() && n != "1" && n != "2" && n.bytes().all(|b| b.is_ascii_digit())
+                })
+        })
+    };
+
+    // the files of each sample, by name
+    let mut groups: BTreeMap<PathBuf, [Option<&PathBuf>; 2]> = BTreeMap::new();
+    let mut single = Vec::new();
+    for (path, stem) in files.iter().zip(&stems) {
+        let parent = path.parent().unwrap_or(Path::new(""));
+        let relative_parent = parent.strip_prefix(root).unwrap_or(parent);
+        match split_mate(stem).filter(|m| !(m.numeric && is_chunk(parent, m))) {
+            Some(mate_name) => {
+                let slot = &mut groups
+                    .entry(relative_parent.join(&mate_name.name))
+                    .or_default()[mate_name.mate - 1];
+                if let Some(other) = slot {
+                    bail!(
+                        "Can't pair the input files: both {:?} and {:?} look like read {} of a pair",
+                        other,
+                        path,
+                        mate_name.mate
+                    );
+                }
+                *slot = Some(path);
+            }
+            None => single.push((relative_parent.join(stem), path)),
+        }
+    }
+
+    let mut samples = Vec::new();
+    let mut sample = |name: PathBuf, r1: &PathBuf, r2: Option<&PathBuf>| {
+        let output_prefix = match outdir {
+            Some(outdir) => outdir.join(&name),
+            None => r1.with_file_name(name.file_name().unwrap_or_default()),
+        };
+        samples.push(Sample {
+            sample: name.to_string_lossy().to_string(),
+            r1: r1.to_owned(),
+            r2: r2.cloned(),
+            output_prefix: Some(output_prefix),
+        });
+    };
+    for (name, path) in single {
+        sample(name, path, None);
+    }
+    for (name, mates) in groups {
+        match mates {
+            [Some(r1), Some(r2)] => sample(name, r1, Some(r2)),
+            [Some(path), None] | [None, Some(path)] => {
+                warn!("{:?} has no mate, so is treated as single-end", path);
+                let (stem, _) = split_file_name(path);
+                sample(name.with_file_name(stem), path, None);
+            }
+            [None, None] => unreachable!("each group has a file"),
+        }
+    }
+    samples.sort_by(|a, b| a.sample.cmp(&b.sample));
+
+    let mut names = HashSet::new();
+    for sample in &samples {
+        if !names.insert(&sample.sample) {
+            bail!(
+                "Can't group the input files: more than one sample would be named {}",
+                sample.sample
+            );
+        }
+    }
+    Ok(samples)
+}
+
+/// Find the sequence files (e.g. "reads.fastq.gz") in `dir` and its subdirectories, such as the
+/// `barcodeXX` directories of a nanopore run's `fastq_pass`, and group them into samples (see
+/// [`group_samples`]). Each sample's outputs are written to the same path relative to `outdir` as
+/// its inputs are to `dir`, and `outdir` is skipped if it is inside `dir`. e.g.
+/// "fastq_pass/barcode01/reads_0.fastq.gz" is sample "barcode01/reads_0", which is written to
+/// `<outdir>/barcode01/reads_0.nohuman.fastq.gz`.
+pub fn samples_from_directory(dir: &Path, outdir: &Path) -> Result<Vec<Sample>> {
+    let absolute_outdir = std::path::absolute(outdir)?;
+    let mut files = Vec::new();
+    let mut dirs = vec![dir.to_path_buf()];
+    while let Some(current) = dirs.pop() {
+        for entry in std::fs::read_dir(&current)
+            .with_context(|| format!("Failed to read directory {:?}", current))?
+        {
+            let path = entry?.path();
+            if path.is_dir() {
+                if std::path::absolute(&path)? != absolute_outdir {
+                    dirs.push(path);
+                }
+            } else if split_file_name(&path).1.is_some() {
+                files.push(path);
+            }
+        }
+    }
+
+    if files.is_empty() {
+        bail!("No FASTA/FASTQ files found in {:?}", dir);
+    }
+    group_samples(&files, dir, Some(outdir))
+}
+
+/// Whether `pattern` is a glob pattern, i.e. has a `*`, `?`, or `{a,b}` alternatives.
+pub fn is_glob(pattern: &str) -> bool {
+    pattern.contains(['*', '?']) || (pattern.contains('{') && pattern.contains('}'))
+}
+
+/// Find the files matching the glob `pattern` and group them into samples (see
+/// [`group_samples`]), e.g. "fastqs/*_R{1,2}.fastq.gz". The outputs are written next to the
+/// inputs, or to the same path relative to `outdir` as the inputs are to the pattern's directory.
+pub fn samples_from_glob(pattern: &str, outdir: Option<&Path>) -> Result<Vec<Sample>> {
+    let files = expand_glob(pattern)?;
+    // the directory the pattern starts from, i.e. the part before any wildcards
+    let literal = &pattern[..pattern.find(['*', '?', '{']).unwrap_or(pattern.len())];
+    let root = match literal.rfind('/') {
+        Some(i) => Path::new(&literal[..i]),
+        None => Path::new(""),
+    };
+    group_samples(&files, root, outdir)
+}
+
+/// Find the files matching the glob `pattern`, sorted. `*` matches any characters and `?` any one
+/// character in a file name, and `{a,b}` matches either alternative. Wildcards can only be used in
+/// the file name, not the directories.
+pub fn expand_glob(pattern: &str) -> Result<Vec<PathBuf>> {
+    let mut files = BTreeSet::new();
+    for pattern in expand_braces(pattern) {
+        let path = Path::new(&pattern);
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        if dir.to_string_lossy().contains(['*', '?']) {
+            bail!(
+                "Wildcards are only supported in file names, not directories, but got {}",
+                pattern
+            );
+        }
+        let Some(name_pattern) = path.file_name().map(|n| n.to_string_lossy()) else {
+            continue;
+        };
+        let entries = std::fs::read_dir(dir)
+            .with_context(|| format!("Failed to read directory {:?} for {}", dir, pattern))?;
+        for entry in entries {
+            let entry = entry?;
+            let name = entry.file_name();
+            if entry.path().is_file()
+                && wildcard_match(name_pattern.as_bytes(), name.as_encoded_bytes())
+            {
+                files.insert(path.with_file_name(name));
+            }
+        }
+    }
+    if files.is_empty() {
+        bail!("No files match {}", pattern);
+    }
+    Ok(files.into_iter().collect())
+}
+
+/// Expand the (non-nested) `{a,b}` alternatives in `pattern` into a pattern for each.
+fn expand_braces(pattern: &str) -> Vec<String> {
+    let Some(start) = pattern.find('{') else {
+        return vec![pattern.to_string()];
+    };
+    let Some(end) = pattern[start..].find('}').map(|i| start + i) else {
+        return vec![pattern.to_string()];
+    };
+    pattern[start + 1..end]
+        .split(',')
+        .flat_map(|alternative| {
+            expand_braces(&format!(
+                "{}{}{}",
+                &pattern[..start],
+                alternative,
+                &pattern[end + 1..]
+            ))
+        })
+        .collect()
+}
+
+/// Whether `name` matches `pattern`, where `*` matches any characters and `?` any one character.
+fn wildcard_match(pattern: &[u8], name: &[u8]) -> bool {
+    let (mut p, mut n) = (0, 0);
+    // where the last `*` is in the pattern, and where its match in the name ends
+    let mut star = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some(b'*') => {
+                star = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == b'?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match star {
+                // let the last `*` match one more character
+                Some((star_p, star_n)) => {
+                    p = star_p + 1;
+                    n = star_n + 1;
+                    star = Some((star_p, star_n + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == b'*')
+}
+
+/// Copy all reads from `reader` to `writer`, prefixing each read ID with `tag` so they can be
+/// traced back to their sample after classification. Returns the number of reads copied.
+pub fn tag_reads<R, W>(tag: usize, reader: R, writer: &mut W) -> Result<u64>
+where
+    R: Read + Send,
+    W: Write,
+{
+    let Some(mut fastx_reader) = fastx_reader(reader)? else {
+        return Ok(0);
+    };
+    let prefix = format!("{}{}", tag, TAG_SEPARATOR as char);
+    let mut id = Vec::new();
+    let mut n_reads = 0;
+
+    while let Some(record) = fastx_reader.next() {
+        let record = record.context("Failed to parse read")?;
+        id.clear();
+        id.extend_from_slice(prefix.as_bytes());
+        id.extend_from_slice(record.id());
+        write_record(writer, &id, &record.seq(), record.qual())?;
+        n_reads += 1;
+    }
+
+    Ok(n_reads)
+}
+
+/// Split tagged reads (see [`tag_reads`]) from `reader` into `writers`, where the tag is the
+/// index of the writer. The tag is removed from each read ID. Returns the number of reads written
+/// to each writer.
+pub fn split_tagged_reads<R, W>(reader: R, writers: &mut [W]) -> Result<Vec<u64>>
+where
+    R: Read + Send,
+    W: Write,
+{
+    let mut counts = vec![0; writers.len()];
+    // kraken2 leaves the file empty if no reads were selected
+    let Some(mut fastx_reader) = fastx_reader(reader)? else {
+        return Ok(counts);
+    };
+
+    while let Some(record) = fastx_reader.next() {
+        let record = record.context("Failed to parse read")?;
+        let id = record.id();
+        let sep = id
+            .iter()
+            .position(|&b| b == TAG_SEPARATOR)
+            .context("Read is missing its sample tag")?;
+        let tag: usize = std::str::from_utf8(&id[..sep])
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .context("Read has an invalid sample tag")?;
+        let writer = writers
+            .get_mut(tag)
+            .with_context(|| format!("Read has an unknown sample tag {}", tag))?;
+        write_record(writer, &id[sep + 1..], &record.seq(), record.qual())?;
+        counts[tag] += 1;
+    }
+
+    Ok(counts)
+}
+
+/// The name of the sample whose reads are in `input`: its file name without the extensions or a
+/// mate marker, e.g. "in" for "in_R1.fq.gz". Reads from stdin are named "stdin".
+pub fn sample_name(input: &Path) -> String {
+    if is_stdio(input) {
+        return "stdin".to_string();
+    }
+    let (stem, _) = split_file_name(input);
+    split_mate(&stem).map_or(stem.clone(), |mate| mate.name)
+}
+
+/// Per-sample counts for the batch summary.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct SampleCounts {
+    pub total: u64,
+    /// Number of reads classified as host.
+    pub host: u64,
+}
+
+/// Write a tab-separated summary of the per-sample classification counts.
+pub fn write_summary<W: Write>(
+    writer: &mut W,
+    samples: &[Sample],
+    counts: &[SampleCounts],
+    host: &Host,
+) -> Result<()> {
+    writeln!(
+        writer,
+        "sample\ttotal_reads\t{host}_reads\tnon_{host}_reads\t{host}_percent",
+        host = host
+    )?;
+    for (sample, c) in samples.iter().zip(counts) {
+        let pct = if c.total == 0 {
+            0.0
+        } else {
+            c.host as f64 / c.total as f64 * 100.0
+        };
+        writeln!(
+            writer,
+            "{}\t{}\t{}\t{}\t{:.2}",
+            sample.sample,
+            c.total,
+            c.host,
+            c.total - c.host,
+            pct
+        )?;
+    }
+    Ok(())
+}
+
+/// The `--summary` of a batch: the inputs of every sample, and the sequence counts summed across
+/// the samples. The caller fills in the rest (e.g. the outputs and timings).
+pub fn batch_summary(samples: &[Sample], counts: &[SampleCounts]) -> Summary {
+    Summary {
+        inputs: samples
+            .iter()
+            .flat_map(|s| s.inputs())
+            .map(Path::to_path_buf)
+            .collect(),
+        sequences: counts.iter().map(|c| c.total).sum(),
+        host_sequences: counts.iter().map(|c| c.host).sum(),
+        ..Default::default()
+    }
+}
+
+/// Write the per-sample classification counts as MultiQC custom content (JSON), to add a table
+/// of the sequences removed and kept to a MultiQC report. MultiQC finds custom content in files
+/// ending in "_mqc.json".
+pub fn write_multiqc<W: Write>(
+    writer: W,
+    samples: &[Sample],
+    counts: &[SampleCounts],
+    host: &Host,
+    keep_host_reads: bool,
+) -> Result<()> {
+    let mut data = serde_json::Map::new();
+    for (sample, c) in samples.iter().zip(counts) {
+        let (kept, removed) = if keep_host_reads {
+            (c.host, c.total - c.host)
+        } else {
+            (c.total - c.host, c.host)
+        };
+        let pct = |n: u64| n as f64 / c.total.max(1) as f64 * 100.0;
+        data.insert(
+            sample.sample.clone(),
+            serde_json::json!({
+                "total": c.total,
+                "host": c.host,
+                "removed": removed,
+                "kept": kept,
+                "host_percent": pct(c.host),
+                "removed_percent": pct(removed),
+            }),
+        );
+    }
+    let count = |title: &str, description: String| serde_json::json!({ "title": title, "description": description, "format": "{:,.0f}" });
+    let percent = |title: &str, description: String| {
+        serde_json::json!({
+            "title": title,
+            "description": description,
+            "suffix": "%",
+            "min": 0,
+            "max": 100,
+            "format": "{:,.2f}",
+        })
+    };
+    let content = serde_json::json!({
+        "id": "nohuman",
+        "section_name": "nohuman",
+        "description": format!(
+            "Sequences classified as {} by nohuman {}. A pair of reads counts as one sequence.",
+            host,
+            env!("CARGO_PKG_VERSION")
+        ),
+        "plot_type": "table",
+        "pconfig": { "id": "nohuman_table", "title": "nohuman" },
+        "headers": {
+            "total": count("Sequences", "Sequences classified".to_string()),
+            "host": count(&format!("{} seqs", host), format!("Sequences classified as {}", host)),
+            "removed": count("Removed", "Sequences removed".to_string()),
+            "kept": count("Kept", "Sequences kept in the output".to_string()),
+            "host_percent": percent(&format!("% {}", host), format!("Percent of sequences classified as {}", host)),
+            "removed_percent": percent("% Removed", "Percent of sequences removed".to_string()),
+        },
+        "data": data,
+    });
+    serde_json::to_writer_pretty(writer, &content)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_samples_from_directory() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().join("fastq_pass");
+        for barcode in ["barcode01", "barcode02", "nohuman"] {
+            std::fs::create_dir_all(dir.join(barcode)).unwrap();
+        }
+        for file in [
+            "barcode01/a_0.fastq.gz",
+            "barcode01/a_1.fastq.gz",
+            "barcode02/a_0.fq",
+            "barcode02/report.txt",
+            "nohuman/barcode01/a_0.nohuman.fastq.gz",
+        ] {
+            let path = dir.join(file);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, "").unwrap();
+        }
+
+        let samples = samples_from_directory(&dir, &dir.join("nohuman")).unwrap();
+        let names: Vec<&str> = samples.iter().map(|s| s.sample.as_str()).collect();
+        assert_eq!(names, ["barcode01/a_0", "barcode01/a_1", "barcode02/a_0"]);
+        assert_eq!(samples[2].r1, dir.join("barcode02/a_0.fq"));
+        assert_eq!(
+            samples[0].output_path(0, &Host::Human),
+            dir.join("nohuman/barcode01/a_0.nohuman.fastq")
+        );
+
+        let empty = tmp.path().join("empty");
+        std::fs::create_dir(&empty).unwrap();
+        assert!(samples_from_directory(&empty, tmp.path()).is_err());
+    }
+
+    #[test]
+    fn test_split_mate() {
+        let mate = split_mate("in_S1_L001_R2_001").unwrap();
+        assert_eq!((mate.name.as_str(), mate.mate), ("in_S1_L001_001", 2));
+        assert!(!mate.numeric);
+        let mate = split_mate("in.1").unwrap();
+        assert_eq!(
+            (mate.name.as_str(), mate.mate, mate.prefix),
+            ("in", 1, "in")
+        );
+        assert!(mate.numeric);
+        assert_eq!(split_mate("in_1_001"), None);
+        assert_eq!(split_mate("in_R3"), None);
+        assert_eq!(split_mate("_1"), None);
+    }
+
+    #[test]
+    fn test_group_samples() {
+        let files: Vec<PathBuf> = [
+            "dir/a_R1.fq.gz",
+            "dir/a_R2.fq.gz",
+            "dir/b_1.fastq",
+            "dir/b_2.fastq",
+            "dir/c_R1.fq",
+            "dir/reads_0.fq",
+            "dir/reads_1.fq",
+            "dir/reads_2.fq",
+        ]
+        .iter()
+        .map(PathBuf::from)
+        .collect();
+
+        let samples = group_samples(&files, Path::new("dir"), None).unwrap();
+        let names: Vec<&str> = samples.iter().map(|s| s.sample.as_str()).collect();
+        assert_eq!(names, ["a", "b", "c_R1", "reads_0", "reads_1", "reads_2"]);
+        assert_eq!(samples[0].r2, Some(PathBuf::from("dir/a_R2.fq.gz")));
+        assert_eq!(samples[1].r1, PathBuf::from("dir/b_1.fastq"));
+        assert!(!samples[2].is_paired());
+        assert!(!samples[4].is_paired());
+        assert_eq!(
+            samples[0].output_path(1, &Host::Human),
+            PathBuf::from("dir/a_2.nohuman.fq")
+        );
+
+        let samples = group_samples(&files[..2], Path::new("dir"), Some(Path::new("out"))).unwrap();
+        assert_eq!(samples[0].output_prefix, Some(PathBuf::from("out/a")));
+
+        let ambiguous: Vec<PathBuf> = ["a_R1.fq", "a_R1.fastq", "a_R2.fq"]
+            .iter()
+            .map(PathBuf::from)
+            .collect();
+        assert!(group_samples(&ambiguous, Path::new(""), None).is_err());
+    }
+
+    #[test]
+    fn test_expand_glob() {
+        let tmp = tempfile::tempdir().unwrap();
+        for name in [
+            "s1_R1.fq.gz",
+            "s1_R2.fq.gz",
+            "s1_R3.fq.gz",
+            "s2_R1.fq.gz",
+            "notes.txt",
+        ] {
+            std::fs::write(tmp.path().join(name), "").unwrap();
+        }
+        let dir = tmp.path().to_string_lossy();
+
+        let files = expand_glob(&format!("{}/*_R{{1,2}}.fq.gz", dir)).unwrap();
+        let names: Vec<_> = files.iter().map(|p| p.file_name().unwrap()).collect();
+        assert_eq!(names, ["s1_R1.fq.gz", "s1_R2.fq.gz", "s2_R1.fq.gz"]);
+        assert_eq!(expand_glob(&format!("{}/s?_R3*", dir)).unwrap().len(), 1);
+        assert!(expand_glob(&format!("{}/*.bam", dir)).is_err());
+        assert!(expand_glob(&format!("{}/*/x.fq", dir)).is_err());
+
+        let samples = samples_from_glob(&format!("{}/s1_R{{1,2}}.fq.gz", dir), None).unwrap();
+        assert_eq!(samples.len(), 1);
+        assert!(samples[0].is_paired());
+
+        assert!(is_glob("fastqs/*_R{1,2}.fastq.gz"));
+        assert!(!is_glob("in_R1.fq"));
+    }
+
+    #[test]
+    fn test_wildcard_match() {
+        assert!(wildcard_match(b"*_R?.fq*", b"a_b_R1.fq.gz"));
+        assert!(wildcard_match(b"*", b""));
+        assert!(!wildcard_match(b"*.fq", b"a.fq.gz"));
+        assert!(!wildcard_match(b"a?", b"a"));
+    }
+
+    #[test]
+    fn test_parse_sample_sheet() {
+        let tmp = tempfile::tempdir().unwrap();
+        let r1 = tmp.path().join("r1.fq");
+        let r2 = tmp.path().join("r2.fq");
+        std::fs::write(&r1, "").unwrap();
+        std::fs::write(&r2, "").unwrap();
+        let sheet = tmp.path().join("samples.csv");
+        std::fs::write(
+            &sheet,
+            format!(
+                "sample,r1,r2,output_prefix\ns1,{r1},{r2},out/s1\ns2,r1.fq,,\n",
+                r1 = r1.display(),
+                r2 = r2.display()
+            ),
+        )
+        .unwrap();
+
+        let samples = parse_sample_sheet(&sheet).unwrap();
+        assert_eq!(samples.len(), 2);
+        // relative paths are relative to the sample sheet
+        assert_eq!(samples[1].r1, r1);
+        assert!(samples[0].is_paired());
+        assert_eq!(
+            samples[0].output_path(1, &Host::Human),
+            PathBuf::from("out/s1_2.nohuman.fq")
+        );
+        assert!(!samples[1].is_paired());
+        assert_eq!(
+            samples[1].output_path(0, &Host::Custom),
+            PathBuf::from("s2.nohost.fq")
+        );
+
+        let template: OutputTemplate = "{stem}_R{read}.clean.{ext}".parse().unwrap();
+        assert_eq!(
+            samples[0].templated_output_path(1, &template, CompressionFormat::Gzip, &Host::Human),
+            PathBuf::from("out/s1_R2.clean.fq.gz")
+        );
+    }
+
+    #[test]
+    fn test_parse_sample_sheet_duplicate_name() {
+        let tmp = tempfile::tempdir().unwrap();
+        let r1 = tmp.path().join("r1.fq");
+        std::fs::write(&r1, "").unwrap();
+        let sheet = tmp.path().join("samples.csv");
+        std::fs::write(
+            &sheet,
+            format!("sample,r1\ns1,{r1}\ns1,{r1}\n", r1 = r1.display()),
+        )
+        .unwrap();
+
+        let result = parse_sample_sheet(&sheet);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_parse_sample_sheet_missing_input() {
+        let tmp = tempfile::tempdir().unwrap();
+        let sheet = tmp.path().join("samples.csv");
+        std::fs::write(&sheet, "sample,r1\ns1,does/not/exist.fq\n").unwrap();
+
+        let result = parse_sample_sheet(&sheet);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_tag_and_split_reads() {
+        let s1 = b"@r1 desc\nACGT\n+\nIIII\n@r2\nGGGG\n+\nIIII\n";
+        let s2 = b"@r3\nTTTT\n+\nIIII\n";
+        let mut combined = Vec::new();
+        assert_eq!(tag_reads(0, Cursor::new(s1), &mut combined).unwrap(), 2);
+        assert_eq!(tag_reads(1, Cursor::new(s2), &mut combined).unwrap(), 1);
+        assert!(combined.starts_with(b"@0|r1 desc\n"));
+
+        let mut writers = vec![Vec::new(), Vec::new()];
+        let counts = split_tagged_reads(Cursor::new(combined), &mut writers).unwrap();
+        assert_eq!(counts, vec![2, 1]);
+        assert_eq!(writers[0], s1.to_vec());
+        assert_eq!(writers[1], s2.to_vec());
+    }
+
+    #[test]
+    fn test_split_tagged_reads_empty() {
+        let mut writers: Vec<Vec<u8>> = vec![Vec::new()];
+        let counts = split_tagged_reads(Cursor::new(b""), &mut writers).unwrap();
+        assert_eq!(counts, vec![0]);
+    }
+
+    #[test]
+    fn test_split_tagged_reads_untagged() {
+        let mut writers: Vec<Vec<u8>> = vec![Vec::new()];
+        let result = split_tagged_reads(Cursor::new(b">r1\nACGT\n"), &mut writers);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_write_summary() {
+        let samples = vec![Sample {
+            sample: "s1".to_string(),
+            r1: PathBuf::from("r1.fq"),
+            r2: None,
+            output_prefix: None,
+        }];
+        let counts = vec![SampleCounts { total: 4, host: 1 }];
+        let mut out = Vec::new();
+        write_summary(&mut out, &samples, &counts, &Host::Human).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "sample\ttotal_reads\thuman_reads\tnon_human_reads\thuman_percent\ns1\t4\t1\t3\t25.00\n"
+        );
+    }
+
+    #[test]
+    fn test_batch_summary() {
+        let samples = vec![
+            Sample {
+                sample: "s1".to_string(),
+                r1: PathBuf::from("s1_R1.fq"),
+                r2: Some(PathBuf::from("s1_R2.fq")),
+                output_prefix: None,
+            },
+            Sample {
+                sample: "s2".to_string(),
+                r1: PathBuf::from("s2.fq"),
+                r2: None,
+                output_prefix: None,
+            },
+        ];
+        let counts = vec![
+            SampleCounts { total: 4, host: 1 },
+            SampleCounts { total: 6, host: 3 },
+        ];
+        let mut out = Vec::new();
+        batch_summary(&samples, &counts)
+            .to_writer(&mut out)
+            .unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(json["sequences"], 10);
+        assert_eq!(json["host_sequences"], 4);
+        assert_eq!(json["inputs"].as_array().unwrap().len(), 3);
+        assert_eq!(json["inputs"][2], "s2.fq");
+    }
+
+    #[test]
+    fn test_write_multiqc() {
+        let samples = vec![Sample {
+            sample: "s1".to_string(),
+            r1: PathBuf::from("s1_R1.fq"),
+            r2: Some(PathBuf::from("s1_R2.fq")),
+            output_prefix: None,
+        }];
+        let counts = vec![SampleCounts { total: 4, host: 1 }];
+        let mut out = Vec::new();
+        write_multiqc(&mut out, &samples, &counts, &Host::Human, false).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(json["plot_type"], "table");
+        assert_eq!(json["headers"]["host"]["title"], "human seqs");
+        assert_eq!(json["data"]["s1"]["removed"], 1);
+        assert_eq!(json["data"]["s1"]["kept"], 3);
+        assert_eq!(json["data"]["s1"]["host_percent"], 25.0);
+
+        let mut out = Vec::new();
+        write_multiqc(&mut out, &samples, &counts, &Host::Human, true).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(json["data"]["s1"]["kept"], 1);
+        assert_eq!(json["data"]["s1"]["removed_percent"], 75.0);
+    }
+
+    #[test]
+    fn test_sample_name() {
+        assert_eq!(sample_name(Path::new("dir/in_R1.fastq.gz")), "in");
+        assert_eq!(sample_name(Path::new("in_R2_001.fq")), "in_001");
+        assert_eq!(sample_name(Path::new("reads.fq")), "reads");
+        assert_eq!(sample_name(Path::new("-")), "stdin");
+    }
+}
@@ -0,0 +1,138 @@
+//! Comparing database versions (or engines) on the same reads (`nohuman bench`), to help choose
+//! between them: how long each takes, how much memory it needs, and how much the reads they
+//! remove agree.
+use crate::align::Aligner;
+use crate::resources::ResourceUsage;
+use anyhow::Result;
+use std::collections::HashSet;
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// The result of running one database version (or engine) on the reads.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BenchRun {
+    /// What was run, e.g. the database version.
+    pub name: String,
+    pub engine: Aligner,
+    /// The database used, if any.
+    pub database: Option<PathBuf>,
+    pub wall_time: Duration,
+    /// The resources used by kraken2, if it was run.
+    pub kraken2_usage: Option<ResourceUsage>,
+    /// The number of sequences classified. A pair of reads counts as one sequence.
+    pub sequences: u64,
+    /// The IDs of the reads classified as host.
+    pub host_ids: HashSet<Vec<u8>>,
+}
+
+/// How much two sets of host reads agree.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Agreement {
+    /// The number of reads in both sets.
+    pub shared: usize,
+    /// The number of shared reads as a fraction of the reads in either set (the Jaccard index).
+    /// This is 1 if both sets are empty.
+    pub jaccard: f64,
+}
+
+impl Agreement {
+    pub fn new(a: &HashSet<Vec<u8>>, b: &HashSet<Vec<u8>>) -> Self {
+        let shared = a.intersection(b).count();
+        let union = a.len() + b.len() - shared;
+        let jaccard = if union == 0 {
+            1.0
+        } else {
+            shared as f64 / union as f64
+        };
+        Self { shared, jaccard }
+    }
+}
+
+/// Write `runs` as a tab-separated table, with the agreement of each with the first run.
+/// Resources that weren't measured are written as "NA".
+pub fn write_tsv<W: Write>(mut writer: W, runs: &[BenchRun]) -> Result<()> {
+    writeln!(
+        writer,
+        "name\tengine\tdatabase\tseconds\tkraken2_cpu_seconds\tkraken2_max_rss_bytes\tsequences\thost_reads\tshared_with_first\tjaccard_with_first"
+    )?;
+    let Some(first) = runs.first() else {
+        return Ok(());
+    };
+    let na = || "NA".to_string();
+    for run in runs {
+        let agreement = Agreement::new(&first.host_ids, &run.host_ids);
+        writeln!(
+            writer,
+            "{}\t{}\t{}\t{:.2}\t{}\t{}\t{}\t{}\t{}\t{:.4}",
+            run.name,
+            run.engine,
+            run.database
+                .as_ref()
+                .map_or_else(na, |path| path.display().to_string()),
+            run.wall_time.as_secs_f64(),
+            run.kraken2_usage
+                .map_or_else(na, |usage| format!("{:.2}", usage.cpu_time.as_secs_f64())),
+            run.kraken2_usage
+                .map_or_else(na, |usage| usage.max_rss.to_string()),
+            run.sequences,
+            run.host_ids.len(),
+            agreement.shared,
+            agreement.jaccard
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ids(ids: &[&str]) -> HashSet<Vec<u8>> {
+        ids.iter().map(|id| id.as_bytes().to_vec()).collect()
+    }
+
+    #[test]
+    fn test_agreement() {
+        let agreement = Agreement::new(&ids(&["r1", "r2", "r3"]), &ids(&["r2", "r3", "r4"]));
+        assert_eq!(agreement.shared, 2);
+        assert_eq!(agreement.jaccard, 0.5);
+        assert_eq!(Agreement::new(&ids(&[]), &ids(&[])).jaccard, 1.0);
+        assert_eq!(Agreement::new(&ids(&["r1"]), &ids(&[])).jaccard, 0.0);
+    }
+
+    #[test]
+    fn test_write_tsv() {
+        let run = |name: &str, engine, host_ids| BenchRun {
+            name: name.to_string(),
+            engine,
+            database: None,
+            wall_time: Duration::from_millis(1500),
+            kraken2_usage: None,
+            sequences: 4,
+            host_ids,
+        };
+        let mut first = run("v1", Aligner::Kraken2, ids(&["r1", "r2"]));
+        first.database = Some(PathBuf::from("db/v1"));
+        first.kraken2_usage = Some(ResourceUsage {
+            wall_time: Duration::from_secs(1),
+            cpu_time: Duration::from_secs(3),
+            max_rss: 1024,
+        });
+        let runs = [first, run("native", Aligner::Native, ids(&["r2", "r3"]))];
+        let mut out = Vec::new();
+        write_tsv(&mut out, &runs).unwrap();
+
+        let out = String::from_utf8(out).unwrap();
+        let lines: Vec<_> = out.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(
+            lines[1],
+            "v1\tkraken2\tdb/v1\t1.50\t3.00\t1024\t4\t2\t2\t1.0000"
+        );
+        assert_eq!(
+            lines[2],
+            "native\tnative\tNA\t1.50\tNA\tNA\t4\t2\t1\t0.3333"
+        );
+    }
+}
@@ -0,0 +1,259 @@
+//! Building custom host databases with `kraken2-build`, or native databases for nohuman's built-in
+//! classifier.
+use crate::compression::open_reader;
+use crate::database::{DatabaseLock, DatabaseMetadata};
+use crate::download::{check_version, index_file_checksums};
+use crate::native::{NativeIndex, NATIVE_INDEX_FILE};
+use crate::Host;
+use anyhow::{bail, Context, Result};
+use log::info;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// The name of the file the host genomes are written to for `kraken2-build --add-to-library`.
+const LIBRARY_INPUT: &str = "nohuman_library.fa";
+
+/// How to build a database.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BuildOptions {
+    /// The (optionally compressed) FASTA files of the host genome(s).
+    pub genomes: Vec<PathBuf>,
+    /// The taxonomy ID to assign the sequences to. If `None`, the sequence IDs must already
+    /// contain a taxonomy ID, e.g. `>chr1|kraken:taxid|9913`.
+    pub taxid: Option<u64>,
+    /// The number of threads kraken2-build uses.
+    pub threads: usize,
+    /// The k-mer length. kraken2's default if `None`.
+    pub kmer_len: Option<u32>,
+    /// The minimizer length. kraken2's default if `None`.
+    pub minimizer_len: Option<u32>,
+    /// The kraken2-build executable.
+    pub command: String,
+}
+
+impl Default for BuildOptions {
+    fn default() -> Self {
+        Self {
+            genomes: Vec::new(),
+            taxid: None,
+            threads: 1,
+            kmer_len: None,
+            minimizer_len: None,
+            command: "kraken2-build".to_string(),
+        }
+    }
+}
+
+impl BuildOptions {
+    /// Run `kraken2-build` with `args` on the database in `db`. Its output is shown to the user,
+    /// as building can take a long time.
+    fn run(&self, db: &Path, args: &[&str]) -> Result<()> {
+        let status = Command::new(&self.command)
+            .args(args)
+            .arg("--db")
+            .arg(db)
+            .status()
+            .with_context(|| format!("Failed to run {}", self.command))?;
+        if !status.success() {
+            bail!("{} {} failed ({})", self.command, args.join(" "), status);
+        }
+        Ok(())
+    }
+}
+
+/// Build a kraken2 database from `options.genomes` and install it in `root` as `version`.
+/// The build steps (downloading the taxonomy, adding the genomes to the library, building, and
+/// cleaning up) are run in a temporary directory in `root`, which is only moved into place if they
+/// all succeed. Returns the path of the installed database.
+pub fn build_database(
+    options: &BuildOptions,
+    root: &Path,
+    version: &str,
+    host: &Host,
+) -> Result<PathBuf> {
+    check_version(version)?;
+    let path = root.join(version);
+    if path.exists() {
+        bail!("A database is already installed at {:?}", path);
+    }
+    if options.genomes.is_empty() {
+        bail!("At least one genome is needed to build a database");
+    }
+
+    fs::create_dir_all(root)?;
+    let build_dir = tempfile::Builder::new()
+        .prefix(".build-")
+        .tempdir_in(root)
+        .context("Failed to create the build directory")?;
+    let db = build_dir.path();
+    let threads = options.threads.to_string();
+
+    info!("Downloading the taxonomy...");
+    // sequences are assigned taxonomy IDs in their headers, so the accession maps aren't needed
+    options.run(db, &["--download-taxonomy", "--skip-maps"])?;
+
+    info!("Adding the genome(s) to the library...");
+    let library_input = db.join(LIBRARY_INPUT);
+    write_library_input(&options.genomes, options.taxid, &library_input)?;
+    options.run(db, &["--add-to-library", &library_input.to_string_lossy()])?;
+    fs::remove_file(&library_input)?;
+
+    info!("Building the database. This can take a while...");
+    let mut build_args = vec!["--build".to_string(), "--threads".to_string(), threads];
+    if let Some(k) = options.kmer_len {
+        build_args.extend(["--kmer-len".to_string(), k.to_string()]);
+    }
+    if let Some(l) = options.minimizer_len {
+        build_args.extend(["--minimizer-len".to_string(), l.to_string()]);
+    }
+    let build_args: Vec<&str> = build_args.iter().map(String::as_str).collect();
+    options.run(db, &build_args)?;
+
+    info!("Removing intermediate files...");
+    options.run(db, &["--clean"])?;
+
+    install_build(db, root, &path, version, host)
+}
+
+/// Build a native database (see [`crate::native`]) from the `genomes` (with k-mer length
+/// `kmer_len` and window length `window_len`), and install it in `root` as `version`. Unlike
+/// [`build_database`], this doesn't need kraken2-build or the taxonomy. Returns the path of the
+/// installed database.
+pub fn build_native_database(
+    genomes: &[PathBuf],
+    kmer_len: u8,
+    window_len: u8,
+    root: &Path,
+    version: &str,
+    host: &Host,
+) -> Result<PathBuf> {
+    check_version(version)?;
+    let path = root.join(version);
+    if path.exists() {
+        bail!("A database is already installed at {:?}", path);
+    }
+    if genomes.is_empty() {
+        bail!("At least one genome is needed to build a database");
+    }
+
+    fs::create_dir_all(root)?;
+    let build_dir = tempfile::Builder::new()
+        .prefix(".build-")
+        .tempdir_in(root)
+        .context("Failed to create the build directory")?;
+    let db = build_dir.path();
+
+    info!("Indexing the genome(s). This can take a while...");
+    let index = NativeIndex::build(genomes, kmer_len, window_len)?;
+    info!("Writing the index of {} minimizers...", index.len());
+    index.write(&db.join(NATIVE_INDEX_FILE))?;
+    drop(index);
+
+    install_build(db, root, &path, version, host)
+}
+
+/// Record the metadata of the database built in `db`, then move it to `path` in `root`.
+fn install_build(
+    db: &Path,
+    root: &Path,
+    path: &Path,
+    version: &str,
+    host: &Host,
+) -> Result<PathBuf> {
+    info!("Recording checksums of the database files...");
+    let mut metadata = DatabaseMetadata::new(version, host);
+    metadata.files = index_file_checksums(db)?;
+    metadata.write(db)?;
+
+    // temporary directories are only accessible by their owner
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(db, fs::Permissions::from_mode(0o755))?;
+    }
+
+    let _lock = DatabaseLock::acquire(root)?;
+    if path.exists() {
+        bail!("A database is already installed at {:?}", path);
+    }
+    // the build directory is removed when dropped, if it wasn't moved
+    fs::rename(db, path).with_context(|| format!("Failed to move the database to {:?}", path))?;
+    Ok(path.to_path_buf())
+}
+
+/// Write the sequences in the FASTA `genomes` to `output`, uncompressed, for
+/// `kraken2-build --add-to-library`. If given, `taxid` is added to each sequence ID.
+fn write_library_input(genomes: &[PathBuf], taxid: Option<u64>, output: &Path) -> Result<()> {
+    let mut writer = BufWriter::new(File::create(output)?);
+    for genome in genomes {
+        let reader = BufReader::new(open_reader(genome)?);
+        for line in reader.lines() {
+            let line = line.with_context(|| format!("Failed to read {:?}", genome))?;
+            match (line.strip_prefix('>'), taxid) {
+                (Some(header), Some(taxid)) => {
+                    let (id, description) = match header.split_once(' ') {
+                        Some((id, description)) => (id, format!(" {}", description)),
+                        None => (header, String::new()),
+                    };
+                    writeln!(writer, ">{}|kraken:taxid|{}{}", id, taxid, description)?;
+                }
+                _ => writeln!(writer, "{}", line)?,
+            }
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_library_input() {
+        let tmp = tempfile::tempdir().unwrap();
+        let genome = tmp.path().join("host.fa");
+        fs::write(&genome, ">chr1 cow chromosome 1\nACGT\nAC\n>chr2\nGGCC\n").unwrap();
+        let output = tmp.path().join(LIBRARY_INPUT);
+
+        write_library_input(std::slice::from_ref(&genome), Some(9913), &output).unwrap();
+        assert_eq!(
+            fs::read_to_string(&output).unwrap(),
+            ">chr1|kraken:taxid|9913 cow chromosome 1\nACGT\nAC\n>chr2|kraken:taxid|9913\nGGCC\n"
+        );
+
+        write_library_input(&[genome], None, &output).unwrap();
+        assert_eq!(
+            fs::read_to_string(&output).unwrap(),
+            ">chr1 cow chromosome 1\nACGT\nAC\n>chr2\nGGCC\n"
+        );
+    }
+
+    #[test]
+    fn test_build_native_database() {
+        let tmp = tempfile::tempdir().unwrap();
+        let genome = tmp.path().join("host.fa");
+        fs::write(&genome, ">chr1\nACGTTGCATGCATGCCGTAGCTAGCTAGGATCGATCG\n").unwrap();
+        let root = tmp.path().join("db");
+
+        let path = build_native_database(
+            std::slice::from_ref(&genome),
+            11,
+            5,
+            &root,
+            "native_cow",
+            &Host::Custom,
+        )
+        .unwrap();
+        assert_eq!(path, root.join("native_cow"));
+        let metadata = DatabaseMetadata::read(&path).unwrap();
+        assert!(metadata.files.contains_key(NATIVE_INDEX_FILE));
+        assert!(NativeIndex::read(&path.join(NATIVE_INDEX_FILE)).is_ok());
+
+        assert!(
+            build_native_database(&[genome], 11, 5, &root, "native_cow", &Host::Custom).is_err()
+        );
+    }
+}
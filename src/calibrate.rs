@@ -0,0 +1,126 @@
+//! Choosing the kraken2 confidence from a negative control (`--negative-control`), i.e. a sample
+//! known to have no host reads, so any read classified in it is a false positive.
+use crate::kraken::{OutputReader, OutputRecord};
+use anyhow::{bail, Result};
+use std::io::BufRead;
+
+/// The confidence is chosen in steps of 1 / STEPS.
+const STEPS: f64 = 100.0;
+
+/// The confidence chosen from a negative control.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Calibration {
+    pub confidence: f32,
+    /// The number of sequences in the control.
+    pub reads: u64,
+    /// The number of control sequences still classified at the chosen confidence.
+    pub classified: u64,
+}
+
+/// The highest confidence a record stays classified at. kraken2 moves a read up the taxonomy
+/// until the fraction of its (non-ambiguous) k-mers in the taxon's clade reaches the confidence,
+/// so it stays classified (at the root, at worst) while the fraction of k-mers mapped to any taxon
+/// does. `None` if the record is unclassified even at a confidence of 0.
+fn max_confidence(record: &OutputRecord) -> Option<f64> {
+    if !record.classified {
+        return None;
+    }
+    let mappings = record.lca_mappings.iter().flatten();
+    let (mut mapped, mut unambiguous) = (0, 0);
+    for mapping in mappings {
+        match mapping.taxid {
+            None => {}
+            Some(0) => unambiguous += mapping.count,
+            Some(_) => {
+                mapped += mapping.count;
+                unambiguous += mapping.count;
+            }
+        }
+    }
+    (mapped > 0).then(|| mapped as f64 / unambiguous as f64)
+}
+
+/// Choose the lowest confidence, in steps of 0.01, at which at most `max_fraction` of the
+/// sequences in a negative control are classified. `output` is kraken2's `--output` for the
+/// control, run with a confidence of 0.
+///
+/// # Examples
+///
+/// ```
+/// use nohuman::calibrate::calibrate;
+///
+/// // r1 has 1 of its 4 k-mers mapped, and r2 has 3 of 4
+/// let output = "C\tr1\t9606\t35\t0:3 9606:1\nC\tr2\t9606\t35\t0:1 9606:3\nU\tr3\t0\t35\t0:4\n";
+/// let calibration = calibrate(output.as_bytes(), 0.4).unwrap();
+/// assert_eq!(calibration.confidence, 0.26);
+/// assert_eq!(calibration.classified, 1);
+/// ```
+pub fn calibrate<R: BufRead>(output: R, max_fraction: f64) -> Result<Calibration> {
+    let mut scores = Vec::new();
+    let mut reads = 0;
+    for record in OutputReader::new(output) {
+        reads += 1;
+        scores.extend(max_confidence(&record?));
+    }
+    let allowed = (max_fraction * reads as f64).floor() as usize;
+    if scores.len() <= allowed {
+        return Ok(Calibration {
+            confidence: 0.0,
+            reads,
+            classified: scores.len() as u64,
+        });
+    }
+    // the confidence has to be above the score of the first read over the allowance
+    scores.sort_by(|a, b| b.total_cmp(a));
+    let limit = scores[allowed];
+    if limit >= 1.0 {
+        bail!(
+            "{} of the {} control sequences are classified even at a confidence of 1",
+            scores.iter().filter(|&&score| score >= 1.0).count(),
+            reads
+        );
+    }
+    let confidence = (((limit * STEPS).floor() + 1.0) / STEPS).min(1.0) as f32;
+    let classified = scores
+        .iter()
+        .filter(|&&score| score >= confidence as f64)
+        .count() as u64;
+    Ok(Calibration {
+        confidence,
+        reads,
+        classified,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_calibrate() {
+        let output = [
+            "U\tr1\t0\t35\t0:5",
+            "C\tr2\t9606\t35\t0:8 9606:2",
+            "C\tr3\t9606\t35\t0:5 9606:5",
+            "C\tr4\t9606\t35|35\t9606:3 A:10 |:| 0:1",
+            "C\tr5\t9606\t35\t9606:5",
+        ]
+        .join("\n");
+        let calibration = |max_fraction| calibrate(output.as_bytes(), max_fraction);
+
+        // nothing needs removing when enough reads are unclassified already
+        let unchanged = calibration(0.8).unwrap();
+        assert_eq!(unchanged.confidence, 0.0);
+        assert_eq!((unchanged.reads, unchanged.classified), (5, 4));
+
+        // r4 is 3 of the 4 unambiguous k-mers, across both mates
+        let calibrated = calibration(0.4).unwrap();
+        assert_eq!(calibrated.confidence, 0.51);
+        assert_eq!(calibrated.classified, 2);
+        assert_eq!(calibration(0.6).unwrap().confidence, 0.21);
+
+        // r5 is classified at any confidence
+        assert!(calibration(0.0).is_err());
+        assert_eq!(calibrate("".as_bytes(), 0.0).unwrap().confidence, 0.0);
+    }
+}
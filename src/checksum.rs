@@ -0,0 +1,158 @@
+//! MD5 and SHA256 checksums of the output files, written as `.md5` and `.sha256` sidecar files
+//! (in the format of `md5sum` and `sha256sum`) for data deposition and checking transfers.
+//!
+//! Outputs are hashed as they are written where possible (see [`ChecksumWriter`]), so they
+//! don't have to be read again.
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// The checksums of a file, as hex strings.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Checksums {
+    pub md5: String,
+    pub sha256: String,
+}
+
+/// The hashes of the data written so far.
+struct Hashers {
+    md5: md5::Context,
+    sha256: Sha256,
+}
+
+impl Hashers {
+    fn new() -> Self {
+        Self {
+            md5: md5::Context::new(),
+            sha256: Sha256::new(),
+        }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        self.md5.consume(data);
+        self.sha256.update(data);
+    }
+
+    fn finish(self) -> Checksums {
+        Checksums {
+            md5: format!("{:x}", self.md5.compute()),
+            sha256: format!("{:x}", self.sha256.finalize()),
+        }
+    }
+}
+
+/// A writer that hashes the data written to the writer it wraps. The checksums are read from the
+/// [`ChecksumHandle`] returned with it, so the writer can be handed off (e.g. to a compressor).
+pub struct ChecksumWriter<W: Write> {
+    writer: W,
+    hashers: Arc<Mutex<Option<Hashers>>>,
+}
+
+/// The checksums of the data written to a [`ChecksumWriter`].
+pub struct ChecksumHandle {
+    hashers: Arc<Mutex<Option<Hashers>>>,
+}
+
+impl<W: Write> ChecksumWriter<W> {
+    pub fn new(writer: W) -> (Self, ChecksumHandle) {
+        let hashers = Arc::new(Mutex::new(Some(Hashers::new())));
+        let handle = ChecksumHandle {
+            hashers: Arc::clone(&hashers),
+        };
+        (Self { writer, hashers }, handle)
+    }
+}
+
+impl<W: Write> Write for ChecksumWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.writer.write(buf)?;
+        if let Some(hashers) = self.hashers.lock().unwrap().as_mut() {
+            hashers.update(&buf[..n]);
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+impl ChecksumHandle {
+    /// The checksums of the data written so far. This should only be called once, when the
+    /// writer is done; calling it again gives the checksums of no data.
+    pub fn finish(&self) -> Checksums {
+        self.hashers
+            .lock()
+            .unwrap()
+            .take()
+            .unwrap_or_else(Hashers::new)
+            .finish()
+    }
+}
+
+/// Compute the checksums of the file at `path`.
+pub fn file_checksums(path: &Path) -> Result<Checksums> {
+    let mut file = File::open(path).with_context(|| format!("Failed to open {:?}", path))?;
+    let mut hashers = Hashers::new();
+    let mut buffer = vec![0; 1024 * 1024];
+    loop {
+        let n = file
+            .read(&mut buffer)
+            .with_context(|| format!("Failed to read {:?}", path))?;
+        if n == 0 {
+            break;
+        }
+        hashers.update(&buffer[..n]);
+    }
+    Ok(hashers.finish())
+}
+
+/// Write `checksums` of the file at `path` to `<path>.md5` and `<path>.sha256`, which can be
+/// checked with `md5sum -c` and `sha256sum -c` in the directory of `path`. Returns the paths of
+/// the checksum files.
+pub fn write_checksum_files(path: &Path, checksums: &Checksums) -> Result<[PathBuf; 2]> {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let sidecar = |extension: &str| {
+        let mut sidecar = path.as_os_str().to_owned();
+        sidecar.push(".");
+        sidecar.push(extension);
+        PathBuf::from(sidecar)
+    };
+    let sidecars = [sidecar("md5"), sidecar("sha256")];
+    for (sidecar, hash) in sidecars.iter().zip([&checksums.md5, &checksums.sha256]) {
+        std::fs::write(sidecar, format!("{}  {}\n", hash, name))
+            .with_context(|| format!("Failed to write {:?}", sidecar))?;
+    }
+    Ok(sidecars)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_checksums() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("out.fq");
+        let (mut writer, handle) = ChecksumWriter::new(File::create(&path).unwrap());
+        writer.write_all(b"@r1\nACGT\n+\nIIII\n").unwrap();
+        drop(writer);
+
+        let checksums = handle.finish();
+        assert_eq!(checksums, file_checksums(&path).unwrap());
+        assert_eq!(
+            checksums.md5,
+            format!("{:x}", md5::compute(b"@r1\nACGT\n+\nIIII\n"))
+        );
+
+        let [md5, sha256] = write_checksum_files(&path, &checksums).unwrap();
+        assert_eq!(md5, tmp.path().join("out.fq.md5"));
+        assert_eq!(
+            std::fs::read_to_string(&sha256).unwrap(),
+            format!("{}  out.fq\n", checksums.sha256)
+        );
+    }
+}
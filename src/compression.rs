@@ -1,17 +1,34 @@
 use anyhow::{bail, Context, Result};
 use bzip2::write::BzEncoder;
+use libdeflater::{CompressionLvl, Compressor};
 use std::fs::File;
 use std::io;
-use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::io::{BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use std::sync::mpsc;
+use std::thread;
 
 const XZ_DEFAULT_LEVEL: u32 = 6;
+/// The most uncompressed data in a BGZF block. This leaves room for the compressed data (plus
+/// the block's header and footer) to fit in 64 KiB even if it doesn't compress.
+const BGZF_BLOCK_DATA: usize = 0xff00;
+/// The uncompressed data in each gzip member when compressing on several threads.
+const GZIP_BLOCK_DATA: usize = 1024 * 1024;
+/// The empty block that ends a BGZF file.
+pub const BGZF_EOF: [u8; 28] = [
+    0x1f, 0x8b, 0x08, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0xff, 0x06, 0x00, 0x42, 0x43, 0x02, 0x00,
+    0x1b, 0x00, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+];
 
 #[derive(Debug, PartialEq, Copy, Clone, Default)]
 pub enum CompressionFormat {
+    /// Blocked gzip, as written by bgzip. It can be read as gzip, and is needed by htslib tools.
+    Bgzf,
     Bzip2,
     Gzip,
+    /// LZ4 frames. Much quicker to compress, but larger, than the other formats.
+    Lz4,
     #[default]
     None,
     Xz,
@@ -39,6 +56,10 @@ impl FromStr for CompressionFormat {
     /// assert_eq!(format, CompressionFormat::Zstd);
     /// let format = "u".parse::<CompressionFormat>().unwrap();
     /// assert_eq!(format, CompressionFormat::None);
+    /// let format = "bgz".parse::<CompressionFormat>().unwrap();
+    /// assert_eq!(format, CompressionFormat::Bgzf);
+    /// let format = "l".parse::<CompressionFormat>().unwrap();
+    /// assert_eq!(format, CompressionFormat::Lz4);
     /// ```
     ///
     /// # Errors
@@ -51,6 +72,8 @@ impl FromStr for CompressionFormat {
             "x" => Ok(CompressionFormat::Xz),
             "z" => Ok(CompressionFormat::Zstd),
             "u" => Ok(CompressionFormat::None),
+            "bgz" => Ok(CompressionFormat::Bgzf),
+            "l" | "lz4" => Ok(CompressionFormat::Lz4),
             _ => bail!("Invalid compression format: {}", s),
         }
     }
@@ -74,11 +97,17 @@ impl std::fmt::Display for CompressionFormat {
     /// assert_eq!(format.to_string(), "xz");
     /// let format = CompressionFormat::Zstd;
     /// assert_eq!(format.to_string(), "zst");
+    /// let format = CompressionFormat::Bgzf;
+    /// assert_eq!(format.to_string(), "bgz");
+    /// let format = CompressionFormat::Lz4;
+    /// assert_eq!(format.to_string(), "lz4");
     /// ```
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let format = match self {
+            CompressionFormat::Bgzf => "bgz",
             CompressionFormat::Bzip2 => "bz2",
             CompressionFormat::Gzip => "gz",
+            CompressionFormat::Lz4 => "lz4",
             CompressionFormat::None => "",
             CompressionFormat::Xz => "xz",
             CompressionFormat::Zstd => "zst",
@@ -92,6 +121,42 @@ impl CompressionFormat {
         detect_compression_format(reader)
     }
 
+    /// Detect the compression format of a reader that can't seek (e.g. a pipe) from its magic
+    /// number. The bytes read to detect it are put back in front of the returned reader, so it
+    /// reads the whole stream.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use nohuman::compression::CompressionFormat;
+    /// use std::io::Read;
+    ///
+    /// let (format, mut reader) = CompressionFormat::from_stream(&b"BZh91AY"[..]).unwrap();
+    /// assert_eq!(format, CompressionFormat::Bzip2);
+    /// let mut data = Vec::new();
+    /// reader.read_to_end(&mut data).unwrap();
+    /// assert_eq!(data, b"BZh91AY");
+    /// ```
+    pub fn from_stream<R: Read>(mut reader: R) -> Result<(Self, impl Read)> {
+        let mut magic = Vec::with_capacity(5);
+        (&mut reader)
+            .take(5)
+            .read_to_end(&mut magic)
+            .context("Failed to read the first five bytes of the stream")?;
+        Ok((magic_format(&magic), io::Cursor::new(magic).chain(reader)))
+    }
+
+    /// Detect the compression format of a file based on its magic number.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let mut file = File::open(path).with_context(|| format!("Failed to open {:?}", path))?;
+        // files shorter than the longest magic number can't be compressed (and would fail detection)
+        if file.metadata()?.len() < 5 {
+            return Ok(CompressionFormat::None);
+        }
+        detect_compression_format(&mut file)
+    }
+
     /// Detect the compression format of a file based on its path extension.
     ///
     /// # Examples
@@ -109,8 +174,10 @@ impl CompressionFormat {
         let extension = path.extension().and_then(|s| s.to_str());
 
         match extension {
+            Some("bgz") => Ok(CompressionFormat::Bgzf),
             Some("bz2") => Ok(CompressionFormat::Bzip2),
             Some("gz") => Ok(CompressionFormat::Gzip),
+            Some("lz4") => Ok(CompressionFormat::Lz4),
             Some("xz") => Ok(CompressionFormat::Xz),
             Some("zst") | Some("zstd") => Ok(CompressionFormat::Zstd),
             _ => Ok(CompressionFormat::None),
@@ -133,6 +200,36 @@ impl CompressionFormat {
         *self != CompressionFormat::None
     }
 
+    /// Check if kraken2 can read files in this format. kraken2 handles gzip and bzip2 itself, but
+    /// not zstd or xz.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use nohuman::compression::CompressionFormat;
+    ///
+    /// assert!(CompressionFormat::Gzip.is_kraken2_readable());
+    /// assert!(!CompressionFormat::Zstd.is_kraken2_readable());
+    /// ```
+    pub fn is_kraken2_readable(&self) -> bool {
+        matches!(self, Self::None | Self::Gzip | Self::Bgzf | Self::Bzip2)
+    }
+
+    /// Whether minimap2 can read this format directly. minimap2 only reads gzip (including BGZF)
+    /// compressed files.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use nohuman::compression::CompressionFormat;
+    ///
+    /// assert!(CompressionFormat::Gzip.is_minimap2_readable());
+    /// assert!(!CompressionFormat::Bzip2.is_minimap2_readable());
+    /// ```
+    pub fn is_minimap2_readable(&self) -> bool {
+        matches!(self, Self::None | Self::Gzip | Self::Bgzf)
+    }
+
     /// Add the compression extension to a path.
     ///
     /// # Examples
@@ -180,15 +277,36 @@ impl CompressionFormat {
     /// format.compress(input, output, threads).unwrap();
     /// ```
     pub fn compress<P: AsRef<Path>>(&self, input: P, output: P, threads: u32) -> Result<()> {
-        let mut input_file = File::open(input).map(BufReader::new)?;
-        let mut output_file = File::create(output)
-            .context("Failed to create output file")
-            .map(BufWriter::new)?;
+        let output_file = File::create(output).context("Failed to create output file")?;
+        self.compress_to(input, output_file, threads)
+    }
+
+    /// Compress a file using the compression format of `self` and number of threads, writing the
+    /// compressed data to `writer` (e.g. stdout).
+    pub fn compress_to<P, W>(&self, input: P, writer: W, threads: u32) -> Result<()>
+    where
+        P: AsRef<Path>,
+        W: Write + Send + 'static,
+    {
+        self.compress_reader_to(File::open(input)?, writer, threads)
+    }
+
+    /// Compress the data read from `reader` using the compression format of `self` and number of
+    /// threads, writing the compressed data to `writer`.
+    pub fn compress_reader_to<R, W>(&self, reader: R, writer: W, threads: u32) -> Result<()>
+    where
+        R: Read,
+        W: Write + Send + 'static,
+    {
+        let mut input_file = BufReader::new(reader);
+        let mut output_file = BufWriter::new(writer);
 
         let result = match self {
             Self::None => io::copy(&mut input_file, &mut output_file),
+            Self::Bgzf => bgzf_compress(&mut input_file, &mut output_file, threads),
             Self::Bzip2 => bzip2_compress(&mut input_file, &mut output_file),
             Self::Gzip => gzip_compress(&mut input_file, output_file, threads),
+            Self::Lz4 => lz4_compress(&mut input_file, &mut output_file),
             Self::Xz => xz_compress(&mut input_file, &mut output_file, threads),
             Self::Zstd => zstd_compress(&mut input_file, &mut output_file, threads),
         };
@@ -198,6 +316,311 @@ impl CompressionFormat {
         }
         Ok(())
     }
+
+    /// Wrap `reader`, which is compressed in this format, to decompress it. Use [`open_reader`]
+    /// to detect the format of a file instead.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::io::{Read, Write};
+    /// use nohuman::compression::CompressionFormat;
+    ///
+    /// let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    /// encoder.write_all(b"@r1\nACGT\n+\nIIII\n").unwrap();
+    /// let compressed = encoder.finish().unwrap();
+    ///
+    /// let mut reader = CompressionFormat::Gzip.reader(compressed.as_slice()).unwrap();
+    /// let mut data = String::new();
+    /// reader.read_to_string(&mut data).unwrap();
+    /// assert_eq!(data, "@r1\nACGT\n+\nIIII\n");
+    /// ```
+    pub fn reader<'a, R: BufRead + Send + 'a>(
+        &self,
+        reader: R,
+    ) -> io::Result<Box<dyn Read + Send + 'a>> {
+        let reader: Box<dyn Read + Send> = match self {
+            Self::None => Box::new(reader),
+            Self::Bzip2 => Box::new(bzip2::read::MultiBzDecoder::new(reader)),
+            Self::Gzip | Self::Bgzf => Box::new(flate2::read::MultiGzDecoder::new(reader)),
+            Self::Lz4 => Box::new(lz4_flex::frame::FrameDecoder::new(reader)),
+            Self::Xz => Box::new(liblzma::read::XzDecoder::new_multi_decoder(reader)),
+            Self::Zstd => Box::new(zstd::stream::read::Decoder::with_buffer(reader)?),
+        };
+        Ok(reader)
+    }
+
+    /// Decompress the file `input`, which is compressed in this format, to `output`. Returns the
+    /// number of decompressed bytes. See [`decompress`] to detect the format of `input` instead.
+    pub fn decompress<P: AsRef<Path>, Q: AsRef<Path>>(&self, input: P, output: Q) -> Result<u64> {
+        let input = input.as_ref();
+        let reader = File::open(input)
+            .map(BufReader::new)
+            .with_context(|| format!("Failed to open {:?}", input))?;
+        let mut reader = self.reader(reader)?;
+        let mut writer = File::create(output)
+            .context("Failed to create decompressed file")
+            .map(BufWriter::new)?;
+        let bytes = io::copy(&mut reader, &mut writer).context("Failed to decompress file")?;
+        writer.flush()?;
+        Ok(bytes)
+    }
+}
+
+/// Open a file for reading, transparently decompressing it if it is compressed. The compression
+/// format is detected from the file's magic number. A pipe (see [`crate::is_pipe`]) can be
+/// opened too, but only read once.
+pub fn open_reader<P: AsRef<Path>>(path: P) -> Result<Box<dyn Read + Send>> {
+    let path = path.as_ref();
+    if crate::is_pipe(path) {
+        // a pipe can only be read once, so its format is detected from the bytes read first
+        let file = File::open(path).with_context(|| format!("Failed to open {:?}", path))?;
+        let (format, reader) = CompressionFormat::from_stream(file)?;
+        return Ok(format.reader(BufReader::new(reader))?);
+    }
+    let format = CompressionFormat::from_file(path)?;
+    let reader = File::open(path)
+        .map(BufReader::new)
+        .with_context(|| format!("Failed to open {:?}", path))?;
+
+    Ok(format.reader(reader)?)
+}
+
+/// Wrap `reader`, which is compressed in `format`, to decompress it.
+pub fn decoder<R: BufRead + Send + 'static>(
+    reader: R,
+    format: CompressionFormat,
+) -> io::Result<Box<dyn Read + Send>> {
+    format.reader(reader)
+}
+
+/// A reader of the data read from another reader on a separate thread.
+pub struct BackgroundReader {
+    receiver: mpsc::Receiver<io::Result<Vec<u8>>>,
+    buffer: Vec<u8>,
+    pos: usize,
+}
+
+impl BackgroundReader {
+    /// Read from `reader` on a separate thread, so that, e.g., decompression runs in parallel
+    /// with whatever consumes the decompressed data.
+    pub fn new<R: Read + Send + 'static>(mut reader: R) -> Self {
+        let (sender, receiver) = mpsc::sync_channel(16);
+        thread::spawn(move || {
+            let mut buffer = vec![0; 1024 * 1024];
+            loop {
+                let chunk = match reader.read(&mut buffer) {
+                    Ok(0) => break,
+                    Ok(n) => Ok(buffer[..n].to_vec()),
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(e) => Err(e),
+                };
+                let failed = chunk.is_err();
+                // stop if the receiver is dropped, i.e., the rest of the data isn't needed
+                if sender.send(chunk).is_err() || failed {
+                    break;
+                }
+            }
+        });
+        Self {
+            receiver,
+            buffer: Vec::new(),
+            pos: 0,
+        }
+    }
+}
+
+impl Read for BackgroundReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos == self.buffer.len() {
+            match self.receiver.recv() {
+                Ok(chunk) => {
+                    self.buffer = chunk?;
+                    self.pos = 0;
+                }
+                // the reading thread is done
+                Err(_) => return Ok(0),
+            }
+        }
+        let n = buf.len().min(self.buffer.len() - self.pos);
+        buf[..n].copy_from_slice(&self.buffer[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+/// Decompress the file at `input` (see [`open_reader`]) and write the result to `output`.
+pub fn decompress<P: AsRef<Path>, Q: AsRef<Path>>(input: P, output: Q) -> Result<u64> {
+    CompressionFormat::from_file(&input)?.decompress(input, output)
+}
+
+/// The format of the blocks written by a [`BlockWriter`].
+#[derive(Debug, PartialEq, Copy, Clone)]
+enum BlockFormat {
+    Bgzf,
+    Gzip,
+}
+
+impl BlockFormat {
+    /// The most uncompressed data in a block.
+    fn block_size(&self) -> usize {
+        match self {
+            Self::Bgzf => BGZF_BLOCK_DATA,
+            Self::Gzip => GZIP_BLOCK_DATA,
+        }
+    }
+}
+
+/// Writes gzip compressed in independent blocks with libdeflate, compressing up to `threads`
+/// blocks at a time in parallel. The blocks are either BGZF blocks, which store their size in
+/// their header, or gzip members, which any gzip reader reads as one stream. Call
+/// [`BlockWriter::finish`] to write the last block; the data in it is lost otherwise.
+pub struct BlockWriter<W: Write> {
+    writer: W,
+    format: BlockFormat,
+    /// A compressor for each thread.
+    compressors: Vec<Compressor>,
+    /// Uncompressed data waiting to fill a block.
+    buffer: Vec<u8>,
+    /// Full blocks waiting to be compressed.
+    blocks: Vec<Vec<u8>>,
+}
+
+impl<W: Write> BlockWriter<W> {
+    /// A writer of BGZF, the blocked gzip written by bgzip and used by BAM.
+    pub fn bgzf(writer: W, threads: u32) -> Self {
+        Self::new(writer, BlockFormat::Bgzf, threads)
+    }
+
+    /// A writer of gzip, as a gzip member per 1 MiB of data.
+    pub fn gzip(writer: W, threads: u32) -> Self {
+        Self::new(writer, BlockFormat::Gzip, threads)
+    }
+
+    fn new(writer: W, format: BlockFormat, threads: u32) -> Self {
+        let compressors = (0..std::cmp::max(threads, 1))
+            .map(|_| Compressor::new(CompressionLvl::default()))
+            .collect();
+        Self {
+            writer,
+            format,
+            compressors,
+            buffer: Vec::with_capacity(format.block_size()),
+            blocks: Vec::new(),
+        }
+    }
+
+    /// Write the remaining data (and BGZF's end-of-file block), and return the underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.flush()?;
+        if self.format == BlockFormat::Bgzf {
+            self.writer.write_all(&BGZF_EOF)?;
+        }
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+
+    /// Compress the waiting blocks, in parallel if there is more than one, and write them in order.
+    fn write_blocks(&mut self) -> io::Result<()> {
+        let format = self.format;
+        let compressed: Vec<io::Result<Vec<u8>>> = if self.blocks.len() > 1 {
+            thread::scope(|scope| {
+                let handles: Vec<_> = self
+                    .blocks
+                    .iter()
+                    .zip(self.compressors.iter_mut())
+                    .map(|(block, compressor)| {
+                        scope.spawn(move || compress_block(format, compressor, block))
+                    })
+                    .collect();
+                handles
+                    .into_iter()
+                    .map(|h| h.join().expect("Compression thread panicked"))
+                    .collect()
+            })
+        } else {
+            let compressor = &mut self.compressors[0];
+            self.blocks
+                .iter()
+                .map(|block| compress_block(format, compressor, block))
+                .collect()
+        };
+        for block in compressed {
+            self.writer.write_all(&block?)?;
+        }
+        self.blocks.clear();
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for BlockWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let block_size = self.format.block_size();
+        let n = (block_size - self.buffer.len()).min(buf.len());
+        self.buffer.extend_from_slice(&buf[..n]);
+        if self.buffer.len() == block_size {
+            let block = std::mem::replace(&mut self.buffer, Vec::with_capacity(block_size));
+            self.blocks.push(block);
+            if self.blocks.len() == self.compressors.len() {
+                self.write_blocks()?;
+            }
+        }
+        Ok(n)
+    }
+
+    /// Write all the data so far, ending a block early if need be.
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.buffer.is_empty() {
+            self.blocks.push(std::mem::take(&mut self.buffer));
+        }
+        self.write_blocks()?;
+        self.writer.flush()
+    }
+}
+
+/// Compress `data` (at most a block of `format`) into a block.
+fn compress_block(
+    format: BlockFormat,
+    compressor: &mut Compressor,
+    data: &[u8],
+) -> io::Result<Vec<u8>> {
+    let compress_error = |e| io::Error::other(format!("Failed to compress block: {}", e));
+    if format == BlockFormat::Gzip {
+        let mut block = vec![0; compressor.gzip_compress_bound(data.len())];
+        let n = compressor
+            .gzip_compress(data, &mut block)
+            .map_err(compress_error)?;
+        block.truncate(n);
+        return Ok(block);
+    }
+
+    // the header (18 bytes) and footer (8 bytes) are part of the block size
+    let mut block = vec![
+        0x1f, 0x8b, 0x08, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0xff, 0x06, 0x00, b'B', b'C', 0x02,
+        0x00, 0x00, 0x00,
+    ];
+    block.resize(18 + compressor.deflate_compress_bound(data.len()), 0);
+    let n = compressor
+        .deflate_compress(data, &mut block[18..])
+        .map_err(compress_error)?;
+    block.truncate(18 + n);
+    block.extend_from_slice(&libdeflater::crc32(data).to_le_bytes());
+    block.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    let bsize =
+        u16::try_from(block.len() - 1).map_err(|_| io::Error::other("BGZF block is too large"))?;
+    block[16..18].copy_from_slice(&bsize.to_le_bytes());
+    Ok(block)
+}
+
+fn bgzf_compress<R, W>(input: &mut R, output: &mut W, threads: u32) -> io::Result<u64>
+where
+    R: Read,
+    W: Write,
+{
+    let mut encoder = BlockWriter::bgzf(output, threads);
+    let bytes = io::copy(input, &mut encoder)?;
+    encoder.finish()?;
+    Ok(bytes)
 }
 
 fn bzip2_compress<R, W>(input: &mut R, output: &mut W) -> io::Result<u64>
@@ -211,7 +634,9 @@ where
     Ok(bytes)
 }
 
-fn gzip_compress<R, W>(input: &mut R, output: W, threads: u32) -> io::Result<u64>
+/// Compress `input` as gzip. With more than one thread, blocks are compressed in parallel with
+/// libdeflate, which is much quicker.
+fn gzip_compress<R, W>(input: &mut R, mut output: W, threads: u32) -> io::Result<u64>
 where
     R: Read,
     W: Write + Send + 'static,
@@ -220,18 +645,31 @@ where
     use gzp::Compression;
     use gzp::ZBuilder;
 
-    let threads = std::cmp::max(threads, 1) as usize;
+    if threads > 1 {
+        let mut encoder = BlockWriter::gzip(&mut output, threads);
+        let bytes = io::copy(input, &mut encoder)?;
+        encoder.finish()?;
+        return Ok(bytes);
+    }
 
-    // unwrap is safe because we know threads is not zero and this is the only circumstance under which the builder will Error
     let mut encoder = ZBuilder::<Gzip, _>::new()
-        .num_threads(threads)
+        .num_threads(1)
         .compression_level(Compression::default())
         .from_writer(output);
     let bytes = io::copy(input, &mut encoder)?;
-    encoder
-        .finish()
-        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+    encoder.finish().map_err(io::Error::other)?;
+
+    Ok(bytes)
+}
 
+fn lz4_compress<R, W>(input: &mut R, output: &mut W) -> io::Result<u64>
+where
+    R: Read,
+    W: Write,
+{
+    let mut encoder = lz4_flex::frame::FrameEncoder::new(output);
+    let bytes = io::copy(input, &mut encoder)?;
+    encoder.finish().map_err(io::Error::other)?;
     Ok(bytes)
 }
 
@@ -269,6 +707,18 @@ where
     Ok(bytes)
 }
 
+/// The compression format with the magic number at the start of `magic`.
+fn magic_format(magic: &[u8]) -> CompressionFormat {
+    match magic {
+        [0x1f, 0x8b, ..] => CompressionFormat::Gzip,
+        [0x42, 0x5a, ..] => CompressionFormat::Bzip2,
+        [0x28, 0xb5, 0x2f, 0xfd, ..] => CompressionFormat::Zstd,
+        [0x04, 0x22, 0x4d, 0x18, ..] => CompressionFormat::Lz4,
+        [0xfd, 0x37, 0x7a, 0x58, 0x5a, ..] => CompressionFormat::Xz,
+        _ => CompressionFormat::None,
+    }
+}
+
 /// Detect the compression format of a file based on its magic number.
 fn detect_compression_format<R: Read + Seek>(reader: &mut R) -> Result<CompressionFormat> {
     let original_position = reader.stream_position()?;
@@ -280,14 +730,7 @@ fn detect_compression_format<R: Read + Seek>(reader: &mut R) -> Result<Compressi
     reader
         .read_exact(&mut magic)
         .context("Failed to read the first five bytes of the file")?;
-
-    let format = match magic {
-        [0x1f, 0x8b, ..] => CompressionFormat::Gzip,
-        [0x42, 0x5a, ..] => CompressionFormat::Bzip2,
-        [0x28, 0xb5, 0x2f, 0xfd, ..] => CompressionFormat::Zstd,
-        [0xfd, 0x37, 0x7a, 0x58, 0x5a] => CompressionFormat::Xz,
-        _ => CompressionFormat::None,
-    };
+    let format = magic_format(&magic);
 
     // Seek back to the original position
     reader
@@ -302,6 +745,15 @@ mod tests {
     use super::*;
     use std::io::Cursor;
 
+    #[test]
+    fn test_background_reader() {
+        let data: Vec<u8> = (0..3_000_000).map(|i| (i % 251) as u8).collect();
+        let mut reader = BackgroundReader::new(Cursor::new(data.clone()));
+        let mut actual = Vec::new();
+        reader.read_to_end(&mut actual).unwrap();
+        assert_eq!(actual, data);
+    }
+
     #[test]
     fn test_detect_gzip_format() {
         let data = vec![
@@ -414,6 +866,12 @@ mod tests {
         let format = "u".parse::<CompressionFormat>().unwrap();
         assert_eq!(format, CompressionFormat::None);
 
+        let format = "BGZ".parse::<CompressionFormat>().unwrap();
+        assert_eq!(format, CompressionFormat::Bgzf);
+
+        let format = "lz4".parse::<CompressionFormat>().unwrap();
+        assert_eq!(format, CompressionFormat::Lz4);
+
         let format = "J".parse::<CompressionFormat>();
         assert!(format.is_err());
     }
@@ -437,6 +895,12 @@ mod tests {
 
         let format = CompressionFormat::from_path("file.txt.zstd").unwrap();
         assert_eq!(format, CompressionFormat::Zstd);
+
+        let format = CompressionFormat::from_path("file.txt.bgz").unwrap();
+        assert_eq!(format, CompressionFormat::Bgzf);
+
+        let format = CompressionFormat::from_path("file.txt.lz4").unwrap();
+        assert_eq!(format, CompressionFormat::Lz4);
     }
 
     #[test]
@@ -455,6 +919,12 @@ mod tests {
 
         let format = CompressionFormat::Zstd;
         assert_eq!(format.to_string(), "zst");
+
+        let format = CompressionFormat::Bgzf;
+        assert_eq!(format.to_string(), "bgz");
+
+        let format = CompressionFormat::Lz4;
+        assert_eq!(format.to_string(), "lz4");
     }
 
     #[test]
@@ -558,6 +1028,58 @@ mod tests {
         assert_eq!(writer.into_inner(), expected);
     }
 
+    #[test]
+    fn test_bgzf_compress() {
+        let data: Vec<u8> = (0..300_000).map(|i| b"ACGT\n"[i % 5]).collect();
+        let mut output = Vec::new();
+        let bytes = bgzf_compress(&mut Cursor::new(&data), &mut output, 4).unwrap();
+        assert_eq!(bytes, data.len() as u64);
+        assert!(output.ends_with(&BGZF_EOF));
+
+        // every block stores its size, so the blocks can be walked
+        let mut offset = 0;
+        let mut n_blocks = 0;
+        while offset < output.len() {
+            assert_eq!(&output[offset + 12..offset + 14], b"BC");
+            let bsize = u16::from_le_bytes([output[offset + 16], output[offset + 17]]) as usize;
+            offset += bsize + 1;
+            n_blocks += 1;
+        }
+        assert_eq!(offset, output.len());
+        // four full blocks, the rest, and the end-of-file block
+        assert_eq!(n_blocks, 6);
+
+        let mut decompressed = Vec::new();
+        decoder(Cursor::new(output), CompressionFormat::Bgzf)
+            .unwrap()
+            .read_to_end(&mut decompressed)
+            .unwrap();
+        assert_eq!(decompressed, data);
+    }
+
+    #[test]
+    fn test_gzip_compress_threads() {
+        let data: Vec<u8> = (0..3_000_000).map(|i| b"ACGT\n"[i % 5]).collect();
+        let tempdir = tempfile::tempdir().unwrap();
+        let path = tempdir.path().join("output.gz");
+        let writer = File::create(&path).unwrap();
+        let bytes = gzip_compress(&mut Cursor::new(&data), writer, 4).unwrap();
+        assert_eq!(bytes, data.len() as u64);
+        let output = std::fs::read(&path).unwrap();
+
+        // a member per MiB
+        let mut decoder = flate2::read::GzDecoder::new(output.as_slice());
+        let mut first = Vec::new();
+        decoder.read_to_end(&mut first).unwrap();
+        assert_eq!(first.len(), GZIP_BLOCK_DATA);
+
+        let mut decompressed = Vec::new();
+        flate2::read::MultiGzDecoder::new(output.as_slice())
+            .read_to_end(&mut decompressed)
+            .unwrap();
+        assert_eq!(decompressed, data);
+    }
+
     #[test]
     fn test_gzip_compress() {
         let data = b"foo bar\n";
@@ -566,7 +1088,7 @@ mod tests {
         let tempdir = tempfile::tempdir().unwrap();
         let temppath = tempdir.path().join("output.gz");
         let writer = File::create(&temppath).map(BufWriter::new).unwrap();
-        let bytes = gzip_compress(&mut reader, writer, 4).unwrap();
+        let bytes = gzip_compress(&mut reader, writer, 1).unwrap();
         let expected = [
             0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x03, 0x4b, 0xcb, 0xcf, 0x57,
             0x48, 0x4a, 0x2c, 0xe2, 0x02, 0x00, 0x27, 0xb4, 0xdd, 0x13, 0x08, 0x00, 0x00, 0x00,
@@ -586,4 +1108,77 @@ mod tests {
             assert_eq!(*byte, expected[i]);
         }
     }
+
+    #[test]
+    fn test_lz4_compress() {
+        let data = b"@r1\nACGT\n+\nIIII\n";
+        let mut compressed = Cursor::new(Vec::new());
+        let bytes = lz4_compress(&mut Cursor::new(data), &mut compressed).unwrap();
+        assert_eq!(bytes, data.len() as u64);
+
+        compressed.set_position(0);
+        let format = detect_compression_format(&mut compressed).unwrap();
+        assert_eq!(format, CompressionFormat::Lz4);
+        let mut decompressed = Vec::new();
+        decoder(compressed, format)
+            .unwrap()
+            .read_to_end(&mut decompressed)
+            .unwrap();
+        assert_eq!(decompressed, data);
+    }
+
+    #[test]
+    fn test_compression_format_from_file() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let path = tempdir.path().join("in.fq.zst");
+        let mut writer = File::create(&path).unwrap();
+        zstd_compress(&mut Cursor::new(b"@r1\nACGT\n+\nIIII\n"), &mut writer, 1).unwrap();
+        assert_eq!(
+            CompressionFormat::from_file(&path).unwrap(),
+            CompressionFormat::Zstd
+        );
+
+        let tiny = tempdir.path().join("tiny.fq");
+        std::fs::write(&tiny, ">r").unwrap();
+        assert_eq!(
+            CompressionFormat::from_file(&tiny).unwrap(),
+            CompressionFormat::None
+        );
+    }
+
+    #[test]
+    fn test_decompress() {
+        let data = b"@r1\nACGT\n+\nIIII\n";
+        let tempdir = tempfile::tempdir().unwrap();
+        let input = tempdir.path().join("in.fq.xz");
+        let output = tempdir.path().join("in.fq");
+        let mut writer = File::create(&input).unwrap();
+        xz_compress(&mut Cursor::new(data), &mut writer, 1).unwrap();
+        drop(writer);
+
+        let bytes = decompress(&input, &output).unwrap();
+        assert_eq!(bytes, data.len() as u64);
+        assert_eq!(std::fs::read(&output).unwrap(), data);
+
+        // every format reads back what it writes
+        for format in [
+            CompressionFormat::Bgzf,
+            CompressionFormat::Bzip2,
+            CompressionFormat::Gzip,
+            CompressionFormat::Lz4,
+            CompressionFormat::None,
+            CompressionFormat::Xz,
+            CompressionFormat::Zstd,
+        ] {
+            let compressed = format.add_extension(tempdir.path().join("out.fq"));
+            format.compress(output.as_path(), &compressed, 2).unwrap();
+            let decompressed = tempdir.path().join("decompressed.fq");
+            let bytes = format.decompress(&compressed, &decompressed).unwrap();
+            assert_eq!(bytes, data.len() as u64, "{:?}", format);
+            assert_eq!(std::fs::read(&decompressed).unwrap(), data);
+        }
+        assert!(CompressionFormat::Zstd
+            .decompress(&input, tempdir.path().join("wrong.fq"))
+            .is_err());
+    }
 }
@@ -0,0 +1,106 @@
+//! Default options read from a config file (`~/.config/nohuman/config.toml`), so the same long
+//! command doesn't have to be repeated every time. Options given on the command line take
+//! precedence.
+//!
+//! ```toml
+//! db = "/data/nohuman/db"
+//! threads = 8
+//! confidence = 0.1
+//! output_type = "g"
+//! tmp_dir = "/scratch"
+//! kraken2_path = "/opt/kraken2/kraken2"
+//! ```
+use crate::compression::CompressionFormat;
+use crate::NohumanError;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Deserializer};
+use std::fmt::Display;
+use std::num::NonZeroU32;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// The name of the config file in the nohuman config directory.
+pub const CONFIG_FILE: &str = "config.toml";
+
+/// The options a config file can set.
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// The database (--db).
+    pub db: Option<PathBuf>,
+    pub threads: Option<NonZeroU32>,
+    /// The kraken2 minimum confidence score (--conf).
+    pub confidence: Option<f32>,
+    /// The output compression format (--output-type).
+    #[serde(default, deserialize_with = "parse_optional")]
+    pub output_type: Option<CompressionFormat>,
+    /// The directory temporary files are written to (--tmp-dir).
+    pub tmp_dir: Option<PathBuf>,
+    /// The kraken2 executable (--kraken2-path).
+    pub kraken2_path: Option<PathBuf>,
+}
+
+impl Config {
+    /// Read the config file at `path`.
+    pub fn read(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config file {:?}", path))?;
+        Self::from_toml(&content).with_context(|| format!("Invalid config file {:?}", path))
+    }
+
+    fn from_toml(content: &str) -> Result<Self> {
+        let config: Self = toml::from_str(content)?;
+        if let Some(confidence) = config.confidence {
+            if !(0.0..=1.0).contains(&confidence) {
+                return Err(NohumanError::InvalidConfidence(confidence.to_string()).into());
+            }
+        }
+        Ok(config)
+    }
+}
+
+/// The default config file: `$XDG_CONFIG_HOME/nohuman/config.toml`, or
+/// `~/.config/nohuman/config.toml` if `XDG_CONFIG_HOME` isn't set.
+pub fn default_config_path() -> Option<PathBuf> {
+    let config_dir = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| dirs::home_dir().map(|home| home.join(".config")))?;
+    Some(config_dir.join("nohuman").join(CONFIG_FILE))
+}
+
+/// Deserialize an optional string with its [`FromStr`] implementation.
+fn parse_optional<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr,
+    T::Err: Display,
+{
+    Option::<String>::deserialize(deserializer)?
+        .map(|s| s.parse().map_err(serde::de::Error::custom))
+        .transpose()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config_from_toml() {
+        let config = Config::from_toml(
+            "db = \"/data/db\"\nthreads = 8\nconfidence = 0.1\noutput_type = \"z\"\n",
+        )
+        .unwrap();
+        assert_eq!(config.db, Some(PathBuf::from("/data/db")));
+        assert_eq!(config.threads, NonZeroU32::new(8));
+        assert_eq!(config.confidence, Some(0.1));
+        assert_eq!(config.output_type, Some(CompressionFormat::Zstd));
+        assert_eq!(config.tmp_dir, None);
+
+        assert_eq!(Config::from_toml("").unwrap(), Config::default());
+        assert!(Config::from_toml("threads = 0").is_err());
+        assert!(Config::from_toml("confidence = 2.0").is_err());
+        assert!(Config::from_toml("output_type = \"rar\"").is_err());
+        assert!(Config::from_toml("thread = 4").is_err());
+    }
+}
@@ -0,0 +1,221 @@
+//! Running kraken2 inside a container, for when it isn't installed.
+//!
+//! The container sees the host's files at the same paths: every directory an argument refers to
+//! (the database, inputs, outputs, and temporary files) is bind-mounted into the container, along
+//! with the working directory, so the arguments don't need rewriting.
+use crate::{CommandRunner, NohumanError};
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::str::FromStr;
+
+/// The image kraken2 is run from, by default.
+pub const KRAKEN2_IMAGE: &str = "quay.io/biocontainers/kraken2:2.1.3--pl5321hdcf5f25_0";
+
+/// A program that runs containers.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum ContainerEngine {
+    Docker,
+    /// Apptainer, or its predecessor Singularity.
+    Apptainer,
+}
+
+impl FromStr for ContainerEngine {
+    type Err = NohumanError;
+
+    /// Parse a container engine name. `s` is case-insensitive, and "singularity" is accepted as
+    /// an alias of apptainer.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "docker" => Ok(ContainerEngine::Docker),
+            "apptainer" | "singularity" => Ok(ContainerEngine::Apptainer),
+            _ => Err(NohumanError::InvalidContainerEngine(s.to_string())),
+        }
+    }
+}
+
+impl std::fmt::Display for ContainerEngine {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let engine = match self {
+            ContainerEngine::Docker => "docker",
+            ContainerEngine::Apptainer => "apptainer",
+        };
+        write!(f, "{}", engine)
+    }
+}
+
+/// A container to run a program in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Container {
+    pub engine: ContainerEngine,
+    pub image: String,
+}
+
+impl Container {
+    /// A container of the default kraken2 image, run with `engine`.
+    pub fn new(engine: ContainerEngine) -> Self {
+        Self {
+            engine,
+            image: KRAKEN2_IMAGE.to_string(),
+        }
+    }
+
+    pub fn with_image(mut self, image: &str) -> Self {
+        self.image = image.to_string();
+        self
+    }
+
+    /// The executable of the container engine. Singularity is used if apptainer isn't installed.
+    pub fn executable(&self) -> &'static str {
+        match self.engine {
+            ContainerEngine::Docker => "docker",
+            ContainerEngine::Apptainer => {
+                if !CommandRunner::new("apptainer").is_executable()
+                    && CommandRunner::new("singularity").is_executable()
+                {
+                    "singularity"
+                } else {
+                    "apptainer"
+                }
+            }
+        }
+    }
+
+    /// Whether the container engine is installed.
+    pub fn is_available(&self) -> bool {
+        CommandRunner::new(self.executable()).is_executable()
+    }
+
+    /// The command to run `program` with `args` in the container, from the directory `cwd`.
+    pub fn command<S: AsRef<OsStr>>(&self, program: &str, args: &[S], cwd: &Path) -> Command {
+        let binds = bind_dirs(args, cwd);
+        let cwd = cwd.to_string_lossy();
+        let mut command = Command::new(self.executable());
+        match self.engine {
+            ContainerEngine::Docker => {
+                command.args(["run", "--rm", "--workdir", &cwd]);
+                for dir in &binds {
+                    let dir = dir.to_string_lossy();
+                    command.args(["--volume", &format!("{}:{}", dir, dir)]);
+                }
+                // run as the current user, so output files aren't owned by root
+                if let Some(user) = current_user() {
+                    command.args(["--user", &user]);
+                }
+                command.arg(&self.image);
+            }
+            ContainerEngine::Apptainer => {
+                let binds: Vec<_> = binds.iter().map(|dir| dir.to_string_lossy()).collect();
+                command.args(["exec", "--pwd", &cwd, "--bind", &binds.join(",")]);
+                command.arg(format!("docker://{}", self.image));
+            }
+        }
+        command.arg(program).args(args);
+        command
+    }
+}
+
+/// The directories to bind-mount so the paths in `args` (relative to `cwd`) can be used in the
+/// container. This is `cwd`, and the directory of each argument (or `--option=value` value) that is
+/// an existing path, or that is in an existing directory (e.g. an output file). Directories within
+/// another directory being mounted are left out, as are device files like `/dev/null`, which the
+/// container has its own of.
+fn bind_dirs<S: AsRef<OsStr>>(args: &[S], cwd: &Path) -> Vec<PathBuf> {
+    let mut dirs = vec![cwd.to_path_buf()];
+    let values = args
+        .iter()
+        .map(AsRef::as_ref)
+        .filter_map(|arg: &OsStr| match arg.to_str() {
+            Some(arg) => match arg.strip_prefix("--") {
+                Some(option) => option.split_once('=').map(|(_, value)| OsStr::new(value)),
+                None => Some(OsStr::new(arg)).filter(|_| !arg.starts_with('-')),
+            },
+            // only a path can be a non-UTF-8 argument
+            None => Some(arg),
+        });
+    for value in values {
+        let path = cwd.join(value);
+        let dir = if path.is_dir() {
+            Some(path)
+        } else {
+            path.parent()
+                .filter(|parent| parent.is_dir())
+                .map(Path::to_path_buf)
+        };
+        if let Some(dir) = dir.and_then(|dir| std::path::absolute(dir).ok()) {
+            if !dir.starts_with("/dev") {
+                dirs.push(dir);
+            }
+        }
+    }
+    dirs.sort();
+    dirs.dedup();
+    let mut binds: Vec<PathBuf> = Vec::new();
+    for dir in dirs {
+        // sorted, so a directory comes before those within it
+        if !binds.iter().any(|bind| dir.starts_with(bind)) {
+            binds.push(dir);
+        }
+    }
+    binds
+}
+
+/// The current user and group IDs, as "uid:gid".
+fn current_user() -> Option<String> {
+    let id = |flag: &str| {
+        let output = Command::new("id").arg(flag).output().ok()?;
+        output
+            .status
+            .success()
+            .then(|| String::from_utf8_lossy(&output.stdout).trim().to_string())
+    };
+    Some(format!("{}:{}", id("-u")?, id("-g")?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_container_engine_from_str() {
+        assert_eq!(
+            "Docker".parse::<ContainerEngine>().unwrap(),
+            ContainerEngine::Docker
+        );
+        assert_eq!(
+            "singularity".parse::<ContainerEngine>().unwrap(),
+            ContainerEngine::Apptainer
+        );
+        assert!("podman".parse::<ContainerEngine>().is_err());
+    }
+
+    #[test]
+    fn test_bind_dirs() {
+        let tmp = tempfile::tempdir().unwrap();
+        let cwd = tmp.path().join("work");
+        let db = tmp.path().join("db");
+        let out = tmp.path().join("out");
+        for dir in [&cwd, &db, &out, &cwd.join("tmp")] {
+            fs::create_dir(dir).unwrap();
+        }
+        let db_arg = db.to_string_lossy().to_string();
+        let out_arg = out.join("reads#.fq").to_string_lossy().to_string();
+        let args = [
+            "--db",
+            &db_arg,
+            "--threads",
+            "4",
+            "--output",
+            "/dev/null",
+            "--unclassified-out",
+            &out_arg,
+            "tmp/in.fq",
+        ];
+        assert_eq!(bind_dirs(&args, &cwd), vec![db, out, cwd.clone()]);
+
+        let report_arg = format!("--report={}", tmp.path().join("kraken.report").display());
+        let args = [report_arg.as_str(), "in.fq"];
+        assert_eq!(bind_dirs(&args, &cwd), vec![tmp.path().to_path_buf()]);
+    }
+}
@@ -0,0 +1,626 @@
+use crate::download::{Checksum, DownloadError};
+use crate::native::find_native_index;
+use crate::{Host, NohumanError};
+use anyhow::{bail, Context, Result};
+use fs2::FileExt;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// The name of the file, in an installed database's directory, that records its metadata.
+pub const METADATA_FILE: &str = "nohuman-db.toml";
+/// The name of the file, in a database root, that is locked while databases are being changed.
+pub const LOCK_FILE: &str = ".nohuman.lock";
+/// The name of the file, in the current directory or a database root, that pins the version of
+/// the database to use.
+pub const PIN_FILE: &str = ".nohuman-version";
+/// The version of the miniature human database listed in the config for testing (see
+/// [`crate::Config::test`]). It is never used by default.
+pub const TEST_DB_VERSION: &str = "test";
+/// The kraken2 index files of a database.
+pub const INDEX_FILES: [&str; 3] = ["hash.k2d", "opts.k2d", "taxo.k2d"];
+
+/// The expected size and checksum of a file in a database.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileChecksum {
+    /// The size of the file, in bytes.
+    #[serde(default)]
+    pub size: Option<u64>,
+    /// The checksum of the file, in the form `<algorithm>:<hash>`, e.g. `blake3:af13...`.
+    pub checksum: String,
+}
+
+/// The result of checking a database file against its [`FileChecksum`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FileStatus {
+    Ok,
+    Missing,
+    WrongSize { expected: u64, actual: u64 },
+    Corrupted,
+}
+
+impl std::fmt::Display for FileStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FileStatus::Ok => write!(f, "OK"),
+            FileStatus::Missing => write!(f, "missing"),
+            FileStatus::WrongSize { expected, actual } if actual < expected => {
+                write!(f, "truncated ({} of {} bytes)", actual, expected)
+            }
+            FileStatus::WrongSize { expected, actual } => {
+                write!(f, "wrong size ({} bytes, expected {})", actual, expected)
+            }
+            FileStatus::Corrupted => write!(f, "checksum does not match"),
+        }
+    }
+}
+
+/// Metadata recorded for an installed database.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DatabaseMetadata {
+    /// The database release version. This is also the name of its directory.
+    pub version: String,
+    /// The host the database is for.
+    pub host: String,
+    /// The URL the database was downloaded from.
+    #[serde(default)]
+    pub url: Option<String>,
+    /// The MD5 of the downloaded tarball.
+    #[serde(default)]
+    pub md5: Option<String>,
+    /// The SHA256 of the downloaded tarball.
+    #[serde(default)]
+    pub sha256: Option<String>,
+    /// The BLAKE3 hash of the downloaded tarball.
+    #[serde(default)]
+    pub blake3: Option<String>,
+    /// When the database was installed, in RFC 3339 format.
+    pub added: String,
+    /// Checksums of the database files, keyed by their path in the database.
+    #[serde(default)]
+    pub files: BTreeMap<String, FileChecksum>,
+    /// Where the database files are, for a database registered with `nohuman db add` rather
+    /// than installed into the database root.
+    #[serde(default)]
+    pub location: Option<PathBuf>,
+    /// The oldest nohuman version the database works with.
+    #[serde(default)]
+    pub min_nohuman_version: Option<String>,
+    /// The oldest kraken2 version the database works with.
+    #[serde(default)]
+    pub min_kraken2_version: Option<String>,
+}
+
+impl DatabaseMetadata {
+    pub fn new(version: &str, host: &Host) -> Self {
+        Self {
+            version: version.to_string(),
+            host: host.to_string(),
+            url: None,
+            md5: None,
+            sha256: None,
+            blake3: None,
+            added: humantime::format_rfc3339_seconds(SystemTime::now()).to_string(),
+            files: BTreeMap::new(),
+            location: None,
+            min_nohuman_version: None,
+            min_kraken2_version: None,
+        }
+    }
+
+    /// Read the metadata of the database in `dir`.
+    pub fn read(dir: &Path) -> Result<Self> {
+        let path = dir.join(METADATA_FILE);
+        let content =
+            fs::read_to_string(&path).with_context(|| format!("Failed to read {:?}", path))?;
+        toml::from_str(&content).with_context(|| format!("Failed to parse {:?}", path))
+    }
+
+    /// Write the metadata to the database directory `dir`.
+    pub fn write(&self, dir: &Path) -> io::Result<()> {
+        let content = toml::to_string(self).map_err(io::Error::other)?;
+        fs::write(dir.join(METADATA_FILE), content)
+    }
+}
+
+/// A database installed (or registered) in the database root directory.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InstalledDatabase {
+    /// The database's directory in the database root, which holds its metadata.
+    pub path: PathBuf,
+    pub metadata: DatabaseMetadata,
+}
+
+impl InstalledDatabase {
+    /// The directory of the database files. This is `path`, unless the database was registered
+    /// from elsewhere.
+    pub fn location(&self) -> &Path {
+        self.metadata.location.as_deref().unwrap_or(&self.path)
+    }
+
+    /// Whether the database was registered from elsewhere, rather than installed in the root.
+    pub fn is_registered(&self) -> bool {
+        self.metadata.location.is_some()
+    }
+
+    /// The size of the database on disk, in bytes. For a registered database, this is only its
+    /// metadata.
+    pub fn size(&self) -> io::Result<u64> {
+        dir_size(&self.path)
+    }
+
+    /// Check each of the database's files against the checksums recorded in its metadata.
+    pub fn verify(&self) -> Result<Vec<(String, FileStatus)>> {
+        let mut statuses = Vec::new();
+        for (name, expected) in &self.metadata.files {
+            let path = self.location().join(name);
+            let status = match fs::metadata(&path) {
+                Err(e) if e.kind() == io::ErrorKind::NotFound => FileStatus::Missing,
+                Err(e) => return Err(e).with_context(|| format!("Failed to read {:?}", path)),
+                Ok(metadata) if expected.size.is_some_and(|size| size != metadata.len()) => {
+                    FileStatus::WrongSize {
+                        expected: expected.size.unwrap_or_default(),
+                        actual: metadata.len(),
+                    }
+                }
+                Ok(_) => {
+                    let checksum: Checksum = expected
+                        .checksum
+                        .parse()
+                        .with_context(|| format!("Invalid checksum for {}", name))?;
+                    match checksum.verify(&path) {
+                        Ok(()) => FileStatus::Ok,
+                        Err(DownloadError::ChecksumMismatch(_)) => FileStatus::Corrupted,
+                        Err(e) => {
+                            return Err(e).with_context(|| format!("Failed to hash {:?}", path))
+                        }
+                    }
+                }
+            };
+            statuses.push((name.to_owned(), status));
+        }
+        Ok(statuses)
+    }
+
+    /// Delete the database. This refuses to delete the directory unless its metadata still
+    /// matches, and its name is the version in the metadata. Only the metadata of a registered
+    /// database is deleted.
+    pub fn remove(&self) -> Result<()> {
+        let on_disk = DatabaseMetadata::read(&self.path)?;
+        let dir_name = self.path.file_name().and_then(|s| s.to_str());
+        if on_disk != self.metadata || dir_name != Some(self.metadata.version.as_str()) {
+            bail!(
+                "The metadata in {:?} does not match database {}, refusing to remove it",
+                self.path,
+                self.metadata.version
+            );
+        }
+        fs::remove_dir_all(&self.path).with_context(|| format!("Failed to remove {:?}", self.path))
+    }
+}
+
+/// An exclusive (advisory) lock on a database root directory, so only one nohuman process
+/// changes the databases in it at a time. The lock is released when this is dropped.
+#[derive(Debug)]
+pub struct DatabaseLock {
+    file: fs::File,
+}
+
+impl DatabaseLock {
+    /// Lock the database root `dir`, waiting for any other process holding the lock to release it.
+    pub fn acquire(dir: &Path) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        let path = dir.join(LOCK_FILE);
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&path)?;
+        if file.try_lock_exclusive().is_err() {
+            info!(
+                "Waiting for another nohuman process to release the lock on {:?}...",
+                path
+            );
+            file.lock_exclusive()?;
+        }
+        Ok(Self { file })
+    }
+}
+
+impl Drop for DatabaseLock {
+    fn drop(&mut self) {
+        let _ = FileExt::unlock(&self.file);
+    }
+}
+
+/// List the databases installed in `root` - i.e., its subdirectories with a [`METADATA_FILE`] -
+/// from the oldest to most recently added.
+pub fn installed_databases(root: &Path) -> Result<Vec<InstalledDatabase>> {
+    let mut databases = Vec::new();
+    if !root.is_dir() {
+        return Ok(databases);
+    }
+
+    for entry in fs::read_dir(root)? {
+        let path = entry?.path();
+        if !path.join(METADATA_FILE).is_file() {
+            continue;
+        }
+        match DatabaseMetadata::read(&path) {
+            Ok(metadata) => databases.push(InstalledDatabase { path, metadata }),
+            Err(e) => warn!("Skipping database {:?}: {:#}", path, e),
+        }
+    }
+    // RFC 3339 timestamps (in UTC) sort chronologically
+    databases.sort_by(|a, b| a.metadata.added.cmp(&b.metadata.added));
+
+    Ok(databases)
+}
+
+/// Get the database to use from `root`. This is the version pinned by a [`PIN_FILE`] (see
+/// [`pinned_version`]) if there is one. Otherwise, it is the most recently added installed
+/// kraken2 database (native and test databases are skipped), or `root` itself if there are none,
+/// as databases were extracted directly into `root` before versioned installs were added.
+pub fn resolve_database(root: &Path) -> Result<PathBuf> {
+    resolve_pinned_database(root, pinned_version(root)?.as_ref())
+}
+
+fn resolve_pinned_database(root: &Path, pin: Option<&Pin>) -> Result<PathBuf> {
+    let databases = installed_databases(root)?;
+    if let Some(pin) = pin {
+        return databases
+            .into_iter()
+            .find(|db| db.metadata.version == pin.version)
+            .map(|db| db.location().to_path_buf())
+            .ok_or_else(|| {
+                NohumanError::InvalidDb(format!(
+                    "Database {} is pinned by {:?}, but is not installed in {:?}",
+                    pin.version, pin.path, root
+                ))
+                .into()
+            });
+    }
+    Ok(databases
+        .iter()
+        .rev()
+        .find(|db| {
+            db.metadata.version != TEST_DB_VERSION && find_native_index(db.location()).is_err()
+        })
+        .map_or_else(|| root.to_path_buf(), |db| db.location().to_path_buf()))
+}
+
+/// Get the database installed in `root` as `version`.
+pub fn find_database(root: &Path, version: &str) -> Result<PathBuf> {
+    installed_databases(root)?
+        .into_iter()
+        .find(|db| db.metadata.version == version)
+        .map(|db| db.location().to_path_buf())
+        .ok_or_else(|| {
+            NohumanError::DbNotInstalled {
+                version: version.to_string(),
+                root: root.to_path_buf(),
+            }
+            .into()
+        })
+}
+
+/// A database version pinned by a [`PIN_FILE`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pin {
+    pub version: String,
+    /// The pin file.
+    pub path: PathBuf,
+}
+
+/// Get the database version pinned in the current directory or, if there isn't one, in `root`.
+pub fn pinned_version(root: &Path) -> Result<Option<Pin>> {
+    read_pin(&[Path::new("."), root])
+}
+
+/// Read the first [`PIN_FILE`] found in `dirs`.
+pub fn read_pin(dirs: &[&Path]) -> Result<Option<Pin>> {
+    for dir in dirs {
+        let path = dir.join(PIN_FILE);
+        if !path.is_file() {
+            continue;
+        }
+        let content =
+            fs::read_to_string(&path).with_context(|| format!("Failed to read {:?}", path))?;
+        let version = content.trim();
+        if version.is_empty() {
+            bail!("No database version is given in {:?}", path);
+        }
+        return Ok(Some(Pin {
+            version: version.to_string(),
+            path,
+        }));
+    }
+    Ok(None)
+}
+
+/// Pin `version` as the database to use from `root`, or remove the pin if `version` is `None`.
+pub fn pin_version(root: &Path, version: Option<&str>) -> io::Result<()> {
+    let path = root.join(PIN_FILE);
+    match version {
+        Some(version) => fs::write(path, format!("{}\n", version)),
+        None => match fs::remove_file(path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        },
+    }
+}
+
+/// The version of a database release, taken from the file name of its tarball URL.
+///
+/// # Examples
+///
+/// ```
+/// use nohuman::database::version_from_url;
+///
+/// let url = "https://zenodo.org/records/8339732/files/k2_HPRC_20230810.tar.gz";
+/// assert_eq!(version_from_url(url), "k2_HPRC_20230810");
+/// ```
+pub fn version_from_url(url: &str) -> String {
+    let fname = file_name_from_url(url);
+    [".tar.gz", ".tgz", ".tar"]
+        .iter()
+        .find_map(|ext| fname.strip_suffix(ext))
+        .unwrap_or(fname)
+        .to_string()
+}
+
+/// The file name at the end of `url`, without any query or fragment.
+pub fn file_name_from_url(url: &str) -> &str {
+    let fname = url
+        .trim_end_matches('/')
+        .rsplit('/')
+        .next()
+        .unwrap_or_default();
+    fname.split(['?', '#']).next().unwrap_or_default()
+}
+
+/// Build parameters of a kraken2 database, read from its `opts.k2d` file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IndexOptions {
+    /// The k-mer length.
+    pub k: u64,
+    /// The minimizer length.
+    pub l: u64,
+    pub spaced_seed_mask: u64,
+    pub toggle_mask: u64,
+    /// Whether the database is of nucleotide (rather than protein) sequences.
+    pub dna_db: bool,
+}
+
+impl IndexOptions {
+    /// Parse the contents of an `opts.k2d` file. This is kraken2's `IndexOptions` struct written
+    /// as raw (64-bit, little endian) memory.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() < 33 {
+            bail!(
+                "opts.k2d is too short ({} bytes) to be a kraken2 index options file",
+                bytes.len()
+            );
+        }
+        let u64_at = |i: usize| u64::from_le_bytes(bytes[i..i + 8].try_into().unwrap());
+        Ok(Self {
+            k: u64_at(0),
+            l: u64_at(8),
+            spaced_seed_mask: u64_at(16),
+            toggle_mask: u64_at(24),
+            dna_db: bytes[32] != 0,
+        })
+    }
+
+    /// Read the `opts.k2d` file of the kraken2 database in `dir`.
+    pub fn from_db(dir: &Path) -> Result<Self> {
+        let path = dir.join("opts.k2d");
+        let bytes = fs::read(&path).with_context(|| format!("Failed to read {:?}", path))?;
+        Self::from_bytes(&bytes).with_context(|| format!("Failed to parse {:?}", path))
+    }
+}
+
+/// The total size, in bytes, of all files under `path`. Symlinks are not followed.
+pub fn dir_size(path: &Path) -> io::Result<u64> {
+    let metadata = fs::symlink_metadata(path)?;
+    if !metadata.is_dir() {
+        return Ok(metadata.len());
+    }
+    let mut size = 0;
+    for entry in fs::read_dir(path)? {
+        size += dir_size(&entry?.path())?;
+    }
+    Ok(size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn install(root: &Path, version: &str, added: &str) -> InstalledDatabase {
+        let path = root.join(version);
+        fs::create_dir_all(&path).unwrap();
+        fs::write(path.join("hash.k2d"), "hash").unwrap();
+        let mut metadata = DatabaseMetadata::new(version, &Host::Human);
+        metadata.added = added.to_string();
+        metadata.write(&path).unwrap();
+        InstalledDatabase { path, metadata }
+    }
+
+    #[test]
+    fn test_installed_databases() {
+        let tmp = tempfile::tempdir().unwrap();
+        let new = install(tmp.path(), "v2", "2024-06-01T00:00:00Z");
+        let old = install(tmp.path(), "v1", "2023-01-01T00:00:00Z");
+        fs::create_dir(tmp.path().join("not_a_db")).unwrap();
+
+        let databases = installed_databases(tmp.path()).unwrap();
+        assert_eq!(databases, vec![old, new.clone()]);
+        assert_eq!(resolve_database(tmp.path()).unwrap(), new.path);
+    }
+
+    #[test]
+    fn test_resolve_database_legacy() {
+        let tmp = tempfile::tempdir().unwrap();
+        assert_eq!(resolve_database(tmp.path()).unwrap(), tmp.path());
+        let missing = tmp.path().join("missing");
+        assert_eq!(resolve_database(&missing).unwrap(), missing);
+    }
+
+    #[test]
+    fn test_pinned_database() {
+        let tmp = tempfile::tempdir().unwrap();
+        let pinned = install(tmp.path(), "v1", "2023-01-01T00:00:00Z");
+        install(tmp.path(), "v2", "2024-06-01T00:00:00Z");
+        let cwd = tempfile::tempdir().unwrap();
+        let dirs = [cwd.path(), tmp.path()];
+        assert_eq!(read_pin(&dirs).unwrap(), None);
+
+        pin_version(tmp.path(), Some("v1")).unwrap();
+        let pin = read_pin(&dirs).unwrap().unwrap();
+        assert_eq!(pin.path, tmp.path().join(PIN_FILE));
+        assert_eq!(
+            resolve_pinned_database(tmp.path(), Some(&pin)).unwrap(),
+            pinned.path
+        );
+
+        // a pin in the current directory takes precedence
+        fs::write(cwd.path().join(PIN_FILE), "v3\n").unwrap();
+        let pin = read_pin(&dirs).unwrap().unwrap();
+        assert_eq!(pin.version, "v3");
+        assert!(resolve_pinned_database(tmp.path(), Some(&pin)).is_err());
+
+        pin_version(tmp.path(), None).unwrap();
+        assert!(!tmp.path().join(PIN_FILE).exists());
+        pin_version(tmp.path(), None).unwrap();
+    }
+
+    #[test]
+    fn test_remove_database() {
+        let tmp = tempfile::tempdir().unwrap();
+        let db = install(tmp.path(), "v1", "2023-01-01T00:00:00Z");
+        assert_eq!(
+            db.size().unwrap(),
+            4 + fs::metadata(db.path.join(METADATA_FILE)).unwrap().len()
+        );
+
+        db.remove().unwrap();
+        assert!(!db.path.exists());
+    }
+
+    #[test]
+    fn test_remove_database_metadata_mismatch() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mut db = install(tmp.path(), "v1", "2023-01-01T00:00:00Z");
+        db.metadata.version = "v2".to_string();
+
+        assert!(db.remove().is_err());
+        assert!(db.path.exists());
+    }
+
+    #[test]
+    fn test_verify_database() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mut db = install(tmp.path(), "v1", "2023-01-01T00:00:00Z");
+        let checksum = |data: &[u8]| format!("blake3:{}", blake3::hash(data).to_hex());
+        for name in ["opts.k2d", "taxo.k2d"] {
+            fs::write(db.path.join(name), "opts").unwrap();
+        }
+        db.metadata.files = BTreeMap::from([
+            (
+                "hash.k2d".to_string(),
+                FileChecksum {
+                    size: Some(4),
+                    checksum: checksum(b"hash"),
+                },
+            ),
+            (
+                "opts.k2d".to_string(),
+                FileChecksum {
+                    size: None,
+                    checksum: checksum(b"data"),
+                },
+            ),
+            (
+                "taxo.k2d".to_string(),
+                FileChecksum {
+                    size: Some(10),
+                    checksum: checksum(b"opts"),
+                },
+            ),
+            (
+                "missing.k2d".to_string(),
+                FileChecksum {
+                    size: None,
+                    checksum: checksum(b""),
+                },
+            ),
+        ]);
+
+        let statuses: BTreeMap<_, _> = db.verify().unwrap().into_iter().collect();
+        assert_eq!(statuses["hash.k2d"], FileStatus::Ok);
+        assert_eq!(statuses["opts.k2d"], FileStatus::Corrupted);
+        assert_eq!(
+            statuses["taxo.k2d"],
+            FileStatus::WrongSize {
+                expected: 10,
+                actual: 4
+            }
+        );
+        assert_eq!(statuses["missing.k2d"], FileStatus::Missing);
+        assert_eq!(
+            statuses["taxo.k2d"].to_string(),
+            "truncated (4 of 10 bytes)"
+        );
+    }
+
+    #[test]
+    fn test_database_lock() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path().join("db");
+        let lock = DatabaseLock::acquire(&root).unwrap();
+        let file = fs::File::open(root.join(LOCK_FILE)).unwrap();
+        assert!(file.try_lock_exclusive().is_err());
+
+        drop(lock);
+        assert!(file.try_lock_exclusive().is_ok());
+        assert!(installed_databases(&root).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_version_from_url() {
+        assert_eq!(
+            version_from_url("https://example.com/db.tgz?download=1"),
+            "db"
+        );
+        assert_eq!(version_from_url("s3://bucket/dbs/mouse_v1.tar"), "mouse_v1");
+        assert_eq!(version_from_url("https://example.com/db"), "db");
+        assert_eq!(
+            file_name_from_url("https://example.com/db.tgz?download=1"),
+            "db.tgz"
+        );
+    }
+
+    #[test]
+    fn test_index_options_from_bytes() {
+        let mut bytes = Vec::new();
+        bytes.extend(35u64.to_le_bytes());
+        bytes.extend(31u64.to_le_bytes());
+        bytes.extend(0x3Cu64.to_le_bytes());
+        bytes.extend(0xe37e28c4271b5a2du64.to_le_bytes());
+        bytes.extend([1, 0, 0, 0, 0, 0, 0, 0]);
+        bytes.extend([0; 24]);
+
+        let opts = IndexOptions::from_bytes(&bytes).unwrap();
+        assert_eq!(opts.k, 35);
+        assert_eq!(opts.l, 31);
+        assert_eq!(opts.spaced_seed_mask, 0x3C);
+        assert!(opts.dna_db);
+
+        assert!(IndexOptions::from_bytes(&bytes[..16]).is_err());
+    }
+}
@@ -0,0 +1,124 @@
+//! Installing kraken2, for users who can't install it another way (e.g. with conda).
+//!
+//! kraken2 doesn't publish prebuilt binaries, so it is built from the source of a tagged release.
+use crate::download::{download_tarball, DownloadOptions};
+use crate::{CommandRunner, NohumanError};
+use anyhow::{bail, Context, Result};
+use flate2::read::GzDecoder;
+use log::info;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use tar::Archive;
+
+/// The kraken2 release installed by default.
+pub const KRAKEN2_VERSION: &str = "2.1.3";
+
+/// The programs needed to build and run kraken2 (its `kraken2` command is a perl script).
+const BUILD_DEPENDENCIES: [&str; 3] = ["make", "g++", "perl"];
+
+/// The URL of the source of kraken2 `version`.
+fn kraken2_source_url(version: &str) -> String {
+    format!(
+        "https://github.com/DerrickWood/kraken2/archive/refs/tags/v{}.tar.gz",
+        version
+    )
+}
+
+/// The path of the program `name` in `bin_dir`, if it is installed there.
+pub fn installed_program(bin_dir: &Path, name: &str) -> Option<PathBuf> {
+    let path = bin_dir.join(name);
+    path.is_file().then_some(path)
+}
+
+/// Download the source of kraken2 `version`, then build and install it into `bin_dir`. Building
+/// needs make and a C++ compiler. Returns the path of the installed kraken2.
+pub fn install_kraken2(
+    version: &str,
+    bin_dir: &Path,
+    options: &DownloadOptions,
+) -> Result<PathBuf> {
+    if version.is_empty() || !version.chars().all(|c| c.is_ascii_digit() || c == '.') {
+        bail!(
+            "Invalid kraken2 version {:?}. Expected e.g. {}",
+            version,
+            KRAKEN2_VERSION
+        );
+    }
+    let missing: Vec<String> = BUILD_DEPENDENCIES
+        .into_iter()
+        .filter(|program| !CommandRunner::new(program).is_executable())
+        .map(String::from)
+        .collect();
+    if !missing.is_empty() {
+        bail!(
+            "{}. kraken2 is built from source, which needs make, g++, and perl",
+            NohumanError::MissingDependency(missing)
+        );
+    }
+
+    let bin_dir = std::path::absolute(bin_dir)?;
+    let parent = bin_dir.parent().unwrap_or(Path::new("."));
+    fs::create_dir_all(parent)?;
+    let build_dir = tempfile::Builder::new()
+        .prefix(".kraken2-build-")
+        .tempdir_in(parent)
+        .context("Failed to create the build directory")?;
+
+    let url = kraken2_source_url(version);
+    let tarball = build_dir.path().join("kraken2.tar.gz");
+    download_tarball(&[&url], &tarball, None, options)
+        .with_context(|| format!("Failed to download kraken2 {}", version))?;
+    info!("Extracting the kraken2 source...");
+    Archive::new(GzDecoder::new(File::open(&tarball)?))
+        .unpack(build_dir.path())
+        .context("Failed to extract the kraken2 source")?;
+
+    info!(
+        "Building kraken2 {} into {:?}. This can take a few minutes...",
+        version, bin_dir
+    );
+    let source = build_dir.path().join(format!("kraken2-{}", version));
+    let status = Command::new("sh")
+        .arg("install_kraken2.sh")
+        .arg(&bin_dir)
+        .current_dir(&source)
+        .status()
+        .context("Failed to run the kraken2 install script")?;
+    if !status.success() {
+        bail!("Building kraken2 {} failed ({})", version, status);
+    }
+
+    let kraken2 = bin_dir.join("kraken2");
+    if CommandRunner::new(&kraken2.to_string_lossy())
+        .version()
+        .is_none()
+    {
+        bail!("kraken2 was installed, but {:?} doesn't run", kraken2);
+    }
+    Ok(kraken2)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_installed_program() {
+        let tmp = tempfile::tempdir().unwrap();
+        assert!(installed_program(tmp.path(), "kraken2").is_none());
+
+        fs::write(tmp.path().join("kraken2"), "").unwrap();
+        assert_eq!(
+            installed_program(tmp.path(), "kraken2"),
+            Some(tmp.path().join("kraken2"))
+        );
+    }
+
+    #[test]
+    fn test_install_kraken2_invalid_version() {
+        let tmp = tempfile::tempdir().unwrap();
+        let result = install_kraken2("latest", tmp.path(), &DownloadOptions::default());
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,287 @@
+//! The checks run by `--check`: that the dependencies are available, the database is complete,
+//! and there is enough memory and disk space for a run.
+use crate::database::IndexOptions;
+use crate::error::FailureKind;
+use crate::{available_memory, validate_db_directory};
+use indicatif::HumanBytes;
+use std::fmt;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::Path;
+
+/// The size of the header of a kraken2 `hash.k2d` file: its capacity, size, key bits and value
+/// bits, as 64-bit integers.
+const HASH_HEADER_SIZE: u64 = 32;
+/// The size of a cell of a kraken2 hash table.
+const HASH_CELL_SIZE: u64 = 4;
+/// The start of a kraken2 `taxo.k2d` file.
+const TAXONOMY_MAGIC: &[u8; 8] = b"K2TAXDAT";
+
+/// The outcome of a check.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Pass,
+    /// Not a failure, but something that makes a run slower or may cause it to fail.
+    Warn,
+    Fail,
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Pass => f.pad("PASS"),
+            Self::Warn => f.pad("WARN"),
+            Self::Fail => f.pad("FAIL"),
+        }
+    }
+}
+
+/// The result of a check.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Check {
+    pub name: String,
+    pub status: Status,
+    pub detail: String,
+    /// The class of failure a run would exit with if the check fails.
+    pub kind: FailureKind,
+}
+
+impl Check {
+    pub fn pass(name: &str, kind: FailureKind, detail: String) -> Self {
+        Self::new(name, Status::Pass, kind, detail)
+    }
+
+    pub fn warn(name: &str, kind: FailureKind, detail: String) -> Self {
+        Self::new(name, Status::Warn, kind, detail)
+    }
+
+    pub fn fail(name: &str, kind: FailureKind, detail: String) -> Self {
+        Self::new(name, Status::Fail, kind, detail)
+    }
+
+    fn new(name: &str, status: Status, kind: FailureKind, detail: String) -> Self {
+        Self {
+            name: name.to_string(),
+            status,
+            detail,
+            kind,
+        }
+    }
+}
+
+/// Check the kraken2 database at `path` (or its `db` subdirectory): that its files are present,
+/// readable and of a sane size. The contents of `hash.k2d` aren't read, as it is large.
+pub fn check_database(path: &Path) -> Vec<Check> {
+    let kind = FailureKind::Database;
+    let dir = match validate_db_directory(path) {
+        Ok(dir) => dir,
+        Err(e) => return vec![Check::fail("database", kind, e.to_string())],
+    };
+    let mut checks = vec![Check::pass("database", kind, format!("{:?}", dir))];
+    for (name, check) in [
+        (
+            "hash.k2d",
+            check_hash_file as fn(&Path) -> Result<String, String>,
+        ),
+        ("opts.k2d", check_options_file),
+        ("taxo.k2d", check_taxonomy_file),
+    ] {
+        let check = match check(&dir.join(name)) {
+            Ok(detail) => Check::pass(name, kind, detail),
+            Err(detail) => Check::fail(name, kind, detail),
+        };
+        checks.push(check);
+    }
+    checks
+}
+
+/// Read the first `n` bytes of the file at `path`, or all of it if it is smaller.
+fn read_start(path: &Path, n: u64) -> Result<Vec<u8>, String> {
+    let mut bytes = Vec::new();
+    File::open(path)
+        .and_then(|file| file.take(n).read_to_end(&mut bytes))
+        .map_err(|e| format!("Unable to read {:?}: {}", path, e))?;
+    Ok(bytes)
+}
+
+/// Check the size of `hash.k2d` matches the capacity in its header.
+fn check_hash_file(path: &Path) -> Result<String, String> {
+    let header = read_start(path, HASH_HEADER_SIZE)?;
+    if header.len() < HASH_HEADER_SIZE as usize {
+        return Err(format!("{} bytes, which is too short", header.len()));
+    }
+    let capacity = u64::from_le_bytes(header[..8].try_into().unwrap());
+    let size = std::fs::metadata(path).map_err(|e| e.to_string())?.len();
+    let expected = capacity
+        .checked_mul(HASH_CELL_SIZE)
+        .and_then(|cells| cells.checked_add(HASH_HEADER_SIZE));
+    match expected {
+        Some(expected) if expected == size => Ok(HumanBytes(size).to_string()),
+        _ => Err(format!(
+            "{} bytes, but its header is for a table of {} cells. It may be truncated",
+            size, capacity
+        )),
+    }
+}
+
+fn check_options_file(path: &Path) -> Result<String, String> {
+    let bytes = read_start(path, 1024)?;
+    let options = IndexOptions::from_bytes(&bytes).map_err(|e| e.to_string())?;
+    Ok(format!("k={}, l={}", options.k, options.l))
+}
+
+fn check_taxonomy_file(path: &Path) -> Result<String, String> {
+    let magic = read_start(path, TAXONOMY_MAGIC.len() as u64)?;
+    if magic != TAXONOMY_MAGIC {
+        return Err("not a kraken2 taxonomy file".to_string());
+    }
+    let size = std::fs::metadata(path).map_err(|e| e.to_string())?.len();
+    Ok(HumanBytes(size).to_string())
+}
+
+/// Check the database in `k2d_dir` fits in the available memory. If it doesn't, kraken2 is run
+/// with `--memory-mapping`, which is slower, so this only warns.
+pub fn check_memory(k2d_dir: &Path) -> Check {
+    let name = "memory";
+    let kind = FailureKind::Kraken2;
+    let db_size = std::fs::metadata(k2d_dir.join("hash.k2d")).map_or(0, |m| m.len());
+    match available_memory() {
+        Some(available) if db_size > available => Check::warn(
+            name,
+            kind,
+            format!(
+                "{} available, but hash.k2d is {}, so it will be memory-mapped (slower)",
+                HumanBytes(available),
+                HumanBytes(db_size)
+            ),
+        ),
+        Some(available) => Check::pass(
+            name,
+            kind,
+            format!(
+                "{} available for hash.k2d ({})",
+                HumanBytes(available),
+                HumanBytes(db_size)
+            ),
+        ),
+        None => Check::warn(
+            name,
+            kind,
+            "Unable to determine the available memory".to_string(),
+        ),
+    }
+}
+
+/// Check a file can be created in `dir`, and that it has at least `needed` bytes free. `name` is
+/// what the directory is used for, e.g. "output".
+pub fn check_directory(name: &str, dir: &Path, needed: u64) -> Vec<Check> {
+    let kind = FailureKind::Io;
+    let writable = match tempfile::tempfile_in(dir) {
+        Ok(_) => Check::pass(&format!("{} writable", name), kind, format!("{:?}", dir)),
+        Err(e) => Check::fail(
+            &format!("{} writable", name),
+            kind,
+            format!("Unable to create a file in {:?}: {}", dir, e),
+        ),
+    };
+    let space_name = format!("{} space", name);
+    let space = match fs2::available_space(dir) {
+        Ok(free) if free < needed => Check::fail(
+            &space_name,
+            kind,
+            format!(
+                "{} free in {:?}, but the inputs are {}",
+                HumanBytes(free),
+                dir,
+                HumanBytes(needed)
+            ),
+        ),
+        Ok(free) => Check::pass(
+            &space_name,
+            kind,
+            format!("{} free in {:?}", HumanBytes(free), dir),
+        ),
+        Err(e) => Check::warn(
+            &space_name,
+            kind,
+            format!("Unable to determine the free space in {:?}: {}", dir, e),
+        ),
+    };
+    vec![writable, space]
+}
+
+/// Write `checks` as a table, with a column each for the name, status, and detail.
+pub fn write_table<W: Write>(mut writer: W, checks: &[Check]) -> std::io::Result<()> {
+    let width = checks
+        .iter()
+        .map(|check| check.name.len())
+        .chain(["CHECK".len()])
+        .max()
+        .unwrap_or_default();
+    writeln!(writer, "{:<width$}  STATUS  DETAIL", "CHECK")?;
+    for check in checks {
+        writeln!(
+            writer,
+            "{:<width$}  {:<6}  {}",
+            check.name, check.status, check.detail
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Write a kraken2 database with a hash table of `capacity` cells, but `cells` cells in
+    /// `hash.k2d`.
+    fn write_db(dir: &Path, capacity: u64, cells: u64) {
+        let mut hash = capacity.to_le_bytes().to_vec();
+        hash.resize((HASH_HEADER_SIZE + cells * HASH_CELL_SIZE) as usize, 0);
+        std::fs::write(dir.join("hash.k2d"), hash).unwrap();
+        let mut opts = [35u64, 31, 0, 0].map(u64::to_le_bytes).concat();
+        opts.push(1);
+        std::fs::write(dir.join("opts.k2d"), opts).unwrap();
+        std::fs::write(dir.join("taxo.k2d"), b"K2TAXDAT\0\0").unwrap();
+    }
+
+    #[test]
+    fn test_check_database() {
+        let tmp = tempfile::tempdir().unwrap();
+        let checks = check_database(tmp.path());
+        assert_eq!(checks.len(), 1);
+        assert_eq!(checks[0].status, Status::Fail);
+
+        write_db(tmp.path(), 10, 10);
+        let checks = check_database(tmp.path());
+        assert_eq!(checks.len(), 4);
+        assert!(checks.iter().all(|check| check.status == Status::Pass));
+        assert_eq!(checks[2].detail, "k=35, l=31");
+
+        write_db(tmp.path(), 10, 5);
+        std::fs::write(tmp.path().join("taxo.k2d"), b"K2TAX").unwrap();
+        let failed: Vec<_> = check_database(tmp.path())
+            .into_iter()
+            .filter(|check| check.status == Status::Fail)
+            .map(|check| check.name)
+            .collect();
+        assert_eq!(failed, ["hash.k2d", "taxo.k2d"]);
+    }
+
+    #[test]
+    fn test_check_directory_and_table() {
+        let tmp = tempfile::tempdir().unwrap();
+        let checks = check_directory("output", tmp.path(), 0);
+        assert_eq!(checks[0].status, Status::Pass);
+        assert_ne!(checks[1].status, Status::Fail);
+        let checks = check_directory("output", &tmp.path().join("missing"), 0);
+        assert_eq!(checks[0].status, Status::Fail);
+
+        let mut table = Vec::new();
+        write_table(&mut table, &checks[..1]).unwrap();
+        let table = String::from_utf8(table).unwrap();
+        let lines: Vec<_> = table.lines().collect();
+        assert_eq!(lines[0], "CHECK            STATUS  DETAIL");
+        assert!(lines[1].starts_with("output writable  FAIL    Unable to create"));
+    }
+}
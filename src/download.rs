@@ -1,27 +1,45 @@
-use crate::Config;
+use crate::cloud::{is_cloud_url, object_request, ObjectRequest};
+use crate::compression::{decoder, BackgroundReader, CompressionFormat};
+use crate::database::{
+    file_name_from_url, DatabaseLock, DatabaseMetadata, FileChecksum, INDEX_FILES, METADATA_FILE,
+};
+use crate::native::find_native_index;
+use crate::progress::{ProgressCallback, ProgressEvent};
+use crate::signature::{signature_url, SignaturePolicy};
+use crate::{validate_db_directory, CommandRunner, Config, DatabaseRelease, Host, NohumanError};
 use async_std::task;
-use flate2::read::GzDecoder;
+use futures_util::future::try_join_all;
 use futures_util::StreamExt;
-use indicatif::{ProgressBar, ProgressStyle};
-use reqwest::blocking::get;
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
+use log::{debug, info, log_enabled, warn, Level};
+use reqwest::header::{CONTENT_RANGE, RANGE};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
 use std::fs;
-use std::fs::File;
-use std::io::Read;
-use std::io::Write;
-use std::path::Path;
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
 use tar::Archive;
 use thiserror::Error;
 
 // create a variable to store the url for the config file
 const CONFIG_URL: &str = "https://raw.githubusercontent.com/mbhall88/nohuman/main/config.toml";
+/// The name of the file, in the database root, that the default config is cached in.
+pub const MANIFEST_CACHE_FILE: &str = "manifest.toml";
+/// How long a cached config is used before it is downloaded again.
+pub const MANIFEST_CACHE_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+/// The longest time to wait between download attempts.
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
 
 #[derive(Error, Debug)]
 pub enum DownloadError {
     #[error("Failed to download the tarball")]
     DownloadFailed,
 
-    #[error("Tarball MD5 hash does not match the expected value")]
-    Md5Mismatch,
+    #[error("Tarball {0} hash does not match the expected value")]
+    ChecksumMismatch(&'static str),
 
     #[error("Failed to extract the tarball")]
     ExtractionFailed,
@@ -29,12 +47,54 @@ pub enum DownloadError {
     #[error("Failed to download the config file")]
     ConfigDownloadFailed,
 
+    #[error("Failed to read the config file {0:?}")]
+    ConfigReadFailed(PathBuf),
+
     #[error("Failed to parse the config file")]
     ConfigParseFailed,
 
     #[error("Failed to compute MD5 hash")]
     Md5Error,
 
+    #[error("No database is available to download for host {0}")]
+    NoDatabaseForHost(Host),
+
+    #[error("No native database is available to download for host {0}. Build one with `nohuman db build --native`")]
+    NoNativeDatabaseForHost(Host),
+
+    #[error("Database version {0} is not available to download")]
+    NoSuchRelease(String),
+
+    #[error("No checksum is given for database {0}")]
+    MissingChecksum(String),
+
+    #[error("Invalid checksum {0:?}. Expected <algorithm>:<hash>, e.g. sha256:ba78...")]
+    InvalidChecksum(String),
+
+    #[error("Invalid minisign public key {0:?}")]
+    InvalidPublicKey(String),
+
+    #[error("No signature found for {0}")]
+    SignatureMissing(String),
+
+    #[error("The signature of {0} is not valid")]
+    InvalidSignature(String),
+
+    #[error("Invalid URL {0}")]
+    InvalidUrl(String),
+
+    #[error("Invalid database version {0:?}")]
+    InvalidVersion(String),
+
+    #[error("A database is already installed at {0:?}")]
+    AlreadyInstalled(PathBuf),
+
+    #[error("Database {0} has been yanked. Use --allow-yanked to download it anyway")]
+    Yanked(String),
+
+    #[error(transparent)]
+    InvalidDatabase(#[from] NohumanError),
+
     #[error(transparent)]
     IoError(#[from] std::io::Error),
 
@@ -42,39 +102,268 @@ pub enum DownloadError {
     ReqwestError(#[from] reqwest::Error),
 }
 
-/// function to compute md5 without reading whole file into memory
-fn compute_md5(path: &Path) -> Result<String, DownloadError> {
+/// The expected hash of a database tarball.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Checksum {
+    Blake3(String),
+    Sha256(String),
+    Md5(String),
+}
+
+impl Checksum {
+    /// The name of the hash algorithm.
+    pub fn algorithm(&self) -> &'static str {
+        match self {
+            Checksum::Blake3(_) => "BLAKE3",
+            Checksum::Sha256(_) => "SHA256",
+            Checksum::Md5(_) => "MD5",
+        }
+    }
+
+    /// The expected hash, as a hex string.
+    pub fn hash(&self) -> &str {
+        match self {
+            Checksum::Blake3(hash) | Checksum::Sha256(hash) | Checksum::Md5(hash) => hash,
+        }
+    }
+
+    /// Check that the file at `path` has the expected hash.
+    pub fn verify(&self, path: &Path) -> Result<(), DownloadError> {
+        let actual = match self {
+            Checksum::Blake3(_) => compute_blake3(path)?,
+            Checksum::Sha256(_) => compute_sha256(path)?,
+            Checksum::Md5(_) => compute_md5(path)?,
+        };
+        if actual.eq_ignore_ascii_case(self.hash().trim()) {
+            Ok(())
+        } else {
+            Err(DownloadError::ChecksumMismatch(self.algorithm()))
+        }
+    }
+}
+
+impl std::str::FromStr for Checksum {
+    type Err = DownloadError;
+
+    /// Parse a checksum of the form `<algorithm>:<hash>`, e.g. `sha256:ba78...`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (algorithm, hash) = s
+            .split_once(':')
+            .ok_or_else(|| DownloadError::InvalidChecksum(s.to_string()))?;
+        let hash = hash.trim().to_string();
+        match algorithm.trim().to_lowercase().as_str() {
+            "blake3" => Ok(Checksum::Blake3(hash)),
+            "sha256" => Ok(Checksum::Sha256(hash)),
+            "md5" => Ok(Checksum::Md5(hash)),
+            _ => Err(DownloadError::InvalidChecksum(s.to_string())),
+        }
+    }
+}
+
+impl std::fmt::Display for Checksum {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}:{}",
+            self.algorithm().to_lowercase(),
+            self.hash().to_lowercase()
+        )
+    }
+}
+
+/// Record the size and BLAKE3 hash of each of the kraken2 index files (or the native index file)
+/// of the database in `dir`. Files are keyed by their path relative to `dir`.
+pub fn index_file_checksums(dir: &Path) -> Result<BTreeMap<String, FileChecksum>, DownloadError> {
+    let index_files = match find_native_index(dir) {
+        Ok(path) => vec![path],
+        Err(_) => {
+            let k2d_dir = validate_db_directory(dir)?;
+            INDEX_FILES.iter().map(|name| k2d_dir.join(name)).collect()
+        }
+    };
+    let mut files = BTreeMap::new();
+    for path in index_files {
+        let key = path
+            .strip_prefix(dir)
+            .unwrap_or(&path)
+            .to_string_lossy()
+            .into_owned();
+        files.insert(
+            key,
+            FileChecksum {
+                size: Some(fs::metadata(&path)?.len()),
+                checksum: Checksum::Blake3(compute_blake3(&path)?).to_string(),
+            },
+        );
+    }
+    Ok(files)
+}
+
+/// Feed the file at `path` to `update` in chunks, without reading the whole file into memory.
+fn hash_file<F: FnMut(&[u8])>(path: &Path, mut update: F) -> Result<(), DownloadError> {
     let mut file = fs::File::open(path).map_err(DownloadError::IoError)?;
-    let mut hasher = md5::Context::new();
-    let mut buffer = [0; 1024];
+    let mut buffer = vec![0; 64 * 1024];
     loop {
         let n = file.read(&mut buffer).map_err(DownloadError::IoError)?;
         if n == 0 {
             break;
         }
-        hasher.consume(&buffer[..n]);
+        update(&buffer[..n]);
     }
+    Ok(())
+}
+
+/// function to compute md5 without reading whole file into memory
+pub fn compute_md5(path: &Path) -> Result<String, DownloadError> {
+    let mut hasher = md5::Context::new();
+    hash_file(path, |buf| hasher.consume(buf))?;
     let result = hasher.compute();
     Ok(format!("{:x}", result))
 }
 
-async fn download_from_url(url: &str, dest: &Path) -> Result<(), DownloadError> {
-    let response = reqwest::get(url)
-        .await
-        .map_err(DownloadError::ReqwestError)?;
+/// Compute the SHA256 of the file at `path`, as a hex string.
+pub fn compute_sha256(path: &Path) -> Result<String, DownloadError> {
+    let mut hasher = Sha256::new();
+    hash_file(path, |buf| hasher.update(buf))?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
 
-    if response.status() != reqwest::StatusCode::OK {
-        return Err(DownloadError::DownloadFailed);
+/// Compute the BLAKE3 hash of the file at `path`, as a hex string.
+pub fn compute_blake3(path: &Path) -> Result<String, DownloadError> {
+    let mut hasher = blake3::Hasher::new();
+    hash_file(path, |buf| {
+        hasher.update(buf);
+    })?;
+    Ok(hasher.finalize().to_hex().to_string())
+}
+
+/// Options controlling how databases are downloaded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DownloadOptions {
+    /// The number of times to retry a failed download from each URL.
+    pub retries: u32,
+    /// The longest to wait when connecting, or between receiving data. No limit if `None`.
+    pub timeout: Option<Duration>,
+    /// The number of chunks of a file to download concurrently.
+    pub threads: usize,
+    /// Keep the downloaded tarball at this path (or in this directory, with the name it was
+    /// downloaded with), instead of removing it once it has been extracted.
+    pub keep_tarball: Option<PathBuf>,
+    /// Check the signatures of the config and tarball with this policy. Not checked if `None`.
+    pub signature_policy: Option<SignaturePolicy>,
+    /// A local path or URL to read the config listing the database releases from, instead of
+    /// the nohuman repository.
+    pub manifest: Option<String>,
+    /// The directory to cache downloaded configs in. Not cached if `None`.
+    pub manifest_cache_dir: Option<PathBuf>,
+    /// Download the config, even if there is a recently cached copy.
+    pub refresh_manifest: bool,
+    /// Only warn, rather than fail, if the database needs a newer nohuman or kraken2.
+    pub ignore_compatibility: bool,
+    /// Download the release even if it has been yanked.
+    pub allow_yanked: bool,
+    /// The kraken2 executable, whose version is checked against the database's requirements.
+    pub kraken2: String,
+    /// Download the native database (for nohuman's built-in classifier) instead of the kraken2
+    /// database.
+    pub native: bool,
+    /// Download this version (an earlier release, or the test database) instead of the latest.
+    pub version: Option<String>,
+    /// Called as the database is downloaded and extracted.
+    pub progress: Option<ProgressCallback>,
+}
+
+impl Default for DownloadOptions {
+    fn default() -> Self {
+        Self {
+            retries: 2,
+            timeout: None,
+            threads: 1,
+            keep_tarball: None,
+            signature_policy: None,
+            manifest: None,
+            manifest_cache_dir: None,
+            refresh_manifest: false,
+            ignore_compatibility: false,
+            allow_yanked: false,
+            kraken2: "kraken2".to_string(),
+            native: false,
+            version: None,
+            progress: None,
+        }
+    }
+}
+
+impl DownloadOptions {
+    /// The time to wait before retry number `retry` (starting at 1). This doubles with each retry.
+    fn backoff(retry: u32) -> Duration {
+        Duration::from_secs(1u64 << retry.saturating_sub(1).min(6)).min(MAX_BACKOFF)
     }
 
-    let content_length = response.content_length().unwrap_or(0);
-    let progress_bar = ProgressBar::new(content_length);
+    fn emit(&self, event: ProgressEvent) {
+        if let Some(progress) = &self.progress {
+            progress.emit(event);
+        }
+    }
+}
+
+/// A download progress bar, hidden if info messages aren't logged (e.g. in quiet mode).
+fn progress_bar(len: u64) -> ProgressBar {
+    let progress_bar = ProgressBar::new(len);
+    if !log_enabled!(Level::Info) {
+        progress_bar.set_draw_target(ProgressDrawTarget::hidden());
+    }
     progress_bar.set_style(
         ProgressStyle::default_bar()
             .template("[{elapsed_precise}] [{bar:40.cyan/blue}] {bytes}/{total_bytes} ({eta})")
             .unwrap()
             .progress_chars("#>-"),
     );
+    progress_bar
+}
+
+/// Download `url` to `dest`. If `options.threads` is more than 1, and the server supports range
+/// requests, the file is downloaded in that many chunks concurrently.
+async fn download_from_url(
+    url: &str,
+    dest: &Path,
+    options: &DownloadOptions,
+) -> Result<(), DownloadError> {
+    let mut client = reqwest::Client::builder();
+    if let Some(timeout) = options.timeout {
+        client = client.connect_timeout(timeout).read_timeout(timeout);
+    }
+    let client = client.build()?;
+    let request = object_request(url)?;
+
+    if options.threads > 1 {
+        match content_range_size(&client, &request).await? {
+            Some(size) => {
+                return download_chunks(url, &client, &request, dest, size, options).await
+            }
+            None => debug!(
+                "{} does not support range requests. Downloading it in one stream",
+                url
+            ),
+        }
+    }
+
+    let response = request
+        .get(&client)
+        .send()
+        .await
+        .map_err(DownloadError::ReqwestError)?;
+
+    if response.status() != reqwest::StatusCode::OK {
+        return Err(DownloadError::DownloadFailed);
+    }
+
+    let content_length = response.content_length();
+    options.emit(ProgressEvent::DownloadStarted {
+        url: url.to_string(),
+        total: content_length,
+    });
+    let progress_bar = progress_bar(content_length.unwrap_or(0));
 
     let mut file = File::create(dest).map_err(DownloadError::IoError)?;
 
@@ -83,66 +372,684 @@ async fn download_from_url(url: &str, dest: &Path) -> Result<(), DownloadError>
         let chunk = item?;
         file.write_all(&chunk).map_err(DownloadError::IoError)?;
         progress_bar.inc(chunk.len() as u64);
+        options.emit(ProgressEvent::Downloaded {
+            bytes: chunk.len() as u64,
+        });
     }
 
     progress_bar.finish();
     Ok(())
 }
 
-fn download_and_extract_tarball(
+/// Get the size of the file at `request`, if the server supports range requests for it.
+async fn content_range_size(
+    client: &reqwest::Client,
+    request: &ObjectRequest,
+) -> Result<Option<u64>, DownloadError> {
+    let response = request
+        .get(client)
+        .header(RANGE, "bytes=0-0")
+        .send()
+        .await?;
+    if response.status() != reqwest::StatusCode::PARTIAL_CONTENT {
+        return Ok(None);
+    }
+    // e.g. "bytes 0-0/1234"
+    Ok(response
+        .headers()
+        .get(CONTENT_RANGE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.rsplit_once('/'))
+        .and_then(|(_, size)| size.parse().ok()))
+}
+
+/// Download the `size` byte file at `request` to `dest` in `options.threads` chunks, fetched
+/// concurrently. Each chunk is retried (resuming where it stopped) up to `options.retries` times.
+async fn download_chunks(
     url: &str,
+    client: &reqwest::Client,
+    request: &ObjectRequest,
+    dest: &Path,
+    size: u64,
+    options: &DownloadOptions,
+) -> Result<(), DownloadError> {
+    File::create(dest)?.set_len(size)?;
+    options.emit(ProgressEvent::DownloadStarted {
+        url: url.to_string(),
+        total: Some(size),
+    });
+    let progress_bar = progress_bar(size);
+
+    let chunk_size = size.div_ceil(options.threads as u64).max(1);
+    let chunks = (0..size).step_by(chunk_size as usize).map(|start| {
+        let end = (start + chunk_size).min(size);
+        let progress_bar = &progress_bar;
+        async move {
+            let mut pos = start;
+            let mut retry = 0;
+            loop {
+                match download_range(client, request, dest, &mut pos, end, progress_bar, options)
+                    .await
+                {
+                    Ok(()) => return Ok(()),
+                    Err(e) if retry < options.retries => {
+                        retry += 1;
+                        warn!(
+                            "Download of bytes {}-{} of {} failed (retry {}/{}): {}",
+                            start,
+                            end - 1,
+                            url,
+                            retry,
+                            options.retries,
+                            e
+                        );
+                        task::sleep(DownloadOptions::backoff(retry)).await;
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+    });
+    try_join_all(chunks).await?;
+
+    progress_bar.finish();
+    Ok(())
+}
+
+/// Download bytes `*pos..end` of `request` into the same range of `dest`. `pos` is advanced as
+/// data is written, so a failed download can be resumed.
+async fn download_range(
+    client: &reqwest::Client,
+    request: &ObjectRequest,
+    dest: &Path,
+    pos: &mut u64,
+    end: u64,
+    progress_bar: &ProgressBar,
+    options: &DownloadOptions,
+) -> Result<(), DownloadError> {
+    let response = request
+        .get(client)
+        .header(RANGE, format!("bytes={}-{}", pos, end - 1))
+        .send()
+        .await?;
+    if response.status() != reqwest::StatusCode::PARTIAL_CONTENT {
+        return Err(DownloadError::DownloadFailed);
+    }
+
+    let mut file = OpenOptions::new().write(true).open(dest)?;
+    file.seek(SeekFrom::Start(*pos))?;
+    let mut stream = response.bytes_stream();
+    while let Some(item) = stream.next().await {
+        let chunk = item?;
+        let len = (chunk.len() as u64).min(end - *pos) as usize;
+        file.write_all(&chunk[..len])?;
+        *pos += len as u64;
+        progress_bar.inc(len as u64);
+        options.emit(ProgressEvent::Downloaded { bytes: len as u64 });
+        if *pos == end {
+            return Ok(());
+        }
+    }
+    Err(DownloadError::DownloadFailed)
+}
+
+/// Download a tarball to `dest` and check its hash, if `checksum` is given. Each URL in `urls` is
+/// tried in turn, with failed downloads retried (with exponential backoff) up to `options.retries`
+/// times, until one succeeds. A mirror serving a tarball with the wrong hash is not retried.
+pub(crate) fn download_tarball(
+    urls: &[&str],
+    dest: &Path,
+    checksum: Option<&Checksum>,
+    options: &DownloadOptions,
+) -> Result<(), DownloadError> {
+    let attempts = options.retries + 1;
+    let mut last_err = DownloadError::DownloadFailed;
+    for url in urls {
+        for attempt in 1..=attempts {
+            info!("Downloading {} (attempt {}/{})", url, attempt, attempts);
+            let result = task::block_on(download_from_url(url, dest, options))
+                .and_then(|_| checksum.map_or(Ok(()), |checksum| checksum.verify(dest)));
+            match result {
+                Ok(()) => return Ok(()),
+                Err(e @ DownloadError::ChecksumMismatch(_)) => {
+                    warn!("Download from {} failed: {}", url, e);
+                    last_err = e;
+                    break;
+                }
+                Err(e) => {
+                    warn!(
+                        "Download from {} failed (attempt {}/{}): {}",
+                        url, attempt, attempts, e
+                    );
+                    last_err = e;
+                    if attempt < attempts {
+                        let wait = DownloadOptions::backoff(attempt);
+                        info!("Retrying in {}", humantime::format_duration(wait));
+                        thread::sleep(wait);
+                    }
+                }
+            }
+        }
+    }
+    Err(last_err)
+}
+
+fn download_and_extract_tarball(
+    urls: &[&str],
     output_path: &Path,
-    md5: &str,
+    checksum: &Checksum,
+    signature_url: &str,
+    options: &DownloadOptions,
 ) -> Result<(), DownloadError> {
-    // Create a temporary file to store the downloaded tarball
-    let tarball_path = tempfile::NamedTempFile::new().map_err(DownloadError::IoError)?;
-    task::block_on(download_from_url(url, tarball_path.path()))?;
+    let keep_path = options.keep_tarball.as_ref().map(|path| {
+        if path.is_dir() {
+            path.join(file_name_from_url(urls[0]))
+        } else {
+            path.to_owned()
+        }
+    });
 
-    // Check the MD5 hash of the tarball
-    let md5_hash = compute_md5(tarball_path.path())?;
-    if md5_hash != md5 {
-        return Err(DownloadError::Md5Mismatch);
+    // Create a temporary file to store the downloaded tarball. If it is being kept, this is
+    // created next to where it is kept, so it can be moved there without copying
+    let tarball_path = match &keep_path {
+        Some(path) => {
+            let dir = path
+                .parent()
+                .filter(|dir| !dir.as_os_str().is_empty())
+                .unwrap_or(Path::new("."));
+            tempfile::Builder::new()
+                .prefix(".nohuman-download")
+                .tempfile_in(dir)
+        }
+        None => tempfile::NamedTempFile::new(),
+    }
+    .map_err(DownloadError::IoError)?;
+    download_tarball(urls, tarball_path.path(), Some(checksum), options)?;
+
+    if let Some(policy) = &options.signature_policy {
+        let signature = fetch_text(signature_url)?;
+        policy.verify_file(
+            tarball_path.path(),
+            signature.as_deref(),
+            "the database tarball",
+        )?;
+    }
+
+    options.emit(ProgressEvent::Extracting);
+    if let Some(path) = keep_path {
+        tarball_path.persist(&path).map_err(|e| e.error)?;
+        info!("Downloaded tarball kept at {:?}", path);
+        return extract_tarball(&path, output_path);
     }
 
     // Extract the tarball to the output path
-    let tarball = File::open(tarball_path.path()).map_err(DownloadError::IoError)?;
-    let tar = GzDecoder::new(&tarball);
+    extract_tarball(tarball_path.path(), output_path)?;
+
+    // remove the temporary tarball file
+    fs::remove_file(tarball_path.path()).map_err(DownloadError::IoError)?;
+
+    Ok(())
+}
+
+/// Extract the (compressed) tarball at `tarball` into `output_path`.
+fn extract_tarball(tarball: &Path, output_path: &Path) -> Result<(), DownloadError> {
+    let format =
+        CompressionFormat::from_file(tarball).map_err(|_| DownloadError::ExtractionFailed)?;
+    let tarball = File::open(tarball).map_err(DownloadError::IoError)?;
+
+    // progress is tracked by how much of the (compressed) tarball has been read
+    info!("Extracting database...");
+    let progress_bar = progress_bar(tarball.metadata()?.len());
+    let reader = BufReader::new(progress_bar.wrap_read(tarball));
+    // decompress on a separate thread to the one unpacking the files
+    let tar = BackgroundReader::new(decoder(reader, format)?);
     let mut archive = Archive::new(tar);
     archive
         .unpack(output_path)
         .map_err(|_| DownloadError::ExtractionFailed)?;
+    progress_bar.finish();
+    Ok(())
+}
 
-    // remove the temporary tarball file
-    fs::remove_file(tarball_path.path()).map_err(DownloadError::IoError)?;
-
+/// Check `version` can be used as the name of a database's directory.
+pub(crate) fn check_version(version: &str) -> Result<(), DownloadError> {
+    if version.is_empty()
+        || version.starts_with('.')
+        || version.contains(['/', '\\'])
+        || version == METADATA_FILE
+    {
+        return Err(DownloadError::InvalidVersion(version.to_string()));
+    }
     Ok(())
 }
 
-pub fn download_database(database_path: &Path) -> Result<(), DownloadError> {
-    let config = download_config()?;
-    download_and_extract_tarball(&config.database_url, database_path, &config.database_md5)?;
+/// Register the existing kraken2 database at `database` in `root` as `version`, so it can be
+/// used like an installed database. Only its metadata is written to `root`; the database files
+/// are left where they are. Returns the path of the directory the metadata is written to.
+pub fn register_database(
+    database: &Path,
+    root: &Path,
+    version: &str,
+    host: &Host,
+) -> Result<PathBuf, DownloadError> {
+    check_version(version)?;
+    let location = validate_db_directory(database)?.canonicalize()?;
+    let _lock = DatabaseLock::acquire(root)?;
+    let path = root.join(version);
+    if path.exists() {
+        return Err(DownloadError::AlreadyInstalled(path));
+    }
+
+    let mut metadata = DatabaseMetadata::new(version, host);
+    metadata.location = Some(location);
+    fs::create_dir(&path)?;
+    if let Err(e) = metadata.write(&path) {
+        let _ = fs::remove_dir_all(&path);
+        return Err(e.into());
+    }
+    Ok(path)
+}
+
+/// Install a database from a local tarball into a directory in `root` named `version`. If given,
+/// the tarball's hash is checked against `checksum`. Otherwise, its MD5 is computed and recorded.
+/// Returns the path of the installed database.
+pub fn install_tarball(
+    tarball: &Path,
+    root: &Path,
+    version: &str,
+    host: &Host,
+    checksum: Option<&Checksum>,
+) -> Result<PathBuf, DownloadError> {
+    check_version(version)?;
+    let _lock = DatabaseLock::acquire(root)?;
+    let path = root.join(version);
+    if path.exists() {
+        return Err(DownloadError::AlreadyInstalled(path));
+    }
+
+    let mut metadata = DatabaseMetadata::new(version, host);
+    match checksum {
+        Some(checksum) => {
+            checksum.verify(tarball)?;
+            match checksum {
+                Checksum::Blake3(hash) => metadata.blake3 = Some(hash.to_lowercase()),
+                Checksum::Sha256(hash) => metadata.sha256 = Some(hash.to_lowercase()),
+                Checksum::Md5(hash) => metadata.md5 = Some(hash.to_lowercase()),
+            }
+        }
+        None => metadata.md5 = Some(compute_md5(tarball)?),
+    }
+
+    let result = extract_tarball(tarball, &path)
+        .and_then(|_| index_file_checksums(&path))
+        .and_then(|files| {
+            metadata.files = files;
+            metadata.write(&path).map_err(DownloadError::from)
+        });
+    if let Err(e) = result {
+        // don't leave a partial or invalid database behind
+        if path.exists() {
+            let _ = fs::remove_dir_all(&path);
+        }
+        return Err(e);
+    }
+    Ok(path)
+}
+
+/// The error when the config has no release for `host`.
+fn no_release_error(host: &Host, options: &DownloadOptions) -> DownloadError {
+    if options.native {
+        DownloadError::NoNativeDatabaseForHost(*host)
+    } else {
+        DownloadError::NoDatabaseForHost(*host)
+    }
+}
+
+/// Get the release for `host` from the config: `options.version`, or the latest.
+fn select_release<'a>(
+    config: &'a Config,
+    host: &Host,
+    options: &DownloadOptions,
+) -> Result<&'a DatabaseRelease, DownloadError> {
+    let version = options.version.as_deref();
+    config
+        .release_version(host, options.native, version)
+        .ok_or_else(|| match version {
+            Some(version) => DownloadError::NoSuchRelease(version.to_string()),
+            None => no_release_error(host, options),
+        })
+}
+
+/// Get the latest database release for `host` from the config.
+pub fn latest_release(
+    host: &Host,
+    options: &DownloadOptions,
+) -> Result<DatabaseRelease, DownloadError> {
+    let config = download_config(options)?;
+    config
+        .release_for(host, options.native)
+        .cloned()
+        .ok_or_else(|| no_release_error(host, options))
+}
+
+/// Get the release for `host` from the config without downloading it, i.e. from a local
+/// `options.manifest` or the cached copy of the config (however old it is). `None` if there isn't
+/// one.
+pub fn cached_release(host: &Host, options: &DownloadOptions) -> Option<DatabaseRelease> {
+    let source = ManifestSource::new(options.manifest.as_deref());
+    let config = match source {
+        ManifestSource::File(_) => download_config(options).ok()?,
+        ManifestSource::Url(_) => {
+            let path = source.cache_path(options.manifest_cache_dir.as_deref()?)?;
+            read_cached_config(&path, options.signature_policy.as_ref())?.0
+        }
+    };
+    config.release_for(host, options.native).cloned()
+}
+
+/// Download the database for `host` (`options.version`, or the latest) and extract it into
+/// `database_path`.
+pub fn download_database(
+    database_path: &Path,
+    host: &Host,
+    options: &DownloadOptions,
+) -> Result<(), DownloadError> {
+    fs::create_dir_all(database_path)?;
+    let _lock = DatabaseLock::acquire(database_path)?;
+    let config = download_config(options)?;
+    let release = select_release(&config, host, options)?;
+    install_release(release, database_path, host, options)
+}
+
+/// [`download_database`], for async callers (e.g. a GUI or web frontend). The download runs on a
+/// blocking thread, so it doesn't hold up the executor, and its progress is reported to
+/// `options.progress`.
+pub async fn download_database_async(
+    database_path: PathBuf,
+    host: Host,
+    options: DownloadOptions,
+) -> Result<(), DownloadError> {
+    task::spawn_blocking(move || download_database(&database_path, &host, &options)).await
+}
+
+/// Download the database for `host` (`options.version`, or the latest) into a directory in `root`
+/// named after its version. Returns the path of the installed database.
+pub fn install_database(
+    root: &Path,
+    host: &Host,
+    options: &DownloadOptions,
+) -> Result<PathBuf, DownloadError> {
+    let _lock = DatabaseLock::acquire(root)?;
+    let config = download_config(options)?;
+    let release = select_release(&config, host, options)?;
+    let version = release.version();
+    check_version(&version)?;
+    let path = root.join(version);
+    install_release(release, &path, host, options)?;
+    Ok(path)
+}
+
+/// Download and extract a database release into `path`, and record its metadata. Nothing is
+/// downloaded if the release is already installed there, e.g. by another nohuman process that
+/// held the database lock first.
+fn install_release(
+    release: &DatabaseRelease,
+    path: &Path,
+    host: &Host,
+    options: &DownloadOptions,
+) -> Result<(), DownloadError> {
+    let version = release.version();
+    let installed = DatabaseMetadata::read(path)
+        .is_ok_and(|metadata| metadata.version == version && metadata.host == host.to_string());
+    if installed && (validate_db_directory(path).is_ok() || find_native_index(path).is_ok()) {
+        info!("Database {} is already installed at {:?}", version, path);
+        return Ok(());
+    }
+
+    if release.yanked {
+        if !options.allow_yanked {
+            return Err(DownloadError::Yanked(version));
+        }
+        warn!(
+            "Database {} has been yanked. Downloading it anyway",
+            version
+        );
+    } else if release.deprecated {
+        warn!("Database {} is deprecated", version);
+    }
+
+    // a native database doesn't use kraken2
+    let kraken2_version = if options.native {
+        None
+    } else {
+        CommandRunner::new(&options.kraken2).version()
+    };
+    if let Err(e) = release.check_compatibility(kraken2_version.as_deref()) {
+        if !options.ignore_compatibility {
+            return Err(e.into());
+        }
+        warn!("{}. Continuing anyway", e);
+    }
+
+    let checksum = release
+        .checksum()
+        .ok_or_else(|| DownloadError::MissingChecksum(release.url.to_owned()))?;
+    download_and_extract_tarball(
+        &release.urls(),
+        path,
+        &checksum,
+        &release.signature_url(),
+        options,
+    )?;
+    let mut metadata = DatabaseMetadata::new(&version, host);
+    metadata.url = Some(release.url.to_owned());
+    metadata.md5 = release.md5.to_owned();
+    metadata.sha256 = release.sha256.to_owned();
+    metadata.blake3 = release.blake3.to_owned();
+    metadata.min_nohuman_version = release.min_nohuman_version.to_owned();
+    metadata.min_kraken2_version = release.min_kraken2_version.to_owned();
+    metadata.files = if release.files.is_empty() {
+        info!("Recording checksums of the database files...");
+        index_file_checksums(path)?
+    } else {
+        release.files.clone()
+    };
+    metadata.write(path)?;
     Ok(())
 }
 
-fn download_config() -> Result<Config, DownloadError> {
+/// Where the config listing the database releases is read from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum ManifestSource<'a> {
+    Url(&'a str),
+    File(&'a Path),
+}
+
+impl<'a> ManifestSource<'a> {
+    fn new(manifest: Option<&'a str>) -> Self {
+        match manifest {
+            None => ManifestSource::Url(CONFIG_URL),
+            Some(s) if s.starts_with("http://") || s.starts_with("https://") || is_cloud_url(s) => {
+                ManifestSource::Url(s)
+            }
+            Some(s) => ManifestSource::File(Path::new(s.strip_prefix("file://").unwrap_or(s))),
+        }
+    }
+
+    /// The path to cache the config at in `dir`. Local files aren't cached.
+    fn cache_path(&self, dir: &Path) -> Option<PathBuf> {
+        match self {
+            ManifestSource::Url(CONFIG_URL) => Some(dir.join(MANIFEST_CACHE_FILE)),
+            ManifestSource::Url(url) => {
+                Some(dir.join(format!("manifest-{:x}.toml", md5::compute(url))))
+            }
+            ManifestSource::File(_) => None,
+        }
+    }
+}
+
+/// Get the config listing the database releases from `options.manifest`, or the nohuman
+/// repository by default. A copy of a downloaded config is cached in
+/// `options.manifest_cache_dir` (if set), which is used instead of downloading it again for
+/// [`MANIFEST_CACHE_TTL`], or if it can't be downloaded. Setting `options.refresh_manifest`
+/// always downloads the config.
+fn download_config(options: &DownloadOptions) -> Result<Config, DownloadError> {
+    let source = ManifestSource::new(options.manifest.as_deref());
+    let policy = options.signature_policy.as_ref();
+    let url = match source {
+        ManifestSource::Url(url) => url,
+        ManifestSource::File(path) => {
+            let content = fs::read_to_string(path)
+                .map_err(|_| DownloadError::ConfigReadFailed(path.to_path_buf()))?;
+            if let Some(policy) = policy {
+                let signature = read_signature(path)?;
+                policy.verify_bytes(content.as_bytes(), signature.as_deref(), "the config")?;
+            }
+            return toml::from_str(&content).map_err(|_| DownloadError::ConfigParseFailed);
+        }
+    };
+    let cache = options
+        .manifest_cache_dir
+        .as_deref()
+        .and_then(|dir| source.cache_path(dir));
+    let cache = cache.as_deref();
+    let read_cache = |path| read_cached_config(path, policy);
+    if !options.refresh_manifest {
+        if let Some((config, age)) = cache.and_then(read_cache) {
+            if age < MANIFEST_CACHE_TTL {
+                debug!("Using config cached {} ago", format_age(age));
+                return Ok(config);
+            }
+        }
+    }
+
+    match fetch_config(url, policy) {
+        Ok((config, content, signature)) => {
+            if let Some(path) = cache {
+                if let Err(e) = write_cached_config(path, &content, signature.as_deref()) {
+                    warn!("Failed to cache the config at {:?}: {}", path, e);
+                }
+            }
+            Ok(config)
+        }
+        Err(e @ DownloadError::InvalidSignature(_)) => Err(e),
+        Err(e) if !options.refresh_manifest => match cache.and_then(read_cache) {
+            Some((config, age)) => {
+                warn!(
+                    "{}. Using the copy cached {} ago instead",
+                    e,
+                    format_age(age)
+                );
+                Ok(config)
+            }
+            None => Err(e),
+        },
+        Err(e) => Err(e),
+    }
+}
+
+/// Download and parse the config at `url`, checking its signature if there is a signature
+/// `policy`. The raw content and signature are also returned, for caching.
+fn fetch_config(
+    url: &str,
+    policy: Option<&SignaturePolicy>,
+) -> Result<(Config, String, Option<String>), DownloadError> {
     // Download the config file
-    let mut response = get(CONFIG_URL).map_err(|_| DownloadError::ConfigDownloadFailed)?;
-    let mut config_content = String::new();
-    response
-        .read_to_string(&mut config_content)
-        .map_err(|_| DownloadError::ConfigDownloadFailed)?;
+    let config_content = fetch_text(url)
+        .ok()
+        .flatten()
+        .ok_or(DownloadError::ConfigDownloadFailed)?;
+
+    let signature = match policy {
+        Some(policy) => {
+            let signature = fetch_text(&signature_url(url))?;
+            policy.verify_bytes(
+                config_content.as_bytes(),
+                signature.as_deref(),
+                "the config",
+            )?;
+            signature
+        }
+        None => None,
+    };
 
     // Parse the TOML content into a config struct
     let config: Config =
         toml::from_str(&config_content).map_err(|_| DownloadError::ConfigParseFailed)?;
 
-    Ok(config)
+    Ok((config, config_content, signature))
+}
+
+/// Download the text file at `url`. Returns `None` if it doesn't exist.
+fn fetch_text(url: &str) -> Result<Option<String>, DownloadError> {
+    let request = object_request(url)?;
+    let response = request
+        .get_blocking(&reqwest::blocking::Client::new())
+        .send()?;
+    if response.status() == reqwest::StatusCode::NOT_FOUND {
+        return Ok(None);
+    }
+    Ok(Some(response.error_for_status()?.text()?))
+}
+
+/// Read the signature file for the local file `path`, if there is one.
+fn read_signature(path: &Path) -> Result<Option<String>, DownloadError> {
+    let path = PathBuf::from(signature_url(&path.to_string_lossy()));
+    match fs::read_to_string(path) {
+        Ok(signature) => Ok(Some(signature)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Read the cached config at `path`, along with how long ago it was cached. Returns `None` if
+/// there is no (valid) cached config, or its signature is not valid under `policy`.
+fn read_cached_config(path: &Path, policy: Option<&SignaturePolicy>) -> Option<(Config, Duration)> {
+    let content = fs::read_to_string(path).ok()?;
+    if let Some(policy) = policy {
+        let signature = read_signature(path).ok()?;
+        policy
+            .verify_bytes(
+                content.as_bytes(),
+                signature.as_deref(),
+                "the cached config",
+            )
+            .map_err(|e| warn!("Ignoring cached config {:?}: {}", path, e))
+            .ok()?;
+    }
+    let config = toml::from_str(&content)
+        .map_err(|e| warn!("Ignoring invalid cached config {:?}: {}", path, e))
+        .ok()?;
+    let age = fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|modified| modified.elapsed().ok())
+        .unwrap_or_default();
+    Some((config, age))
+}
+
+/// Cache the config `content`, and its `signature` (if it has one), at `path`.
+fn write_cached_config(path: &Path, content: &str, signature: Option<&str>) -> std::io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, content)?;
+    let signature_path = PathBuf::from(signature_url(&path.to_string_lossy()));
+    match signature {
+        Some(signature) => fs::write(signature_path, signature),
+        None if signature_path.exists() => fs::remove_file(signature_path),
+        None => Ok(()),
+    }
+}
+
+/// Format an age to the nearest second.
+fn format_age(age: Duration) -> humantime::FormattedDuration {
+    humantime::format_duration(Duration::from_secs(age.as_secs()))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::path::PathBuf;
     use tempfile::TempDir;
 
     pub fn check_internet_connection(timeout: std::time::Duration) -> bool {
@@ -164,12 +1071,36 @@ mod tests {
 
         // Download and extract a sample tarball
         let url = "https://github.com/mbhall88/rasusa/releases/download/0.7.1/rasusa-0.7.1-x86_64-unknown-linux-gnu.tar.gz";
-        let md5 = "6c60c417646084eac81fc23a85e9fbc2";
-        let result = download_and_extract_tarball(url, &output_path, md5);
+        let md5 = Checksum::Md5("6c60c417646084eac81fc23a85e9fbc2".to_string());
+        let (sender, receiver) = async_std::channel::unbounded();
+        let options = DownloadOptions {
+            progress: Some(ProgressCallback::channel(sender)),
+            ..Default::default()
+        };
+        let result =
+            download_and_extract_tarball(&[url], &output_path, &md5, &signature_url(url), &options);
 
         // Assert that the function executed successfully
         assert!(result.is_ok());
 
+        // the progress adds up to the size of the tarball
+        let events: Vec<_> = std::iter::from_fn(|| receiver.try_recv().ok()).collect();
+        let Some(ProgressEvent::DownloadStarted {
+            total: Some(total), ..
+        }) = events.first()
+        else {
+            panic!("The download didn't start with its size: {:?}", events);
+        };
+        let downloaded: u64 = events
+            .iter()
+            .map(|event| match event {
+                ProgressEvent::Downloaded { bytes } => *bytes,
+                _ => 0,
+            })
+            .sum();
+        assert_eq!(downloaded, *total);
+        assert_eq!(events.last(), Some(&ProgressEvent::Extracting));
+
         // Assert that the extracted files exist
         let output_path = output_path.join("rasusa-0.7.1-x86_64-unknown-linux-gnu");
         assert!(output_path.exists());
@@ -194,14 +1125,20 @@ mod tests {
 
         // Download and extract a sample tarball
         let url = "https://github.com/mbhall88/rasusa/releases/download/0.7.1/rasusa-0.7.1-x86_64-unknown-linux-gnu.tar.gz";
-        let md5 = "foo";
-        let result = download_and_extract_tarball(url, &output_path, md5);
+        let md5 = Checksum::Md5("foo".to_string());
+        let result = download_and_extract_tarball(
+            &[url],
+            &output_path,
+            &md5,
+            &signature_url(url),
+            &DownloadOptions::default(),
+        );
 
         // Assert that the function executed successfully
         assert!(result.is_err());
         assert_eq!(
             result.unwrap_err().to_string(),
-            DownloadError::Md5Mismatch.to_string()
+            DownloadError::ChecksumMismatch("MD5").to_string()
         );
 
         // Clean up the temporary directory
@@ -220,8 +1157,14 @@ mod tests {
 
         // Download and extract a non-existent tarball
         let url = "https://example.com/nonexistent.tar.gz";
-        let md5 = "foo";
-        let result = download_and_extract_tarball(url, &output_path, md5);
+        let md5 = Checksum::Md5("foo".to_string());
+        let result = download_and_extract_tarball(
+            &[url],
+            &output_path,
+            &md5,
+            &signature_url(url),
+            &DownloadOptions::default(),
+        );
 
         // Assert that the function returns a DownloadFailed error
         assert!(result.is_err());
@@ -246,8 +1189,14 @@ mod tests {
 
         // Download and extract a tarball with invalid format
         let url = "https://raw.githubusercontent.com/mbhall88/rasusa/fa7e87b843419151cc4716c670adbb28544979b1/Cargo.toml";
-        let md5 = "95143b02c21cc9ce1980645d2db69937";
-        let result = download_and_extract_tarball(url, &output_path, md5);
+        let md5 = Checksum::Md5("95143b02c21cc9ce1980645d2db69937".to_string());
+        let result = download_and_extract_tarball(
+            &[url],
+            &output_path,
+            &md5,
+            &signature_url(url),
+            &DownloadOptions::default(),
+        );
 
         // Assert that the function returns an ExtractionFailed error
         assert!(result.is_err());
@@ -272,4 +1221,221 @@ mod tests {
         let expected = "31cf5fcf677d471a05001d8891332ae1".to_string();
         assert_eq!(actual, expected);
     }
+
+    #[test]
+    fn test_parse_checksum() {
+        let checksum: Checksum = "SHA256:ABC".parse().unwrap();
+        assert_eq!(checksum, Checksum::Sha256("ABC".to_string()));
+        assert_eq!(checksum.to_string(), "sha256:abc");
+        assert!("abc".parse::<Checksum>().is_err());
+        assert!("crc32:abc".parse::<Checksum>().is_err());
+    }
+
+    #[test]
+    fn test_checksum_verify() {
+        let tmp = tempfile::NamedTempFile::new().unwrap();
+        fs::write(tmp.path(), "abc").unwrap();
+
+        let sha256 = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+        assert_eq!(compute_sha256(tmp.path()).unwrap(), sha256);
+        assert!(Checksum::Sha256(sha256.to_uppercase())
+            .verify(tmp.path())
+            .is_ok());
+
+        let blake3 = "6437b3ac38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85";
+        assert_eq!(compute_blake3(tmp.path()).unwrap(), blake3);
+        assert!(Checksum::Blake3(blake3.to_string())
+            .verify(tmp.path())
+            .is_ok());
+
+        let result = Checksum::Blake3(sha256.to_string()).verify(tmp.path());
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            DownloadError::ChecksumMismatch("BLAKE3").to_string()
+        );
+    }
+
+    #[test]
+    fn test_download_backoff() {
+        assert_eq!(DownloadOptions::backoff(1), Duration::from_secs(1));
+        assert_eq!(DownloadOptions::backoff(3), Duration::from_secs(4));
+        assert_eq!(DownloadOptions::backoff(100), MAX_BACKOFF);
+    }
+
+    #[test]
+    fn test_download_config_uses_cache() {
+        let tmp = TempDir::new().unwrap();
+        let path = tmp.path().join("db").join(MANIFEST_CACHE_FILE);
+        let content = "database_url = \"https://example.com/db.tar.gz\"\ndatabase_md5 = \"abc\"\n";
+        write_cached_config(&path, content, None).unwrap();
+
+        let (config, age) = read_cached_config(&path, None).unwrap();
+        assert_eq!(config.release.url, "https://example.com/db.tar.gz");
+        assert!(age < MANIFEST_CACHE_TTL);
+
+        // a recently cached config is used without downloading it
+        let options = DownloadOptions {
+            manifest_cache_dir: Some(tmp.path().join("db")),
+            ..Default::default()
+        };
+        let config = download_config(&options).unwrap();
+        assert_eq!(config.release.md5.as_deref(), Some("abc"));
+
+        fs::write(&path, "not a config").unwrap();
+        assert!(read_cached_config(&path, None).is_none());
+    }
+
+    #[test]
+    fn test_download_config_from_file() {
+        let tmp = TempDir::new().unwrap();
+        let path = tmp.path().join("config.toml");
+        fs::write(
+            &path,
+            "database_url = \"https://internal.org/db.tar.gz\"\ndatabase_sha256 = \"abc\"\n",
+        )
+        .unwrap();
+
+        let options = DownloadOptions {
+            manifest: Some(format!("file://{}", path.display())),
+            manifest_cache_dir: Some(tmp.path().join("db")),
+            ..Default::default()
+        };
+        let config = download_config(&options).unwrap();
+        assert_eq!(config.release.url, "https://internal.org/db.tar.gz");
+        // local configs are not cached
+        assert!(!tmp.path().join("db").exists());
+
+        let options = DownloadOptions {
+            manifest: Some(tmp.path().join("missing.toml").display().to_string()),
+            ..Default::default()
+        };
+        assert!(matches!(
+            download_config(&options),
+            Err(DownloadError::ConfigReadFailed(_))
+        ));
+    }
+
+    #[test]
+    fn test_manifest_source() {
+        let dir = Path::new("db");
+        let default = ManifestSource::new(None);
+        assert_eq!(default, ManifestSource::Url(CONFIG_URL));
+        assert_eq!(default.cache_path(dir), Some(dir.join(MANIFEST_CACHE_FILE)));
+
+        let custom = ManifestSource::new(Some("https://internal.org/config.toml"));
+        let cache_path = custom.cache_path(dir).unwrap();
+        assert_ne!(cache_path, dir.join(MANIFEST_CACHE_FILE));
+        assert!(cache_path.starts_with(dir));
+
+        let s3 = ManifestSource::new(Some("s3://bucket/config.toml"));
+        assert_eq!(s3, ManifestSource::Url("s3://bucket/config.toml"));
+
+        let file = ManifestSource::new(Some("config.toml"));
+        assert_eq!(file, ManifestSource::File(Path::new("config.toml")));
+        assert_eq!(file.cache_path(dir), None);
+    }
+
+    /// Write a gzipped tarball of a (fake) kraken2 database to `path`.
+    fn write_db_tarball(path: &Path) {
+        let tar = flate2::write::GzEncoder::new(File::create(path).unwrap(), Default::default());
+        let mut builder = tar::Builder::new(tar);
+        for name in ["hash.k2d", "opts.k2d", "taxo.k2d"] {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(4);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder
+                .append_data(&mut header, name, &b"data"[..])
+                .unwrap();
+        }
+        builder.into_inner().unwrap().finish().unwrap();
+    }
+
+    #[test]
+    fn test_install_tarball() {
+        let tmp = TempDir::new().unwrap();
+        let tarball = tmp.path().join("db.tar.gz");
+        write_db_tarball(&tarball);
+        let root = tmp.path().join("root");
+        let md5 = compute_md5(&tarball).unwrap();
+
+        let path = install_tarball(&tarball, &root, "v1", &Host::Human, None).unwrap();
+        assert_eq!(path, root.join("v1"));
+        assert!(path.join("hash.k2d").exists());
+        let metadata = DatabaseMetadata::read(&path).unwrap();
+        assert_eq!(metadata.version, "v1");
+        assert_eq!(metadata.md5, Some(md5.clone()));
+        assert_eq!(
+            metadata.files["hash.k2d"],
+            FileChecksum {
+                size: Some(4),
+                checksum: format!("blake3:{}", blake3::hash(b"data").to_hex()),
+            }
+        );
+
+        let result = install_tarball(&tarball, &root, "v1", &Host::Human, None);
+        assert!(matches!(result, Err(DownloadError::AlreadyInstalled(_))));
+
+        let checksum = Checksum::Md5("foo".to_string());
+        let result = install_tarball(&tarball, &root, "v2", &Host::Human, Some(&checksum));
+        assert!(matches!(result, Err(DownloadError::ChecksumMismatch(_))));
+        assert!(!root.join("v2").exists());
+
+        let checksum = Checksum::Md5(md5);
+        install_tarball(&tarball, &root, "v2", &Host::Human, Some(&checksum)).unwrap();
+
+        let result = install_tarball(&tarball, &root, "../v3", &Host::Human, None);
+        assert!(matches!(result, Err(DownloadError::InvalidVersion(_))));
+    }
+
+    #[test]
+    fn test_register_database() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path().join("db");
+        let external = tmp.path().join("farm");
+        fs::create_dir(&external).unwrap();
+        for name in crate::database::INDEX_FILES {
+            fs::write(external.join(name), name).unwrap();
+        }
+
+        let path = register_database(&external, &root, "farm", &Host::Custom).unwrap();
+        assert_eq!(path, root.join("farm"));
+        let db = &crate::database::installed_databases(&root).unwrap()[0];
+        assert_eq!(db.location(), external.canonicalize().unwrap());
+        assert_eq!(
+            crate::database::resolve_database(&root).unwrap(),
+            db.location()
+        );
+
+        assert!(matches!(
+            register_database(&external, &root, "farm", &Host::Custom),
+            Err(DownloadError::AlreadyInstalled(_))
+        ));
+        assert!(matches!(
+            register_database(&tmp.path().join("missing"), &root, "other", &Host::Custom),
+            Err(DownloadError::InvalidDatabase(_))
+        ));
+
+        // unregistering leaves the database files alone
+        db.remove().unwrap();
+        assert!(!path.exists());
+        assert!(external.join("hash.k2d").exists());
+    }
+
+    #[test]
+    fn test_install_tarball_invalid_database() {
+        let tmp = TempDir::new().unwrap();
+        let tarball = tmp.path().join("db.tar.gz");
+        let tar =
+            flate2::write::GzEncoder::new(File::create(&tarball).unwrap(), Default::default());
+        tar::Builder::new(tar)
+            .into_inner()
+            .unwrap()
+            .finish()
+            .unwrap();
+
+        let result = install_tarball(&tarball, tmp.path(), "v1", &Host::Human, None);
+        assert!(matches!(result, Err(DownloadError::InvalidDatabase(_))));
+        assert!(!tmp.path().join("v1").exists());
+    }
 }
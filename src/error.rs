@@ -0,0 +1,203 @@
+use crate::download::DownloadError;
+use std::path::PathBuf;
+use thiserror::Error;
+
+/// The errors that can occur in the nohuman library.
+#[derive(Error, Debug)]
+pub enum NohumanError {
+    #[error("{0:?} does not exist")]
+    PathNotFound(PathBuf),
+
+    #[error("Required files ({files}) not found in {path:?} or its 'db' subdirectory")]
+    MissingDb { path: PathBuf, files: String },
+
+    #[error("Database {version} is not installed in {root:?}")]
+    DbNotInstalled { version: String, root: PathBuf },
+
+    /// A database is missing, or failed verification.
+    #[error("{0}")]
+    InvalidDb(String),
+
+    #[error("Confidence score must be a number in the closed interval [0, 1], got {0}")]
+    InvalidConfidence(String),
+
+    #[error("Invalid host: {0}. Must be one of human or custom")]
+    InvalidHost(String),
+
+    #[error("Invalid container engine: {0}. Must be one of docker or apptainer")]
+    InvalidContainerEngine(String),
+
+    #[error("Invalid aligner: {0}. Must be one of kraken2, minimap2, native, or mock")]
+    InvalidAligner(String),
+
+    #[error("Minimum identity must be a number in the closed interval [0, 1], got {0}")]
+    InvalidIdentity(String),
+
+    #[error("Fraction must be a number in the closed interval [0, 1], got {0}")]
+    InvalidFraction(String),
+
+    #[error("Number of threads must be a positive integer, 0, or \"auto\", got {0}")]
+    InvalidThreads(String),
+
+    #[error("Invalid output template {0:?}. Tokens must be one of {{stem}}, {{read}}, {{ext}}, {{comp}}, or {{suffix}}")]
+    InvalidOutputTemplate(String),
+
+    #[error("Unable to parse kraken2 arguments: {0}")]
+    InvalidKraken2Args(String),
+
+    #[error("{0} is set by nohuman and cannot be passed as an extra kraken2 argument")]
+    ManagedKraken2Arg(String),
+
+    #[error("The following dependencies are missing: {}", .0.join(", "))]
+    MissingDependency(Vec<String>),
+
+    #[error(
+        "{command} failed with stderr {stderr}{}",
+        .advice.map(|advice| format!("\n{}", advice)).unwrap_or_default()
+    )]
+    KrakenFailed {
+        command: String,
+        stderr: String,
+        /// What the user can do about the failure, if it was recognised.
+        advice: Option<&'static str>,
+    },
+
+    #[error(
+        "{command} was stopped as it didn't finish within {}",
+        humantime::format_duration(*.timeout)
+    )]
+    KrakenTimedOut {
+        command: String,
+        timeout: std::time::Duration,
+    },
+
+    #[error(
+        "The database requires {program} {required} or later, but {program} {found} is installed"
+    )]
+    IncompatibleVersion {
+        program: String,
+        required: String,
+        found: String,
+    },
+
+    #[error("Failed to run kraken2 with the nohuman server at {socket:?}: {message}")]
+    ServerFailed { socket: PathBuf, message: String },
+
+    /// Checks run by --check failed. `kind` is the class of the first failure.
+    #[error("{} failed: {}", if .checks.len() == 1 { "A check" } else { "Checks" }, .checks.join(", "))]
+    ChecksFailed {
+        checks: Vec<String>,
+        kind: FailureKind,
+    },
+
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+/// The classes of failure that nohuman exits with their own code, so scripts and schedulers can
+/// react to them. Any other failure exits with 1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailureKind {
+    /// A required program (e.g. kraken2) is missing, or too old for the database.
+    MissingDependency,
+    /// The database is missing or invalid, or couldn't be downloaded.
+    Database,
+    /// kraken2 failed or timed out.
+    Kraken2,
+    /// Reading the inputs or writing the outputs failed.
+    Io,
+    /// The fraction of host sequences is outside --min-human-frac and --max-human-frac.
+    HostFraction,
+}
+
+impl FailureKind {
+    /// The exit code for the failure.
+    pub fn exit_code(self) -> u8 {
+        match self {
+            Self::MissingDependency => 2,
+            Self::Database => 3,
+            Self::Kraken2 => 4,
+            Self::Io => 5,
+            Self::HostFraction => 6,
+        }
+    }
+
+    /// The class of `error`, from the first error in its chain of causes that has one.
+    pub fn of(error: &anyhow::Error) -> Option<Self> {
+        error.chain().find_map(Self::of_cause)
+    }
+
+    fn of_cause(cause: &(dyn std::error::Error + 'static)) -> Option<Self> {
+        if let Some(error) = cause.downcast_ref::<NohumanError>() {
+            return Self::of_nohuman_error(error);
+        }
+        match cause.downcast_ref::<DownloadError>() {
+            Some(DownloadError::InvalidDatabase(error)) => Self::of_nohuman_error(error),
+            Some(DownloadError::IoError(_)) => Some(Self::Io),
+            Some(_) => Some(Self::Database),
+            None => cause.is::<std::io::Error>().then_some(Self::Io),
+        }
+    }
+
+    fn of_nohuman_error(error: &NohumanError) -> Option<Self> {
+        match error {
+            NohumanError::MissingDependency(_) | NohumanError::IncompatibleVersion { .. } => {
+                Some(Self::MissingDependency)
+            }
+            NohumanError::MissingDb { .. }
+            | NohumanError::DbNotInstalled { .. }
+            | NohumanError::InvalidDb(_) => Some(Self::Database),
+            NohumanError::KrakenFailed { .. }
+            | NohumanError::KrakenTimedOut { .. }
+            | NohumanError::ServerFailed { .. } => Some(Self::Kraken2),
+            NohumanError::PathNotFound(_) | NohumanError::Io(_) => Some(Self::Io),
+            NohumanError::ChecksFailed { kind, .. } => Some(*kind),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Context;
+
+    #[test]
+    fn test_failure_kind() {
+        let kind = |error: anyhow::Error| FailureKind::of(&error);
+        assert_eq!(
+            kind(NohumanError::MissingDependency(vec!["kraken2".to_string()]).into()),
+            Some(FailureKind::MissingDependency)
+        );
+        let io = || std::io::Error::new(std::io::ErrorKind::NotFound, "missing");
+        assert_eq!(
+            kind(
+                Err::<(), _>(io())
+                    .context("Failed to write output")
+                    .unwrap_err()
+            ),
+            Some(FailureKind::Io)
+        );
+        let failed = NohumanError::KrakenFailed {
+            command: "kraken2".to_string(),
+            stderr: String::new(),
+            advice: None,
+        };
+        assert_eq!(
+            kind(anyhow::Error::new(failed).context("Failed to run kraken2")),
+            Some(FailureKind::Kraken2)
+        );
+        assert_eq!(
+            kind(DownloadError::InvalidDatabase(NohumanError::InvalidDb("x".to_string())).into()),
+            Some(FailureKind::Database)
+        );
+        let checks = NohumanError::ChecksFailed {
+            checks: vec!["hash.k2d".to_string()],
+            kind: FailureKind::Database,
+        };
+        assert_eq!(checks.to_string(), "A check failed: hash.k2d");
+        assert_eq!(kind(checks.into()), Some(FailureKind::Database));
+        assert_eq!(kind(anyhow::anyhow!("Invalid input")), None);
+        assert_eq!(FailureKind::HostFraction.exit_code(), 6);
+    }
+}
@@ -0,0 +1,231 @@
+//! Evaluating host read removal against reads whose origin is known, e.g. a simulated dataset
+//! (`nohuman eval`).
+use crate::fastx::read_id;
+use anyhow::{bail, Context, Result};
+use std::collections::HashSet;
+use std::io::{BufRead, Write};
+
+/// The labels that mark a read as a host read in a labelled truth set.
+const HOST_LABELS: [&str; 5] = ["host", "human", "1", "true", "yes"];
+
+/// The reads known to be host reads.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Truth {
+    /// The IDs of the host reads.
+    pub host: HashSet<Vec<u8>>,
+    /// The number of reads labelled, if the truth set labels every read (rather than only listing
+    /// the host reads).
+    pub labelled: Option<u64>,
+}
+
+impl Truth {
+    /// Read a truth set. Each line is either the ID of a host read, or a read ID and a label,
+    /// separated by a tab, with "host", "human", "1", "true", or "yes" (in any case) labelling a
+    /// host read. As in [`crate::fastx::read_ids_from`], leading `@`/`>` characters and anything
+    /// after the first whitespace in the ID are ignored, so a list of headers can be used.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use nohuman::eval::Truth;
+    ///
+    /// let truth = Truth::from_reader("r1\thuman\nr2\tbacteria\n@r3 comment\thost\n".as_bytes()).unwrap();
+    /// assert_eq!(truth.host.len(), 2);
+    /// assert_eq!(truth.labelled, Some(3));
+    /// ```
+    pub fn from_reader<R: BufRead>(reader: R) -> Result<Self> {
+        let mut truth = Self::default();
+        let (mut labelled, mut unlabelled) = (0, 0);
+        for (i, line) in reader.split(b'\n').enumerate() {
+            let line = line.context("Failed to read the truth set")?;
+            let line = line.strip_prefix(b"@").unwrap_or(&line);
+            let line = line.strip_prefix(b">").unwrap_or(line);
+            let (id, label) = match line.iter().position(|&b| b == b'\t') {
+                Some(tab) => (
+                    &line[..tab],
+                    Some(String::from_utf8_lossy(&line[tab + 1..])),
+                ),
+                None => (line, None),
+            };
+            let id = read_id(id);
+            if id.is_empty() {
+                continue;
+            }
+            match label.as_deref().map(str::trim) {
+                Some(label) => {
+                    labelled += 1;
+                    if HOST_LABELS
+                        .iter()
+                        .any(|host| host.eq_ignore_ascii_case(label))
+                    {
+                        truth.host.insert(id.to_vec());
+                    }
+                }
+                None => {
+                    unlabelled += 1;
+                    truth.host.insert(id.to_vec());
+                }
+            }
+            if labelled > 0 && unlabelled > 0 {
+                bail!(
+                    "Line {} of the truth set mixes labelled and unlabelled reads. Either list the host reads, or label every read",
+                    i + 1
+                );
+            }
+        }
+        truth.labelled = (labelled > 0).then_some(labelled);
+        Ok(truth)
+    }
+}
+
+/// How well the reads classified as host match the truth.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Evaluation {
+    pub true_positives: u64,
+    pub false_positives: u64,
+    pub false_negatives: u64,
+    /// The non-host reads classified as non-host. Only known if the number of reads is.
+    pub true_negatives: Option<u64>,
+}
+
+impl Evaluation {
+    /// Compare the reads `predicted` to be host reads with the `truth`. `total` is the number of
+    /// reads, if known; it isn't needed if the truth set labels every read.
+    pub fn new(truth: &Truth, predicted: &HashSet<Vec<u8>>, total: Option<u64>) -> Self {
+        let true_positives = predicted.intersection(&truth.host).count() as u64;
+        let false_positives = predicted.len() as u64 - true_positives;
+        let false_negatives = truth.host.len() as u64 - true_positives;
+        let true_negatives = truth
+            .labelled
+            .or(total)
+            .map(|total| total.saturating_sub(true_positives + false_positives + false_negatives));
+        Self {
+            true_positives,
+            false_positives,
+            false_negatives,
+            true_negatives,
+        }
+    }
+
+    /// The fraction of the reads classified as host that are host reads.
+    pub fn precision(&self) -> f64 {
+        ratio(
+            self.true_positives,
+            self.true_positives + self.false_positives,
+        )
+    }
+
+    /// The fraction of the host reads that were classified as host (i.e. the sensitivity).
+    pub fn recall(&self) -> f64 {
+        ratio(
+            self.true_positives,
+            self.true_positives + self.false_negatives,
+        )
+    }
+
+    /// The harmonic mean of precision and recall.
+    pub fn f1(&self) -> f64 {
+        ratio(
+            2 * self.true_positives,
+            2 * self.true_positives + self.false_positives + self.false_negatives,
+        )
+    }
+
+    /// The fraction of the non-host reads that were classified as non-host, if it is known.
+    pub fn specificity(&self) -> Option<f64> {
+        self.true_negatives
+            .map(|tn| ratio(tn, tn + self.false_positives))
+    }
+}
+
+/// `numerator / denominator`, or 1 if there is nothing to get wrong (i.e. both are 0).
+fn ratio(numerator: u64, denominator: u64) -> f64 {
+    if denominator == 0 {
+        1.0
+    } else {
+        numerator as f64 / denominator as f64
+    }
+}
+
+/// Write the `evaluations` of each named run as a tab-separated table. Unknown values are written
+/// as "NA".
+pub fn write_tsv<W: Write>(mut writer: W, evaluations: &[(String, Evaluation)]) -> Result<()> {
+    writeln!(
+        writer,
+        "name\ttrue_positives\tfalse_positives\tfalse_negatives\ttrue_negatives\tprecision\trecall\tf1\tspecificity"
+    )?;
+    let na = || "NA".to_string();
+    for (name, evaluation) in evaluations {
+        writeln!(
+            writer,
+            "{}\t{}\t{}\t{}\t{}\t{:.4}\t{:.4}\t{:.4}\t{}",
+            name,
+            evaluation.true_positives,
+            evaluation.false_positives,
+            evaluation.false_negatives,
+            evaluation
+                .true_negatives
+                .map_or_else(na, |tn| tn.to_string()),
+            evaluation.precision(),
+            evaluation.recall(),
+            evaluation.f1(),
+            evaluation
+                .specificity()
+                .map_or_else(na, |specificity| format!("{:.4}", specificity))
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ids(ids: &[&str]) -> HashSet<Vec<u8>> {
+        ids.iter().map(|id| id.as_bytes().to_vec()).collect()
+    }
+
+    #[test]
+    fn test_truth_from_reader() {
+        let truth = Truth::from_reader("r1/1\n>r2 comment\n\n".as_bytes()).unwrap();
+        assert_eq!(truth.host, ids(&["r1", "r2"]));
+        assert_eq!(truth.labelled, None);
+
+        let truth = Truth::from_reader("r1/1\tHUMAN \nr2\t0\nr3\tmicrobe\n".as_bytes()).unwrap();
+        assert_eq!(truth.host, ids(&["r1"]));
+        assert_eq!(truth.labelled, Some(3));
+
+        assert!(Truth::from_reader("r1\thost\nr2\n".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_evaluation() {
+        let truth = Truth {
+            host: ids(&["r1", "r2", "r3", "r4"]),
+            labelled: None,
+        };
+        let predicted = ids(&["r1", "r2", "r3", "r5"]);
+        let evaluation = Evaluation::new(&truth, &predicted, Some(10));
+        assert_eq!(evaluation.true_positives, 3);
+        assert_eq!(evaluation.false_positives, 1);
+        assert_eq!(evaluation.false_negatives, 1);
+        assert_eq!(evaluation.true_negatives, Some(5));
+        assert_eq!(evaluation.precision(), 0.75);
+        assert_eq!(evaluation.recall(), 0.75);
+        assert_eq!(evaluation.f1(), 0.75);
+        assert_eq!(evaluation.specificity(), Some(5.0 / 6.0));
+
+        let evaluation = Evaluation::new(&truth, &ids(&[]), None);
+        assert_eq!(evaluation.precision(), 1.0);
+        assert_eq!(evaluation.recall(), 0.0);
+        assert_eq!(evaluation.specificity(), None);
+
+        let mut out = Vec::new();
+        write_tsv(&mut out, &[("v1".to_string(), evaluation)]).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert_eq!(
+            out.lines().nth(1),
+            Some("v1\t0\t0\t4\tNA\t1.0000\t0.0000\t0.0000\tNA")
+        );
+    }
+}
@@ -0,0 +1,634 @@
+use anyhow::{bail, Context, Result};
+use needletail::errors::ParseErrorKind;
+use needletail::{parse_fastx_reader, FastxReader};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::io::{BufRead, Read, Write};
+
+/// Create a FASTA/Q parser for `reader`, or `None` if there is nothing to parse.
+pub fn fastx_reader<'a, R: Read + Send + 'a>(
+    reader: R,
+) -> Result<Option<Box<dyn FastxReader + 'a>>> {
+    match parse_fastx_reader(reader) {
+        Ok(r) => Ok(Some(r)),
+        Err(e) if e.kind == ParseErrorKind::EmptyFile => Ok(None),
+        Err(e) => Err(e).context("Failed to parse reads"),
+    }
+}
+
+/// The number of records [`validate_reads`] parses by default.
+pub const VALIDATION_RECORDS: usize = 10_000;
+
+/// Check that `reader` holds valid FASTA/FASTQ by parsing its first `n_records` records, then
+/// reading the rest of it. Reading all of a compressed input finds files that are truncated or
+/// corrupt. Returns the number of records parsed.
+pub fn validate_reads<R: Read + Send>(mut reader: R, n_records: usize) -> Result<usize> {
+    let mut parsed = 0;
+    if let Some(mut fastx_reader) = fastx_reader(&mut reader)? {
+        while parsed < n_records {
+            let Some(record) = fastx_reader.next() else {
+                break;
+            };
+            record.with_context(|| format!("Failed to parse record {}", parsed + 1))?;
+            parsed += 1;
+        }
+    }
+    std::io::copy(&mut reader, &mut std::io::sink()).context("Failed to read to the end")?;
+    Ok(parsed)
+}
+
+/// The number of read IDs [`check_pairs`] compares by default.
+pub const PAIR_CHECK_IDS: u64 = 10_000;
+
+/// How two files of paired reads are out of sync.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PairMismatch {
+    /// The `n`th reads have different read IDs.
+    Ids { n: u64, id1: String, id2: String },
+    /// The files have a different number of reads.
+    Counts { n1: u64, n2: u64 },
+}
+
+impl fmt::Display for PairMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Ids { n, id1, id2 } => write!(
+                f,
+                "read {} is {} in one file but {} in the other",
+                n, id1, id2
+            ),
+            Self::Counts { n1, n2 } => write!(f, "the files have {} and {} reads", n1, n2),
+        }
+    }
+}
+
+/// Check that the paired reads in `reader1` and `reader2` are in sync - i.e., that they have the
+/// same number of reads, and that the first `n_ids` reads have the same read IDs (see
+/// [`read_id`]). Returns the first mismatch found, if any.
+pub fn check_pairs<R: Read + Send>(
+    reader1: R,
+    reader2: R,
+    n_ids: u64,
+) -> Result<Option<PairMismatch>> {
+    let mut reader1 = fastx_reader(reader1)?;
+    let mut reader2 = fastx_reader(reader2)?;
+    let (mut n1, mut n2) = (0, 0);
+
+    loop {
+        let check_id = n1 < n_ids;
+        match (
+            next_id(&mut reader1, check_id)?,
+            next_id(&mut reader2, check_id)?,
+        ) {
+            (Some(id1), Some(id2)) => {
+                n1 += 1;
+                n2 += 1;
+                if id1 != id2 {
+                    return Ok(Some(PairMismatch::Ids {
+                        n: n1,
+                        id1: String::from_utf8_lossy(&id1).into_owned(),
+                        id2: String::from_utf8_lossy(&id2).into_owned(),
+                    }));
+                }
+            }
+            (Some(_), None) => n1 += 1 + count_remaining(&mut reader1)?,
+            (None, Some(_)) => n2 += 1 + count_remaining(&mut reader2)?,
+            (None, None) => break,
+        }
+        if n1 != n2 {
+            return Ok(Some(PairMismatch::Counts { n1, n2 }));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Parse the next read from `reader`, returning its read ID if `check_id` is set (or an empty ID
+/// if not), or `None` at the end of the reads.
+fn next_id(
+    reader: &mut Option<Box<dyn FastxReader + '_>>,
+    check_id: bool,
+) -> Result<Option<Vec<u8>>> {
+    let Some(record) = reader.as_mut().and_then(|r| r.next()) else {
+        return Ok(None);
+    };
+    let record = record.context("Failed to parse read")?;
+    Ok(Some(if check_id {
+        read_id(record.id()).to_vec()
+    } else {
+        Vec::new()
+    }))
+}
+
+/// Count the reads in `reader`.
+pub fn count_reads<R: Read + Send>(reader: R) -> Result<u64> {
+    count_remaining(&mut fastx_reader(reader)?)
+}
+
+/// Count the remaining reads in `reader`.
+fn count_remaining(reader: &mut Option<Box<dyn FastxReader + '_>>) -> Result<u64> {
+    let mut n = 0;
+    if let Some(reader) = reader {
+        while let Some(record) = reader.next() {
+            record.context("Failed to parse read")?;
+            n += 1;
+        }
+    }
+    Ok(n)
+}
+
+/// Counts from re-pairing reads with [`repair_pairs`].
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct RepairStats {
+    /// The number of pairs written.
+    pub pairs: u64,
+    /// The number of reads in the first file without a mate.
+    pub orphans1: u64,
+    /// The number of reads in the second file without a mate.
+    pub orphans2: u64,
+}
+
+/// A read's header, sequence and quality.
+type OwnedRecord = (Vec<u8>, Vec<u8>, Option<Vec<u8>>);
+
+/// Re-pair two files of paired reads, writing the reads whose read ID (see [`read_id`]) is in
+/// both to `writer1` and `writer2`, in the order of the first file. Reads without a mate are
+/// written to `singletons`, or dropped if it isn't given. The files are read twice, so they are
+/// given as functions that open them.
+///
+/// Mates that come earlier in the second file than in the first are held in memory until they
+/// are written, so re-pairing files in a very different order can use a lot of memory.
+pub fn repair_pairs<R, W, F1, F2>(
+    open1: F1,
+    open2: F2,
+    writer1: &mut W,
+    writer2: &mut W,
+    mut singletons: Option<&mut W>,
+) -> Result<RepairStats>
+where
+    R: Read + Send,
+    W: Write,
+    F1: Fn() -> Result<R>,
+    F2: Fn() -> Result<R>,
+{
+    let mut stats = RepairStats::default();
+    let ids1 = read_ids_in_order(open1()?)?;
+    let ids2 = read_ids_in_order(open2()?)?;
+    let set1 = unique_ids(&ids1, "first")?;
+    let set2 = unique_ids(&ids2, "second")?;
+    let order: Vec<&[u8]> = ids1
+        .iter()
+        .map(Vec::as_slice)
+        .filter(|id| set2.contains(*id))
+        .collect();
+    stats.orphans1 = (ids1.len() - order.len()) as u64;
+    stats.orphans2 = (set2.len() - order.len()) as u64;
+
+    if let Some(mut reader) = fastx_reader(open1()?)? {
+        while let Some(record) = reader.next() {
+            let record = record.context("Failed to parse read")?;
+            if set2.contains(read_id(record.id())) {
+                write_record(writer1, record.id(), &record.seq(), record.qual())?;
+            } else if let Some(singletons) = singletons.as_mut() {
+                write_record(singletons, record.id(), &record.seq(), record.qual())?;
+            }
+        }
+    }
+
+    // mates that come before they are needed, by read ID
+    let mut pending: HashMap<Vec<u8>, OwnedRecord> = HashMap::new();
+    if let Some(mut reader) = fastx_reader(open2()?)? {
+        while let Some(record) = reader.next() {
+            let record = record.context("Failed to parse read")?;
+            let id = read_id(record.id());
+            if !set1.contains(id) {
+                if let Some(singletons) = singletons.as_mut() {
+                    write_record(singletons, record.id(), &record.seq(), record.qual())?;
+                }
+                continue;
+            }
+            if order.get(stats.pairs as usize) != Some(&id) {
+                pending.insert(
+                    id.to_vec(),
+                    (
+                        record.id().to_vec(),
+                        record.seq().to_vec(),
+                        record.qual().map(<[u8]>::to_vec),
+                    ),
+                );
+                continue;
+            }
+            write_record(writer2, record.id(), &record.seq(), record.qual())?;
+            stats.pairs += 1;
+            while let Some((header, seq, qual)) = order
+                .get(stats.pairs as usize)
+                .and_then(|id| pending.remove(*id))
+            {
+                write_record(writer2, &header, &seq, qual.as_deref())?;
+                stats.pairs += 1;
+            }
+        }
+    }
+
+    Ok(stats)
+}
+
+/// The set of `ids`, which must be unique. `file` names the file they are from for errors.
+fn unique_ids<'a>(ids: &'a [Vec<u8>], file: &str) -> Result<HashSet<&'a [u8]>> {
+    let mut unique = HashSet::with_capacity(ids.len());
+    for id in ids {
+        if !unique.insert(id.as_slice()) {
+            bail!(
+                "Read ID {} is in the {} file more than once",
+                String::from_utf8_lossy(id),
+                file
+            );
+        }
+    }
+    Ok(unique)
+}
+
+/// The read IDs (see [`read_id`]) in `reader`, in order.
+fn read_ids_in_order<R: Read + Send>(reader: R) -> Result<Vec<Vec<u8>>> {
+    let mut ids = Vec::new();
+    if let Some(mut reader) = fastx_reader(reader)? {
+        while let Some(record) = reader.next() {
+            let record = record.context("Failed to parse read")?;
+            ids.push(read_id(record.id()).to_vec());
+        }
+    }
+    Ok(ids)
+}
+
+/// Write a FASTA or FASTQ record (depending on whether `qual` is given).
+pub fn write_record<W: Write>(
+    writer: &mut W,
+    id: &[u8],
+    seq: &[u8],
+    qual: Option<&[u8]>,
+) -> Result<()> {
+    match qual {
+        Some(qual) => {
+            writer.write_all(b"@")?;
+            writer.write_all(id)?;
+            writer.write_all(b"\n")?;
+            writer.write_all(seq)?;
+            writer.write_all(b"\n+\n")?;
+            writer.write_all(qual)?;
+        }
+        None => {
+            writer.write_all(b">")?;
+            writer.write_all(id)?;
+            writer.write_all(b"\n")?;
+            writer.write_all(seq)?;
+        }
+    }
+    writer.write_all(b"\n")?;
+    Ok(())
+}
+
+/// Get the read ID from a FASTA/Q header - i.e., everything up to the first whitespace, with any
+/// `/1` or `/2` mate suffix removed. This matches the read IDs kraken2 reports.
+///
+/// # Examples
+///
+/// ```
+/// use nohuman::fastx::read_id;
+///
+/// assert_eq!(read_id(b"read1 length=100"), b"read1");
+/// assert_eq!(read_id(b"read1/2"), b"read1");
+/// ```
+pub fn read_id(header: &[u8]) -> &[u8] {
+    let end = header
+        .iter()
+        .position(|b| b.is_ascii_whitespace())
+        .unwrap_or(header.len());
+    let id = &header[..end];
+    match id {
+        [rest @ .., b'/', b'1' | b'2'] => rest,
+        _ => id,
+    }
+}
+
+/// Read a set of read IDs, one per line. Leading `@`/`>` characters and anything after the first
+/// whitespace are ignored, so a list of FASTA/Q headers can be used directly.
+pub fn read_ids_from<R: BufRead>(reader: R) -> Result<HashSet<Vec<u8>>> {
+    let mut ids = HashSet::new();
+    for line in reader.split(b'\n') {
+        let line = line.context("Failed to read read IDs")?;
+        let line = line.strip_prefix(b"@").unwrap_or(&line);
+        let line = line.strip_prefix(b">").unwrap_or(line);
+        let id = read_id(line);
+        if !id.is_empty() {
+            ids.insert(id.to_vec());
+        }
+    }
+    Ok(ids)
+}
+
+/// Counts from filtering reads by ID.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct FilterStats {
+    /// The number of reads read.
+    pub total: u64,
+    /// The number of reads written.
+    pub written: u64,
+}
+
+/// Copy the reads from `reader` to `writer`, filtering them by their read ID (see [`read_id`]).
+/// If `keep` is true, only reads whose ID is in `ids` are written, otherwise only reads whose ID
+/// is not in `ids` are written.
+pub fn filter_reads_by_id<R, W>(
+    reader: R,
+    writer: &mut W,
+    ids: &HashSet<Vec<u8>>,
+    keep: bool,
+) -> Result<FilterStats>
+where
+    R: Read + Send,
+    W: Write,
+{
+    let mut stats = FilterStats::default();
+    let Some(mut fastx_reader) = fastx_reader(reader)? else {
+        return Ok(stats);
+    };
+
+    while let Some(record) = fastx_reader.next() {
+        let record = record.context("Failed to parse read")?;
+        stats.total += 1;
+        if ids.contains(read_id(record.id())) == keep {
+            write_record(writer, record.id(), &record.seq(), record.qual())?;
+            stats.written += 1;
+        }
+    }
+
+    Ok(stats)
+}
+
+/// Split interleaved paired reads from `reader` into `writer1` and `writer2`. The mates of a pair
+/// must be consecutive and have the same read ID (see [`read_id`]). Returns the number of pairs.
+pub fn deinterleave<R, W>(reader: R, writer1: &mut W, writer2: &mut W) -> Result<u64>
+where
+    R: Read + Send,
+    W: Write,
+{
+    let Some(mut fastx_reader) = fastx_reader(reader)? else {
+        return Ok(0);
+    };
+    let mut n_pairs = 0;
+
+    while let Some(record) = fastx_reader.next() {
+        let record = record.context("Failed to parse read")?;
+        write_record(writer1, record.id(), &record.seq(), record.qual())?;
+        let id1 = read_id(record.id()).to_vec();
+
+        let Some(mate) = fastx_reader.next() else {
+            bail!(
+                "Interleaved input has an odd number of reads - {} has no mate",
+                String::from_utf8_lossy(&id1)
+            );
+        };
+        let mate = mate.context("Failed to parse read")?;
+        if read_id(mate.id()) != id1 {
+            bail!(
+                "Interleaved reads are not paired - expected a mate for {} but found {}",
+                String::from_utf8_lossy(&id1),
+                String::from_utf8_lossy(read_id(mate.id()))
+            );
+        }
+        write_record(writer2, mate.id(), &mate.seq(), mate.qual())?;
+        n_pairs += 1;
+    }
+
+    Ok(n_pairs)
+}
+
+/// Interleave the paired reads from `reader1` and `reader2` into `writer`. Returns the number of
+/// pairs.
+pub fn interleave<R, W>(reader1: R, reader2: R, writer: &mut W) -> Result<u64>
+where
+    R: Read + Send,
+    W: Write,
+{
+    let (mut reader1, mut reader2) = match (fastx_reader(reader1)?, fastx_reader(reader2)?) {
+        (Some(reader1), Some(reader2)) => (reader1, reader2),
+        (None, None) => return Ok(0),
+        _ => bail!("Paired read files have a different number of reads"),
+    };
+    let mut n_pairs = 0;
+
+    loop {
+        match (reader1.next(), reader2.next()) {
+            (Some(r1), Some(r2)) => {
+                let r1 = r1.context("Failed to parse read")?;
+                let r2 = r2.context("Failed to parse read")?;
+                write_record(writer, r1.id(), &r1.seq(), r1.qual())?;
+                write_record(writer, r2.id(), &r2.seq(), r2.qual())?;
+                n_pairs += 1;
+            }
+            (None, None) => break,
+            _ => bail!("Paired read files have a different number of reads"),
+        }
+    }
+
+    Ok(n_pairs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    const READS: &[u8] = b"@r1 desc\nACGT\n+\nIIII\n@r2/1\nGGGG\n+\nIIII\n@r3\nTTTT\n+\nIIII\n";
+
+    #[test]
+    fn test_validate_reads() {
+        let fastq = b"@r1\nACGT\n+\nIIII\n@r2\nGG\n+\nII\n";
+        assert_eq!(validate_reads(&fastq[..], 10).unwrap(), 2);
+        assert_eq!(validate_reads(&fastq[..], 1).unwrap(), 1);
+        assert_eq!(validate_reads(&b""[..], 10).unwrap(), 0);
+        // the quality is shorter than the sequence
+        assert!(validate_reads(&b"@r1\nACGT\n+\nII\n"[..], 10).is_err());
+
+        let mut gzipped = Vec::new();
+        let mut encoder =
+            flate2::write::GzEncoder::new(&mut gzipped, flate2::Compression::default());
+        encoder.write_all(fastq).unwrap();
+        encoder.finish().unwrap();
+        let truncated = &gzipped[..gzipped.len() - 10];
+        let reader = flate2::read::MultiGzDecoder::new(truncated);
+        assert!(validate_reads(reader, 1).is_err());
+    }
+
+    #[test]
+    fn test_read_id() {
+        assert_eq!(read_id(b"r1"), b"r1");
+        assert_eq!(read_id(b"r1 desc"), b"r1");
+        assert_eq!(read_id(b"r1\tdesc"), b"r1");
+        assert_eq!(read_id(b"r1/1"), b"r1");
+        assert_eq!(read_id(b"r1/3"), b"r1/3");
+        assert_eq!(read_id(b""), b"");
+    }
+
+    #[test]
+    fn test_read_ids_from() {
+        let ids = read_ids_from(Cursor::new(b"r1\n@r2 desc\n>r3/1\n\n")).unwrap();
+        assert_eq!(ids.len(), 3);
+        assert!(ids.contains(b"r1".as_slice()));
+        assert!(ids.contains(b"r2".as_slice()));
+        assert!(ids.contains(b"r3".as_slice()));
+    }
+
+    #[test]
+    fn test_filter_reads_by_id_remove() {
+        let ids = HashSet::from([b"r2".to_vec()]);
+        let mut out = Vec::new();
+        let stats = filter_reads_by_id(Cursor::new(READS), &mut out, &ids, false).unwrap();
+        assert_eq!(
+            stats,
+            FilterStats {
+                total: 3,
+                written: 2
+            }
+        );
+        assert_eq!(out, b"@r1 desc\nACGT\n+\nIIII\n@r3\nTTTT\n+\nIIII\n");
+    }
+
+    #[test]
+    fn test_filter_reads_by_id_keep() {
+        let ids = HashSet::from([b"r2".to_vec()]);
+        let mut out = Vec::new();
+        let stats = filter_reads_by_id(Cursor::new(READS), &mut out, &ids, true).unwrap();
+        assert_eq!(stats.written, 1);
+        assert_eq!(out, b"@r2/1\nGGGG\n+\nIIII\n");
+    }
+
+    #[test]
+    fn test_filter_reads_by_id_fasta() {
+        let ids = HashSet::from([b"r1".to_vec()]);
+        let mut out = Vec::new();
+        filter_reads_by_id(
+            Cursor::new(b">r1\nACGT\n>r2\nAAAA\n"),
+            &mut out,
+            &ids,
+            false,
+        )
+        .unwrap();
+        assert_eq!(out, b">r2\nAAAA\n");
+    }
+
+    #[test]
+    fn test_filter_reads_by_id_empty() {
+        let ids = HashSet::new();
+        let mut out = Vec::new();
+        let stats = filter_reads_by_id(Cursor::new(b""), &mut out, &ids, false).unwrap();
+        assert_eq!(stats, FilterStats::default());
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn test_deinterleave_and_interleave() {
+        let reads = b"@r1/1\nACGT\n+\nIIII\n@r1/2\nTTTT\n+\nIIII\n@r2 1:N\nGGGG\n+\nIIII\n@r2 2:N\nCCCC\n+\nIIII\n";
+        let mut r1 = Vec::new();
+        let mut r2 = Vec::new();
+        let n = deinterleave(Cursor::new(reads), &mut r1, &mut r2).unwrap();
+        assert_eq!(n, 2);
+        assert_eq!(r1, b"@r1/1\nACGT\n+\nIIII\n@r2 1:N\nGGGG\n+\nIIII\n");
+        assert_eq!(r2, b"@r1/2\nTTTT\n+\nIIII\n@r2 2:N\nCCCC\n+\nIIII\n");
+
+        let mut out = Vec::new();
+        let n = interleave(Cursor::new(r1), Cursor::new(r2), &mut out).unwrap();
+        assert_eq!(n, 2);
+        assert_eq!(out, reads);
+    }
+
+    #[test]
+    fn test_deinterleave_unpaired() {
+        let mut r1 = Vec::new();
+        let mut r2 = Vec::new();
+        let odd = deinterleave(Cursor::new(b">r1/1\nA\n"), &mut r1, &mut r2);
+        assert!(odd.is_err());
+
+        let mismatched = deinterleave(Cursor::new(b">r1\nA\n>r2\nC\n"), &mut r1, &mut r2);
+        assert!(mismatched.is_err());
+    }
+
+    #[test]
+    fn test_interleave_different_lengths() {
+        let mut out = Vec::new();
+        let result = interleave(
+            Cursor::new(b">r1\nA\n>r2\nC\n".to_vec()),
+            Cursor::new(b">r1\nA\n".to_vec()),
+            &mut out,
+        );
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_check_pairs() {
+        let check = |r1: &'static [u8], r2: &'static [u8], n_ids| {
+            check_pairs(Cursor::new(r1), Cursor::new(r2), n_ids).unwrap()
+        };
+        assert_eq!(
+            check(b">r1/1\nA\n>r2/1\nC\n", b">r1/2\nA\n>r2/2\nC\n", 10),
+            None
+        );
+        assert_eq!(check(b"", b"", 10), None);
+        assert_eq!(
+            check(b">r1\nA\n>r2\nC\n", b">r2\nA\n>r1\nC\n", 10),
+            Some(PairMismatch::Ids {
+                n: 1,
+                id1: "r1".to_string(),
+                id2: "r2".to_string()
+            })
+        );
+        // only the first read IDs are compared
+        assert_eq!(check(b">r1\nA\n>r2\nC\n", b">r1\nA\n>r3\nC\n", 1), None);
+        assert_eq!(
+            check(b">r1\nA\n>r2\nC\n>r3\nG\n", b">r1\nA\n", 1),
+            Some(PairMismatch::Counts { n1: 3, n2: 1 })
+        );
+        assert_eq!(
+            check(b"", b">r1\nA\n", 1),
+            Some(PairMismatch::Counts { n1: 0, n2: 1 })
+        );
+    }
+
+    #[test]
+    fn test_repair_pairs() {
+        let r1: &[u8] = b">r1/1\nA\n>r2/1\nC\n>r3/1\nG\n>r4/1\nT\n";
+        let r2: &[u8] = b">r3/2\nGG\n>r5/2\nNN\n>r1/2\nAA\n>r4/2\nTT\n";
+        let mut out1 = Vec::new();
+        let mut out2 = Vec::new();
+        let mut singletons = Vec::new();
+        let stats = repair_pairs(
+            || Ok(Cursor::new(r1)),
+            || Ok(Cursor::new(r2)),
+            &mut out1,
+            &mut out2,
+            Some(&mut singletons),
+        )
+        .unwrap();
+
+        assert_eq!(
+            stats,
+            RepairStats {
+                pairs: 3,
+                orphans1: 1,
+                orphans2: 1
+            }
+        );
+        assert_eq!(out1, b">r1/1\nA\n>r3/1\nG\n>r4/1\nT\n");
+        assert_eq!(out2, b">r1/2\nAA\n>r3/2\nGG\n>r4/2\nTT\n");
+        assert_eq!(singletons, b">r2/1\nC\n>r5/2\nNN\n");
+
+        let duplicated = repair_pairs(
+            || Ok(Cursor::new(r1)),
+            || Ok(Cursor::new(&b">r1\nA\n>r1\nA\n"[..])),
+            &mut Vec::new(),
+            &mut Vec::new(),
+            None,
+        );
+        assert!(duplicated.is_err());
+    }
+}
@@ -0,0 +1,206 @@
+//! Cleaning up when nohuman is interrupted (e.g. with Ctrl-C).
+//!
+//! Temporary directories and running child processes (kraken2) are registered while they exist.
+//! When [`install_handler`] has been called, SIGINT, SIGTERM, or SIGHUP (SIGINT or SIGTERM on
+//! Windows) kills the registered processes and removes the registered directories, before exiting
+//! with the conventional code for the signal (128 plus the signal number, e.g. 130 for Ctrl-C).
+use crate::shred_dir;
+use log::warn;
+#[cfg(unix)]
+use signal_hook::consts::SIGHUP;
+use signal_hook::consts::{SIGINT, SIGTERM};
+#[cfg(unix)]
+use signal_hook::iterator::Signals;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+/// What to clean up if nohuman is interrupted.
+struct Registry {
+    /// Temporary directories, and whether to securely delete them.
+    dirs: Vec<(PathBuf, bool)>,
+    /// Child process IDs, and whether each is the leader of its own process group.
+    processes: Vec<(u32, bool)>,
+}
+
+static HANDLER_INSTALLED: AtomicBool = AtomicBool::new(false);
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+static REGISTRY: Mutex<Registry> = Mutex::new(Registry {
+    dirs: Vec::new(),
+    processes: Vec::new(),
+});
+
+fn registry() -> std::sync::MutexGuard<'static, Registry> {
+    // a panic while holding the lock doesn't leave the registry inconsistent
+    REGISTRY.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Clean up and exit when nohuman receives SIGINT, SIGTERM, or SIGHUP.
+#[cfg(unix)]
+pub fn install_handler() -> io::Result<()> {
+    let mut signals = Signals::new([SIGINT, SIGTERM, SIGHUP])?;
+    thread::spawn(move || {
+        if let Some(signal) = signals.forever().next() {
+            exit_interrupted(signal);
+        }
+    });
+    HANDLER_INSTALLED.store(true, Ordering::SeqCst);
+    Ok(())
+}
+
+/// Clean up and exit when nohuman receives SIGINT or SIGTERM. Without a signal iterator, a thread
+/// checks for a signal every 100 ms.
+#[cfg(not(unix))]
+pub fn install_handler() -> io::Result<()> {
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::Arc;
+
+    let received = Arc::new(AtomicUsize::new(0));
+    for signal in [SIGINT, SIGTERM] {
+        signal_hook::flag::register_usize(signal, Arc::clone(&received), signal as usize)?;
+    }
+    thread::spawn(move || loop {
+        match received.load(Ordering::SeqCst) {
+            0 => thread::sleep(std::time::Duration::from_millis(100)),
+            signal => exit_interrupted(signal as i32),
+        }
+    });
+    HANDLER_INSTALLED.store(true, Ordering::SeqCst);
+    Ok(())
+}
+
+fn exit_interrupted(signal: i32) -> ! {
+    warn!("Interrupted. Stopping kraken2 and removing temporary files...");
+    INTERRUPTED.store(true, Ordering::SeqCst);
+    cleanup();
+    std::process::exit(128 + signal);
+}
+
+/// Whether [`install_handler`] has been called. If so, child processes are run in their own
+/// process group, so they can be stopped after the temporary files they use are cleaned up, rather
+/// than being interrupted along with nohuman.
+pub(crate) fn handler_installed() -> bool {
+    HANDLER_INSTALLED.load(Ordering::SeqCst)
+}
+
+/// If nohuman has been interrupted, block until the handler exits the process. This stops a child
+/// process that was killed while cleaning up from being reported as having failed.
+pub(crate) fn wait_if_interrupted() {
+    while INTERRUPTED.load(Ordering::SeqCst) {
+        thread::park();
+    }
+}
+
+fn cleanup() {
+    registry().cleanup();
+}
+
+impl Registry {
+    /// Kill the registered processes and remove the registered directories.
+    fn cleanup(&self) {
+        for &(pid, group) in &self.processes {
+            kill(pid, group);
+        }
+        for (dir, secure_delete) in &self.dirs {
+            if *secure_delete {
+                if let Err(e) = shred_dir(dir) {
+                    warn!(
+                        "Failed to securely delete temporary files in {:?}: {}",
+                        dir, e
+                    );
+                }
+            }
+            if let Err(e) = fs::remove_dir_all(dir) {
+                warn!("Failed to remove temporary directory {:?}: {}", dir, e);
+            }
+        }
+    }
+}
+
+/// Kill the process `pid`, or its whole process group if `group` is set.
+#[cfg(unix)]
+pub(crate) fn kill(pid: u32, group: bool) {
+    let target = if group {
+        format!("-{}", pid)
+    } else {
+        pid.to_string()
+    };
+    let _ = Command::new("kill")
+        .args(["-s", "KILL", "--", &target])
+        .status();
+}
+
+/// Kill the process `pid`, and the processes it started if `group` is set. Windows has no process
+/// groups to kill, so `taskkill` stops the process tree instead.
+#[cfg(not(unix))]
+pub(crate) fn kill(pid: u32, group: bool) {
+    let pid = pid.to_string();
+    let mut args = vec!["/F", "/PID", &pid];
+    if group {
+        args.push("/T");
+    }
+    let _ = Command::new("taskkill").args(args).status();
+}
+
+/// Removes a directory from the registry when dropped.
+pub struct DirGuard(PathBuf);
+
+impl Drop for DirGuard {
+    fn drop(&mut self) {
+        registry().dirs.retain(|(dir, _)| dir != &self.0);
+    }
+}
+
+/// Remove `dir` (securely deleting its contents first if `secure_delete` is set) if nohuman is
+/// interrupted before the returned guard is dropped.
+pub fn remove_on_interrupt(dir: &Path, secure_delete: bool) -> DirGuard {
+    registry().dirs.push((dir.to_path_buf(), secure_delete));
+    DirGuard(dir.to_path_buf())
+}
+
+/// Removes a process from the registry when dropped.
+pub(crate) struct ProcessGuard(u32);
+
+impl Drop for ProcessGuard {
+    fn drop(&mut self) {
+        registry().processes.retain(|(pid, _)| *pid != self.0);
+    }
+}
+
+/// Kill the process `pid` (or its process group, if `group` is set) if nohuman is interrupted
+/// before the returned guard is dropped.
+pub(crate) fn kill_on_interrupt(pid: u32, group: bool) -> ProcessGuard {
+    registry().processes.push((pid, group));
+    ProcessGuard(pid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_remove_on_interrupt() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().join("nohuman");
+        fs::create_dir(&dir).unwrap();
+        fs::write(dir.join("reads.fq"), "@r1\nACGT\n+\nIIII\n").unwrap();
+
+        let guard = remove_on_interrupt(&dir, false);
+        assert!(registry().dirs.iter().any(|(d, _)| d == &dir));
+        drop(guard);
+        assert!(!registry().dirs.iter().any(|(d, _)| d == &dir));
+
+        // the global registry isn't cleaned up, as other tests' directories are registered in it
+        let registry = Registry {
+            dirs: vec![(dir.clone(), true)],
+            processes: Vec::new(),
+        };
+        registry.cleanup();
+        assert!(!dir.exists());
+    }
+}
@@ -0,0 +1,320 @@
+use crate::compression::open_reader;
+use crate::fastx::read_id;
+use anyhow::{bail, Context, Result};
+use std::collections::HashSet;
+use std::io::{BufRead, BufReader, Lines, Read};
+use std::path::Path;
+use std::str::FromStr;
+
+/// A taxon and the number of k-mers mapped to it, from the LCA mapping column of kraken2 output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KmerMapping {
+    /// The taxonomy ID, or `None` for k-mers containing ambiguous nucleotides (`A`).
+    pub taxid: Option<u64>,
+    pub count: u64,
+}
+
+/// A single record of kraken2's read classification output (`--output`).
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutputRecord {
+    /// Whether the read was classified (`C`) or not (`U`).
+    pub classified: bool,
+    pub read_id: String,
+    /// The taxonomy ID the read was assigned to, or 0 if unclassified.
+    pub taxid: u64,
+    /// The read length(s) - two for a pair.
+    pub length: Vec<u64>,
+    /// The k-mer LCA mappings for each read (mate) in the record.
+    pub lca_mappings: Vec<Vec<KmerMapping>>,
+}
+
+impl OutputRecord {
+    /// Parse the taxonomy ID column. This is usually just the ID, but with `--use-names` it is
+    /// the name followed by "(taxid N)".
+    fn parse_taxid(s: &str) -> Result<u64> {
+        let s = s.trim();
+        let id = match s.rfind("(taxid ") {
+            Some(start) => s[start + 7..].trim_end_matches(')'),
+            None => s,
+        };
+        id.parse()
+            .with_context(|| format!("Invalid taxonomy ID: {}", s))
+    }
+
+    fn parse_lca_mappings(s: &str) -> Result<Vec<Vec<KmerMapping>>> {
+        s.split("|:|")
+            .map(|mate| {
+                mate.split_whitespace()
+                    .map(|mapping| {
+                        let (taxid, count) = mapping
+                            .rsplit_once(':')
+                            .with_context(|| format!("Invalid LCA mapping: {}", mapping))?;
+                        let taxid = match taxid {
+                            "A" => None,
+                            id => Some(id.parse().with_context(|| {
+                                format!("Invalid taxonomy ID in LCA mapping: {}", mapping)
+                            })?),
+                        };
+                        let count = count
+                            .parse()
+                            .with_context(|| format!("Invalid k-mer count: {}", mapping))?;
+                        Ok(KmerMapping { taxid, count })
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+}
+
+impl FromStr for OutputRecord {
+    type Err = anyhow::Error;
+
+    /// Parse a line of kraken2 output.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use nohuman::kraken::OutputRecord;
+    ///
+    /// let record: OutputRecord = "C\tread1\t9606\t150|150\t9606:116 |:| 9606:116"
+    ///     .parse()
+    ///     .unwrap();
+    /// assert!(record.classified);
+    /// assert_eq!(record.taxid, 9606);
+    /// assert_eq!(record.length, vec![150, 150]);
+    /// ```
+    fn from_str(s: &str) -> Result<Self> {
+        let mut fields = s.trim_end_matches(['\r', '\n']).split('\t');
+        let classified = match fields.next().unwrap_or_default() {
+            "C" => true,
+            "U" => false,
+            status => bail!("Invalid classification status: {:?}", status),
+        };
+        let read_id = fields.next().context("Missing read ID")?.to_string();
+        let taxid = Self::parse_taxid(fields.next().context("Missing taxonomy ID")?)?;
+        let length = fields
+            .next()
+            .context("Missing read length")?
+            .split('|')
+            .map(|l| {
+                l.parse()
+                    .with_context(|| format!("Invalid read length: {}", l))
+            })
+            .collect::<Result<_>>()?;
+        let lca_mappings = Self::parse_lca_mappings(fields.next().unwrap_or_default())?;
+
+        Ok(Self {
+            classified,
+            read_id,
+            taxid,
+            length,
+            lca_mappings,
+        })
+    }
+}
+
+/// An iterator over the records of a kraken2 read classification output.
+pub struct OutputReader<R: BufRead> {
+    lines: Lines<R>,
+    line_number: usize,
+}
+
+impl<R: BufRead> OutputReader<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            lines: reader.lines(),
+            line_number: 0,
+        }
+    }
+}
+
+impl OutputReader<BufReader<Box<dyn Read + Send>>> {
+    /// Read the (possibly compressed) kraken2 output at `path`.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Ok(Self::new(BufReader::new(open_reader(path)?)))
+    }
+}
+
+impl<R: BufRead> Iterator for OutputReader<R> {
+    type Item = Result<OutputRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let line = self.lines.next()?;
+            self.line_number += 1;
+            let line = match line.context("Failed to read kraken2 output") {
+                Ok(line) if line.is_empty() => continue,
+                Ok(line) => line,
+                Err(e) => return Some(Err(e)),
+            };
+            let line_number = self.line_number;
+            return Some(line.parse().with_context(|| {
+                format!("Failed to parse line {} of the kraken2 output", line_number)
+            }));
+        }
+    }
+}
+
+/// Collect the IDs of all reads marked as classified (`C`) in a kraken2 read classification
+/// output. IDs are normalised with [`read_id`] so they can be matched against FASTA/Q headers.
+pub fn classified_read_ids<R: BufRead>(reader: R) -> Result<HashSet<Vec<u8>>> {
+    let mut ids = HashSet::new();
+    for record in OutputReader::new(reader) {
+        let record = record?;
+        if record.classified {
+            ids.insert(read_id(record.read_id.as_bytes()).to_vec());
+        }
+    }
+    Ok(ids)
+}
+
+/// Collect the IDs of the reads classified as one of `taxa` in a kraken2 read classification
+/// output. IDs are normalised with [`read_id`] so they can be matched against FASTA/Q headers.
+pub fn read_ids_in_taxa<R: BufRead>(reader: R, taxa: &HashSet<u64>) -> Result<HashSet<Vec<u8>>> {
+    let mut ids = HashSet::new();
+    for record in OutputReader::new(reader) {
+        let record = record?;
+        if record.classified && taxa.contains(&record.taxid) {
+            ids.insert(read_id(record.read_id.as_bytes()).to_vec());
+        }
+    }
+    Ok(ids)
+}
+
+/// A kind of kraken2 failure nohuman recognises, from how kraken2 exited and what it printed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Failure {
+    /// kraken2 was killed with SIGKILL (usually by the out-of-memory killer), or couldn't allocate
+    /// memory.
+    OutOfMemory,
+    /// Reading a file failed, e.g. because a network filesystem was briefly unavailable.
+    Io,
+    /// An input file ended early, or isn't valid FASTA/FASTQ.
+    MalformedInput,
+}
+
+impl Failure {
+    /// Work out why kraken2 failed, from the `signal` that killed it (if any) and its `stderr`.
+    /// `None` if the failure isn't recognised.
+    pub fn diagnose(signal: Option<i32>, stderr: &str) -> Option<Self> {
+        const SIGKILL: i32 = 9;
+        let stderr = stderr.to_lowercase();
+        let mentions = |patterns: &[&str]| patterns.iter().any(|p| stderr.contains(p));
+        if signal == Some(SIGKILL)
+            || mentions(&["bad_alloc", "cannot allocate memory", "out of memory"])
+        {
+            Some(Failure::OutOfMemory)
+        } else if mentions(&["unable to map", "input/output error", "stale file handle"]) {
+            Some(Failure::Io)
+        } else if mentions(&["truncated", "unexpected end of", "malformed"]) {
+            Some(Failure::MalformedInput)
+        } else {
+            None
+        }
+    }
+
+    /// What the user can do about the failure.
+    pub fn advice(&self) -> &'static str {
+        match self {
+            Failure::OutOfMemory => "kraken2 ran out of memory. Use --low-mem to memory-map the database instead of loading it, or run on a machine with more memory",
+            Failure::Io => "kraken2 couldn't read a file, e.g. because a network filesystem was unavailable. Check the database is available and complete (see `nohuman db verify`)",
+            Failure::MalformedInput => "An input file looks truncated or malformed. Check it is complete, e.g. with `gzip -t` for gzipped files",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_classified_read_ids() {
+        let data = b"C\tr1\t9606\t150\t9606:116\nU\tr2\t0\t150\t0:116\nC\tr3/1\t9606\t150|150\t9606:1 |:| 9606:1\n";
+        let ids = classified_read_ids(Cursor::new(data)).unwrap();
+        assert_eq!(ids.len(), 2);
+        assert!(ids.contains(b"r1".as_slice()));
+        assert!(ids.contains(b"r3".as_slice()));
+    }
+
+    #[test]
+    fn test_read_ids_in_taxa() {
+        let data = b"C\tr1\t9606\t150\t9606:116\nC\tr2\t562\t150\t562:116\nC\tr3\tHomo sapiens neanderthalensis (taxid 63221)\t150\t63221:1\nU\tr4\t0\t150\t0:116\n";
+        let ids = read_ids_in_taxa(Cursor::new(data), &HashSet::from([9606, 63221])).unwrap();
+        assert_eq!(ids.len(), 2);
+        assert!(ids.contains(b"r1".as_slice()));
+        assert!(ids.contains(b"r3".as_slice()));
+    }
+
+    #[test]
+    fn test_diagnose_failure() {
+        assert_eq!(Failure::diagnose(Some(9), ""), Some(Failure::OutOfMemory));
+        assert_eq!(
+            Failure::diagnose(
+                None,
+                "terminate called after throwing an instance of 'std::bad_alloc'"
+            ),
+            Some(Failure::OutOfMemory)
+        );
+        assert_eq!(
+            Failure::diagnose(None, "classify: unable to map hash.k2d"),
+            Some(Failure::Io)
+        );
+        assert_eq!(
+            Failure::diagnose(None, "Error: unexpected end of file in reads.fq"),
+            Some(Failure::MalformedInput)
+        );
+        assert_eq!(Failure::diagnose(Some(15), "something else"), None);
+    }
+
+    #[test]
+    fn test_classified_read_ids_malformed() {
+        let result = classified_read_ids(Cursor::new(b"C\n"));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_parse_output_record() {
+        let record: OutputRecord = "U\tr2\t0\t150\t0:110 A:6".parse().unwrap();
+        assert_eq!(
+            record,
+            OutputRecord {
+                classified: false,
+                read_id: "r2".to_string(),
+                taxid: 0,
+                length: vec![150],
+                lca_mappings: vec![vec![
+                    KmerMapping {
+                        taxid: Some(0),
+                        count: 110
+                    },
+                    KmerMapping {
+                        taxid: None,
+                        count: 6
+                    },
+                ]],
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_output_record_with_names() {
+        let record: OutputRecord = "C\tr1\tHomo sapiens (taxid 9606)\t100|98\t9606:66 |:| 9606:64"
+            .parse()
+            .unwrap();
+        assert_eq!(record.taxid, 9606);
+        assert_eq!(record.lca_mappings.len(), 2);
+        assert_eq!(record.lca_mappings[1][0].count, 64);
+    }
+
+    #[test]
+    fn test_output_reader() {
+        let data = b"C\tr1\t9606\t150\t9606:116\n\nU\tr2\t0\t150\t0:116\nX\tr3\t0\t1\t0:1\n";
+        let mut reader = OutputReader::new(Cursor::new(data));
+        assert_eq!(reader.next().unwrap().unwrap().read_id, "r1");
+        assert!(!reader.next().unwrap().unwrap().classified);
+        let err = reader.next().unwrap().unwrap_err();
+        assert!(err.to_string().contains("line 4"));
+        assert!(reader.next().is_none());
+    }
+}
@@ -1,80 +1,813 @@
+pub mod align;
+pub mod bam;
+pub mod batch;
+pub mod bench;
+pub mod build;
+pub mod calibrate;
+pub mod checksum;
+pub mod cloud;
 pub mod compression;
+pub mod config;
+pub mod container;
+pub mod database;
+pub mod deps;
+pub mod diagnostics;
 pub mod download;
+pub mod error;
+pub mod eval;
+pub mod fastx;
+pub mod interrupt;
+pub mod kraken;
+pub mod mock;
+pub mod native;
+pub mod pipeline;
+pub mod preset;
+pub mod progress;
+pub mod provenance;
+pub mod report;
+pub mod resources;
+pub mod server;
+pub mod signature;
+pub mod simulate;
+pub mod summary;
+pub mod taxonomy;
 
-use log::{debug, info};
+pub use error::NohumanError;
+
+use container::{Container, ContainerEngine};
+use database::FileChecksum;
+use download::Checksum;
+use kraken::Failure;
+use log::{debug, info, trace, warn};
+use resources::{wait_with_usage, ResourceUsage};
 use serde::Deserialize;
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
 use std::ffi::OsStr;
-use std::io::{self};
-use std::num::ParseIntError;
+use std::fs::OpenOptions;
+use std::io::{self, Read, Write};
+use std::num::{NonZeroU32, ParseIntError};
+#[cfg(unix)]
+use std::os::unix::process::{CommandExt, ExitStatusExt};
 use std::path::{Path, PathBuf};
-use std::process::Command;
+use std::process::{Command, ExitStatus, Stdio};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// The host organism whose reads are being removed.
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Default)]
+pub enum Host {
+    #[default]
+    Human,
+    /// A user-provided database. Reads classified against it are treated as host reads.
+    Custom,
+}
+
+impl FromStr for Host {
+    type Err = NohumanError;
+
+    /// Parse a host name. `s` is case-insensitive.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use nohuman::Host;
+    ///
+    /// let host = "Custom".parse::<Host>().unwrap();
+    /// assert_eq!(host, Host::Custom);
+    /// ```
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "human" => Ok(Host::Human),
+            "custom" => Ok(Host::Custom),
+            _ => Err(NohumanError::InvalidHost(s.to_string())),
+        }
+    }
+}
+
+impl std::fmt::Display for Host {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let host = match self {
+            Host::Human => "human",
+            Host::Custom => "host",
+        };
+        write!(f, "{}", host)
+    }
+}
+
+impl Host {
+    /// The suffix added to default output file names. e.g. "nohuman" or "nohost".
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use nohuman::Host;
+    ///
+    /// assert_eq!(Host::Human.output_suffix(), "nohuman");
+    /// assert_eq!(Host::Custom.output_suffix(), "nohost");
+    /// ```
+    pub fn output_suffix(&self) -> String {
+        format!("no{}", self)
+    }
+
+    /// The NCBI taxonomy ID of the host species. `None` for a custom host.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use nohuman::Host;
+    ///
+    /// assert_eq!(Host::Human.taxid(), Some(9606));
+    /// assert_eq!(Host::Custom.taxid(), None);
+    /// ```
+    pub fn taxid(&self) -> Option<u64> {
+        match self {
+            Host::Human => Some(9606),
+            Host::Custom => None,
+        }
+    }
+}
 
 #[derive(Deserialize)]
 pub struct Config {
-    pub database_url: String,
-    pub database_md5: String,
+    /// The human database release.
+    #[serde(flatten)]
+    pub release: DatabaseRelease,
+    /// The human database for nohuman's built-in classifier (see [`native`]).
+    #[serde(default)]
+    pub native: Option<DatabaseRelease>,
+    /// A miniature human database, built from part of a chromosome, for testing a whole run
+    /// quickly (`--db-version test`). It is too small to remove human reads reliably.
+    #[serde(default)]
+    pub test: Option<DatabaseRelease>,
+}
+
+/// A database release listed in the config. At least one checksum of the tarball must be given.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct DatabaseRelease {
+    #[serde(rename = "database_url")]
+    pub url: String,
+    /// The version to install the release as, instead of the tarball name.
+    #[serde(rename = "database_version", default)]
+    pub name: Option<String>,
+    /// Mirrors of `url`, tried in order if downloading from it fails.
+    #[serde(rename = "database_urls", default)]
+    pub mirrors: Vec<String>,
+    #[serde(rename = "database_md5", default)]
+    pub md5: Option<String>,
+    #[serde(rename = "database_sha256", default)]
+    pub sha256: Option<String>,
+    #[serde(rename = "database_blake3", default)]
+    pub blake3: Option<String>,
+    /// The URL of the tarball's minisign signature. Defaults to `url` with ".minisig" appended.
+    #[serde(rename = "database_minisig", default)]
+    pub signature_url: Option<String>,
+    /// Checksums of the extracted database files, keyed by their path in the database.
+    #[serde(rename = "database_files", default)]
+    pub files: BTreeMap<String, FileChecksum>,
+    /// The oldest nohuman version the database works with.
+    #[serde(default)]
+    pub min_nohuman_version: Option<String>,
+    /// The oldest kraken2 version the database works with.
+    #[serde(default)]
+    pub min_kraken2_version: Option<String>,
+    /// The release should not be used, e.g. because the database was built incorrectly.
+    #[serde(rename = "database_yanked", default)]
+    pub yanked: bool,
+    /// The release can still be used, but has been superseded.
+    #[serde(rename = "database_deprecated", default)]
+    pub deprecated: bool,
+    /// Earlier releases, listed so they can be marked as yanked or deprecated.
+    #[serde(rename = "previous_releases", default)]
+    pub previous: Vec<DatabaseRelease>,
+}
+
+impl DatabaseRelease {
+    /// The URLs the release can be downloaded from, in the order they should be tried.
+    pub fn urls(&self) -> Vec<&str> {
+        let mut urls = vec![self.url.as_str()];
+        for mirror in &self.mirrors {
+            if !urls.contains(&mirror.as_str()) {
+                urls.push(mirror);
+            }
+        }
+        urls
+    }
+
+    /// The URL of the minisign signature of the release's tarball.
+    pub fn signature_url(&self) -> String {
+        self.signature_url
+            .to_owned()
+            .unwrap_or_else(|| signature::signature_url(&self.url))
+    }
+
+    /// The strongest checksum available for the release's tarball.
+    pub fn checksum(&self) -> Option<Checksum> {
+        if let Some(hash) = &self.blake3 {
+            Some(Checksum::Blake3(hash.to_owned()))
+        } else if let Some(hash) = &self.sha256 {
+            Some(Checksum::Sha256(hash.to_owned()))
+        } else {
+            self.md5.as_ref().map(|hash| Checksum::Md5(hash.to_owned()))
+        }
+    }
+
+    /// The release version, given in the config or taken from the tarball name.
+    pub fn version(&self) -> String {
+        self.name
+            .clone()
+            .unwrap_or_else(|| database::version_from_url(&self.url))
+    }
+
+    /// Find the release (this or a previous one) with `version`.
+    pub fn find(&self, version: &str) -> Option<&DatabaseRelease> {
+        std::iter::once(self)
+            .chain(&self.previous)
+            .find(|release| release.version() == version)
+    }
+
+    /// "yanked" or "deprecated" if the release has been marked as such.
+    pub fn status(&self) -> Option<&'static str> {
+        if self.yanked {
+            Some("yanked")
+        } else if self.deprecated {
+            Some("deprecated")
+        } else {
+            None
+        }
+    }
+
+    /// Check the release works with the running nohuman and, if its version is known, kraken2.
+    pub fn check_compatibility(&self, kraken2_version: Option<&str>) -> Result<(), NohumanError> {
+        check_compatibility(
+            self.min_nohuman_version.as_deref(),
+            self.min_kraken2_version.as_deref(),
+            kraken2_version,
+        )
+    }
 }
 
 impl Config {
     pub fn new(database_url: &str, database_md5: &str) -> Self {
         Self {
-            database_url: database_url.to_string(),
-            database_md5: database_md5.to_string(),
+            release: DatabaseRelease {
+                url: database_url.to_string(),
+                name: None,
+                mirrors: Vec::new(),
+                md5: Some(database_md5.to_string()),
+                sha256: None,
+                blake3: None,
+                signature_url: None,
+                files: BTreeMap::new(),
+                min_nohuman_version: None,
+                min_kraken2_version: None,
+                yanked: false,
+                deprecated: false,
+                previous: Vec::new(),
+            },
+            native: None,
+            test: None,
+        }
+    }
+
+    /// Get the database release for `host`, if the config has one. Only human has a release.
+    pub fn database_for(&self, host: &Host) -> Option<&DatabaseRelease> {
+        match host {
+            Host::Human => Some(&self.release),
+            Host::Custom => None,
+        }
+    }
+
+    /// Get the native database release for `host` if `native` is set, or the kraken2 database
+    /// release otherwise. Only human has a native database.
+    pub fn release_for(&self, host: &Host, native: bool) -> Option<&DatabaseRelease> {
+        match (native, host) {
+            (false, _) => self.database_for(host),
+            (true, Host::Human) => self.native.as_ref(),
+            (true, _) => None,
+        }
+    }
+
+    /// Get the release of `version` (see [`Config::release_for`]): the latest release, an earlier
+    /// one, or the test database ([`database::TEST_DB_VERSION`]). The latest release if `version`
+    /// is `None`.
+    pub fn release_version(
+        &self,
+        host: &Host,
+        native: bool,
+        version: Option<&str>,
+    ) -> Option<&DatabaseRelease> {
+        match version {
+            None => self.release_for(host, native),
+            Some(database::TEST_DB_VERSION) if !native && *host == Host::Human => {
+                self.test.as_ref()
+            }
+            Some(version) => self.release_for(host, native)?.find(version),
         }
     }
 }
 
+/// How long to wait before retrying kraken2 after it failed to read a file.
+const RETRY_WAIT: Duration = Duration::from_secs(5);
+
+/// The sequence counts kraken2 reports, and how long it ran for.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct KrakenStats {
+    /// The number of sequences processed. A pair of reads counts as one sequence.
+    pub total: usize,
+    pub classified: usize,
+    pub unclassified: usize,
+    /// The time from starting kraken2 to it finishing, over all attempts (see
+    /// [`CommandRunner::run_with_usage`]).
+    pub duration: Duration,
+}
+
+/// The outcome of running a command once.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Execution {
+    pub success: bool,
+    /// The signal that killed the command, if any.
+    pub signal: Option<i32>,
+    pub stderr: String,
+    /// The resources the command used, if known.
+    pub usage: Option<ResourceUsage>,
+}
+
+/// Runs the commands of a [`CommandRunner`]. The default, [`ProcessExecutor`], starts a process
+/// (or asks a server to). Another can be given with [`CommandRunner::with_executor`], e.g. a fake
+/// kraken2 in tests, which checks the arguments it is given and returns canned stderr without
+/// needing kraken2 or a database.
+pub trait Executor: std::fmt::Debug + Send + Sync {
+    /// Run `runner`'s command once with `args`. Retrying, logging, and parsing the output are left
+    /// to the runner.
+    fn execute(&self, runner: &CommandRunner, args: &[&OsStr]) -> Result<Execution, NohumanError>;
+}
+
+/// Runs commands as processes, in the runner's container if it has one, or with its server.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ProcessExecutor;
+
+impl Executor for ProcessExecutor {
+    fn execute(&self, runner: &CommandRunner, args: &[&OsStr]) -> Result<Execution, NohumanError> {
+        match &runner.server {
+            Some(socket) => {
+                let response =
+                    server::submit(socket, args, runner.timeout).map_err(|e| match e.kind() {
+                        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => runner.timed_out(),
+                        _ => NohumanError::ServerFailed {
+                            socket: socket.to_owned(),
+                            message: e.to_string(),
+                        },
+                    })?;
+                Ok(Execution {
+                    success: response.success,
+                    signal: response.signal,
+                    stderr: response.stderr,
+                    usage: None,
+                })
+            }
+            None => runner.output(runner.command(args)?),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct CommandRunner {
     pub command: String,
+    /// The host name used when logging classification statistics.
+    pub host: Host,
+    /// The socket of a nohuman server (see [`server`]) to run kraken2 with, instead of running it
+    /// directly.
+    pub server: Option<PathBuf>,
+    /// The container to run the command in, if it isn't run directly.
+    pub container: Option<Container>,
+    /// How long the command can run for before it is stopped.
+    pub timeout: Option<Duration>,
+    /// A file each run's command line and stderr are appended to.
+    pub log: Option<PathBuf>,
+    /// What runs the command. Defaults to [`ProcessExecutor`].
+    pub executor: Arc<dyn Executor>,
 }
 
 impl CommandRunner {
     pub fn new(command: &str) -> Self {
         Self {
             command: command.to_string(),
+            host: Host::default(),
+            server: None,
+            container: None,
+            timeout: None,
+            log: None,
+            executor: Arc::new(ProcessExecutor),
         }
     }
 
-    pub fn run(&self, args: &[&str]) -> io::Result<()> {
-        let output = Command::new(&self.command).args(args).output()?;
+    pub fn with_log(mut self, log: Option<PathBuf>) -> Self {
+        self.log = log;
+        self
+    }
 
-        let stderr_log = String::from_utf8_lossy(&output.stderr);
-        if !output.status.success() {
-            return Err(io::Error::new(
-                io::ErrorKind::Other,
-                format!("{} failed with stderr {}", self.command, stderr_log),
-            ));
+    pub fn with_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn with_container(mut self, container: Option<Container>) -> Self {
+        self.container = container;
+        self
+    }
+
+    pub fn with_executor(mut self, executor: Arc<dyn Executor>) -> Self {
+        self.executor = executor;
+        self
+    }
+
+    /// The command to run with `args`, from the directory `cwd`.
+    pub fn command_in<S: AsRef<OsStr>>(&self, args: &[S], cwd: &Path) -> Command {
+        match &self.container {
+            Some(container) => container.command(&self.command, args, cwd),
+            None => {
+                let mut command = Command::new(&self.command);
+                command.args(args).current_dir(cwd);
+                command
+            }
         }
+    }
+
+    /// The command to run with `args`, from the current directory.
+    fn command<S: AsRef<OsStr>>(&self, args: &[S]) -> io::Result<Command> {
+        Ok(self.command_in(args, &std::env::current_dir()?))
+    }
+
+    pub fn with_server(mut self, socket: Option<PathBuf>) -> Self {
+        self.server = socket;
+        self
+    }
+
+    pub fn with_host(mut self, host: Host) -> Self {
+        self.host = host;
+        self
+    }
+
+    /// Run the command with `args`. Returns the sequence counts kraken2 reports, and how long it
+    /// took. See [`CommandRunner::run_with_usage`].
+    pub fn run<S: AsRef<OsStr>>(&self, args: &[S]) -> Result<KrakenStats, NohumanError> {
+        self.run_with_usage(args).map(|(stats, _)| stats)
+    }
 
-        debug!("kraken2 stderr:\n {}", stderr_log);
+    /// Run the command with `args`. Returns the sequence counts kraken2 reports and how long it
+    /// took, and the resources it used (over all attempts). The resource usage is
+    /// unknown when the command is run by a server or in a docker container, as the process that
+    /// runs it is elsewhere.
+    ///
+    /// If kraken2 fails in a way that might not happen again, it is retried once: with
+    /// `--memory-mapping` if it ran out of memory, or after a short wait if it couldn't read a file.
+    /// Otherwise the error includes advice on fixing recognised failures (see [`Failure`]).
+    ///
+    /// The arguments are passed on as they are, so paths with spaces, or that aren't UTF-8, can be
+    /// used.
+    pub fn run_with_usage<S: AsRef<OsStr>>(
+        &self,
+        args: &[S],
+    ) -> Result<(KrakenStats, Option<ResourceUsage>), NohumanError> {
+        let start = Instant::now();
+        let mut args: Vec<&OsStr> = args.iter().map(AsRef::as_ref).collect();
+        let mut retried = false;
+        let mut total_usage: Option<ResourceUsage> = None;
+        let stderr_log = loop {
+            let Execution {
+                success,
+                signal,
+                stderr,
+                usage,
+            } = self.execute(&args)?;
+            total_usage = match (total_usage, usage) {
+                (Some(total), Some(usage)) => Some(total.combine(usage)),
+                (total, usage) => total.or(usage),
+            };
+            if success {
+                break stderr;
+            }
+            let failure = Failure::diagnose(signal, &stderr);
+            match failure {
+                Some(Failure::OutOfMemory)
+                    if !retried
+                        && self.server.is_none()
+                        && !args.iter().any(|arg| *arg == "--memory-mapping") =>
+                {
+                    warn!(
+                        "{} ran out of memory. Retrying with --memory-mapping, which uses less memory but is slower",
+                        self.command
+                    );
+                    args.push(OsStr::new("--memory-mapping"));
+                }
+                Some(Failure::Io) if !retried => {
+                    warn!(
+                        "{} failed to read a file. Retrying in {}",
+                        self.command,
+                        humantime::format_duration(RETRY_WAIT)
+                    );
+                    thread::sleep(RETRY_WAIT);
+                }
+                _ => {
+                    return Err(NohumanError::KrakenFailed {
+                        command: self.command.to_owned(),
+                        stderr,
+                        advice: failure.map(|failure| failure.advice()),
+                    })
+                }
+            }
+            retried = true;
+        };
 
-        let (total, classified, unclassified) =
-            parse_kraken_stderr(&stderr_log).unwrap_or((0, 0, 0));
+        trace!("kraken2 stderr:\n {}", stderr_log);
+
+        let stats = KrakenStats {
+            duration: start.elapsed(),
+            ..parse_kraken_stderr(&stderr_log).unwrap_or_default()
+        };
 
         info!(
-            "{} / {} ({:.2}%) sequences classified as human; {} ({:.2}%) as non-human",
-            classified,
-            total,
-            (classified as f64 / total as f64) * 100.0,
-            unclassified,
-            (unclassified as f64 / total as f64) * 100.0
+            "{} / {} ({:.2}%) sequences classified as {}; {} ({:.2}%) as non-{}",
+            stats.classified,
+            stats.total,
+            (stats.classified as f64 / stats.total as f64) * 100.0,
+            self.host,
+            stats.unclassified,
+            (stats.unclassified as f64 / stats.total as f64) * 100.0,
+            self.host
         );
 
-        Ok(())
+        if let Some(usage) = &total_usage {
+            debug!("{} took {}", self.command, usage);
+        }
+
+        Ok((stats, total_usage))
+    }
+
+    /// Run the command with `args` once with the executor, logging it if a log is set.
+    fn execute(&self, args: &[&OsStr]) -> Result<Execution, NohumanError> {
+        let execution = self.executor.execute(self, args)?;
+        if let Some(path) = &self.log {
+            let mut log = OpenOptions::new().create(true).append(true).open(path)?;
+            let args: Vec<_> = args.iter().map(|arg| arg.to_string_lossy()).collect();
+            writeln!(log, "$ {} {}", self.command, args.join(" "))?;
+            log.write_all(execution.stderr.as_bytes())?;
+        }
+        Ok(execution)
+    }
+
+    /// Run `command` to completion, returning whether it succeeded, its stderr, and the resources
+    /// it used (on Unix). If it runs for longer than the timeout, it is killed, along with any
+    /// processes it started. It is also killed if nohuman is interrupted (see [`interrupt`]).
+    fn output(&self, mut command: Command) -> Result<Execution, NohumanError> {
+        // in a new process group, so the command and its children can be killed together
+        let group = self.timeout.is_some() || interrupt::handler_installed();
+        #[cfg(unix)]
+        if group {
+            command.process_group(0);
+        }
+        command.stdout(Stdio::null()).stderr(Stdio::piped());
+        let start = Instant::now();
+        let mut child = command.spawn()?;
+        let _guard = interrupt::kill_on_interrupt(child.id(), group);
+        let mut stderr = child.stderr.take().expect("stderr is piped");
+        // read stderr as the command runs, so it doesn't block on a full pipe
+        let reader = thread::spawn(move || {
+            let mut buf = Vec::new();
+            let _ = stderr.read_to_end(&mut buf);
+            String::from_utf8_lossy(&buf).to_string()
+        });
+
+        let elapsed = || start.elapsed();
+        let Some(timeout) = self.timeout else {
+            let (status, usage) =
+                wait_with_usage(&mut child, true, elapsed)?.expect("child has exited");
+            interrupt::wait_if_interrupted();
+            let stderr = reader.join().unwrap_or_default();
+            return Ok(Execution {
+                success: status.success(),
+                signal: self.signal(status),
+                stderr,
+                usage: usage.and_then(|usage| self.usage(usage)),
+            });
+        };
+        let (status, usage) = loop {
+            if let Some(exited) = wait_with_usage(&mut child, false, elapsed)? {
+                interrupt::wait_if_interrupted();
+                break exited;
+            }
+            if start.elapsed() >= timeout {
+                warn!(
+                    "{} has run for longer than {}. Stopping it",
+                    self.command,
+                    humantime::format_duration(timeout)
+                );
+                interrupt::kill(child.id(), true);
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(self.timed_out());
+            }
+            thread::sleep(Duration::from_millis(100));
+        };
+        let stderr = reader.join().unwrap_or_default();
+        Ok(Execution {
+            success: status.success(),
+            signal: self.signal(status),
+            stderr,
+            usage: usage.and_then(|usage| self.usage(usage)),
+        })
+    }
+
+    /// The resources used by the command, which are those of the container engine's client, not
+    /// the command, with docker.
+    fn usage(&self, usage: ResourceUsage) -> Option<ResourceUsage> {
+        match &self.container {
+            Some(container) if container.engine == ContainerEngine::Docker => None,
+            _ => Some(usage),
+        }
+    }
+
+    /// The signal that killed the command, if any. Container engines exit with 128 plus the
+    /// number of the signal that killed the program in the container.
+    fn signal(&self, status: ExitStatus) -> Option<i32> {
+        #[cfg(unix)]
+        let signal = status.signal();
+        #[cfg(not(unix))]
+        let signal = None;
+        signal.or_else(|| {
+            status
+                .code()
+                .filter(|code| self.container.is_some() && *code > 128)
+                .map(|code| code - 128)
+        })
+    }
+
+    fn timed_out(&self) -> NohumanError {
+        NohumanError::KrakenTimedOut {
+            command: self.command.to_owned(),
+            timeout: self.timeout.unwrap_or_default(),
+        }
     }
 
     pub fn is_executable(&self) -> bool {
-        let cmd = format!("command -v {}", &self.command);
-        let result = Command::new("sh").args(["-c", &cmd]).output();
-        match result {
-            Ok(output) => output.status.success(),
-            Err(_) => false,
+        match &self.container {
+            Some(container) => container.is_available(),
+            None => self.path().is_some(),
+        }
+    }
+
+    /// The path of the executable the command resolves to. See [`find_executable`].
+    pub fn path(&self) -> Option<PathBuf> {
+        find_executable(&self.command)
+    }
+
+    /// The version of the command, from the output of `<command> --version`. `None` if it can't
+    /// be run or the version isn't found.
+    pub fn version(&self) -> Option<String> {
+        let output = self.command(&["--version"]).ok()?.output().ok()?;
+        parse_version_output(&String::from_utf8_lossy(&output.stdout))
+    }
+}
+
+/// Where output that isn't wanted (e.g. kraken2's per-read classifications) is sent.
+pub const NULL_DEVICE: &str = if cfg!(windows) { "NUL" } else { "/dev/null" };
+
+/// The path of the executable `command` runs, like `command -v` (or `where` on Windows), without
+/// needing a shell. A command with a directory in it is used as it is; otherwise the directories
+/// on the `PATH` are searched. On Windows, the extensions in `PATHEXT` (e.g. `.exe`) are tried
+/// too. `None` if it isn't found.
+pub fn find_executable(command: &str) -> Option<PathBuf> {
+    let extensions: Vec<String> = if cfg!(windows) {
+        std::iter::once(String::new())
+            .chain(executable_extensions())
+            .collect()
+    } else {
+        vec![String::new()]
+    };
+    let candidates = |path: PathBuf| {
+        extensions.iter().map(move |extension| {
+            let mut candidate = path.clone().into_os_string();
+            candidate.push(extension);
+            PathBuf::from(candidate)
+        })
+    };
+    if Path::new(command).components().count() > 1 {
+        return candidates(PathBuf::from(command)).find(|path| is_executable_file(path));
+    }
+    let paths = std::env::var_os("PATH")?;
+    std::env::split_paths(&paths)
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .flat_map(|dir| candidates(dir.join(command)))
+        .find(|path| is_executable_file(path))
+}
+
+/// The extensions of the files Windows can run, e.g. ".EXE", from `PATHEXT`.
+fn executable_extensions() -> Vec<String> {
+    let pathext = std::env::var("PATHEXT").unwrap_or_else(|_| ".COM;.EXE;.BAT;.CMD".into());
+    pathext
+        .split(';')
+        .filter(|extension| !extension.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Whether `path` is a file that can be executed. Without Unix permissions, that is a file with
+/// one of the [`executable_extensions`].
+fn is_executable_file(path: &Path) -> bool {
+    let Ok(metadata) = path.metadata() else {
+        return false;
+    };
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        metadata.is_file() && metadata.permissions().mode() & 0o111 != 0
+    }
+    #[cfg(not(unix))]
+    {
+        let extension = path.extension().unwrap_or_default().to_string_lossy();
+        metadata.is_file()
+            && executable_extensions()
+                .iter()
+                .any(|e| e.trim_start_matches('.').eq_ignore_ascii_case(&extension))
+    }
+}
+
+/// Get the version from the output of `kraken2 --version`, e.g. "Kraken version 2.1.3".
+fn parse_version_output(output: &str) -> Option<String> {
+    let mut words = output.split_whitespace();
+    words.find(|word| word.eq_ignore_ascii_case("version"))?;
+    words.next().map(str::to_string)
+}
+
+/// Compare dot-separated version numbers, e.g. "2.1.3". A leading "v" and any non-numeric
+/// suffix of a component (e.g. "0-beta") are ignored, and missing components are treated as 0.
+pub fn compare_versions(a: &str, b: &str) -> Ordering {
+    fn components(version: &str) -> Vec<u64> {
+        version
+            .trim()
+            .trim_start_matches('v')
+            .split('.')
+            .map(|part| {
+                let digits: String = part.chars().take_while(char::is_ascii_digit).collect();
+                digits.parse().unwrap_or(0)
+            })
+            .collect()
+    }
+    let (a, b) = (components(a), components(b));
+    for i in 0..a.len().max(b.len()) {
+        let ordering = a.get(i).unwrap_or(&0).cmp(b.get(i).unwrap_or(&0));
+        if ordering != Ordering::Equal {
+            return ordering;
         }
     }
+    Ordering::Equal
 }
 
-/// Parses the kraken2 stderr to get thenumber of total, classified and unclassifed reads.
-fn parse_kraken_stderr(stderr: &str) -> Result<(usize, usize, usize), ParseIntError> {
+/// Check the running nohuman, and kraken2 (if `kraken2_version` is known), are at least the
+/// minimum versions a database requires.
+pub fn check_compatibility(
+    min_nohuman_version: Option<&str>,
+    min_kraken2_version: Option<&str>,
+    kraken2_version: Option<&str>,
+) -> Result<(), NohumanError> {
+    let nohuman_version = env!("CARGO_PKG_VERSION");
+    let requirements = [
+        ("nohuman", min_nohuman_version, Some(nohuman_version)),
+        ("kraken2", min_kraken2_version, kraken2_version),
+    ];
+    for (program, required, found) in requirements {
+        if let (Some(required), Some(found)) = (required, found) {
+            if compare_versions(found, required) == Ordering::Less {
+                return Err(NohumanError::IncompatibleVersion {
+                    program: program.to_string(),
+                    required: required.to_string(),
+                    found: found.to_string(),
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Parses the kraken2 stderr to get the number of total, classified and unclassified sequences.
+/// The duration isn't parsed, so is zero.
+///
+/// # Examples
+///
+/// ```
+/// use nohuman::parse_kraken_stderr;
+///
+/// let stderr = "Loading database information... done.\n\
+///     10 sequences (0.01 Mbp) processed in 0.020s (30.0 Kseq/m, 4.50 Mbp/m).\n\
+///       4 sequences classified (40.00%)\n\
+///       6 sequences unclassified (60.00%)\n";
+/// let stats = parse_kraken_stderr(stderr).unwrap();
+/// assert_eq!((stats.total, stats.classified, stats.unclassified), (10, 4, 6));
+/// ```
+pub fn parse_kraken_stderr(stderr: &str) -> Result<KrakenStats, ParseIntError> {
     let mut total_sequences: usize = 0;
     let mut classified_sequences: usize = 0;
     let mut unclassified_sequences: usize = 0;
@@ -105,20 +838,45 @@ fn parse_kraken_stderr(stderr: &str) -> Result<(usize, usize, usize), ParseIntEr
         }
     }
 
-    Ok((
-        total_sequences,
-        classified_sequences,
-        unclassified_sequences,
-    ))
+    Ok(KrakenStats {
+        total: total_sequences,
+        classified: classified_sequences,
+        unclassified: unclassified_sequences,
+        duration: Duration::ZERO,
+    })
 }
 
 /// A utility function that allows the CLI to error if a path doesn't exist
-pub fn check_path_exists<S: AsRef<OsStr> + ?Sized>(s: &S) -> Result<PathBuf, String> {
+pub fn check_path_exists<S: AsRef<OsStr> + ?Sized>(s: &S) -> Result<PathBuf, NohumanError> {
     let path = PathBuf::from(s);
     if path.exists() {
         Ok(path)
     } else {
-        Err(format!("{:?} does not exist", path))
+        Err(NohumanError::PathNotFound(path))
+    }
+}
+
+/// The path used on the command line to mean stdin (for inputs) or stdout (for outputs).
+pub const STDIO_PATH: &str = "-";
+
+/// Check if `path` refers to stdin/stdout (i.e., is `-`).
+pub fn is_stdio(path: &Path) -> bool {
+    path.as_os_str() == STDIO_PATH
+}
+
+/// Check if `path` is a pipe or another file that isn't a regular file or directory, e.g. a
+/// process substitution like `<(zcat reads.fq.gz)`. These can only be read once.
+pub fn is_pipe(path: &Path) -> bool {
+    std::fs::metadata(path).is_ok_and(|metadata| !metadata.is_file() && !metadata.is_dir())
+}
+
+/// Like [`check_path_exists`], but also allows `-` (stdin) and glob patterns (see
+/// [`batch::is_glob`]).
+pub fn check_input_path<S: AsRef<OsStr> + ?Sized>(s: &S) -> Result<PathBuf, NohumanError> {
+    if s.as_ref() == STDIO_PATH || batch::is_glob(&s.as_ref().to_string_lossy()) {
+        Ok(PathBuf::from(s))
+    } else {
+        check_path_exists(s)
     }
 }
 
@@ -131,9 +889,9 @@ pub fn check_path_exists<S: AsRef<OsStr> + ?Sized>(s: &S) -> Result<PathBuf, Str
 ///
 /// # Returns
 ///
-/// * `Result<PathBuf, String>` - Ok with the valid path if the files are found, Err otherwise.
-pub fn validate_db_directory(path: &Path) -> Result<PathBuf, String> {
-    let required_files = ["hash.k2d", "opts.k2d", "taxo.k2d"];
+/// * `Result<PathBuf, NohumanError>` - Ok with the valid path if the files are found, Err otherwise.
+pub fn validate_db_directory(path: &Path) -> Result<PathBuf, NohumanError> {
+    let required_files = database::INDEX_FILES;
     let files_str = required_files.join(", ");
 
     // Check if the path is a directory and contains the required files
@@ -151,25 +909,261 @@ pub fn validate_db_directory(path: &Path) -> Result<PathBuf, String> {
         return Ok(db_path);
     }
 
-    Err(format!(
-        "Required files ({}) not found in {:?} or its 'db' subdirectory",
-        files_str, path
-    ))
+    Err(NohumanError::MissingDb {
+        path: path.to_path_buf(),
+        files: files_str,
+    })
+}
+
+/// The memory available for loading a database, in bytes. This is the smaller of the system's
+/// available memory and what is left of any cgroup (e.g. container) memory limit. `None` if it
+/// can't be determined, e.g. when not on Linux.
+pub fn available_memory() -> Option<u64> {
+    let system = std::fs::read_to_string("/proc/meminfo")
+        .ok()
+        .and_then(|meminfo| parse_meminfo(&meminfo));
+    let cgroup = std::fs::read_to_string("/sys/fs/cgroup/memory.max")
+        .ok()
+        .and_then(|max| max.trim().parse::<u64>().ok())
+        .map(|max| {
+            let current = std::fs::read_to_string("/sys/fs/cgroup/memory.current")
+                .ok()
+                .and_then(|current| current.trim().parse::<u64>().ok())
+                .unwrap_or_default();
+            max.saturating_sub(current)
+        });
+    match (system, cgroup) {
+        (Some(system), Some(cgroup)) => Some(system.min(cgroup)),
+        (system, cgroup) => system.or(cgroup),
+    }
+}
+
+/// Get the available memory, in bytes, from the contents of /proc/meminfo.
+fn parse_meminfo(meminfo: &str) -> Option<u64> {
+    let line = meminfo
+        .lines()
+        .find(|line| line.starts_with("MemAvailable:"))?;
+    let kb: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kb * 1024)
+}
+
+/// Whether kraken2 should be run with `--memory-mapping` for the database in `k2d_dir`, rather
+/// than loading it into memory. This is the case if `low_memory` is set, or the database is larger
+/// than the [`available_memory`], in which case a warning is logged.
+pub fn use_memory_mapping(k2d_dir: &Path, low_memory: bool) -> bool {
+    if low_memory {
+        return true;
+    }
+    let db_size: u64 = database::INDEX_FILES
+        .iter()
+        .filter_map(|name| std::fs::metadata(k2d_dir.join(name)).ok())
+        .map(|metadata| metadata.len())
+        .sum();
+    match available_memory() {
+        Some(available) if db_size > available => {
+            warn!(
+                "The database ({}) is larger than the available memory ({}), so it will be memory-mapped (as with --low-mem) instead of loaded. This is slower",
+                indicatif::HumanBytes(db_size),
+                indicatif::HumanBytes(available)
+            );
+            true
+        }
+        available => {
+            debug!(
+                "Database size: {} bytes; available memory: {:?} bytes",
+                db_size, available
+            );
+            false
+        }
+    }
+}
+
+/// Overwrite a file with zeros, flush the overwrite to disk, and then remove the file.
+///
+/// Note, this can't guarantee the original data is unrecoverable on copy-on-write or journaling
+/// filesystems, or on SSDs with wear levelling.
+pub fn shred_file(path: &Path) -> io::Result<()> {
+    let mut file = OpenOptions::new().write(true).open(path)?;
+    let zeros = [0u8; 64 * 1024];
+    let mut remaining = file.metadata()?.len();
+    while remaining > 0 {
+        let n = std::cmp::min(remaining, zeros.len() as u64) as usize;
+        file.write_all(&zeros[..n])?;
+        remaining -= n as u64;
+    }
+    file.sync_all()?;
+    drop(file);
+    std::fs::remove_file(path)
+}
+
+/// Shred (see [`shred_file`]) every file under `dir`, recursively. `dir` itself is left in place.
+pub fn shred_dir(dir: &Path) -> io::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            shred_dir(&entry.path())?;
+            std::fs::remove_dir(entry.path())?;
+        } else if file_type.is_file() {
+            shred_file(&entry.path())?;
+        } else {
+            std::fs::remove_file(entry.path())?;
+        }
+    }
+    Ok(())
+}
+
+/// Shreds (see [`shred_dir`]) the contents of a directory when dropped, so reads aren't left behind
+/// in recoverable deleted files if nohuman exits early with an error. Does nothing if the directory
+/// is `None`.
+pub struct ShredGuard(Option<PathBuf>);
+
+impl ShredGuard {
+    pub fn new(dir: Option<PathBuf>) -> Self {
+        Self(dir)
+    }
+}
+
+impl Drop for ShredGuard {
+    fn drop(&mut self) {
+        if let Some(dir) = self.0.as_ref().filter(|dir| dir.exists()) {
+            if let Err(e) = shred_dir(dir) {
+                warn!(
+                    "Failed to securely delete temporary files in {:?}: {}",
+                    dir, e
+                );
+            }
+        }
+    }
+}
+
+/// Keep `tmpdir` when it is dropped, e.g. to inspect the reads kraken2 wrote when debugging, and
+/// log where it is. It is still removed if nohuman is interrupted.
+pub fn keep_tmpdir(tmpdir: &mut tempfile::TempDir) {
+    tmpdir.disable_cleanup(true);
+    info!("Temporary files will be kept in {:?}", tmpdir.path());
+}
+
+/// Remove a temporary directory, securely deleting its contents first if `secure_delete` is set.
+/// Failing to remove the directory only issues a warning.
+pub fn cleanup_tmpdir(tmpdir: tempfile::TempDir, secure_delete: bool) -> io::Result<()> {
+    if secure_delete {
+        shred_dir(tmpdir.path())?;
+        info!("Temporary files securely deleted");
+    }
+
+    if let Err(e) = tmpdir.close() {
+        warn!("Failed to remove temporary output directory: {}", e);
+    }
+    Ok(())
+}
+
+/// Parse a duration from the command line: a number of seconds (e.g. "3600"), or a duration with
+/// units (e.g. "1h 30m").
+pub fn parse_duration(s: &str) -> Result<Duration, humantime::DurationError> {
+    match s.trim().parse::<u64>() {
+        Ok(seconds) => Ok(Duration::from_secs(seconds)),
+        Err(_) => humantime::parse_duration(s),
+    }
 }
 
 /// Parse confidence score from the command line. Will be passed on to kraken2. Must be in the
 /// closed interval [0, 1] - i.e. 0 <= confidence <= 1.
-pub fn parse_confidence_score(s: &str) -> Result<f32, String> {
-    let confidence: f32 = s.parse().map_err(|_| "Confidence score must be a number")?;
+pub fn parse_confidence_score(s: &str) -> Result<f32, NohumanError> {
+    let confidence: f32 = s
+        .parse()
+        .map_err(|_| NohumanError::InvalidConfidence(s.to_string()))?;
     if !(0.0..=1.0).contains(&confidence) {
-        return Err("Confidence score must be in the closed interval [0, 1]".to_string());
+        return Err(NohumanError::InvalidConfidence(s.to_string()));
     }
     Ok(confidence)
 }
 
+/// Parse a fraction, which must be in [0, 1].
+pub fn parse_fraction(s: &str) -> Result<f64, NohumanError> {
+    let fraction: f64 = s
+        .parse()
+        .map_err(|_| NohumanError::InvalidFraction(s.to_string()))?;
+    if !(0.0..=1.0).contains(&fraction) {
+        return Err(NohumanError::InvalidFraction(s.to_string()));
+    }
+    Ok(fraction)
+}
+
+/// The number of CPUs nohuman can use. This accounts for CPU affinity and cgroup (e.g. container
+/// or job scheduler) quotas, and is 1 if it can't be determined.
+pub fn available_threads() -> NonZeroU32 {
+    thread::available_parallelism()
+        .ok()
+        .and_then(|n| NonZeroU32::new(u32::try_from(n.get()).unwrap_or(u32::MAX)))
+        .unwrap_or(NonZeroU32::MIN)
+}
+
+/// Parse a number of threads. "auto" or 0 is all the [`available_threads`].
+pub fn parse_threads(s: &str) -> Result<NonZeroU32, NohumanError> {
+    if s.eq_ignore_ascii_case("auto") {
+        return Ok(available_threads());
+    }
+    let threads: u32 = s
+        .parse()
+        .map_err(|_| NohumanError::InvalidThreads(s.to_string()))?;
+    Ok(NonZeroU32::new(threads).unwrap_or_else(available_threads))
+}
+
+/// Reduce `threads` to the [`available_threads`], warning if it is more. Running more threads than
+/// there are CPUs only slows kraken2 down, and on a shared node takes time from other jobs.
+pub fn clamp_threads(threads: NonZeroU32) -> NonZeroU32 {
+    let available = available_threads();
+    if threads > available {
+        warn!(
+            "{} threads were requested, but only {} CPUs are available. Using {} threads",
+            threads, available, available
+        );
+        return available;
+    }
+    threads
+}
+
+/// Flags that nohuman sets on the kraken2 command itself and therefore cannot be passed through
+/// by the user.
+pub const MANAGED_KRAKEN2_FLAGS: &[&str] = &[
+    "--threads",
+    "--db",
+    "--output",
+    "--confidence",
+    "--paired",
+    "--classified-out",
+    "--unclassified-out",
+];
+
+/// Split a string of extra kraken2 arguments into individual arguments, using shell-like
+/// quoting rules. Errors if the string can't be split, or if any of the arguments are flags that
+/// nohuman already manages (see [`MANAGED_KRAKEN2_FLAGS`]). kraken2 accepts abbreviated flags
+/// (e.g. `--thread`), so an abbreviation of a managed flag is rejected too.
+pub fn parse_kraken2_args(s: &str) -> Result<Vec<String>, NohumanError> {
+    let args = shlex::split(s).ok_or_else(|| NohumanError::InvalidKraken2Args(s.to_string()))?;
+
+    for arg in &args {
+        // handle the --flag=value form
+        let flag = arg.split('=').next().unwrap_or_default();
+        if flag.len() <= 2 || !flag.starts_with("--") {
+            continue;
+        }
+        if let Some(managed) = MANAGED_KRAKEN2_FLAGS
+            .iter()
+            .find(|managed| managed.starts_with(flag))
+        {
+            return Err(NohumanError::ManagedKraken2Arg(managed.to_string()));
+        }
+    }
+
+    Ok(args)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::sync::Mutex;
 
     #[test]
     fn test_new() {
@@ -192,6 +1186,7 @@ mod tests {
     }
 
     #[test]
+    #[cfg(unix)]
     fn test_is_executable() {
         let command = CommandRunner::new("ls");
         assert!(command.is_executable());
@@ -203,6 +1198,22 @@ mod tests {
         assert!(!command.is_executable());
     }
 
+    #[test]
+    #[cfg(unix)]
+    fn test_command_path() {
+        let path = CommandRunner::new("sh").path().unwrap();
+        assert!(path.is_absolute());
+        assert_eq!(
+            CommandRunner::new(&path.to_string_lossy()).path(),
+            Some(path)
+        );
+        assert!(CommandRunner::new("not-a-real-command").path().is_none());
+        assert!(CommandRunner::new("/not/a/real/command").path().is_none());
+        // a file that isn't executable isn't a command
+        assert!(find_executable("Cargo.toml").is_none());
+        assert!(find_executable("./Cargo.toml").is_none());
+    }
+
     #[test]
     fn check_path_exists_it_doesnt() {
         let result = check_path_exists(OsStr::new("fake.path"));
@@ -216,6 +1227,113 @@ mod tests {
         assert_eq!(actual, expected)
     }
 
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("90").unwrap(), Duration::from_secs(90));
+        assert_eq!(parse_duration("1h 30m").unwrap(), Duration::from_secs(5400));
+        assert!(parse_duration("soon").is_err());
+    }
+
+    #[test]
+    fn test_run_timeout() {
+        let command = CommandRunner::new("sleep").with_timeout(Some(Duration::from_millis(200)));
+        let start = Instant::now();
+        let result = command.run(&["10"]);
+        assert!(matches!(result, Err(NohumanError::KrakenTimedOut { .. })));
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_run_retries_out_of_memory() {
+        // killed, unless run with --memory-mapping
+        let script =
+            r#"for arg in "$0" "$@"; do [ "$arg" = --memory-mapping ] && exit 0; done; kill -9 $$"#;
+        assert!(CommandRunner::new("sh").run(&["-c", script]).is_ok());
+
+        // not retried if --memory-mapping was already used
+        let result = CommandRunner::new("sh").run(&["-c", "kill -9 $$", "--memory-mapping"]);
+        assert!(matches!(
+            result,
+            Err(NohumanError::KrakenFailed {
+                advice: Some(_),
+                ..
+            })
+        ));
+    }
+
+    /// A kraken2 that records its arguments, and runs out of memory unless given
+    /// --memory-mapping.
+    #[derive(Debug, Default)]
+    struct FakeKraken2 {
+        calls: Mutex<Vec<Vec<String>>>,
+    }
+
+    impl Executor for FakeKraken2 {
+        fn execute(&self, _: &CommandRunner, args: &[&OsStr]) -> Result<Execution, NohumanError> {
+            let args: Vec<String> = args
+                .iter()
+                .map(|arg| arg.to_string_lossy().into())
+                .collect();
+            let mapped = args.iter().any(|arg| arg == "--memory-mapping");
+            self.calls.lock().unwrap().push(args);
+            if !mapped {
+                return Ok(Execution {
+                    signal: Some(9),
+                    ..Default::default()
+                });
+            }
+            Ok(Execution {
+                success: true,
+                stderr: "10 sequences (0.01 Mbp) processed in 0.1s\n  4 sequences classified (40.00%)\n  6 sequences unclassified (60.00%)\n".to_string(),
+                ..Default::default()
+            })
+        }
+    }
+
+    #[test]
+    fn test_run_with_executor() {
+        let fake = Arc::new(FakeKraken2::default());
+        let tmp = tempfile::tempdir().unwrap();
+        let log = tmp.path().join("kraken2.log");
+        let command = CommandRunner::new("kraken2")
+            .with_executor(fake.clone())
+            .with_log(Some(log.clone()));
+        let stats = command.run(&["--db", "db", "in.fq"]).unwrap();
+        assert_eq!(
+            (stats.total, stats.classified, stats.unclassified),
+            (10, 4, 6)
+        );
+
+        let calls = fake.calls.lock().unwrap();
+        assert_eq!(
+            *calls,
+            [
+                vec!["--db", "db", "in.fq"],
+                vec!["--db", "db", "in.fq", "--memory-mapping"]
+            ]
+        );
+        // the runner still logs each run
+        let log = std::fs::read_to_string(&log).unwrap();
+        assert!(log.starts_with(
+            "$ kraken2 --db db in.fq\n$ kraken2 --db db in.fq --memory-mapping\n10 sequences"
+        ));
+    }
+
+    #[test]
+    fn test_run_log() {
+        let tmp = tempfile::tempdir().unwrap();
+        let log = tmp.path().join("kraken2.log");
+        let command = CommandRunner::new("sh").with_log(Some(log.clone()));
+        command.run(&["-c", "echo loading >&2"]).unwrap();
+        command.run(&["-c", "echo done >&2"]).unwrap();
+
+        assert_eq!(
+            std::fs::read_to_string(&log).unwrap(),
+            "$ sh -c echo loading >&2\nloading\n$ sh -c echo done >&2\ndone\n"
+        );
+    }
+
     #[test]
     fn test_parse_confidence_score() {
         let result = parse_confidence_score("0.5");
@@ -236,4 +1354,265 @@ mod tests {
         let result = parse_confidence_score("-0.1");
         assert!(result.is_err());
     }
+
+    #[test]
+    fn test_parse_threads() {
+        let available = available_threads();
+        assert_eq!(parse_threads("4").unwrap().get(), 4);
+        assert_eq!(parse_threads("auto").unwrap(), available);
+        assert_eq!(parse_threads("0").unwrap(), available);
+        assert!(parse_threads("-1").is_err());
+        assert!(parse_threads("all").is_err());
+        assert_eq!(clamp_threads(NonZeroU32::MIN), NonZeroU32::MIN);
+        assert_eq!(clamp_threads(NonZeroU32::MAX), available);
+    }
+
+    #[test]
+    fn test_parse_fraction() {
+        assert_eq!(parse_fraction("0.05").unwrap(), 0.05);
+        assert!(parse_fraction("5%").is_err());
+        assert!(parse_fraction("1.5").is_err());
+    }
+
+    #[test]
+    fn test_parse_kraken2_args() {
+        let result = parse_kraken2_args("--minimum-hit-groups 3 --memory-mapping").unwrap();
+        assert_eq!(
+            result,
+            vec!["--minimum-hit-groups", "3", "--memory-mapping"]
+        );
+
+        let result = parse_kraken2_args("--report 'my report.txt'").unwrap();
+        assert_eq!(result, vec!["--report", "my report.txt"]);
+
+        let result = parse_kraken2_args("").unwrap();
+        assert!(result.is_empty());
+    }
+
+    #[test]
+    fn test_parse_kraken2_args_managed_flag() {
+        let result = parse_kraken2_args("--quick --threads 4");
+        assert!(result.is_err());
+
+        let result = parse_kraken2_args("--confidence=0.1");
+        assert!(result.is_err());
+
+        // kraken2 accepts abbreviations of its flags
+        for args in ["--thread 4", "--conf=0.5", "--unclass x#.fq"] {
+            assert!(matches!(
+                parse_kraken2_args(args),
+                Err(NohumanError::ManagedKraken2Arg(_))
+            ));
+        }
+        assert!(parse_kraken2_args("--use-names --report-minimizer-data -- x").is_ok());
+    }
+
+    #[test]
+    fn test_parse_kraken2_args_unbalanced_quotes() {
+        let result = parse_kraken2_args("--report 'foo");
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_host_from_str() {
+        assert_eq!("human".parse::<Host>().unwrap(), Host::Human);
+        assert_eq!("HUMAN".parse::<Host>().unwrap(), Host::Human);
+        assert_eq!("custom".parse::<Host>().unwrap(), Host::Custom);
+        assert!("mouse".parse::<Host>().is_err());
+        assert!("cow".parse::<Host>().is_err());
+    }
+
+    #[test]
+    fn test_config_database_for() {
+        let config: Config = toml::from_str(
+            r#"
+            database_url = "https://example.com/human.tar.gz"
+            database_urls = ["https://mirror.org/human.tar.gz", "https://example.com/human.tar.gz"]
+            database_md5 = "def"
+            database_sha256 = "123"
+            database_blake3 = "ghi"
+
+            [native]
+            database_url = "https://example.com/native_v1.tar.gz"
+            database_md5 = "jkl"
+            "#,
+        )
+        .unwrap();
+
+        let human = config.database_for(&Host::Human).unwrap();
+        assert_eq!(
+            human.urls(),
+            vec![
+                "https://example.com/human.tar.gz",
+                "https://mirror.org/human.tar.gz"
+            ]
+        );
+        assert_eq!(human.checksum(), Some(Checksum::Blake3("ghi".to_string())));
+        assert_eq!(config.database_for(&Host::Custom), None);
+        let native = config.release_for(&Host::Human, true).unwrap();
+        assert_eq!(native.version(), "native_v1");
+        assert_eq!(config.release_for(&Host::Custom, true), None);
+        assert_eq!(
+            config.release_version(&Host::Human, false, Some("test")),
+            None
+        );
+    }
+
+    #[test]
+    fn test_release_status() {
+        let config: Config = toml::from_str(
+            r#"
+            database_url = "https://example.com/k2_v3.tar.gz"
+            database_md5 = "abc"
+
+            [[previous_releases]]
+            database_url = "https://example.com/k2_v2.tar.gz"
+            database_deprecated = true
+
+            [[previous_releases]]
+            database_url = "https://example.com/k2_v1.tar.gz"
+            database_yanked = true
+            database_deprecated = true
+            "#,
+        )
+        .unwrap();
+
+        let release = config.database_for(&Host::Human).unwrap();
+        assert_eq!(release.version(), "k2_v3");
+        assert_eq!(release.status(), None);
+        assert_eq!(release.find("k2_v2").unwrap().status(), Some("deprecated"));
+        assert_eq!(release.find("k2_v1").unwrap().status(), Some("yanked"));
+        assert_eq!(release.find("k2_v0"), None);
+    }
+
+    #[test]
+    fn test_config_release_version() {
+        let config: Config = toml::from_str(
+            r#"
+            database_url = "https://example.com/k2_v2.tar.gz"
+            database_md5 = "abc"
+
+            [[previous_releases]]
+            database_url = "https://example.com/k2_v1.tar.gz"
+
+            [test]
+            database_url = "https://example.com/k2_chr21_subset.tar.gz"
+            database_version = "test"
+            database_md5 = "def"
+            "#,
+        )
+        .unwrap();
+
+        let version = |host, native, version| {
+            config
+                .release_version(&host, native, version)
+                .map(DatabaseRelease::version)
+        };
+        assert_eq!(version(Host::Human, false, None).unwrap(), "k2_v2");
+        assert_eq!(version(Host::Human, false, Some("k2_v1")).unwrap(), "k2_v1");
+        assert_eq!(version(Host::Human, false, Some("test")).unwrap(), "test");
+        assert_eq!(version(Host::Human, false, Some("k2_v0")), None);
+        assert_eq!(version(Host::Human, true, Some("test")), None);
+        assert_eq!(version(Host::Custom, false, Some("test")), None);
+    }
+
+    #[test]
+    fn test_parse_meminfo() {
+        let meminfo = "MemTotal:       16318412 kB\nMemFree:         1030516 kB\nMemAvailable:    8161076 kB\n";
+        assert_eq!(parse_meminfo(meminfo), Some(8161076 * 1024));
+        assert_eq!(parse_meminfo("MemTotal:       16318412 kB\n"), None);
+    }
+
+    #[test]
+    fn test_use_memory_mapping() {
+        let tmp = tempfile::tempdir().unwrap();
+        for name in database::INDEX_FILES {
+            std::fs::write(tmp.path().join(name), name).unwrap();
+        }
+        assert!(use_memory_mapping(tmp.path(), true));
+        // a tiny database always fits
+        assert!(!use_memory_mapping(tmp.path(), false));
+    }
+
+    #[test]
+    fn test_compare_versions() {
+        assert_eq!(compare_versions("2.1.3", "2.1.3"), Ordering::Equal);
+        assert_eq!(compare_versions("v2.1", "2.1.0"), Ordering::Equal);
+        assert_eq!(compare_versions("2.1.10", "2.1.9"), Ordering::Greater);
+        assert_eq!(compare_versions("2.0.9-beta", "2.1"), Ordering::Less);
+        assert_eq!(
+            parse_version_output("Kraken version 2.1.3\nCopyright 2013-2023, Derrick Wood"),
+            Some("2.1.3".to_string())
+        );
+    }
+
+    #[test]
+    fn test_check_compatibility() {
+        let mut release = Config::new("https://example.com/db.tar.gz", "abc").release;
+        assert!(release.check_compatibility(None).is_ok());
+
+        release.min_kraken2_version = Some("2.1.3".to_string());
+        assert!(release.check_compatibility(None).is_ok());
+        assert!(release.check_compatibility(Some("2.1.3")).is_ok());
+        assert!(matches!(
+            release.check_compatibility(Some("2.0.8-beta")),
+            Err(NohumanError::IncompatibleVersion { program, .. }) if program == "kraken2"
+        ));
+
+        release.min_nohuman_version = Some("999.0.0".to_string());
+        assert!(matches!(
+            release.check_compatibility(Some("2.1.3")),
+            Err(NohumanError::IncompatibleVersion { program, .. }) if program == "nohuman"
+        ));
+    }
+
+    #[test]
+    fn test_shred_file() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("reads.fq");
+        std::fs::write(&path, "@r1\nACGT\n+\nIIII\n").unwrap();
+
+        shred_file(&path).unwrap();
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_shred_dir() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::write(tmp.path().join("a.fq"), "foo").unwrap();
+        std::fs::create_dir(tmp.path().join("sub")).unwrap();
+        std::fs::write(tmp.path().join("sub").join("b.fq"), "bar").unwrap();
+
+        shred_dir(tmp.path()).unwrap();
+        assert!(tmp.path().exists());
+        assert_eq!(std::fs::read_dir(tmp.path()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_check_input_path() {
+        assert_eq!(check_input_path("-").unwrap(), PathBuf::from("-"));
+        assert!(check_input_path("does/not/exist.fq").is_err());
+        assert!(is_stdio(Path::new("-")));
+        assert!(!is_stdio(Path::new("./-")));
+    }
+
+    #[test]
+    fn test_error_variants() {
+        assert!(matches!(
+            parse_confidence_score("2"),
+            Err(NohumanError::InvalidConfidence(_))
+        ));
+        assert!(matches!(
+            validate_db_directory(Path::new("does/not/exist")),
+            Err(NohumanError::MissingDb { .. })
+        ));
+        assert!(matches!(
+            parse_kraken2_args("--threads 2"),
+            Err(NohumanError::ManagedKraken2Arg(flag)) if flag == "--threads"
+        ));
+        assert!(matches!(
+            CommandRunner::new("false").run::<&str>(&[]),
+            Err(NohumanError::KrakenFailed { .. })
+        ));
+    }
 }
@@ -1,52 +1,303 @@
-use std::num::NonZeroU32;
-use std::path::PathBuf;
+use std::collections::HashSet;
+use std::ffi::OsStr;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, IsTerminal, Write};
+use std::num::{NonZeroU32, NonZeroUsize};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
 use std::sync::LazyLock;
+use std::time::{Duration, Instant, SystemTime};
 
 use anyhow::{bail, Context, Result};
-use clap::Parser;
+use clap::builder::{OsStringValueParser, TypedValueParser};
+use clap::parser::ValueSource;
+use clap::{ArgMatches, CommandFactory, FromArgMatches, Parser, Subcommand};
 use env_logger::Builder;
+use indicatif::HumanBytes;
 use log::{debug, error, info, warn, LevelFilter};
-use nohuman::compression::CompressionFormat;
+use nohuman::align::{parse_identity, Aligner, AlignmentFilter, Engines, Minimap2};
+use nohuman::bam::OutputFormat;
+use nohuman::batch::{
+    batch_summary, is_glob, parse_sample_sheet, sample_name, samples_from_directory,
+    samples_from_glob, split_tagged_reads, tag_reads, write_multiqc, write_summary, Sample,
+    SampleCounts,
+};
+use nohuman::bench::{write_tsv, Agreement, BenchRun};
+use nohuman::build::{build_database, build_native_database, BuildOptions};
+use nohuman::calibrate::calibrate;
+use nohuman::compression::{open_reader, CompressionFormat};
+use nohuman::config::{default_config_path, Config};
+use nohuman::container::{Container, ContainerEngine, KRAKEN2_IMAGE};
+use nohuman::deps::{install_kraken2, installed_program, KRAKEN2_VERSION};
+use nohuman::diagnostics::{
+    check_database, check_directory, check_memory, write_table, Check, Status,
+};
+use nohuman::error::FailureKind;
+use nohuman::eval::{write_tsv as write_evaluation_tsv, Evaluation, Truth};
+use nohuman::fastx::{read_ids_from, repair_pairs};
+use nohuman::interrupt::{self, remove_on_interrupt};
+use nohuman::mock::MockClassifier;
+use nohuman::native::{find_native_index, NativeClassifier, DEFAULT_KMER_LEN, DEFAULT_WINDOW_LEN};
+use nohuman::pipeline::{
+    check_outputs, finish_output, output_progress, suffixed_output_path, DehostPipeline,
+    OutputTemplate,
+};
+use nohuman::preset::Preset;
+use nohuman::provenance::{
+    hostname, provenance_path, timestamp, DatabaseRecord, FileRecord, Provenance,
+};
+use nohuman::resources::{format_seconds, ResourceUsage};
+#[cfg(unix)]
+use nohuman::server::Server;
+use nohuman::signature::SignaturePolicy;
+use nohuman::simulate::{simulate, truth_path, Reference, SimulationOptions};
+use nohuman::summary::{Summary, Timings};
+use nohuman::taxonomy::{read_taxid_list, Taxonomy};
 use nohuman::{
-    check_path_exists, download::download_database, parse_confidence_score, validate_db_directory,
-    CommandRunner,
+    check_compatibility, check_input_path, check_path_exists, clamp_threads, cleanup_tmpdir,
+    database::{
+        dir_size, find_database, installed_databases, pin_version, pinned_version, read_pin,
+        resolve_database, DatabaseLock, DatabaseMetadata, FileStatus, IndexOptions,
+    },
+    download::{
+        cached_release, compute_md5, download_database, install_database, install_tarball,
+        latest_release, register_database, Checksum, DownloadOptions,
+    },
+    is_stdio, keep_tmpdir, parse_confidence_score, parse_duration, parse_fraction,
+    parse_kraken2_args, parse_threads, use_memory_mapping, validate_db_directory, CommandRunner,
+    Host, NohumanError, ShredGuard, NULL_DEVICE,
 };
 
-static DEFAULT_DB_LOCATION: LazyLock<String> = LazyLock::new(|| {
+static NOHUMAN_DIR: LazyLock<PathBuf> = LazyLock::new(|| {
     let home = dirs::home_dir().unwrap_or_default();
     home.join(".nohuman")
-        .join("db")
-        .to_string_lossy()
-        .to_string()
 });
 
+/// Parses a path that must exist. The path is taken as it is given, so it needn't be UTF-8.
+fn existing_path() -> impl TypedValueParser<Value = PathBuf> {
+    OsStringValueParser::new().try_map(|path| check_path_exists(&path))
+}
+
+/// Parses an input path, like [`existing_path`], but also allowing `-` and glob patterns.
+fn input_path() -> impl TypedValueParser<Value = PathBuf> {
+    OsStringValueParser::new().try_map(|path| check_input_path(&path))
+}
+
+/// The extra arguments to pass to kraken2: those given with --kraken2-args, followed by the
+/// kraken2 options nohuman exposes directly (e.g. --quick). An option can't be given both ways.
+fn kraken2_args(args: &Args) -> Result<Vec<String>> {
+    let mut kraken2_args = match &args.kraken2_args {
+        Some(s) => parse_kraken2_args(s)?,
+        None => Vec::new(),
+    };
+    let given = |flag: &str| {
+        kraken2_args
+            .iter()
+            .any(|arg| arg == flag || arg.starts_with(&format!("{}=", flag)))
+    };
+    let mut options = Vec::new();
+    if let Some(n) = args.min_hit_groups {
+        options.push((
+            "--min-hit-groups",
+            "--minimum-hit-groups",
+            Some(n.to_string()),
+        ));
+    }
+    if args.quick {
+        options.push(("--quick", "--quick", None));
+    }
+    for (option, flag, _) in &options {
+        if given(flag) {
+            bail!(
+                "{} is set by {}, so can't also be given in --kraken2-args",
+                flag,
+                option
+            );
+        }
+    }
+    for (_, flag, value) in options {
+        kraken2_args.push(flag.to_string());
+        kraken2_args.extend(value);
+    }
+    Ok(kraken2_args)
+}
+
+/// The directory `nohuman deps install` installs programs into. Programs installed there are used
+/// instead of those on the PATH.
+fn bin_dir() -> PathBuf {
+    NOHUMAN_DIR.join("bin")
+}
+
+/// The program `name`, preferring the one installed by `nohuman deps install`.
+fn program(name: &str) -> String {
+    match installed_program(&bin_dir(), name) {
+        Some(path) => path.to_string_lossy().to_string(),
+        None => name.to_string(),
+    }
+}
+
+/// The most recently installed native database in `db_root`, or the pinned version if it is a
+/// native database. `None` if none are installed.
+fn native_database(db_root: &Path) -> Result<Option<PathBuf>> {
+    if let Ok(path) = resolve_database(db_root) {
+        if find_native_index(&path).is_ok() {
+            return Ok(Some(path));
+        }
+    }
+    Ok(installed_databases(db_root)?
+        .into_iter()
+        .rev()
+        .map(|db| db.location().to_path_buf())
+        .find(|path| find_native_index(path).is_ok()))
+}
+
+/// The taxa whose reads are host reads with the kraken2 `database`: the clades of `taxids`, or of
+/// the host species if the database has other taxa too. `None` if every classified read is a host
+/// read.
+fn host_taxa(taxids: &[u64], host: Host, database: &Path) -> Result<Option<HashSet<u64>>> {
+    let taxonomy = validate_db_directory(database)
+        .ok()
+        .and_then(|dir| Taxonomy::read(&dir).ok());
+    if !taxids.is_empty() {
+        let Some(taxonomy) = taxonomy else {
+            warn!("The database taxonomy couldn't be read, so only reads classified as exactly the given taxa are host reads");
+            return Ok(Some(taxids.iter().copied().collect()));
+        };
+        return Ok(Some(taxonomy.clade(taxids)));
+    }
+    match (host.taxid(), taxonomy) {
+        (Some(taxid), Some(taxonomy))
+            if taxonomy.contains(taxid) && !taxonomy.is_lineage_of(taxid) =>
+        {
+            info!(
+                "The database has taxa other than {}, so only reads classified as taxid {} (or below) are {} reads",
+                host, taxid, host
+            );
+            Ok(Some(taxonomy.clade(&[taxid])))
+        }
+        (Some(_), None) => {
+            warn!(
+                "The database taxonomy couldn't be read, so every classified read is a {} read",
+                host
+            );
+            Ok(None)
+        }
+        _ => Ok(None),
+    }
+}
+
+/// The default socket of the nohuman server.
+fn default_socket() -> PathBuf {
+    NOHUMAN_DIR.join("nohuman.sock")
+}
+
+/// The default database root for `host`. This is ~/.nohuman/db for human, and ~/.nohuman/<host>/db
+/// for other hosts. Downloaded databases are installed into a directory named after their version
+/// within the root.
+fn default_db_location(host: &Host) -> PathBuf {
+    match host {
+        Host::Human => NOHUMAN_DIR.join("db"),
+        _ => NOHUMAN_DIR.join(host.to_string()).join("db"),
+    }
+}
+
 #[derive(Parser, Debug)]
-#[command(author, version, about, long_about = None)]
+#[command(author, version, about, long_about = None, subcommand_negates_reqs = true)]
 struct Args {
-    /// Input file(s) to remove human reads from
-    #[arg(name = "INPUT", required_unless_present_any = &["check", "download"], value_parser = check_path_exists, verbatim_doc_comment)]
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Input file(s) to remove host (e.g. human) reads from.
+    ///
+    /// Provide two files for paired-end reads. A third file of unpaired (singleton) reads can be
+    /// given after the pair. Use "-" to read an input from stdin.
+    /// Or give a directory, e.g. a nanopore run's fastq_pass, to process each FASTQ file in it (and
+    /// its subdirectories) as a sample, as with --sample-sheet. The outputs are written to the
+    /// same paths in --out-dir. Or give a (quoted) glob of files, e.g. 'fastqs/*_R{1,2}.fq.gz',
+    /// whose outputs are written next to them. Files named like "in_R1"/"in_R2" or "in_1"/"in_2"
+    /// are paired.
+    #[arg(name = "INPUT", required_unless_present_any = &["check", "download", "sample_sheet"], value_parser = input_path(), verbatim_doc_comment)]
     input: Option<Vec<PathBuf>>,
 
     /// First output file.
     ///
-    /// Defaults to the name of the first input file with the suffix "nohuman" appended.
-    /// e.g. "input_1.fastq" -> "input_1.nohuman.fq".
+    /// Defaults to the name of the first input file with the suffix "nohuman" (or "no<host>") appended.
+    /// e.g. "input_1.fastq" -> "input_1.nohuman.fastq".
     /// Compression of the output file is determined by the file extension of the output file name.
     /// Or by using the `--output-type` option. If no output path is given, the same compression
     /// as the input file will be used.
+    /// Use "-" to write to stdout. This is the default if the input is read from stdin.
     #[arg(short, long, name = "OUTPUT_1", verbatim_doc_comment)]
     pub out1: Option<PathBuf>,
     /// Second output file.
     ///
-    /// Defaults to the name of the first input file with the suffix "nohuman" appended.
-    /// e.g. "input_2.fastq" -> "input_2.nohuman.fq".
+    /// Defaults to the name of the first input file with the suffix "nohuman" (or "no<host>") appended.
+    /// e.g. "input_2.fastq" -> "input_2.nohuman.fastq".
     /// Compression of the output file is determined by the file extension of the output file name.
     /// Or by using the `--output-type` option. If no output path is given, the same compression
     /// as the input file will be used.
+    /// Use "-" to write to stdout. This is the default if the input is read from stdin.
     #[arg(short = 'O', long, name = "OUTPUT_2", verbatim_doc_comment)]
     pub out2: Option<PathBuf>,
+    /// Third output file - for the singletons input.
+    ///
+    /// Defaults to the name of the third input file with the suffix "nohuman" (or "no<host>") appended.
+    /// e.g. "input_singletons.fastq" -> "input_singletons.nohuman.fastq".
+    /// Compression of the output file is determined as for the other output files.
+    #[arg(long, name = "OUTPUT_3", verbatim_doc_comment)]
+    pub out3: Option<PathBuf>,
+
+    /// A template for the names of the output files that aren't given with --out1, --out2, or
+    /// --out3, e.g. "{stem}.dehosted.fastq". The tokens are:
+    ///   {stem}    the input file name without extensions (or the sample's output prefix)
+    ///   {read}    the number of the input (1, 2, or 3 for singletons)
+    ///   {ext}     the extension of the input, without compression (e.g. fastq)
+    ///   {comp}    the compression extension of the output (e.g. .gz), or nothing
+    ///   {suffix}  "nohuman" (or "no<host>")
+    /// The compression extension is appended if the template doesn't have {comp}.
+    #[arg(long, value_name = "TEMPLATE", verbatim_doc_comment)]
+    pub out_template: Option<OutputTemplate>,
+
+    /// Don't check the inputs are valid FASTA/FASTQ before running kraken2.
+    ///
+    /// By default, the first reads of each input are parsed and compressed inputs are read to
+    /// the end, so malformed or truncated files are found before the database is loaded.
+    #[arg(long, verbatim_doc_comment)]
+    skip_validation: bool,
+
+    /// Re-pair paired inputs that are out of sync, rather than failing.
+    ///
+    /// Paired inputs are checked to have the same number of reads, and the same read IDs for the
+    /// first reads. If they don't, only the reads in both files are kept, and the mates are put in
+    /// the same order. Reads without a mate are dropped.
+    #[arg(long, conflicts_with_all = ["sample_sheet", "interleaved"], verbatim_doc_comment)]
+    repair_pairs: bool,
+
+    /// Only warn, rather than fail, if the reads in the outputs don't add up.
+    ///
+    /// After the outputs are written, their reads are counted and checked against the number of
+    /// sequences kept, and paired outputs are checked to have the same number of reads.
+    #[arg(long, conflicts_with = "sample_sheet", verbatim_doc_comment)]
+    no_verify: bool,
+
+    /// Write MD5 and SHA256 checksums of every output file to .md5 and .sha256 files next to it.
+    ///
+    /// This covers the output reads, and --kraken-output and a kraken2 --report if given. The
+    /// files can be checked with `md5sum -c` or `sha256sum -c`. Outputs written to stdout are
+    /// skipped.
+    #[arg(long, verbatim_doc_comment)]
+    write_checksums: bool,
+
+    /// Keep the temporary files, e.g. the reads kraken2 selected before they were compressed and
+    /// renamed, and log where they are. This is useful for debugging.
+    #[arg(long, conflicts_with = "secure_delete", verbatim_doc_comment)]
+    keep_tmp: bool,
 
-    /// Check that all required dependencies are available and exit.
+    /// Overwrite output files that already exist. An input file is never overwritten.
+    #[arg(long)]
+    force: bool,
+
+    /// Check the dependencies, database, memory, and disk space needed for a run, and exit.
     #[arg(short, long)]
     check: bool,
 
@@ -54,261 +305,3049 @@ struct Args {
     #[arg(short, long)]
     download: bool,
 
+    /// Number of times to retry a failed database download (from each URL)
+    #[arg(long, value_name = "INT", default_value = "2")]
+    download_retries: u32,
+
+    /// Give up on a database download if connecting, or waiting for data, takes longer than this.
+    ///
+    /// e.g. "30s" or "5m". By default, there is no timeout.
+    #[arg(long, value_name = "DURATION", value_parser = humantime::parse_duration, verbatim_doc_comment)]
+    download_timeout: Option<Duration>,
+
+    /// Number of chunks of the database to download concurrently
+    ///
+    /// Only used if the server supports range requests. Otherwise, the database is downloaded in
+    /// a single stream.
+    #[arg(long, value_name = "INT", default_value = "1", verbatim_doc_comment)]
+    download_threads: NonZeroUsize,
+
+    /// Keep the downloaded database tarball, e.g. to copy it to another machine.
+    ///
+    /// The tarball is kept in the current directory by default, or at PATH if given. If PATH is a
+    /// directory, the tarball is kept in it.
+    #[arg(
+        long,
+        value_name = "PATH",
+        num_args = 0..=1,
+        default_missing_value = ".",
+        verbatim_doc_comment
+    )]
+    keep_tarball: Option<PathBuf>,
+
+    /// Verify the minisign signatures of the list of database releases and the database tarball
+    /// with this public key (or public key file).
+    ///
+    /// Signatures are read from the file or URL with ".minisig" appended. The download fails if a
+    /// signature is missing or doesn't validate.
+    #[arg(
+        long,
+        value_name = "KEY",
+        env = "NOHUMAN_TRUSTED_KEY",
+        verbatim_doc_comment
+    )]
+    trusted_key: Option<String>,
+
+    /// Only warn, rather than fail, if a file has no signature when using --trusted-key.
+    ///
+    /// Invalid signatures are always an error.
+    #[arg(long, requires = "trusted_key", verbatim_doc_comment)]
+    allow_unsigned: bool,
+
+    /// Read the list of database releases from this file or URL (http, https, s3, or gs) instead
+    /// of the nohuman repository
+    #[arg(long, value_name = "PATH_OR_URL", env = "NOHUMAN_MANIFEST")]
+    manifest: Option<String>,
+
+    /// Download the list of database releases, rather than using the copy cached in the database
+    /// directory.
+    ///
+    /// The list is otherwise only downloaded once a day, with the cached copy used if it can't be
+    /// downloaded (e.g. when offline).
+    #[arg(long, verbatim_doc_comment)]
+    refresh_manifest: bool,
+
+    /// Download the database, if it isn't installed, before removing host reads.
+    ///
+    /// Without this, you are asked whether to download it when running in a terminal.
+    #[arg(long, conflicts_with = "download", verbatim_doc_comment)]
+    auto_download: bool,
+
+    /// Only warn, rather than fail, if the database requires a newer version of nohuman or
+    /// kraken2.
+    ///
+    /// Database releases can list the oldest nohuman and kraken2 versions they work with. These
+    /// are checked when downloading the database and before running kraken2.
+    #[arg(long, verbatim_doc_comment)]
+    ignore_compatibility: bool,
+
+    /// Download the database release even if it has been yanked (marked as not to be used).
+    #[arg(long)]
+    allow_yanked: bool,
+
+    /// Classify reads with a running `nohuman serve`, instead of loading the database.
+    ///
+    /// Connects to the server listening on SOCKET (e.g. --server=/path/to/nohuman.sock), or
+    /// ~/.nohuman/nohuman.sock if not given.
+    #[arg(
+        long,
+        value_name = "SOCKET",
+        num_args = 0..=1,
+        require_equals = true,
+        env = "NOHUMAN_SERVER",
+        verbatim_doc_comment
+    )]
+    server: Option<Option<PathBuf>>,
+
+    /// The kraken2 executable to use, instead of the first kraken2 on the PATH.
+    #[arg(
+        long,
+        value_name = "PATH",
+        env = "NOHUMAN_KRAKEN2",
+        value_parser = existing_path()
+    )]
+    kraken2_path: Option<PathBuf>,
+
+    /// Stop kraken2 if it runs for longer than this, e.g. "3600" (seconds) or "2h".
+    ///
+    /// kraken2 (and any processes it started) is killed, and the temporary files are removed.
+    /// By default, there is no timeout.
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration, verbatim_doc_comment)]
+    timeout: Option<Duration>,
+
+    /// If kraken2 isn't installed, run it in a container with this engine (docker or apptainer).
+    ///
+    /// The directories of the database, inputs, outputs, and temporary files are bind-mounted
+    /// into the container.
+    #[arg(
+        long,
+        value_name = "ENGINE",
+        env = "NOHUMAN_CONTAINER",
+        verbatim_doc_comment
+    )]
+    container: Option<ContainerEngine>,
+
+    /// The image to run kraken2 from with --container.
+    #[arg(long, value_name = "IMAGE", default_value = KRAKEN2_IMAGE)]
+    container_image: String,
+
+    /// How to find host reads: classify them with kraken2, align them to a host reference
+    /// genome (--reference) with minimap2, or classify them with nohuman's built-in classifier.
+    ///
+    /// With minimap2, reads with an alignment passing --min-identity and --min-aligned-len are
+    /// host reads, and no database is needed. The built-in classifier (native) needs no external
+    /// programs, but uses its own database (see `db build --native`) and is less sensitive than
+    /// kraken2.
+    /// It uses --conf and --min-hit-groups like kraken2. mock marks a random fraction of the reads
+    /// (--mock-fraction) as host without classifying them, to test a workflow without kraken2 or a
+    /// database.
+    ///
+    /// Engines can be combined with +, e.g. kraken2+minimap2, to remove only the reads at least
+    /// --min-votes of them flag as host.
+    #[arg(
+        long = "engine",
+        alias = "aligner",
+        value_name = "ENGINE",
+        default_value = "kraken2",
+        verbatim_doc_comment
+    )]
+    engine: Engines,
+
+    /// With several engines (e.g. --engine kraken2+minimap2), the number of them that must flag a
+    /// read as host for it to be removed. Defaults to all of them, so a read is kept unless they
+    /// all agree it is a host read. With 1, a read is kept only if they all agree it isn't.
+    #[arg(long, value_name = "INT", verbatim_doc_comment)]
+    min_votes: Option<NonZeroUsize>,
+
+    /// The host reference genome to align reads to with --engine minimap2 or
+    /// --confirm-with-alignment, as FASTA or a minimap2 index (e.g. hg38.mmi). An index is much
+    /// quicker to load.
+    #[arg(
+        long,
+        value_name = "PATH",
+        value_parser = existing_path(),
+        conflicts_with = "sample_sheet",
+        verbatim_doc_comment
+    )]
+    reference: Option<PathBuf>,
+
+    /// Only remove the reads kraken2 classifies as host if they also align to --reference with
+    /// minimap2 (see --min-identity and --min-aligned-len).
+    ///
+    /// Classified reads that don't align are rescued, i.e. kept in the output as non-host reads.
+    /// This reduces the non-host reads removed by spurious kraken2 classifications.
+    #[arg(long, requires = "reference", verbatim_doc_comment)]
+    confirm_with_alignment: bool,
+
+    /// The minimum identity (matching bases / alignment length) of an alignment that makes a read a
+    /// host read, with --engine minimap2.
+    #[arg(long, value_name = "[0, 1]", default_value = "0.9", value_parser = parse_identity, verbatim_doc_comment)]
+    min_identity: f64,
+
+    /// The minimum length of an alignment that makes a read a host read, with --engine minimap2.
+    #[arg(long, value_name = "INT", default_value = "50")]
+    min_aligned_len: u64,
+
+    /// The minimap2 preset (-x) to align with, e.g. "sr" for short reads or "map-ont" for
+    /// nanopore reads. minimap2's default if not given.
+    #[arg(long, value_name = "PRESET", verbatim_doc_comment)]
+    minimap2_preset: Option<String>,
+
+    /// The fraction of the reads marked as host with --engine mock.
+    #[arg(long, value_name = "[0, 1]", default_value = "0.1", value_parser = parse_fraction)]
+    mock_fraction: f64,
+
+    /// The seed that picks the reads marked as host with --engine mock. The same seed marks the
+    /// same reads.
+    #[arg(long, value_name = "INT", default_value = "0", verbatim_doc_comment)]
+    mock_seed: u64,
+
     /// Path to the database
-    #[arg(short = 'D', long = "db", value_name = "PATH", default_value = &**DEFAULT_DB_LOCATION)]
-    database: PathBuf,
+    ///
+    /// Defaults to the most recently installed (or pinned, see `nohuman db pin`) database in
+    /// ~/.nohuman/db for human, or ~/.nohuman/<host>/db for other hosts. Required for a custom host, unless a database has
+    /// been installed for it with `nohuman --host custom db install --from-file`.
+    #[arg(short = 'D', long = "db", value_name = "PATH", verbatim_doc_comment)]
+    database: Option<PathBuf>,
+
+    /// Use this installed database version, instead of the default (see --db). With --download,
+    /// it is downloaded if it isn't installed
+    #[arg(long, value_name = "VERSION", conflicts_with = "database")]
+    db_version: Option<String>,
 
-    /// Output compression format. u: uncompressed; b: Bzip2; g: Gzip; x: Xz (Lzma); z: Zstd
+    /// The host organism to remove reads of. human or custom.
+    ///
+    /// A custom host requires a kraken2 database to be provided with --db (or installed with
+    /// `db install --from-file`). Reads classified against that database are treated as host
+    /// reads.
+    #[arg(
+        long,
+        value_name = "HOST",
+        default_value = "human",
+        global = true,
+        verbatim_doc_comment
+    )]
+    host: Host,
+
+    /// Output compression format. u: uncompressed; b: Bzip2; g: Gzip; x: Xz (Lzma); z: Zstd; l: LZ4; bgz: BGZF
     ///
     /// If not provided, the format will be inferred from the given output file name(s), or the
     /// format of the input file(s) if no output file name(s) are given.
+    /// BGZF (as written by bgzip, with a .bgz extension) is gzip that htslib-based tools can index.
     #[clap(short = 'F', long, value_name = "FORMAT", verbatim_doc_comment)]
     pub output_type: Option<CompressionFormat>,
 
-    /// Number of threads to use in kraken2 and optional output compression. Cannot be 0.
-    #[arg(short, long, value_name = "INT", default_value = "1")]
+    /// Output file format. fastx: the same as the input; bam: unaligned BAM (uBAM).
+    ///
+    /// With bam, both reads of a pair are written to the first output, and default output names
+    /// end in ".bam", e.g. "input_1.fastq.gz" -> "input_1.nohuman.bam".
+    #[arg(
+        long,
+        value_name = "FORMAT",
+        default_value = "fastx",
+        conflicts_with_all = ["output_type", "stats_only", "sample_sheet"],
+        verbatim_doc_comment
+    )]
+    output_format: OutputFormat,
+
+    /// Number of threads to use in kraken2 and optional output compression. Use "auto" (or 0) for
+    /// all the available CPUs. More threads than there are CPUs are reduced to the number of CPUs.
+    #[arg(short, long, value_name = "INT", default_value = "1", value_parser = parse_threads)]
     threads: NonZeroU32,
 
-    /// Output human reads instead of removing them
+    /// Output host (e.g. human) reads instead of removing them
     #[arg(short = 'H', long = "human")]
     keep_human_reads: bool,
 
+    /// Only report how many reads are host reads, without writing any output files.
+    ///
+    /// This is quicker, and needs no disk space for the output. Use --summary to also write the
+    /// counts as JSON.
+    #[arg(
+        long,
+        conflicts_with_all = ["OUTPUT_1", "OUTPUT_2", "OUTPUT_3", "out_template", "output_type", "sample_sheet"],
+        verbatim_doc_comment
+    )]
+    stats_only: bool,
+
+    /// Exit with code 6 if more than this fraction of sequences are host (e.g. human) sequences,
+    /// e.g. 0.05 for 5%. The output is still written.
+    ///
+    /// This lets workflow managers act on the contamination level without parsing the log.
+    #[arg(long, value_name = "[0, 1]", value_parser = parse_fraction, conflicts_with = "sample_sheet", verbatim_doc_comment)]
+    max_human_frac: Option<f64>,
+
+    /// Exit with code 6 if less than this fraction of sequences are host sequences, e.g. to check
+    /// a sample is mostly host when keeping host reads with --human.
+    #[arg(long, value_name = "[0, 1]", value_parser = parse_fraction, conflicts_with = "sample_sheet", verbatim_doc_comment)]
+    min_human_frac: Option<f64>,
+
+    /// Warn if less than this fraction of sequences are host sequences, for inputs known to be
+    /// host-derived (e.g. human clinical samples) [default: 0.001]
+    ///
+    /// Removing almost no reads from such inputs usually means the wrong database is in use.
+    /// Unlike --min-human-frac, this only warns. With --sample-sheet, each sample is checked. Give
+    /// a fraction with e.g. --expect-human=0.01.
+    #[arg(
+        long,
+        value_name = "[0, 1]",
+        value_parser = parse_fraction,
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "0.001",
+        verbatim_doc_comment
+    )]
+    expect_human: Option<f64>,
+
+    /// Write the IDs of the host reads removed (or kept, with --human) to this file, one per line
+    /// in sorted order, e.g. to check which reads were classified as host.
+    #[arg(
+        long,
+        value_name = "FILE",
+        conflicts_with = "sample_sheet",
+        verbatim_doc_comment
+    )]
+    removed_ids: Option<PathBuf>,
+
     /// Kraken2 minimum confidence score
     #[arg(short = 'C', long = "conf", value_name = "[0, 1]", default_value = "0.0", value_parser = parse_confidence_score)]
     confidence: f32,
 
+    /// Choose --conf from a negative control: a sample (or pair of files) known to have no host
+    /// reads. It is classified first, and the lowest confidence (in steps of 0.01) at which at most
+    /// --control-max-frac of its sequences are classified is used for the inputs.
+    ///
+    /// This takes precedence over the confidence from a --preset or config file. As it takes one
+    /// or two files, give INPUT after -- or another option.
+    #[arg(
+        long,
+        value_name = "FILE",
+        num_args = 1..=2,
+        value_parser = existing_path(),
+        conflicts_with = "confidence",
+        verbatim_doc_comment
+    )]
+    negative_control: Option<Vec<PathBuf>>,
+
+    /// The largest fraction of the --negative-control sequences that may be classified as host
+    #[arg(long, value_name = "[0, 1]", default_value = "0.001", value_parser = parse_fraction, requires = "negative_control")]
+    control_max_frac: f64,
+
+    /// Tune the options for a read technology, or how aggressively host reads are removed.
+    /// illumina, ont, pacbio, strict, or lenient.
+    ///
+    /// A preset sets --conf, --min-hit-groups, --min-identity, and (for a read technology)
+    /// --minimap2-preset. Options given on the command line take precedence.
+    #[arg(long, value_name = "PRESET", verbatim_doc_comment)]
+    preset: Option<Preset>,
+
+    /// Kraken2 minimum number of hit groups (overlapping k-mers sharing the same minimizer)
+    /// needed to classify a read. kraken2's default is 2.
+    ///
+    /// Higher values reduce false positive host classifications, at the cost of sensitivity.
+    #[arg(long, value_name = "INT", verbatim_doc_comment)]
+    min_hit_groups: Option<NonZeroU32>,
+
+    /// The taxonomy IDs of the host, e.g. --taxid 9606,10090. Reads kraken2 classifies as one of
+    /// these taxa, or a taxon below them, are host reads.
+    ///
+    /// Defaults to the host species (e.g. 9606 for human), but only if the database has other
+    /// taxa too (e.g. kraken2's standard database). Otherwise, every classified read is a host
+    /// read.
+    #[arg(
+        long = "taxid",
+        value_name = "TAXID",
+        value_delimiter = ',',
+        verbatim_doc_comment
+    )]
+    taxids: Vec<u64>,
+
+    /// A file of taxonomy IDs to remove the reads of, one per line (e.g. 9443 for all primates).
+    /// Like --taxid, the reads classified as a taxon below one of them are removed too.
+    #[arg(long, value_name = "FILE", value_parser = existing_path(), verbatim_doc_comment)]
+    remove_taxids: Option<PathBuf>,
+
+    /// Use kraken2's quick operation: reads are classified by the first database hit.
+    ///
+    /// This is faster, but less accurate.
+    #[arg(long, verbatim_doc_comment)]
+    quick: bool,
+
+    /// Memory-map the database instead of loading it into memory (kraken2's --memory-mapping).
+    ///
+    /// This uses much less memory, but is slower. It is turned on automatically if the database
+    /// is larger than the available memory.
+    #[arg(long, verbatim_doc_comment)]
+    low_mem: bool,
+
     /// Write the Kraken2 read classification output to a file.
     #[arg(short, long, value_name = "FILE")]
     kraken_output: Option<PathBuf>,
 
-    /// Set the logging level to verbose
-    #[arg(short, long)]
-    verbose: bool,
-}
+    /// Write kraken2's log (its stderr, with the database loading and classification statistics)
+    /// to a file.
+    #[arg(
+        short = 'l',
+        long,
+        value_name = "FILE",
+        conflicts_with = "from_kraken_output",
+        verbatim_doc_comment
+    )]
+    kraken2_log: Option<PathBuf>,
 
-fn main() -> Result<()> {
-    let args = Args::parse();
+    /// Extra arguments to pass on to kraken2.
+    ///
+    /// These are appended to the kraken2 command nohuman constructs, so they must be given as a
+    /// single (quoted) string. e.g. --kraken2-args "--report k2.report --use-names".
+    /// Options that nohuman sets itself (e.g. --threads, --db, --confidence) are not allowed, nor
+    /// are abbreviations of them (e.g. --thread).
+    #[arg(
+        long = "kraken2-args",
+        value_name = "ARGS",
+        allow_hyphen_values = true,
+        verbatim_doc_comment
+    )]
+    kraken2_args: Option<String>,
 
-    // Initialize logger
-    let log_lvl = if args.verbose {
-        LevelFilter::Debug
-    } else {
-        LevelFilter::Info
-    };
-    let mut log_builder = Builder::new();
-    log_builder
-        .filter(None, log_lvl)
-        .filter_module("reqwest", LevelFilter::Off)
-        .format_module_path(false)
-        .format_target(false)
-        .init();
-
-    // Check if the database exists
-    if !args.database.exists() && !args.download && !args.check {
-        bail!("Database does not exist. Use --download to download the database");
-    }
-
-    if args.download {
-        info!("Downloading database...");
-        download_database(&args.database).context("Failed to download database")?;
-        info!("Database downloaded");
-        if args.input.is_none() {
-            info!("No input files provided. Exiting.");
-            return Ok(());
-        }
-    }
+    /// Process multiple samples listed in a CSV sample sheet, loading the database only once.
+    ///
+    /// The sample sheet must have the header "sample,r1,r2,output_prefix". r2 is only needed for
+    /// paired-end samples and output_prefix defaults to the sample name. Relative r1 and r2 paths
+    /// are relative to the sample sheet's directory. Outputs are written to
+    /// "<output_prefix>.nohuman.fq", or "<output_prefix>_1.nohuman.fq" and
+    /// "<output_prefix>_2.nohuman.fq" for paired-end samples ("nohuman" is "no<host>" for other
+    /// hosts, and "fq" is the extension of the input).
+    #[arg(
+        long,
+        value_name = "CSV",
+        value_parser = existing_path(),
+        conflicts_with_all = &["INPUT", "OUTPUT_1", "OUTPUT_2", "OUTPUT_3", "kraken_output"],
+        verbatim_doc_comment
+    )]
+    sample_sheet: Option<PathBuf>,
 
-    let kraken = CommandRunner::new("kraken2");
+    /// The directory to write the outputs to when INPUT is a directory or glob, mirroring the
+    /// structure of the inputs [default: <INPUT>.nohuman for a directory, next to the inputs for a
+    /// glob]
+    #[arg(long, value_name = "DIR")]
+    out_dir: Option<PathBuf>,
 
-    let external_commands = vec![&kraken];
+    /// Write the per-sample read counts of a --sample-sheet, directory, or glob run to this
+    /// (tab-separated) file.
+    #[arg(long, value_name = "FILE", verbatim_doc_comment)]
+    batch_summary: Option<PathBuf>,
 
-    let mut missing_commands = Vec::new();
-    for cmd in external_commands {
-        if !cmd.is_executable() {
-            debug!("{} is not executable", cmd.command);
-            missing_commands.push(cmd.command.to_owned());
-        } else {
-            debug!("{} is executable", cmd.command);
+    /// Filter the input using an existing kraken2 read classification output instead of running
+    /// kraken2.
+    ///
+    /// Reads marked as classified in the file are treated as host reads. The database and kraken2
+    /// are not required when using this option.
+    #[arg(
+        long,
+        value_name = "FILE",
+        value_parser = existing_path(),
+        conflicts_with_all = &["kraken_output", "sample_sheet", "kraken2_args"],
+        verbatim_doc_comment
+    )]
+    from_kraken_output: Option<PathBuf>,
+
+    /// Securely delete temporary files by overwriting them before they are removed.
+    ///
+    /// Use this to ensure reads (e.g. human reads) do not persist in deleted-but-recoverable
+    /// temporary files. Note, overwriting can't guarantee data is unrecoverable on copy-on-write
+    /// or journaling filesystems, or SSDs.
+    #[arg(long, verbatim_doc_comment)]
+    secure_delete: bool,
+
+    /// Write a JSON summary of the run to a file.
+    #[arg(long, value_name = "FILE")]
+    summary: Option<PathBuf>,
+
+    /// Write the sequences removed and kept (for each sample) as MultiQC custom content.
+    ///
+    /// MultiQC only finds files ending in "_mqc.json", e.g. sample_nohuman_mqc.json. The sample
+    /// name is the input file name without extensions or R1/R2 marker.
+    #[arg(long, value_name = "FILE", verbatim_doc_comment)]
+    multiqc: Option<PathBuf>,
+
+    /// Write an audit record of the run to <OUTPUT>.provenance.json, e.g.
+    /// in_1.nohuman.provenance.json for the output in_1.nohuman.fq.gz.
+    ///
+    /// It records the command line, the nohuman and kraken2 versions, the database version and
+    /// checksums, the checksums of the inputs and outputs, the hostname, the start and end times,
+    /// and the read counts.
+    #[arg(long, conflicts_with = "sample_sheet", verbatim_doc_comment)]
+    provenance: bool,
+
+    /// Convert the kraken2 report to Krona's text format and write it to a file.
+    ///
+    /// Needs a report from kraken2, e.g. --kraken2-args "--report k2.report". The file can be
+    /// turned into an interactive chart with Krona's `ktImportText`.
+    #[arg(
+        long,
+        value_name = "FILE",
+        conflicts_with = "sample_sheet",
+        verbatim_doc_comment
+    )]
+    krona: Option<PathBuf>,
+
+    /// The (single) input contains interleaved paired reads.
+    ///
+    /// The reads are classified as pairs and written to a single interleaved output, unless a
+    /// second output is given with `--out2`, in which case the mates are written to separate files.
+    #[arg(long, conflicts_with = "sample_sheet", verbatim_doc_comment)]
+    interleaved: bool,
+
+    /// Log more: -v for debug messages, -vv to also log kraken2's stderr.
+    #[arg(short, long, action = clap::ArgAction::Count, global = true)]
+    verbose: u8,
+
+    /// Only log errors, and don't show progress bars.
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    quiet: bool,
+
+    /// Read default options from this config file [default: ~/.config/nohuman/config.toml].
+    ///
+    /// The file (TOML) can set db, threads, confidence, output_type, tmp_dir, and kraken2_path.
+    /// Options given on the command line take precedence.
+    #[arg(long, value_name = "FILE", global = true, value_parser = existing_path(), verbatim_doc_comment)]
+    config: Option<PathBuf>,
+
+    /// The directory to write temporary files to [default: the current directory]
+    #[arg(long, value_name = "DIR", global = true)]
+    tmp_dir: Option<PathBuf>,
+
+    /// Also write the log to a file, with millisecond timestamps. The file is overwritten.
+    #[arg(long, value_name = "FILE", global = true)]
+    log_file: Option<PathBuf>,
+}
+
+impl Args {
+    /// The socket of the nohuman server to classify reads with, if one is used.
+    fn server_socket(&self) -> Option<PathBuf> {
+        self.server
+            .as_ref()
+            .map(|socket| socket.clone().unwrap_or_else(default_socket))
+    }
+
+    /// The kraken2 executable to run: --kraken2-path, or the one installed by
+    /// `nohuman deps install`, or the kraken2 on the PATH.
+    fn kraken2_command(&self) -> String {
+        match &self.kraken2_path {
+            // made absolute, as `nohuman serve` runs it from each client's working directory
+            Some(path) => std::path::absolute(path)
+                .unwrap_or_else(|_| path.clone())
+                .to_string_lossy()
+                .to_string(),
+            None => program("kraken2"),
         }
     }
 
-    if !missing_commands.is_empty() {
-        error!("The following dependencies are missing:");
-        for cmd in missing_commands {
-            error!("{}", cmd);
+    /// The classifier used by `--engine mock`.
+    fn mock_classifier(&self) -> MockClassifier {
+        MockClassifier {
+            fraction: self.mock_fraction,
+            seed: self.mock_seed,
         }
-        bail!("Missing dependencies");
     }
 
-    if args.check {
-        info!("All dependencies are available");
-        return Ok(());
+    /// The options for downloading databases into `db_root`.
+    fn download_options(&self, db_root: &Path) -> Result<DownloadOptions> {
+        let signature_policy = self
+            .trusted_key
+            .as_deref()
+            .map(|key| SignaturePolicy::new(key, self.allow_unsigned))
+            .transpose()?;
+        Ok(DownloadOptions {
+            retries: self.download_retries,
+            timeout: self.download_timeout,
+            threads: self.download_threads.get(),
+            keep_tarball: self.keep_tarball.clone(),
+            manifest: self.manifest.clone(),
+            manifest_cache_dir: Some(db_root.to_path_buf()),
+            refresh_manifest: self.refresh_manifest,
+            ignore_compatibility: self.ignore_compatibility,
+            allow_yanked: self.allow_yanked,
+            kraken2: self.kraken2_command(),
+            native: self.engine.single() == Some(Aligner::Native),
+            version: None,
+            progress: None,
+            signature_policy,
+        })
     }
+}
 
-    // error out if input files are not provided, otherwise unwrap to a variable
-    let input = args.input.context("No input files provided")?;
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Manage installed databases
+    #[command(subcommand)]
+    Db(DbCommand),
+    /// Manage nohuman's external dependencies (kraken2)
+    #[command(subcommand)]
+    Deps(DepsCommand),
+    /// Keep the database in memory and classify reads for other nohuman runs
+    ///
+    /// Loading the database is the slowest step when processing many small files. Run the server
+    /// once (e.g. in the background), then run nohuman with --server to classify reads with the
+    /// database it holds in memory. The server uses the same database as a normal nohuman run
+    /// (see --db and --db-version). Stop it with Ctrl-C.
+    #[command(verbatim_doc_comment)]
+    Serve {
+        /// Listen for requests on this Unix socket [default: ~/.nohuman/nohuman.sock]
+        #[arg(long, value_name = "PATH")]
+        socket: Option<PathBuf>,
+    },
+    /// Re-pair paired read files that are out of sync
+    ///
+    /// Tools such as read trimmers can drop one read of a pair, leaving paired files with
+    /// different reads. The reads in both files are written to the outputs, with the mates in the
+    /// same order, and the reads without a mate are written to the singletons output. Outputs are
+    /// compressed like R1, unless their extension says otherwise.
+    #[command(verbatim_doc_comment)]
+    Repair {
+        /// The first file of paired reads
+        #[arg(value_name = "R1", value_parser = existing_path())]
+        read1: PathBuf,
+        /// The second file of paired reads
+        #[arg(value_name = "R2", value_parser = existing_path())]
+        read2: PathBuf,
+        /// First output [default: <R1 stem>.repaired.<ext>]
+        #[arg(long, value_name = "PATH")]
+        out1: Option<PathBuf>,
+        /// Second output [default: <R2 stem>.repaired.<ext>]
+        #[arg(long, value_name = "PATH")]
+        out2: Option<PathBuf>,
+        /// Output for reads without a mate [default: <R1 stem>.singletons.<ext>]
+        #[arg(long, value_name = "PATH")]
+        singletons: Option<PathBuf>,
+        /// Overwrite output files that already exist
+        #[arg(long)]
+        force: bool,
+    },
+    /// Compare database versions (or engines) on the same reads
+    ///
+    /// Each database version (or engine) classifies the reads, without writing any output, and a
+    /// table (TSV) of the time each took, kraken2's CPU time and peak memory, the number of host
+    /// reads found, and their agreement with the first run is written. Options such as --threads
+    /// and --conf are given before `bench`, e.g. `nohuman -t 8 bench --input in.fq`.
+    #[command(verbatim_doc_comment)]
+    Bench {
+        /// The reads to compare on. Give two files for paired reads
+        #[arg(long, value_name = "FILE", required = true, num_args = 1..=2, value_parser = existing_path())]
+        input: Vec<PathBuf>,
+        /// The installed database versions to compare, separated by commas [default: all installed versions]
+        #[arg(long, value_name = "VERSIONS", value_delimiter = ',')]
+        db_version: Vec<String>,
+        /// Also compare these engines (kraken2, minimap2, or native), separated by commas. They
+        /// use the default database
+        #[arg(long, value_name = "ENGINES", value_delimiter = ',')]
+        engine: Vec<Aligner>,
+        /// The host reference to align to with the minimap2 engine
+        #[arg(long, value_name = "FILE", value_parser = existing_path())]
+        reference: Option<PathBuf>,
+        /// Write the table to this file [default: stdout]
+        #[arg(short, long, value_name = "FILE")]
+        output: Option<PathBuf>,
+    },
+    /// Score host read removal against reads whose origin is known
+    ///
+    /// The reads are classified (without writing any output), and the precision, recall, and F1
+    /// of the reads classified as host are written as a table (TSV), e.g. to evaluate a database
+    /// on a simulated dataset. The truth set lists the IDs of the host reads, one per line, or
+    /// labels every read, with a read ID, a tab, and a label (host/human, or anything else) per
+    /// line.
+    /// Use --predicted to score reads that have already been classified, e.g. with --removed-ids.
+    #[command(verbatim_doc_comment)]
+    Eval {
+        /// The host reads, or every read with a label
+        #[arg(long, value_name = "FILE", value_parser = existing_path())]
+        truth: PathBuf,
+        /// The reads to classify. Give two files for paired reads
+        #[arg(long, value_name = "FILE", required_unless_present = "predicted", num_args = 1..=2, value_parser = existing_path())]
+        input: Vec<PathBuf>,
+        /// Score these read IDs, classified as host by an earlier run, instead of classifying reads
+        #[arg(long, value_name = "FILE", conflicts_with_all = ["input", "db_version", "engine"], value_parser = existing_path())]
+        predicted: Option<PathBuf>,
+        /// The installed database versions to score, separated by commas [default: the default database]
+        #[arg(long, value_name = "VERSIONS", value_delimiter = ',')]
+        db_version: Vec<String>,
+        /// Score these engines (kraken2, minimap2, or native), separated by commas
+        #[arg(long, value_name = "ENGINES", value_delimiter = ',')]
+        engine: Vec<Aligner>,
+        /// The host reference to align to with the minimap2 engine
+        #[arg(long, value_name = "FILE", value_parser = existing_path())]
+        reference: Option<PathBuf>,
+        /// Write the table to this file [default: stdout]
+        #[arg(short, long, value_name = "FILE")]
+        output: Option<PathBuf>,
+    },
+    /// Simulate a mix of human and contaminant reads, with their origins
+    ///
+    /// Reads are sampled from random positions and strands of the two references, with
+    /// substitution errors, and written as FASTQ in a random order. A truth set labelling each read
+    /// as host or contaminant is written alongside, so an installation can be checked end to end:
+    ///     nohuman simulate --human GRCh38.fa --contaminant phage.fa -o sim.fq
+    ///     nohuman eval --truth sim.truth.tsv --input sim.fq
+    #[command(verbatim_doc_comment)]
+    Simulate {
+        /// The human (host) reference to sample reads from
+        #[arg(long, value_name = "FILE", value_parser = existing_path())]
+        human: PathBuf,
+        /// The contaminant reference to sample the other reads from
+        #[arg(long, value_name = "FILE", value_parser = existing_path())]
+        contaminant: PathBuf,
+        /// The fraction of the reads sampled from the human reference
+        #[arg(long, value_name = "[0, 1]", default_value = "0.1", value_parser = parse_fraction)]
+        human_frac: f64,
+        /// The number of reads
+        #[arg(long = "n", value_name = "INT", default_value = "100000")]
+        reads: u64,
+        /// The length of the reads
+        #[arg(long, value_name = "INT", default_value = "150")]
+        read_length: NonZeroUsize,
+        /// The probability of each base being a sequencing error (a substitution)
+        #[arg(long, value_name = "[0, 1]", default_value = "0.001", value_parser = parse_fraction)]
+        error_rate: f64,
+        /// Seed the random number generator, to simulate the same reads again [default: random]
+        #[arg(long, value_name = "INT")]
+        seed: Option<u64>,
+        /// The simulated reads. Compressed if the extension says so
+        #[arg(short, long, value_name = "FILE", default_value = "simulated.fq")]
+        output: PathBuf,
+        /// The truth set [default: <output stem>.truth.tsv]
+        #[arg(long, value_name = "FILE")]
+        truth: Option<PathBuf>,
+        /// Overwrite output files that already exist
+        #[arg(long)]
+        force: bool,
+    },
+}
 
-    let kraken_output = args.kraken_output.unwrap_or(PathBuf::from("/dev/null"));
-    let kraken_output = kraken_output.to_string_lossy();
-    let threads = args.threads.to_string();
-    let confidence = args.confidence.to_string();
-    let db = validate_db_directory(&args.database)
-        .map_err(|e| anyhow::anyhow!(e))?
-        .to_string_lossy()
-        .to_string();
-    let mut kraken_cmd = vec![
-        "--threads",
-        &threads,
-        "--db",
-        &db,
-        "--output",
-        &kraken_output,
-        "--confidence",
-        &confidence,
-    ];
-    match input.len() {
-        0 => bail!("No input files provided"),
-        2 => kraken_cmd.push("--paired"),
-        i if i > 2 => bail!("Only one or two input files are allowed"),
-        _ => {}
-    }
-
-    // safe to do this as we know the input vector is not empty
-    let output_compression = if let Some(format) = args.output_type {
-        Ok(format)
-    } else if let Some(out1) = &args.out1 {
-        CompressionFormat::from_path(out1)
-    } else {
-        let mut reader = std::io::BufReader::new(std::fs::File::open(&input[0])?);
-        CompressionFormat::from_reader(&mut reader)
-    }?;
+#[derive(Subcommand, Debug)]
+enum DepsCommand {
+    /// Build kraken2 from source and install it into ~/.nohuman/bin
+    ///
+    /// The installed kraken2 is used instead of any kraken2 on the PATH (but not over
+    /// --kraken2-path). Building needs make, g++, and perl.
+    #[command(verbatim_doc_comment)]
+    Install {
+        /// The kraken2 release to install
+        #[arg(long, value_name = "VERSION", default_value = KRAKEN2_VERSION)]
+        kraken2_version: String,
 
-    // create a temporary output directory in the current directory and don't delete it
-    let tmpdir = tempfile::Builder::new()
-        .prefix("nohuman")
-        .tempdir_in(std::env::current_dir().unwrap())
-        .context("Failed to create temporary directory")?;
-    let outfile = if input.len() == 2 {
-        tmpdir.path().join("kraken_out#.fq")
-    } else {
-        tmpdir.path().join("kraken_out.fq")
-    };
-    let outfile = outfile.to_string_lossy().to_string();
+        /// Reinstall kraken2 if it is already installed
+        #[arg(long)]
+        force: bool,
+    },
+}
 
-    if args.keep_human_reads {
-        kraken_cmd.extend(&["--classified-out", &outfile]);
-        info!("Keeping human reads...");
-    } else {
-        kraken_cmd.extend(&["--unclassified-out", &outfile]);
-        info!("Removing human reads...");
+#[derive(Subcommand, Debug)]
+enum DbCommand {
+    /// Install a database
+    ///
+    /// By default, the latest release for the host is downloaded. Use --from-file to install a
+    /// database tarball that has already been downloaded (e.g. on an air-gapped machine), or
+    /// --version to download an earlier release.
+    #[command(verbatim_doc_comment)]
+    Install {
+        /// Install the database from this (gzipped) tarball instead of downloading it
+        #[arg(long, value_name = "TARBALL", requires = "version", value_parser = existing_path())]
+        from_file: Option<PathBuf>,
+
+        /// Version to download, or to install the --from-file database as, e.g. k2_HPRC_20230810
+        #[arg(long)]
+        version: Option<String>,
+
+        /// Expected MD5 of the --from-file tarball
+        #[arg(long, requires = "from_file", conflicts_with_all = &["sha256", "blake3"])]
+        md5: Option<String>,
+
+        /// Expected SHA256 of the --from-file tarball
+        #[arg(long, requires = "from_file", conflicts_with = "blake3")]
+        sha256: Option<String>,
+
+        /// Expected BLAKE3 hash of the --from-file tarball
+        #[arg(long, requires = "from_file")]
+        blake3: Option<String>,
+    },
+    /// Remove installed database(s)
+    Remove {
+        /// Version of the database to remove
+        #[arg(required_unless_present = "all")]
+        version: Option<String>,
+
+        /// Remove all installed databases (for the host)
+        #[arg(long, conflicts_with = "version")]
+        all: bool,
+
+        /// Show what would be removed, and the space reclaimed, without removing anything
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Check installed database(s) for corrupted or truncated files
+    ///
+    /// Each file is checked against the checksum recorded when the database was installed.
+    #[command(verbatim_doc_comment)]
+    Verify {
+        /// Version of the database to verify. Defaults to all installed databases
+        version: Option<String>,
+    },
+    /// Check whether a newer database release than those installed is available
+    CheckUpdates {
+        /// Download the latest release if it isn't installed
+        #[arg(long)]
+        upgrade: bool,
+
+        /// Remove the other installed databases after upgrading
+        #[arg(long, requires = "upgrade")]
+        prune: bool,
+    },
+    /// Show details of installed database(s)
+    ///
+    /// This includes the MD5 of the database's hash.k2d, which can take a while to compute for
+    /// large databases.
+    Info {
+        /// Version of the database to show. Defaults to all installed databases
+        version: Option<String>,
+    },
+    /// Register an existing kraken2 database, e.g. one you have built, as an installed database
+    ///
+    /// The database files are left where they are. The database can then be used like a
+    /// downloaded release, e.g. with --db-version or `nohuman db pin`. Use `nohuman db remove` to
+    /// unregister it.
+    #[command(verbatim_doc_comment)]
+    Add {
+        /// Name to install the database as (used like a version)
+        #[arg(long)]
+        name: String,
+
+        /// Directory of the kraken2 database
+        #[arg(long, value_parser = existing_path())]
+        path: PathBuf,
+    },
+    /// Build a kraken2 database from host genome(s) with kraken2-build, and install it
+    ///
+    /// This downloads the NCBI taxonomy, adds the genome(s) to the database library, builds the
+    /// database (using --threads), and removes the intermediate files. Building a database for
+    /// a large genome needs a lot of memory and can take hours.
+    /// e.g. nohuman --host custom -t 8 db build --genome cow.fa.gz --taxid 9913 --name cow
+    #[command(verbatim_doc_comment)]
+    Build {
+        /// FASTA file of the host genome. Can be given multiple times
+        #[arg(long, required = true, value_parser = existing_path())]
+        genome: Vec<PathBuf>,
+
+        /// Name to install the database as (used like a version)
+        #[arg(long)]
+        name: String,
+
+        /// Taxonomy ID to assign the genome sequences to.
+        ///
+        /// Without this, the sequence IDs must already include one, e.g. >chr1|kraken:taxid|9913
+        #[arg(long, verbatim_doc_comment)]
+        taxid: Option<u64>,
+
+        /// K-mer length of the database (kraken2's default if not given)
+        #[arg(long)]
+        kmer_len: Option<u32>,
+
+        /// Minimizer length of the database (kraken2's default if not given)
+        #[arg(long, conflicts_with = "native")]
+        minimizer_len: Option<u32>,
+
+        /// Build a native database for the built-in classifier (--engine native) instead of a
+        /// kraken2 database. This doesn't need kraken2-build or the taxonomy.
+        ///
+        /// The k-mer length defaults to 31 (at most 31).
+        #[arg(long, conflicts_with = "taxid", verbatim_doc_comment)]
+        native: bool,
+
+        /// The number of consecutive k-mers each minimizer of a native database is chosen from.
+        /// Larger windows make a smaller, but less sensitive, database.
+        #[arg(long, requires = "native", value_name = "INT", default_value_t = DEFAULT_WINDOW_LEN, verbatim_doc_comment)]
+        window_len: u8,
+    },
+    /// Pin the database version to use, instead of the most recently installed one
+    ///
+    /// The version is written to ~/.nohuman/db/.nohuman-version (or the equivalent for other
+    /// hosts). A .nohuman-version file in the current directory takes precedence over it. Without
+    /// a version, the pinned version (if any) is printed.
+    #[command(verbatim_doc_comment)]
+    Pin {
+        /// Version of the database to pin
+        version: Option<String>,
+
+        /// Remove the pin, so the most recently installed database is used
+        #[arg(long, conflicts_with = "version")]
+        clear: bool,
+    },
+    /// Print the path of the database nohuman uses, for use in scripts
+    ///
+    /// This is the most recently installed database, unless --db-version is given. Nothing else is
+    /// printed, e.g. DB=$(nohuman db path).
+    #[command(verbatim_doc_comment)]
+    Path {
+        /// Print the path of this installed version instead
+        #[arg(long, value_name = "VERSION")]
+        db_version: Option<String>,
+    },
+}
+
+/// Logs to stderr and to a file.
+struct TeeLogger {
+    stderr: env_logger::Logger,
+    file: env_logger::Logger,
+}
+
+impl log::Log for TeeLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        self.stderr.enabled(metadata)
     }
 
-    kraken_cmd.extend(input.iter().map(|p| p.to_str().unwrap()));
-    debug!("Running kraken2...");
-    debug!("With arguments: {:?}", &kraken_cmd);
-    kraken.run(&kraken_cmd).context("Failed to run kraken2")?;
-    info!("Kraken2 finished. Organising output...");
+    fn log(&self, record: &log::Record) {
+        self.stderr.log(record);
+        self.file.log(record);
+    }
 
-    let outputs = if input.len() == 2 {
-        let out1 = args.out1.unwrap_or_else(|| {
-            let parent = input[0].parent().unwrap();
-            // get the part of the file name before the extension.
-            // if the file is compressed, the extension will be .gz, we want to remove this first before getting the file stem
-            let ext = CompressionFormat::from_path(&input[0])
-                .unwrap_or_default()
-                .to_string();
-            let fname = if input[0].extension().unwrap_or_default() == ext.as_str() {
-                let no_ext = input[0].with_extension("");
-                no_ext.file_stem().unwrap().to_owned()
-            } else {
-                input[0].file_stem().unwrap().to_owned()
-            };
-            let fname = format!("{}.nohuman.fq", fname.to_string_lossy());
-            let fname = parent.join(fname);
-            output_compression.add_extension(&fname)
-        });
-        let out2 = args.out2.unwrap_or_else(|| {
-            let parent = input[1].parent().unwrap();
-            // get the part of the file name before the extension.
-            // if the file is compressed, the extension will be .gz, we want to remove this first before getting the file stem
-            let ext = CompressionFormat::from_path(&input[1])
-                .unwrap_or_default()
-                .to_string();
-            let fname = if input[1].extension().unwrap_or_default() == ext.as_str() {
-                let no_ext = input[1].with_extension("");
-                no_ext.file_stem().unwrap().to_owned()
-            } else {
-                input[1].file_stem().unwrap().to_owned()
-            };
-            let fname = format!("{}.nohuman.fq", fname.to_string_lossy());
-            let fname = parent.join(fname);
-            output_compression.add_extension(&fname)
-        });
-        let tmpout1 = tmpdir.path().join("kraken_out_1.fq");
-        let tmpout2 = tmpdir.path().join("kraken_out_2.fq");
-        vec![(tmpout1, out1), (tmpout2, out2)]
-        // move the output files to the correct location
-        // std::fs::rename(tmpout1, &out1).unwrap();
-        // std::fs::rename(tmpout2, &out2).unwrap();
-        // info!("Output files written to: {:?} and {:?}", &out1, &out2);
-    } else {
-        let out1 = args.out1.unwrap_or_else(|| {
-            let parent = input[0].parent().unwrap();
-            // get the part of the file name before the extension.
-            // if the file is compressed, the extension will be .gz, we want to remove this first before getting the file stem
-            let ext = CompressionFormat::from_path(&input[0])
-                .unwrap_or_default()
-                .to_string();
-            let fname = if input[0].extension().unwrap_or_default() == ext.as_str() {
-                let no_ext = input[0].with_extension("");
-                no_ext.file_stem().unwrap().to_owned()
-            } else {
-                input[0].file_stem().unwrap().to_owned()
-            };
-            let fname = format!("{}.nohuman.fq", fname.to_string_lossy());
-            let fname = parent.join(fname);
-            output_compression.add_extension(&fname)
-        });
-        let tmpout1 = tmpdir.path().join("kraken_out.fq");
-        vec![(tmpout1, out1)]
-        // move the output files to the correct location
-        // std::fs::rename(tmpout1, &out1).unwrap();
-        // info!("Output file written to: {:?}", &out1);
-    };
+    fn flush(&self) {
+        self.stderr.flush();
+        self.file.flush();
+    }
+}
 
-    // if we have one output file and multiple threads, we pass all threads to the compression command
-    // if we have two output files, we pass half the threads to each compression command
-    let threads = if outputs.len() == 1 {
-        args.threads.get()
-    } else {
-        args.threads.get() / 2
+/// Log messages at `level` and above to stderr, and to `log_file` if given.
+fn init_logger(level: LevelFilter, log_file: Option<&Path>) -> Result<()> {
+    let builder = || {
+        let mut builder = Builder::new();
+        // other crates (e.g. the HTTP client) are too chatty at trace level
+        builder
+            .filter(None, level.min(LevelFilter::Debug))
+            .filter_module("nohuman", level)
+            .filter_module("reqwest", LevelFilter::Off)
+            .format_module_path(false)
+            .format_target(false);
+        builder
+    };
+    let Some(path) = log_file else {
+        builder().init();
+        return Ok(());
     };
+    let file =
+        File::create(path).with_context(|| format!("Failed to create log file {:?}", path))?;
+    let stderr = builder().build();
+    let file = builder()
+        .format_timestamp_millis()
+        .target(env_logger::Target::Pipe(Box::new(file)))
+        .build();
+    log::set_max_level(stderr.filter());
+    log::set_boxed_logger(Box::new(TeeLogger { stderr, file }))?;
+    Ok(())
+}
+
+/// The directory temporary directories are created in: --tmp-dir, or the current directory.
+fn tmp_root(args: &Args) -> Result<PathBuf> {
+    match &args.tmp_dir {
+        Some(dir) => Ok(dir.to_owned()),
+        None => Ok(std::env::current_dir()?),
+    }
+}
 
-    // if we have two output files and two or more threads, compress them in parallel
-    if outputs.len() == 2 && threads > 1 {
-        let mut handles = Vec::new();
-        for (input, output) in outputs {
-            let handle = std::thread::spawn(move || {
-                info!("Writing output file to: {:?}", &output);
-                output_compression.compress(&input, &output, threads)
+/// Run the checks of --check, printing a table of the results. `commands` are the programs the
+/// run needs, and `database` the database, if it needs one. Returns an error if a check failed.
+fn run_checks(
+    args: &Args,
+    commands: &[&CommandRunner],
+    database: Option<&Path>,
+    native_only: bool,
+) -> Result<()> {
+    let mut checks = Vec::new();
+    let mut kraken2_version = None;
+    for cmd in commands {
+        let kind = FailureKind::MissingDependency;
+        let name = Path::new(&cmd.command)
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string();
+        let location = match (&cmd.container, cmd.path()) {
+            (Some(container), _) if container.is_available() => {
+                format!("in the {} container {}", container.engine, container.image)
+            }
+            (Some(container), _) => {
+                checks.push(Check::fail(
+                    &name,
+                    kind,
+                    format!("{} is not installed", container.executable()),
+                ));
+                continue;
+            }
+            (None, Some(path)) => format!("at {:?}", path),
+            (None, None) => {
+                checks.push(Check::fail(&name, kind, "not found".to_string()));
+                continue;
+            }
+        };
+        let version = cmd.version();
+        let detail = match &version {
+            Some(version) => format!("{} {}", version, location),
+            None => format!("unknown version {}", location),
+        };
+        checks.push(Check::pass(&name, kind, detail));
+        if cmd.command == args.kraken2_command() {
+            kraken2_version = version;
+        }
+    }
+
+    if let Some(database) = database {
+        let kind = FailureKind::Database;
+        if native_only {
+            checks.push(match find_native_index(database) {
+                Ok(index) => Check::pass("database", kind, format!("{:?}", index)),
+                Err(e) => Check::fail("database", kind, e.to_string()),
             });
-            handles.push(handle);
+        } else {
+            let database_checks = check_database(database);
+            let complete = database_checks
+                .iter()
+                .all(|check| check.status != Status::Fail);
+            checks.extend(database_checks);
+            if let Ok(metadata) = DatabaseMetadata::read(database) {
+                let kind = FailureKind::MissingDependency;
+                checks.push(
+                    match check_compatibility(
+                        metadata.min_nohuman_version.as_deref(),
+                        metadata.min_kraken2_version.as_deref(),
+                        kraken2_version.as_deref(),
+                    ) {
+                        Ok(()) => Check::pass(
+                            "compatibility",
+                            kind,
+                            format!("database {}", metadata.version),
+                        ),
+                        Err(e) => Check::fail("compatibility", kind, e.to_string()),
+                    },
+                );
+            }
+            if complete && args.low_mem {
+                checks.push(Check::pass(
+                    "memory",
+                    FailureKind::Kraken2,
+                    "the database will be memory-mapped (--low-mem)".to_string(),
+                ));
+            } else if let (true, Ok(k2d_dir)) = (complete, validate_db_directory(database)) {
+                checks.push(check_memory(&k2d_dir));
+            }
         }
-        for handle in handles {
-            handle
-                .join()
-                .map_err(|e| anyhow::anyhow!("Thread panicked when writing output: {:?}", e))??;
+    }
+
+    // the temporary files and outputs are, at most, about the size of the inputs
+    let inputs = args.input.as_deref().unwrap_or_default();
+    let input_size = inputs
+        .iter()
+        .filter(|path| !is_stdio(path))
+        .filter_map(|path| std::fs::metadata(path).ok())
+        .filter(|metadata| metadata.is_file())
+        .map(|metadata| metadata.len())
+        .sum();
+    checks.extend(check_directory("tmp dir", &tmp_root(args)?, input_size));
+    let output = args
+        .out1
+        .as_deref()
+        .or(inputs.first().map(PathBuf::as_path))
+        .filter(|path| !is_stdio(path) && !path.is_dir());
+    let output_dir = match (&args.out_dir, output) {
+        (Some(dir), _) => dir.to_owned(),
+        (None, Some(path)) => path
+            .parent()
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .map_or_else(|| PathBuf::from("."), Path::to_path_buf),
+        (None, None) => std::env::current_dir()?,
+    };
+    checks.extend(check_directory("output dir", &output_dir, input_size));
+
+    write_table(std::io::stdout().lock(), &checks)?;
+    let failed: Vec<_> = checks
+        .iter()
+        .filter(|check| check.status == Status::Fail)
+        .collect();
+    match failed.first() {
+        None => {
+            info!("All checks passed");
+            Ok(())
         }
-    } else {
-        for (input, output) in outputs {
-            output_compression.compress(&input, &output, threads)?;
-            info!("Output file written to: {:?}", &output);
+        Some(first) => Err(NohumanError::ChecksFailed {
+            checks: failed.iter().map(|check| check.name.clone()).collect(),
+            kind: first.kind,
         }
+        .into()),
+    }
+}
+
+/// Print a command line parsing error (or the help or version) and exit. Usage errors exit with 1
+/// rather than clap's 2, which is kept for a missing dependency.
+fn exit_on_parse_error(err: clap::Error) -> ! {
+    if err.use_stderr() {
+        let _ = err.print();
+        std::process::exit(1);
+    }
+    err.exit()
+}
+
+/// Whether the option `id` wasn't given on the command line (or by an environment variable).
+fn is_default(matches: &ArgMatches, id: &str) -> bool {
+    matches!(
+        matches.value_source(id),
+        None | Some(ValueSource::DefaultValue)
+    )
+}
+
+/// Fill in the options that weren't given on the command line from the config file.
+fn apply_config(args: &mut Args, matches: &ArgMatches, config: Config) -> Result<()> {
+    let unset = |id: &str| is_default(matches, id);
+    if unset("database") && unset("db_version") {
+        args.database = config.db.or(args.database.take());
+    }
+    if let Some(threads) = config.threads.filter(|_| unset("threads")) {
+        args.threads = threads;
+    }
+    if let Some(confidence) = config.confidence.filter(|_| unset("confidence")) {
+        args.confidence = confidence;
+    }
+    // BAM output can't be compressed
+    if unset("output_type") && args.output_format != OutputFormat::Bam {
+        args.output_type = config.output_type.or(args.output_type);
+    }
+    if unset("tmp_dir") {
+        args.tmp_dir = config.tmp_dir.or(args.tmp_dir.take());
+    }
+    if let Some(path) = config.kraken2_path.filter(|_| unset("kraken2_path")) {
+        let path = check_path_exists(&path).context("Invalid kraken2_path in the config file")?;
+        args.kraken2_path = Some(path);
+    }
+    Ok(())
+}
+
+/// Set the options of the --preset that weren't given on the command line. These take precedence
+/// over the config file.
+fn apply_preset(args: &mut Args, matches: &ArgMatches) {
+    let Some(preset) = args.preset else {
+        return;
+    };
+    let options = preset.options();
+    debug!("Using the {} preset: {:?}", preset, options);
+    if is_default(matches, "confidence") {
+        args.confidence = options.confidence;
+    }
+    // kraken2's option can also be given in --kraken2-args
+    let in_kraken2_args = args
+        .kraken2_args
+        .as_deref()
+        .is_some_and(|kraken2_args| kraken2_args.contains("--minimum-hit-groups"));
+    if is_default(matches, "min_hit_groups") && !in_kraken2_args {
+        args.min_hit_groups = Some(options.min_hit_groups);
+    }
+    if is_default(matches, "minimap2_preset") {
+        if let Some(minimap2_preset) = options.minimap2_preset {
+            args.minimap2_preset = Some(minimap2_preset.to_string());
+        }
+    }
+    if is_default(matches, "min_identity") {
+        args.min_identity = options.min_identity;
+    }
+}
+
+fn main() -> ExitCode {
+    let matches = Args::command()
+        .try_get_matches()
+        .unwrap_or_else(|err| exit_on_parse_error(err));
+    let args = Args::from_arg_matches(&matches).unwrap_or_else(|err| exit_on_parse_error(err));
+    match run(args, &matches) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("Error: {:?}", err);
+            ExitCode::from(FailureKind::of(&err).map_or(1, FailureKind::exit_code))
+        }
+    }
+}
+
+fn run(mut args: Args, matches: &ArgMatches) -> Result<()> {
+    let start_time = SystemTime::now();
+    // Initialize logger
+    let log_lvl = if matches!(args.command, Some(Command::Db(DbCommand::Path { .. }))) {
+        // only the path should be printed
+        LevelFilter::Off
+    } else if args.quiet {
+        LevelFilter::Error
+    } else {
+        match args.verbose {
+            0 => LevelFilter::Info,
+            1 => LevelFilter::Debug,
+            _ => LevelFilter::Trace,
+        }
+    };
+    init_logger(log_lvl, args.log_file.as_deref())?;
+
+    let config_path = args
+        .config
+        .clone()
+        .or_else(|| default_config_path().filter(|path| path.is_file()));
+    if let Some(path) = config_path {
+        debug!("Reading default options from {:?}", path);
+        apply_config(&mut args, matches, Config::read(&path)?)?;
+    }
+    apply_preset(&mut args, matches);
+    args.threads = clamp_threads(args.threads);
+
+    interrupt::install_handler().context("Failed to set up signal handling")?;
+
+    if let Some(Command::Db(cmd)) = &args.command {
+        return run_db_command(cmd, &args, &default_db_location(&args.host));
+    }
+    if let Some(Command::Deps(cmd)) = &args.command {
+        return run_deps_command(cmd, &args);
+    }
+    if let Some(Command::Repair {
+        read1,
+        read2,
+        out1,
+        out2,
+        singletons,
+        force,
+    }) = &args.command
+    {
+        let outputs = [out1.as_deref(), out2.as_deref(), singletons.as_deref()];
+        return run_repair(read1, read2, outputs, *force, &args);
+    }
+    if let Some(Command::Bench {
+        input,
+        db_version,
+        engine,
+        reference,
+        output,
+    }) = &args.command
+    {
+        let runs = bench_runs(&args, db_version, engine, reference.as_deref())?;
+        return run_bench(&args, input, runs, reference.as_deref(), output.as_deref());
+    }
+    if let Some(Command::Simulate {
+        human,
+        contaminant,
+        human_frac,
+        reads,
+        read_length,
+        error_rate,
+        seed,
+        output,
+        truth,
+        force,
+    }) = &args.command
+    {
+        let options = SimulationOptions {
+            reads: *reads,
+            host_fraction: *human_frac,
+            read_length: read_length.get(),
+            error_rate: *error_rate,
+            seed: seed.unwrap_or_else(|| fastrand::u64(..)),
+        };
+        let truth = truth.clone().unwrap_or_else(|| truth_path(output));
+        return run_simulate(human, contaminant, &options, output, &truth, *force, &args);
+    }
+    if let Some(Command::Eval {
+        truth,
+        input,
+        predicted,
+        db_version,
+        engine,
+        reference,
+        output,
+    }) = &args.command
+    {
+        let truth = Truth::from_reader(BufReader::new(
+            File::open(truth).with_context(|| format!("Failed to open {:?}", truth))?,
+        ))?;
+        if truth.host.is_empty() {
+            warn!("The truth set has no {} reads", args.host);
+        }
+        let evaluations = match predicted {
+            Some(path) => {
+                let file =
+                    File::open(path).with_context(|| format!("Failed to open {:?}", path))?;
+                let predicted = read_ids_from(BufReader::new(file))?;
+                let name = path.file_name().unwrap_or_default().to_string_lossy();
+                vec![(name.to_string(), Evaluation::new(&truth, &predicted, None))]
+            }
+            None => {
+                // unlike bench, only the default database is scored unless told otherwise
+                let engine = if db_version.is_empty() && engine.is_empty() {
+                    &[Aligner::Kraken2][..]
+                } else {
+                    engine
+                };
+                let runs = bench_runs(&args, db_version, engine, reference.as_deref())?;
+                classify_runs(&args, input, runs, reference.as_deref())?
+                    .into_iter()
+                    .map(|run| {
+                        let evaluation =
+                            Evaluation::new(&truth, &run.host_ids, Some(run.sequences));
+                        (run.name, evaluation)
+                    })
+                    .collect()
+            }
+        };
+        for (name, evaluation) in &evaluations {
+            info!(
+                "{}: precision {:.4}, recall {:.4}, F1 {:.4}",
+                name,
+                evaluation.precision(),
+                evaluation.recall(),
+                evaluation.f1()
+            );
+        }
+        return write_output(output.as_deref(), "Evaluation", |writer| {
+            write_evaluation_tsv(writer, &evaluations)
+        });
+    }
+
+    let serving = matches!(args.command, Some(Command::Serve { .. }));
+    let uses_kraken2 = args.engine.contains(Aligner::Kraken2);
+    let aligning = args.engine.contains(Aligner::Minimap2);
+    let native = args.engine.contains(Aligner::Native);
+    // the kraken2 database is only used by kraken2, and minimap2 uses no database
+    let native_only = native && !uses_kraken2;
+    let needs_db = uses_kraken2 || native;
+    let consensus = args.engine.len() > 1;
+    if args.engine.single() != Some(Aligner::Kraken2) && serving {
+        bail!("nohuman serve only classifies reads with kraken2");
+    }
+    if serving && cfg!(not(unix)) {
+        bail!("nohuman serve needs Unix sockets, so it isn't available on this platform");
+    }
+    if aligning && args.reference.is_none() {
+        bail!("--reference is required with --engine minimap2");
+    }
+    if args.reference.is_some() && !aligning && !args.confirm_with_alignment {
+        bail!("--reference is only used with --engine minimap2 or --confirm-with-alignment");
+    }
+    if let (Some(min), Some(max)) = (args.min_human_frac, args.max_human_frac) {
+        if min > max {
+            bail!(
+                "--min-human-frac {} is more than --max-human-frac {}",
+                min,
+                max
+            );
+        }
+    }
+    // a directory or glob input is processed like a sample sheet, with a sample for each file (or
+    // pair of files) it holds
+    let input_dir = match args.input.as_deref() {
+        Some([path]) if path.is_dir() || (!path.exists() && is_glob(&path.to_string_lossy())) => {
+            Some(path.to_owned())
+        }
+        Some(input) if input.iter().any(|p| p.is_dir()) => {
+            bail!("A directory INPUT must be the only input")
+        }
+        Some(input) if input.iter().any(|p| !p.exists() && !is_stdio(p)) => {
+            bail!(
+                "A glob INPUT must be the only input (and quoted, e.g. 'fastqs/*_R{{1,2}}.fq.gz')"
+            )
+        }
+        _ => None,
+    };
+    if input_dir.is_some() {
+        let single_options = [
+            ("--out1", args.out1.is_some()),
+            ("--out2", args.out2.is_some()),
+            ("--out3", args.out3.is_some()),
+            ("--kraken-output", args.kraken_output.is_some()),
+            ("--from-kraken-output", args.from_kraken_output.is_some()),
+            ("--reference", args.reference.is_some()),
+            ("--removed-ids", args.removed_ids.is_some()),
+            ("--stats-only", args.stats_only),
+            ("--max-human-frac", args.max_human_frac.is_some()),
+            ("--min-human-frac", args.min_human_frac.is_some()),
+            ("--interleaved", args.interleaved),
+            ("--repair-pairs", args.repair_pairs),
+            ("--no-verify", args.no_verify),
+            ("--krona", args.krona.is_some()),
+            ("--provenance", args.provenance),
+            (
+                "--output-format bam",
+                args.output_format == OutputFormat::Bam,
+            ),
+        ];
+        if let Some((option, _)) = single_options.iter().find(|(_, given)| *given) {
+            bail!("{} can't be used with a directory or glob INPUT", option);
+        }
+    } else if args.out_dir.is_some() {
+        bail!("--out-dir is only used when INPUT is a directory or glob");
+    }
+    let min_votes = args.min_votes.map_or(args.engine.len(), NonZeroUsize::get);
+    if min_votes > args.engine.len() {
+        bail!(
+            "--min-votes {} is more than the number of engines in --engine {}",
+            min_votes,
+            args.engine
+        );
+    }
+    if consensus {
+        let single_options = [
+            ("--confirm-with-alignment", args.confirm_with_alignment),
+            ("--sample-sheet", args.sample_sheet.is_some()),
+            ("A directory or glob INPUT", input_dir.is_some()),
+        ];
+        if let Some((option, _)) = single_options.iter().find(|(_, given)| *given) {
+            bail!("{} can't be used with --engine {}", option, args.engine);
+        }
+    }
+    if !uses_kraken2 {
+        let kraken_options = [
+            ("--kraken-output", args.kraken_output.is_some()),
+            ("--from-kraken-output", args.from_kraken_output.is_some()),
+            ("--kraken2-args", args.kraken2_args.is_some()),
+            ("--krona", args.krona.is_some()),
+            ("--server", args.server.is_some()),
+            ("--confirm-with-alignment", args.confirm_with_alignment),
+            ("--sample-sheet", args.sample_sheet.is_some()),
+            ("A directory or glob INPUT", input_dir.is_some()),
+            ("--quick", args.quick),
+            ("--taxid", !args.taxids.is_empty()),
+            ("--remove-taxids", args.remove_taxids.is_some()),
+            ("--negative-control", args.negative_control.is_some()),
+        ];
+        if let Some((option, _)) = kraken_options.iter().find(|(_, given)| *given) {
+            bail!("{} can't be used with --engine {}", option, args.engine);
+        }
+    }
+
+    let db_root = default_db_location(&args.host);
+    let mut database = match (&args.database, args.host) {
+        // the database isn't used when aligning
+        _ if !needs_db => PathBuf::new(),
+        (Some(path), _) => path.to_owned(),
+        (None, _) if args.db_version.is_some() => {
+            let version = args.db_version.as_deref().unwrap_or_default();
+            match find_database(&db_root, version) {
+                Ok(path) => path,
+                // it is installed below
+                Err(_) if args.download => db_root.join(version),
+                Err(e) => return Err(e),
+            }
+        }
+        (None, Host::Custom) if installed_databases(&db_root)?.is_empty() => {
+            return Err(NohumanError::InvalidDb("A database must be provided with --db, or installed with `nohuman db install --from-file`, for a custom host".to_string()).into());
+        }
+        // native databases are installed alongside the kraken2 databases
+        (None, _) if native_only => native_database(&db_root)?.unwrap_or_else(|| db_root.clone()),
+        (None, _) => resolve_database(&db_root)?,
+    };
+
+    // Check if the database exists. It isn't needed if we are filtering with an existing kraken2 output.
+    // The default database root can exist without a database in it, e.g. after a failed download
+    let missing_db = if native_only {
+        find_native_index(&database).is_err()
+    } else {
+        !database.exists() || (args.database.is_none() && validate_db_directory(&database).is_err())
+    };
+    let mut download = args.download;
+    if missing_db && !download && !args.check && args.from_kraken_output.is_none() && needs_db {
+        if args.host == Host::Custom {
+            return Err(NohumanError::InvalidDb("Database does not exist".to_string()).into());
+        }
+        if native_only {
+            return Err(NohumanError::InvalidDb(
+                "No native database is installed. Build one with `nohuman db build --native`"
+                    .to_string(),
+            )
+            .into());
+        }
+        download = args.auto_download || prompt_download(&args)?;
+        if !download {
+            return Err(NohumanError::InvalidDb(
+                "Database does not exist. Use --download to download the database".to_string(),
+            )
+            .into());
+        }
+    }
+
+    if download {
+        let kind = if native_only { "native " } else { "" };
+        info!("Downloading {}{} database...", kind, args.host);
+        let mut options = args.download_options(&db_root)?;
+        options.version = args.db_version.clone();
+        if args.database.is_some() {
+            download_database(&database, &args.host, &options)
+                .context("Failed to download database")?;
+        } else {
+            database = install_database(&db_root, &args.host, &options)
+                .context("Failed to download database")?;
+        }
+        info!("Database downloaded to {:?}", database);
+        if args.input.is_none() && args.sample_sheet.is_none() {
+            info!("No input files provided. Exiting.");
+            return Ok(());
+        }
+    }
+
+    let server = args.server_socket().filter(|_| !serving);
+    let mut kraken = CommandRunner::new(&args.kraken2_command())
+        .with_host(args.host)
+        .with_server(server.clone())
+        .with_timeout(args.timeout)
+        .with_log(args.kraken2_log.clone());
+    let needs_kraken2 =
+        ((args.from_kraken_output.is_none() && server.is_none()) || args.check) && uses_kraken2;
+    if let Some(engine) = args.container {
+        if needs_kraken2 && !kraken.is_executable() {
+            let container = Container::new(engine).with_image(&args.container_image);
+            info!(
+                "{} isn't installed, so it will be run in the {} container {}",
+                kraken.command, engine, container.image
+            );
+            kraken = kraken.with_container(Some(container));
+        }
+    }
+
+    let minimap2 = CommandRunner::new(&program("minimap2"));
+
+    // kraken2 isn't needed if we are filtering with an existing kraken2 output, or the server
+    // runs it
+    let mut external_commands = if needs_kraken2 { vec![&kraken] } else { vec![] };
+    if args.reference.is_some() {
+        external_commands.push(&minimap2);
+    }
+
+    let mut missing_commands = Vec::new();
+    for cmd in &external_commands {
+        if !cmd.is_executable() {
+            debug!("{} is not executable", cmd.command);
+            match &cmd.container {
+                Some(container) => missing_commands.push(container.executable().to_string()),
+                None => missing_commands.push(cmd.command.to_owned()),
+            }
+        } else {
+            debug!("{} is executable", cmd.command);
+        }
+    }
+
+    if args.check {
+        let database =
+            (needs_db && args.from_kraken_output.is_none()).then_some(database.as_path());
+        return run_checks(&args, &external_commands, database, native_only);
+    }
+
+    if !missing_commands.is_empty() {
+        if kraken.container.is_none() && missing_commands.contains(&kraken.command) {
+            info!("kraken2 can be installed with `nohuman deps install`, or run in a container with --container");
+        }
+        return Err(NohumanError::MissingDependency(missing_commands).into());
+    }
+
+    if args.reference.is_some() {
+        let path = minimap2
+            .path()
+            .unwrap_or_else(|| PathBuf::from(&minimap2.command));
+        info!("Using minimap2 at {:?}", path);
+    }
+    let kraken2_version = if !needs_kraken2 {
+        None
+    } else {
+        let version = kraken.version();
+        let location = match &kraken.container {
+            Some(container) => format!("in the {} container {}", container.engine, container.image),
+            None => {
+                let path = kraken
+                    .path()
+                    .unwrap_or_else(|| PathBuf::from(&kraken.command));
+                format!("at {:?}", path)
+            }
+        };
+        match &version {
+            Some(version) => info!("Using kraken2 {} {}", version, location),
+            None => warn!("Unable to determine the version of kraken2 {}", location),
+        }
+        version
+    };
+
+    // databases installed by nohuman record the versions they need
+    if args.from_kraken_output.is_none() && needs_db {
+        if let Ok(metadata) = DatabaseMetadata::read(&database) {
+            if let (Some(required), None, true) = (
+                &metadata.min_kraken2_version,
+                &kraken2_version,
+                needs_kraken2,
+            ) {
+                warn!(
+                    "The database requires kraken2 {} or later, but the installed version couldn't be checked",
+                    required
+                );
+            }
+            if let Err(e) = check_compatibility(
+                metadata.min_nohuman_version.as_deref(),
+                metadata.min_kraken2_version.as_deref(),
+                kraken2_version.as_deref(),
+            ) {
+                if !args.ignore_compatibility {
+                    bail!("{}. Use --ignore-compatibility to run anyway", e);
+                }
+                warn!("{}. Continuing anyway", e);
+            }
+            warn_release_status(&metadata, &args, &db_root);
+        }
+    }
+
+    #[cfg(unix)]
+    if let Some(Command::Serve { socket }) = &args.command {
+        let socket = socket.clone().unwrap_or_else(default_socket);
+        info!("Loading database {:?} into memory...", database);
+        let server = Server::new(&database)
+            .context("Failed to load the database")?
+            .with_kraken2(kraken.clone());
+        return server.run(&socket);
+    }
+
+    let extra_kraken_args = kraken2_args(&args)?;
+    let taxa = if uses_kraken2 {
+        let mut taxids = args.taxids.clone();
+        if let Some(path) = &args.remove_taxids {
+            let list = read_taxid_list(BufReader::new(open_reader(path)?))
+                .with_context(|| format!("Failed to read the taxonomy IDs in {:?}", path))?;
+            if list.is_empty() {
+                bail!("{:?} has no taxonomy IDs", path);
+            }
+            taxids.extend(list);
+        }
+        host_taxa(&taxids, args.host, &database)?
+    } else {
+        None
+    };
+
+    if let Some(control) = &args.negative_control {
+        if native || args.from_kraken_output.is_some() {
+            bail!("--negative-control only calibrates kraken2, so it can't be used with --engine native or --from-kraken-output");
+        }
+        if taxa.is_some() {
+            bail!("--negative-control can't be used with a database of more than the host, --taxid, or --remove-taxids");
+        }
+        if args.input.is_none() && args.sample_sheet.is_none() {
+            bail!("No input files provided");
+        }
+        args.confidence = calibrate_confidence(
+            &args,
+            control,
+            &kraken,
+            &database,
+            &extra_kraken_args,
+            server.clone(),
+        )?;
+    }
+
+    let batch = match (&args.sample_sheet, &input_dir) {
+        (Some(sample_sheet), _) => Some((parse_sample_sheet(sample_sheet)?, sample_sheet)),
+        (None, Some(dir)) if dir.is_dir() => {
+            let out_dir = match &args.out_dir {
+                Some(out_dir) => out_dir.to_owned(),
+                None => default_out_dir(dir, &args.host)?,
+            };
+            Some((samples_from_directory(dir, &out_dir)?, dir))
+        }
+        (None, Some(pattern)) => Some((
+            samples_from_glob(&pattern.to_string_lossy(), args.out_dir.as_deref())?,
+            pattern,
+        )),
+        (None, None) => None,
+    };
+    if let Some((samples, source)) = batch {
+        if taxa.is_some() {
+            bail!("--sample-sheet and directory or glob inputs can't be used with a database of more than the host, --taxid, or --remove-taxids");
+        }
+        return run_batch(
+            &args,
+            samples,
+            source,
+            &kraken,
+            kraken2_version.as_deref(),
+            &database,
+            &extra_kraken_args,
+        );
+    }
+
+    // error out if input files are not provided, otherwise unwrap to a variable
+    let input = args.input.take().context("No input files provided")?;
+
+    let mut pipeline = DehostPipeline::new(input.clone(), &database)
+        .with_kraken2(kraken.command.clone())
+        .with_outputs(vec![args.out1.take(), args.out2.take(), args.out3.take()])
+        .with_threads(args.threads.get())
+        .with_confidence(args.confidence)
+        .keep_host_reads(args.keep_human_reads)
+        .with_host(args.host)
+        .with_kraken2_args(extra_kraken_args)
+        .interleaved(args.interleaved)
+        .low_memory(args.low_mem)
+        .secure_delete(args.secure_delete)
+        .stats_only(args.stats_only)
+        .overwrite(args.force)
+        .keep_tmp(args.keep_tmp)
+        .validate_inputs(!args.skip_validation)
+        .repair_pairs(args.repair_pairs)
+        .verify_outputs(!args.no_verify)
+        .with_output_format(args.output_format)
+        .write_checksums(args.write_checksums);
+    if let Some(format) = args.output_type {
+        pipeline = pipeline.with_compression(format);
+    }
+    if let Some(template) = args.out_template.clone() {
+        pipeline = pipeline.with_output_template(template);
+    }
+    if let Some(path) = args.kraken_output.take() {
+        pipeline = pipeline.with_kraken_output(path);
+    }
+    if let Some(path) = &args.from_kraken_output {
+        pipeline = pipeline.with_classifications(path);
+    }
+    if let Some(path) = &args.krona {
+        pipeline = pipeline.with_krona(path);
+    }
+    if let Some(dir) = &args.tmp_dir {
+        pipeline = pipeline.with_tmp_dir(dir);
+    }
+    if let Some(taxa) = taxa {
+        pipeline = pipeline.with_taxa(taxa);
+    }
+    if let Some(path) = &args.removed_ids {
+        pipeline = pipeline.with_removed_ids(path);
+    }
+    if let Some(socket) = server {
+        pipeline = pipeline.with_server(socket);
+    }
+    if let Some(container) = kraken.container {
+        pipeline = pipeline.with_container(container);
+    }
+    if let Some(timeout) = args.timeout {
+        pipeline = pipeline.with_timeout(timeout);
+    }
+    if let Some(path) = &args.kraken2_log {
+        pipeline = pipeline.with_kraken2_log(path);
+    }
+    if native {
+        if uses_kraken2 {
+            // kraken2 uses the database, so the native one is found separately
+            let Some(path) = native_database(&db_root)? else {
+                return Err(NohumanError::InvalidDb(
+                    "No native database is installed. Build one with `nohuman db build --native`"
+                        .to_string(),
+                )
+                .into());
+            };
+            info!("Using the built-in classifier with database {:?}", path);
+            pipeline = pipeline.with_native_database(path);
+        } else {
+            info!("Using the built-in classifier with database {:?}", database);
+        }
+        pipeline = pipeline.with_native(NativeClassifier {
+            min_hits: args.min_hit_groups.map_or(2, NonZeroU32::get),
+            confidence: args.confidence,
+        });
+    }
+    if args.engine.contains(Aligner::Mock) {
+        warn!(
+            "The mock engine marks {:.1}% of the reads as {} at random. Only use it for testing",
+            args.mock_fraction * 100.0,
+            args.host
+        );
+        pipeline = pipeline.with_mock(args.mock_classifier());
+    }
+    if let Some(reference) = &args.reference {
+        pipeline = pipeline
+            .confirm_with_alignment(args.confirm_with_alignment)
+            .with_minimap2(Minimap2 {
+                command: minimap2.command.clone(),
+                reference: reference.to_owned(),
+                preset: args.minimap2_preset.clone(),
+                filter: AlignmentFilter {
+                    min_identity: args.min_identity,
+                    min_length: args.min_aligned_len,
+                },
+            });
+    }
+    if consensus {
+        info!(
+            "Reads flagged as {} by at least {} of {} are host reads",
+            args.host, min_votes, args.engine
+        );
+        pipeline = pipeline.with_consensus(args.engine.as_slice().to_vec(), min_votes);
+    }
+    let stats = pipeline.run()?;
+    let host_fraction = stats.host_fraction();
+    let timings = Timings {
+        total: start_time.elapsed().unwrap_or_default(),
+        kraken2: stats.kraken2_usage,
+        compression: stats.compression_time,
+    };
+    info!("Time taken: {}", timings);
+    if args.stats_only {
+        info!(
+            "{} / {} ({:.2}%) sequences are {}; {} ({:.2}%) are non-{}",
+            stats.host,
+            stats.total,
+            stats.host_fraction() * 100.0,
+            args.host,
+            stats.non_host(),
+            (1.0 - stats.host_fraction()) * 100.0,
+            args.host
+        );
+    }
+
+    if let Some(path) = &args.multiqc {
+        let sample = Sample {
+            sample: sample_name(&input[0]),
+            r1: input[0].clone(),
+            r2: input.get(1).cloned(),
+            output_prefix: None,
+        };
+        let counts = SampleCounts {
+            total: stats.total,
+            host: stats.host,
+        };
+        write_multiqc_json(
+            path,
+            &[sample],
+            &[counts],
+            &args.host,
+            args.keep_human_reads,
+        )?;
+    }
+
+    if args.provenance {
+        let path = provenance_path(
+            stats.outputs.first().map(PathBuf::as_path),
+            &input[0],
+            &args.host,
+        );
+        let database =
+            (needs_db && args.from_kraken_output.is_none()).then(|| DatabaseRecord::new(&database));
+        let provenance = Provenance {
+            command_line: std::env::args().collect(),
+            nohuman_version: env!("CARGO_PKG_VERSION").to_string(),
+            kraken2_version: kraken2_version.clone(),
+            hostname: hostname(),
+            start_time: timestamp(start_time),
+            end_time: timestamp(SystemTime::now()),
+            database,
+            inputs: input
+                .iter()
+                .map(|path| FileRecord::new(path))
+                .collect::<Result<_>>()?,
+            outputs: stats
+                .outputs
+                .iter()
+                .map(|path| FileRecord::new(path))
+                .collect::<Result<_>>()?,
+            sequences: stats.total,
+            host_sequences: stats.host,
+            output_reads: stats.output_reads.clone(),
+        };
+        let file = File::create(&path).with_context(|| format!("Failed to create {:?}", path))?;
+        provenance.to_writer(BufWriter::new(file))?;
+        info!("Provenance written to: {:?}", path);
+    }
+
+    if let Some(path) = &args.summary {
+        let summary = Summary {
+            host: args.host.to_string(),
+            keep_host_reads: args.keep_human_reads,
+            inputs: input,
+            sequences: stats.total,
+            host_sequences: stats.host,
+            outputs: stats.outputs,
+            engine_host_sequences: stats.engine_host,
+            output_reads: stats.output_reads,
+            secure_delete: args.secure_delete,
+            kraken2_version,
+            timings,
+            ..Default::default()
+        };
+        write_summary_json(&summary, path)?;
+    }
+
+    if let Some(min) = args.expect_human.filter(|min| host_fraction < *min) {
+        warn_low_host_fraction("the inputs", host_fraction, min, &args.host);
+    }
+    if let Some(max) = args.max_human_frac.filter(|max| host_fraction > *max) {
+        error!(
+            "{:.2}% of sequences are {}, more than --max-human-frac {}",
+            host_fraction * 100.0,
+            args.host,
+            max
+        );
+        std::process::exit(FailureKind::HostFraction.exit_code().into());
+    }
+    if let Some(min) = args.min_human_frac.filter(|min| host_fraction < *min) {
+        error!(
+            "{:.2}% of sequences are {}, less than --min-human-frac {}",
+            host_fraction * 100.0,
+            args.host,
+            min
+        );
+        std::process::exit(FailureKind::HostFraction.exit_code().into());
+    }
+
+    info!("Done.");
+
+    Ok(())
+}
+
+/// Warn that only `fraction` of the sequences in `what` are `host` sequences, less than the
+/// `min` expected with --expect-human.
+fn warn_low_host_fraction(what: &str, fraction: f64, min: f64, host: &Host) {
+    warn!(
+        "Only {:.2}% of sequences in {} are {}, but at least {:.2}% were expected (--expect-human). \
+         This usually means the wrong database is in use - check --db and --db-version",
+        fraction * 100.0,
+        what,
+        host,
+        min * 100.0
+    );
+}
+
+/// Re-pair `read1` and `read2` (see [`repair_pairs`]). The outputs are the two files of pairs and
+/// the singletons, which are named after the inputs if not given. Existing outputs are only
+/// overwritten if `force` is set.
+fn run_repair(
+    read1: &Path,
+    read2: &Path,
+    outputs: [Option<&Path>; 3],
+    force: bool,
+    args: &Args,
+) -> Result<()> {
+    let input_compression = CompressionFormat::from_file(read1)?;
+    let [out1, out2, singletons] = outputs;
+    let output = |path: Option<&Path>, input: &Path, suffix: &str| match path {
+        Some(path) => CompressionFormat::from_path(path).map(|format| (path.to_path_buf(), format)),
+        None => Ok((
+            suffixed_output_path(input, suffix, input_compression),
+            input_compression,
+        )),
+    };
+    let outputs = [
+        output(out1, read1, "repaired")?,
+        output(out2, read2, "repaired")?,
+        output(singletons, read1, "singletons")?,
+    ];
+    let output_paths: Vec<PathBuf> = outputs.iter().map(|(path, _)| path.clone()).collect();
+    if output_paths.iter().collect::<HashSet<_>>().len() < output_paths.len() {
+        bail!(
+            "The output files must be different, but got {:?}",
+            output_paths
+        );
+    }
+    check_outputs(
+        &[read1.to_path_buf(), read2.to_path_buf()],
+        &output_paths,
+        force,
+    )?;
+
+    let tmpdir = tempfile::Builder::new()
+        .prefix("nohuman")
+        .tempdir_in(tmp_root(args)?)
+        .context("Failed to create temporary directory")?;
+    let _interrupt_guard = remove_on_interrupt(tmpdir.path(), false);
+    let tmp_paths = [
+        tmpdir.path().join("repaired_1.fq"),
+        tmpdir.path().join("repaired_2.fq"),
+        tmpdir.path().join("singletons.fq"),
+    ];
+    let mut writers = tmp_paths
+        .iter()
+        .map(|path| Ok(BufWriter::new(File::create(path)?)))
+        .collect::<Result<Vec<_>>>()?;
+    let [writer1, writer2, singletons_writer] = writers.as_mut_slice() else {
+        unreachable!("there are three outputs");
+    };
+
+    info!("Re-pairing {:?} and {:?}...", read1, read2);
+    let stats = repair_pairs(
+        || open_reader(read1),
+        || open_reader(read2),
+        writer1,
+        writer2,
+        Some(singletons_writer),
+    )
+    .with_context(|| format!("Failed to re-pair {:?} and {:?}", read1, read2))?;
+    for writer in &mut writers {
+        writer.flush()?;
+    }
+
+    for (tmp_path, (output, format)) in tmp_paths.iter().zip(&outputs) {
+        let progress = output_progress(std::fs::metadata(tmp_path)?.len(), output);
+        let threads = args.threads.get();
+        finish_output(tmp_path, output, *format, threads, false, false, &progress)?;
+        info!("Output file written to: {:?}", output);
+    }
+    info!(
+        "{} read pairs, and {} reads from R1 and {} reads from R2 without a mate",
+        stats.pairs, stats.orphans1, stats.orphans2
+    );
+
+    Ok(())
+}
+
+/// Simulate reads from the `human` and `contaminant` references (see [`simulate`]), writing them
+/// to `output` and their origins to `truth`.
+fn run_simulate(
+    human: &Path,
+    contaminant: &Path,
+    options: &SimulationOptions,
+    output: &Path,
+    truth: &Path,
+    force: bool,
+    args: &Args,
+) -> Result<()> {
+    if output == truth {
+        bail!("The reads and the truth set must be written to different files");
+    }
+    check_outputs(
+        &[human.to_path_buf(), contaminant.to_path_buf()],
+        &[output.to_path_buf(), truth.to_path_buf()],
+        force,
+    )?;
+    let compression = CompressionFormat::from_path(output)?;
+    let read_reference = |path: &Path| {
+        Reference::from_reader(open_reader(path)?, options.read_length)
+            .with_context(|| format!("Failed to read the reference {:?}", path))
+    };
+    info!("Reading the references...");
+    let human = read_reference(human)?;
+    let contaminant = read_reference(contaminant)?;
+
+    let tmpdir = tempfile::Builder::new()
+        .prefix("nohuman")
+        .tempdir_in(tmp_root(args)?)
+        .context("Failed to create temporary directory")?;
+    let _interrupt_guard = remove_on_interrupt(tmpdir.path(), false);
+    let tmp_reads = tmpdir.path().join("simulated.fq");
+    let mut reads_writer = BufWriter::new(File::create(&tmp_reads)?);
+    let mut truth_writer = BufWriter::new(
+        File::create(truth).with_context(|| format!("Failed to create {:?}", truth))?,
+    );
+    info!(
+        "Simulating {} reads (seed {})...",
+        options.reads, options.seed
+    );
+    simulate(
+        &human,
+        &contaminant,
+        options,
+        &mut reads_writer,
+        &mut truth_writer,
+    )?;
+    reads_writer.flush()?;
+    truth_writer.flush()?;
+
+    let progress = output_progress(std::fs::metadata(&tmp_reads)?.len(), output);
+    finish_output(
+        &tmp_reads,
+        output,
+        compression,
+        args.threads.get(),
+        false,
+        false,
+        &progress,
+    )?;
+    info!(
+        "{} human and {} contaminant reads written to: {:?}",
+        options.host_reads(),
+        options.reads - options.host_reads(),
+        output
+    );
+    info!("Truth set written to: {:?}", truth);
+    Ok(())
+}
+
+/// The database versions and engines `nohuman bench` runs: a name for each, its engine, and its
+/// database. With no versions or engines, all the installed versions are run.
+fn bench_runs(
+    args: &Args,
+    versions: &[String],
+    engines: &[Aligner],
+    reference: Option<&Path>,
+) -> Result<Vec<(String, Aligner, Option<PathBuf>)>> {
+    let db_root = default_db_location(&args.host);
+    let versions = if versions.is_empty() && engines.is_empty() {
+        installed_databases(&db_root)?
+            .into_iter()
+            .filter(|db| find_native_index(db.location()).is_err())
+            .map(|db| db.metadata.version)
+            .collect()
+    } else {
+        versions.to_vec()
+    };
+    let mut runs = Vec::new();
+    for version in versions {
+        let database = find_database(&db_root, &version)?;
+        runs.push((version, Aligner::Kraken2, Some(database)));
+    }
+    for &engine in engines {
+        let database = match engine {
+            Aligner::Kraken2 => Some(match &args.database {
+                Some(path) => path.to_owned(),
+                None => resolve_database(&db_root)?,
+            }),
+            Aligner::Native => {
+                let Some(path) = native_database(&db_root)? else {
+                    return Err(NohumanError::InvalidDb("No native database is installed. Build one with `nohuman db build --native`".to_string()).into());
+                };
+                Some(path)
+            }
+            Aligner::Minimap2 if reference.is_none() => {
+                bail!("--reference is required to compare the minimap2 engine")
+            }
+            Aligner::Minimap2 | Aligner::Mock => None,
+        };
+        runs.push((engine.to_string(), engine, database));
+    }
+    if runs.is_empty() {
+        return Err(NohumanError::InvalidDb(format!(
+            "No databases are installed in {:?} to compare",
+            db_root
+        ))
+        .into());
+    }
+    Ok(runs)
+}
+
+/// Run `nohuman bench`: classify `input` with each of the `runs` (see [`bench_runs`]), and write a
+/// table comparing them to `output`, or stdout.
+fn run_bench(
+    args: &Args,
+    input: &[PathBuf],
+    runs: Vec<(String, Aligner, Option<PathBuf>)>,
+    reference: Option<&Path>,
+    output: Option<&Path>,
+) -> Result<()> {
+    let results = classify_runs(args, input, runs, reference)?;
+    for run in results.iter().skip(1) {
+        let agreement = Agreement::new(&results[0].host_ids, &run.host_ids);
+        info!(
+            "{} and {} agree on {} {} reads (Jaccard index {:.4})",
+            results[0].name, run.name, agreement.shared, args.host, agreement.jaccard
+        );
+    }
+    write_output(output, "Comparison", |writer| write_tsv(writer, &results))
+}
+
+/// Write a table with `write` to `output`, or stdout. `what` names the table in the log.
+fn write_output(
+    output: Option<&Path>,
+    what: &str,
+    write: impl FnOnce(&mut dyn Write) -> Result<()>,
+) -> Result<()> {
+    match output {
+        Some(path) => {
+            let file =
+                File::create(path).with_context(|| format!("Failed to create {:?}", path))?;
+            let mut writer = BufWriter::new(file);
+            write(&mut writer)?;
+            writer.flush()?;
+            info!("{} written to: {:?}", what, path);
+        }
+        None => write(&mut std::io::stdout().lock())?,
+    }
+    Ok(())
+}
+
+/// Classify the `control` reads (known to have no host reads) at a confidence of 0, and choose the
+/// confidence for the inputs from their kraken2 output.
+fn calibrate_confidence(
+    args: &Args,
+    control: &[PathBuf],
+    kraken: &CommandRunner,
+    database: &Path,
+    extra_kraken_args: &[String],
+    server: Option<PathBuf>,
+) -> Result<f32> {
+    info!("Classifying the negative control {:?}...", control);
+    let tmpdir = tempfile::Builder::new()
+        .prefix("nohuman")
+        .tempdir_in(tmp_root(args)?)
+        .context("Failed to create temporary directory")?;
+    let _interrupt_guard = remove_on_interrupt(tmpdir.path(), false);
+    let kraken_output = tmpdir.path().join("control.kraken.out");
+    let mut pipeline = DehostPipeline::new(control.to_vec(), database)
+        .with_kraken2(kraken.command.clone())
+        .with_threads(args.threads.get())
+        .with_confidence(0.0)
+        .with_host(args.host)
+        .with_kraken2_args(extra_kraken_args.to_vec())
+        .low_memory(args.low_mem)
+        .validate_inputs(!args.skip_validation)
+        .stats_only(true)
+        .with_kraken_output(&kraken_output)
+        .with_tmp_dir(tmpdir.path());
+    if let Some(socket) = server {
+        pipeline = pipeline.with_server(socket);
+    }
+    if let Some(container) = kraken.container.clone() {
+        pipeline = pipeline.with_container(container);
+    }
+    if let Some(timeout) = args.timeout {
+        pipeline = pipeline.with_timeout(timeout);
+    }
+    pipeline
+        .run()
+        .context("Failed to classify the negative control")?;
+    let calibration = calibrate(
+        BufReader::new(File::open(&kraken_output)?),
+        args.control_max_frac,
+    )
+    .context("Failed to calibrate the confidence with the negative control")?;
+    info!(
+        "Using a confidence of {} for the inputs, at which {} of the {} negative control sequences ({:.3}%) are classified as {}",
+        calibration.confidence,
+        calibration.classified,
+        calibration.reads,
+        calibration.classified as f64 * 100.0 / calibration.reads.max(1) as f64,
+        args.host
+    );
+    Ok(calibration.confidence)
+}
+
+/// Classify `input` with each of the `runs` (see [`bench_runs`]), without writing any output,
+/// keeping the IDs of the reads each classifies as host.
+fn classify_runs(
+    args: &Args,
+    input: &[PathBuf],
+    runs: Vec<(String, Aligner, Option<PathBuf>)>,
+    reference: Option<&Path>,
+) -> Result<Vec<BenchRun>> {
+    let kraken = CommandRunner::new(&args.kraken2_command());
+    let minimap2 = CommandRunner::new(&program("minimap2"));
+    let mut missing = Vec::new();
+    for (engine, cmd) in [(Aligner::Kraken2, &kraken), (Aligner::Minimap2, &minimap2)] {
+        if runs.iter().any(|(_, e, _)| *e == engine) && !cmd.is_executable() {
+            missing.push(cmd.command.clone());
+        }
+    }
+    if !missing.is_empty() {
+        return Err(NohumanError::MissingDependency(missing).into());
+    }
+    let extra_kraken_args = kraken2_args(args)?;
+    let tmpdir = tempfile::Builder::new()
+        .prefix("nohuman")
+        .tempdir_in(tmp_root(args)?)
+        .context("Failed to create temporary directory")?;
+
+    let mut results = Vec::new();
+    for (i, (name, engine, database)) in runs.into_iter().enumerate() {
+        info!("Running {} ({})...", name, engine);
+        let ids_path = tmpdir.path().join(format!("host_ids_{}.txt", i));
+        let mut pipeline =
+            DehostPipeline::new(input.to_vec(), database.clone().unwrap_or_default())
+                .with_kraken2(kraken.command.clone())
+                .with_threads(args.threads.get())
+                .with_confidence(args.confidence)
+                .with_host(args.host)
+                .with_kraken2_args(extra_kraken_args.clone())
+                .low_memory(args.low_mem)
+                .stats_only(true)
+                .with_removed_ids(&ids_path)
+                .with_tmp_dir(tmpdir.path());
+        match engine {
+            Aligner::Kraken2 => {}
+            Aligner::Native => {
+                pipeline = pipeline.with_native(NativeClassifier {
+                    min_hits: args.min_hit_groups.map_or(2, NonZeroU32::get),
+                    confidence: args.confidence,
+                });
+            }
+            Aligner::Minimap2 => {
+                pipeline = pipeline.with_minimap2(Minimap2 {
+                    command: minimap2.command.clone(),
+                    reference: reference.map(Path::to_path_buf).unwrap_or_default(),
+                    preset: args.minimap2_preset.clone(),
+                    filter: AlignmentFilter {
+                        min_identity: args.min_identity,
+                        min_length: args.min_aligned_len,
+                    },
+                });
+            }
+            Aligner::Mock => pipeline = pipeline.with_mock(args.mock_classifier()),
+        }
+        let start = Instant::now();
+        let stats = pipeline
+            .run()
+            .with_context(|| format!("Failed to run {}", name))?;
+        let wall_time = start.elapsed();
+        let host_ids = read_ids_from(BufReader::new(File::open(&ids_path)?))?;
+        info!(
+            "{} found {} of {} sequences to be {} in {}",
+            name,
+            stats.host,
+            stats.total,
+            args.host,
+            format_seconds(wall_time)
+        );
+        results.push(BenchRun {
+            name,
+            engine,
+            database,
+            wall_time,
+            kraken2_usage: stats.kraken2_usage,
+            sequences: stats.total,
+            host_ids,
+        });
+    }
+    Ok(results)
+}
+
+fn run_deps_command(cmd: &DepsCommand, args: &Args) -> Result<()> {
+    match cmd {
+        DepsCommand::Install {
+            kraken2_version,
+            force,
+        } => {
+            let bin_dir = bin_dir();
+            if let Some(path) = installed_program(&bin_dir, "kraken2") {
+                if !force {
+                    let version = CommandRunner::new(&path.to_string_lossy()).version();
+                    info!(
+                        "kraken2 {} is already installed at {:?}. Use --force to reinstall it",
+                        version.as_deref().unwrap_or("(unknown version)"),
+                        path
+                    );
+                    return Ok(());
+                }
+            }
+            let options = args.download_options(&default_db_location(&args.host))?;
+            let path = install_kraken2(kraken2_version, &bin_dir, &options)
+                .context("Failed to install kraken2")?;
+            info!("kraken2 {} installed to {:?}", kraken2_version, path);
+        }
+    }
+    Ok(())
+}
+
+fn run_db_command(cmd: &DbCommand, args: &Args, db_root: &Path) -> Result<()> {
+    match cmd {
+        DbCommand::Install {
+            from_file,
+            version,
+            md5,
+            sha256,
+            blake3,
+        } => {
+            let path = match (from_file, version) {
+                (Some(tarball), Some(version)) => {
+                    let checksum = match (md5, sha256, blake3) {
+                        (_, _, Some(hash)) => Some(Checksum::Blake3(hash.to_owned())),
+                        (_, Some(hash), _) => Some(Checksum::Sha256(hash.to_owned())),
+                        (Some(hash), _, _) => Some(Checksum::Md5(hash.to_owned())),
+                        _ => None,
+                    };
+                    info!(
+                        "Installing {:?} as {} database {}...",
+                        tarball, args.host, version
+                    );
+                    install_tarball(tarball, db_root, version, &args.host, checksum.as_ref())
+                        .context("Failed to install database")?
+                }
+                _ => {
+                    if args.host == Host::Custom {
+                        bail!("There is no database to download for a custom host");
+                    }
+                    info!("Downloading {} database...", args.host);
+                    let mut options = args.download_options(db_root)?;
+                    options.version = version.clone();
+                    install_database(db_root, &args.host, &options)
+                        .context("Failed to download database")?
+                }
+            };
+            info!("Database installed to {:?}", path);
+        }
+        DbCommand::Remove {
+            version,
+            all,
+            dry_run,
+        } => {
+            let _lock = if *dry_run {
+                None
+            } else {
+                Some(DatabaseLock::acquire(db_root)?)
+            };
+            let installed = installed_databases(db_root)?;
+            let to_remove: Vec<_> = match version {
+                Some(version) if !all => {
+                    let db = installed
+                        .into_iter()
+                        .find(|db| &db.metadata.version == version)
+                        .ok_or_else(|| NohumanError::DbNotInstalled {
+                            version: version.to_string(),
+                            root: db_root.to_path_buf(),
+                        })?;
+                    vec![db]
+                }
+                _ => installed,
+            };
+            if to_remove.is_empty() {
+                info!("No databases installed in {:?}", db_root);
+                return Ok(());
+            }
+
+            let mut reclaimed = 0;
+            for db in to_remove {
+                let size = db.size()?;
+                reclaimed += size;
+                if *dry_run {
+                    info!(
+                        "Would remove {} ({:?}, {})",
+                        db.metadata.version,
+                        db.path,
+                        HumanBytes(size)
+                    );
+                    continue;
+                }
+                db.remove()?;
+                if db.is_registered() {
+                    info!(
+                        "Unregistered {} (its files in {:?} were not removed)",
+                        db.metadata.version,
+                        db.location()
+                    );
+                } else {
+                    info!("Removed {} ({:?})", db.metadata.version, db.path);
+                }
+                let root_pin = read_pin(&[db_root])?;
+                if root_pin.is_some_and(|pin| pin.version == db.metadata.version) {
+                    pin_version(db_root, None)?;
+                    warn!("Unpinned {} as it has been removed", db.metadata.version);
+                }
+            }
+            if *dry_run {
+                info!("{} would be reclaimed", HumanBytes(reclaimed));
+            } else {
+                info!("Reclaimed {}", HumanBytes(reclaimed));
+            }
+        }
+        DbCommand::Verify { version } => {
+            let installed: Vec<_> = installed_databases(db_root)?
+                .into_iter()
+                .filter(|db| version.as_ref().map_or(true, |v| &db.metadata.version == v))
+                .collect();
+            if installed.is_empty() {
+                match version {
+                    Some(version) => {
+                        return Err(NohumanError::DbNotInstalled {
+                            version: version.to_string(),
+                            root: db_root.to_path_buf(),
+                        }
+                        .into())
+                    }
+                    None => info!("No databases installed in {:?}", db_root),
+                }
+            }
+
+            let mut failed = Vec::new();
+            for db in installed {
+                info!("Verifying {} ({:?})...", db.metadata.version, db.path);
+                if db.metadata.files.is_empty() {
+                    warn!(
+                        "No file checksums are recorded for {}, so it can't be verified",
+                        db.metadata.version
+                    );
+                    continue;
+                }
+                let mut ok = true;
+                for (name, status) in db.verify()? {
+                    println!("{}\t{}\t{}", db.metadata.version, name, status);
+                    ok &= status == FileStatus::Ok;
+                }
+                if !ok {
+                    failed.push(db.metadata.version);
+                }
+            }
+            if !failed.is_empty() {
+                return Err(NohumanError::InvalidDb(format!(
+                    "Database(s) failed verification: {}",
+                    failed.join(", ")
+                ))
+                .into());
+            }
+        }
+        DbCommand::CheckUpdates { upgrade, prune } => {
+            if args.host == Host::Custom {
+                bail!("There are no database releases for a custom host");
+            }
+            let options = args.download_options(db_root)?;
+            let release = latest_release(&args.host, &options)
+                .context("Failed to get the latest database release")?;
+            let latest = release.version();
+            let installed = installed_databases(db_root)?;
+            let pin = pinned_version(db_root)?;
+            let current = match &pin {
+                Some(pin) => Some(pin.version.as_str()),
+                None => installed.last().map(|db| db.metadata.version.as_str()),
+            };
+            for db in &installed {
+                if let Some(status) = release.find(&db.metadata.version).and_then(|r| r.status()) {
+                    println!(
+                        "Note: the installed {} database {} is {}",
+                        args.host, db.metadata.version, status
+                    );
+                }
+            }
+
+            if installed.iter().any(|db| db.metadata.version == latest) {
+                println!(
+                    "The latest {} database ({}) is installed",
+                    args.host, latest
+                );
+                match &pin {
+                    Some(pin) if pin.version != latest => println!(
+                        "Note: {} is pinned by {:?}, so it is used by default",
+                        pin.version, pin.path
+                    ),
+                    // a database installed more recently takes precedence
+                    None if current != Some(latest.as_str()) => println!(
+                        "Note: {} was installed more recently, so it is used by default",
+                        current.unwrap_or_default()
+                    ),
+                    _ => {}
+                }
+                return Ok(());
+            }
+
+            let available = match release.status() {
+                Some(status) => format!("{} ({})", latest, status),
+                None => latest.clone(),
+            };
+            match current {
+                Some(current) => println!(
+                    "A newer {} database is available: {} (installed: {})",
+                    args.host, available, current
+                ),
+                None => println!(
+                    "No {} database is installed. The latest is {}",
+                    args.host, available
+                ),
+            }
+            if !upgrade {
+                println!("Run `nohuman db check-updates --upgrade` to install it");
+                return Ok(());
+            }
+
+            info!("Downloading {} database {}...", args.host, latest);
+            let path = install_database(db_root, &args.host, &options)
+                .context("Failed to download database")?;
+            info!("Database installed to {:?}", path);
+
+            if *prune {
+                let _lock = DatabaseLock::acquire(db_root)?;
+                // registered databases aren't releases, so are kept
+                for db in installed_databases(db_root)? {
+                    if db.path != path && !db.is_registered() {
+                        db.remove()?;
+                        info!("Removed {} ({:?})", db.metadata.version, db.path);
+                    }
+                }
+            }
+        }
+        DbCommand::Build {
+            genome,
+            name,
+            taxid,
+            kmer_len,
+            minimizer_len,
+            native,
+            window_len,
+        } => {
+            if *native {
+                let kmer_len = match kmer_len {
+                    Some(k) => u8::try_from(*k)
+                        .ok()
+                        .filter(|k| *k <= 31)
+                        .context("The k-mer length of a native database must be at most 31")?,
+                    None => DEFAULT_KMER_LEN,
+                };
+                let path =
+                    build_native_database(genome, kmer_len, *window_len, db_root, name, &args.host)
+                        .context("Failed to build database")?;
+                info!("Native database built and installed to {:?}", path);
+                return Ok(());
+            }
+            let options = BuildOptions {
+                genomes: genome.to_owned(),
+                taxid: *taxid,
+                threads: args.threads.get() as usize,
+                kmer_len: *kmer_len,
+                minimizer_len: *minimizer_len,
+                command: program("kraken2-build"),
+            };
+            let kraken2_build = CommandRunner::new(&options.command);
+            if !kraken2_build.is_executable() {
+                return Err(NohumanError::MissingDependency(vec![options.command]).into());
+            }
+            let path = build_database(&options, db_root, name, &args.host)
+                .context("Failed to build database")?;
+            info!("Database built and installed to {:?}", path);
+        }
+        DbCommand::Add { name, path } => {
+            let dir = register_database(path, db_root, name, &args.host)
+                .context("Failed to register database")?;
+            info!("Registered {:?} as {} ({:?})", path, name, dir);
+        }
+        DbCommand::Pin { version, clear } => {
+            if *clear {
+                let _lock = DatabaseLock::acquire(db_root)?;
+                pin_version(db_root, None)?;
+                info!("Removed the {} database pin", args.host);
+                return Ok(());
+            }
+            let Some(version) = version else {
+                match pinned_version(db_root)? {
+                    Some(pin) => println!("{}\t{}", pin.version, pin.path.display()),
+                    None => info!("No {} database version is pinned", args.host),
+                }
+                return Ok(());
+            };
+            let _lock = DatabaseLock::acquire(db_root)?;
+            if !installed_databases(db_root)?
+                .iter()
+                .any(|db| &db.metadata.version == version)
+            {
+                return Err(NohumanError::DbNotInstalled {
+                    version: version.to_string(),
+                    root: db_root.to_path_buf(),
+                }
+                .into());
+            }
+            pin_version(db_root, Some(version))?;
+            info!("Pinned the {} database to {}", args.host, version);
+            if let Some(pin) = pinned_version(db_root)?.filter(|pin| &pin.version != version) {
+                warn!(
+                    "{} is pinned by {:?}, which takes precedence in the current directory",
+                    pin.version, pin.path
+                );
+            }
+        }
+        DbCommand::Path { db_version } => {
+            let path = match db_version {
+                Some(version) => find_database(db_root, version)?,
+                None => resolve_database(db_root)?,
+            };
+            // the kraken2 index files can be in a 'db' subdirectory
+            let k2d_dir = validate_db_directory(&path)
+                .with_context(|| format!("No {} database is installed", args.host))?;
+            println!("{}", k2d_dir.display());
+        }
+        DbCommand::Info { version } => {
+            let installed: Vec<_> = installed_databases(db_root)?
+                .into_iter()
+                .filter(|db| version.as_ref().map_or(true, |v| &db.metadata.version == v))
+                .collect();
+            if installed.is_empty() {
+                match version {
+                    Some(version) => {
+                        return Err(NohumanError::DbNotInstalled {
+                            version: version.to_string(),
+                            root: db_root.to_path_buf(),
+                        }
+                        .into())
+                    }
+                    // databases installed before versioning have no metadata
+                    None if validate_db_directory(db_root).is_ok() => {
+                        print_db_info(db_root, None, None)?
+                    }
+                    None => info!("No databases installed in {:?}", db_root),
+                }
+            }
+            let release = args
+                .download_options(db_root)
+                .ok()
+                .and_then(|options| cached_release(&args.host, &options));
+            for db in installed {
+                let status = release
+                    .as_ref()
+                    .and_then(|release| release.find(&db.metadata.version))
+                    .and_then(|release| release.status());
+                print_db_info(db.location(), Some(&db.metadata), status)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Ask the user whether to download the missing database. This is only asked if nohuman is
+/// running in a terminal, and stdin isn't an input.
+fn prompt_download(args: &Args) -> Result<bool> {
+    let stdin = std::io::stdin();
+    let reads_stdin = args.input.iter().flatten().any(|path| is_stdio(path));
+    if reads_stdin || !stdin.is_terminal() || !std::io::stderr().is_terminal() {
+        return Ok(false);
+    }
+
+    eprint!(
+        "The {} database is not installed. Download it now? [y/N] ",
+        args.host
+    );
+    std::io::stderr().flush()?;
+    let mut answer = String::new();
+    stdin.read_line(&mut answer)?;
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+/// Warn if the installed database with `metadata` has been yanked or deprecated, according to
+/// the cached list of releases. The list isn't downloaded, so this works offline.
+fn warn_release_status(metadata: &DatabaseMetadata, args: &Args, db_root: &Path) {
+    let Some(release) = args
+        .download_options(db_root)
+        .ok()
+        .and_then(|options| cached_release(&args.host, &options))
+    else {
+        return;
+    };
+    match release.find(&metadata.version).and_then(|r| r.status()) {
+        Some("yanked") => warn!(
+            "Database {} has been yanked and should not be used. Run `nohuman db check-updates --upgrade` to install the latest release",
+            metadata.version
+        ),
+        Some(status) => warn!(
+            "Database {} is {}. Run `nohuman db check-updates --upgrade` to install the latest release",
+            metadata.version, status
+        ),
+        None => {}
+    }
+}
+
+/// Print the details of the database at `path` to stdout. `status` is whether the release has
+/// been yanked or deprecated.
+fn print_db_info(
+    path: &Path,
+    metadata: Option<&DatabaseMetadata>,
+    status: Option<&str>,
+) -> Result<()> {
+    if find_native_index(path).is_ok() {
+        println!("{}", metadata.map_or("unversioned", |m| m.version.as_str()));
+        println!("  path: {}", path.display());
+        if let Some(metadata) = metadata {
+            println!("  host: {}", metadata.host);
+            println!("  added: {}", metadata.added);
+        }
+        println!("  type: native");
+        println!("  size: {}", HumanBytes(dir_size(path)?));
+        return Ok(());
+    }
+    let k2d_dir = validate_db_directory(path)?;
+    debug!("Computing MD5 of {:?}...", k2d_dir.join("hash.k2d"));
+    let md5 = compute_md5(&k2d_dir.join("hash.k2d"))?;
+    let opts = IndexOptions::from_db(&k2d_dir)?;
+
+    println!("{}", metadata.map_or("unversioned", |m| m.version.as_str()));
+    println!("  path: {}", path.display());
+    if let Some(metadata) = metadata {
+        println!("  host: {}", metadata.host);
+        println!("  added: {}", metadata.added);
+    }
+    if let Some(status) = status {
+        println!("  status: {}", status);
+    }
+    println!("  size: {}", HumanBytes(dir_size(path)?));
+    println!("  hash.k2d md5: {}", md5);
+    println!("  k-mer length: {}", opts.k);
+    println!("  minimizer length: {}", opts.l);
+    Ok(())
+}
+
+/// Write the counts of `samples` as MultiQC custom content to `path`.
+fn write_multiqc_json(
+    path: &Path,
+    samples: &[Sample],
+    counts: &[SampleCounts],
+    host: &Host,
+    keep_host_reads: bool,
+) -> Result<()> {
+    if !path.to_string_lossy().ends_with("_mqc.json") {
+        warn!(
+            "MultiQC won't find {:?}, as its name doesn't end in _mqc.json",
+            path
+        );
+    }
+    let file = File::create(path).context("Failed to create MultiQC file")?;
+    write_multiqc(BufWriter::new(file), samples, counts, host, keep_host_reads)?;
+    info!("MultiQC content written to: {:?}", path);
+    Ok(())
+}
+
+fn write_summary_json(summary: &Summary, path: &Path) -> Result<()> {
+    let file = std::fs::File::create(path).context("Failed to create summary file")?;
+    summary.to_writer(std::io::BufWriter::new(file))?;
+    info!("Summary written to: {:?}", path);
+    Ok(())
+}
+
+/// The default output directory for a directory INPUT, e.g. "fastq_pass" -> "fastq_pass.nohuman".
+fn default_out_dir(dir: &Path, host: &Host) -> Result<PathBuf> {
+    let dir = dir
+        .canonicalize()
+        .with_context(|| format!("Failed to resolve {:?}", dir))?;
+    let Some(name) = dir.file_name() else {
+        bail!(
+            "Can't name the output directory for {:?}. Use --out-dir",
+            dir
+        );
+    };
+    Ok(dir.with_file_name(format!(
+        "{}.{}",
+        name.to_string_lossy(),
+        host.output_suffix()
+    )))
+}
+
+/// Classify all samples (from a sample sheet or directory, `source`) while only loading the
+/// database once. The reads of all samples are concatenated (with their sample tagged onto each
+/// read ID) and classified in a single kraken2 run, before being split back out into per-sample
+/// outputs.
+fn run_batch(
+    args: &Args,
+    samples: Vec<Sample>,
+    source: &Path,
+    kraken: &CommandRunner,
+    kraken2_version: Option<&str>,
+    database: &Path,
+    extra_kraken_args: &[String],
+) -> Result<()> {
+    let start = Instant::now();
+    let mut kraken2_usage: Option<ResourceUsage> = None;
+    let k2d_dir = validate_db_directory(database)?;
+    // the server memory-maps the database itself
+    let memory_mapping = kraken.server.is_none()
+        && !extra_kraken_args
+            .iter()
+            .any(|arg| arg == "--memory-mapping")
+        && use_memory_mapping(&k2d_dir, args.low_mem);
+    if let Some(path) = &kraken.log {
+        // each kraken2 run is appended to the log
+        std::fs::File::create(path).with_context(|| format!("Failed to create {:?}", path))?;
+    }
+    info!("Processing {} samples from {:?}", samples.len(), source);
+
+    // without --output-type, each sample's output is compressed like its input
+    let output_compression = samples
+        .iter()
+        .map(|sample| match args.output_type {
+            Some(format) => Ok(format),
+            None => {
+                let mut reader = std::io::BufReader::new(std::fs::File::open(&sample.r1)?);
+                CompressionFormat::from_reader(&mut reader)
+            }
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let output_path = |i: usize, n: usize| {
+        let (sample, format) = (&samples[i], output_compression[i]);
+        match &args.out_template {
+            Some(template) => sample.templated_output_path(n, template, format, &args.host),
+            None => format.add_extension(sample.output_path(n, &args.host)),
+        }
+    };
+    let (mut all_inputs, mut all_outputs) = (Vec::new(), Vec::new());
+    for (i, sample) in samples.iter().enumerate() {
+        for (n, input) in sample.inputs().into_iter().enumerate() {
+            all_inputs.push(input.to_path_buf());
+            all_outputs.push(output_path(i, n));
+        }
+    }
+    all_outputs.extend(args.batch_summary.clone());
+    check_outputs(&all_inputs, &all_outputs, args.force)?;
+    for output in &all_outputs {
+        if let Some(parent) = output.parent().filter(|p| !p.as_os_str().is_empty()) {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create output directory {:?}", parent))?;
+        }
+    }
+
+    let mut tmpdir = tempfile::Builder::new()
+        .prefix("nohuman")
+        .tempdir_in(tmp_root(args)?)
+        .context("Failed to create temporary directory")?;
+    if args.keep_tmp {
+        keep_tmpdir(&mut tmpdir);
+    }
+    let _shred_guard = ShredGuard::new(args.secure_delete.then(|| tmpdir.path().to_path_buf()));
+    let _interrupt_guard = remove_on_interrupt(tmpdir.path(), args.secure_delete);
+
+    let read_selection_flag = if args.keep_human_reads {
+        info!("Keeping {} reads...", args.host);
+        "--classified-out"
+    } else {
+        info!("Removing {} reads...", args.host);
+        "--unclassified-out"
+    };
+
+    let threads = args.threads.to_string();
+    let confidence = args.confidence.to_string();
+    let mut counts = vec![SampleCounts::default(); samples.len()];
+    let mut outputs = Vec::new();
+
+    // kraken2 can't classify paired and single-end reads together, so each gets its own run
+    for (paired, name) in [(true, "paired"), (false, "single")] {
+        let group: Vec<(usize, &Sample)> = samples
+            .iter()
+            .enumerate()
+            .filter(|(_, s)| s.is_paired() == paired)
+            .collect();
+        if group.is_empty() {
+            continue;
+        }
+        let n_files = if paired { 2 } else { 1 };
+
+        let mut combined = Vec::new();
+        for n in 0..n_files {
+            let path = tmpdir.path().join(format!("{}_{}.fq", name, n + 1));
+            let mut writer = std::io::BufWriter::new(std::fs::File::create(&path)?);
+            for (tag, (i, sample)) in group.iter().enumerate() {
+                let input = sample.inputs()[n];
+                let n_reads = tag_reads(tag, open_reader(input)?, &mut writer)
+                    .with_context(|| format!("Failed to read {:?}", input))?;
+                // kraken2 counts a pair as one sequence, so only count the first file
+                if n == 0 {
+                    counts[*i].total = n_reads;
+                }
+            }
+            writer.flush()?;
+            combined.push(path);
+        }
+
+        let outfile = if paired {
+            tmpdir.path().join(format!("{}_kraken_out#.fq", name))
+        } else {
+            tmpdir.path().join(format!("{}_kraken_out_1.fq", name))
+        };
+        let mut kraken_cmd: Vec<&OsStr> = vec![
+            OsStr::new("--threads"),
+            OsStr::new(&threads),
+            OsStr::new("--db"),
+            k2d_dir.as_os_str(),
+            OsStr::new("--output"),
+            OsStr::new(NULL_DEVICE),
+            OsStr::new("--confidence"),
+            OsStr::new(&confidence),
+        ];
+        if paired {
+            kraken_cmd.push(OsStr::new("--paired"));
+        }
+        if memory_mapping {
+            kraken_cmd.push(OsStr::new("--memory-mapping"));
+        }
+        kraken_cmd.extend([OsStr::new(read_selection_flag), outfile.as_os_str()]);
+        kraken_cmd.extend(extra_kraken_args.iter().map(OsStr::new));
+        kraken_cmd.extend(combined.iter().map(|path| path.as_os_str()));
+        debug!("Running kraken2 on {} {}-end samples...", group.len(), name);
+        debug!("With arguments: {:?}", &kraken_cmd);
+        let (_, usage) = kraken
+            .run_with_usage(&kraken_cmd)
+            .context("Failed to run kraken2")?;
+        if let Some(usage) = usage {
+            kraken2_usage = Some(kraken2_usage.map_or(usage, |total| total.combine(usage)));
+        }
+
+        for n in 0..n_files {
+            let selected = tmpdir
+                .path()
+                .join(format!("{}_kraken_out_{}.fq", name, n + 1));
+            let mut sample_paths = Vec::new();
+            let mut writers = Vec::new();
+            for (tag, (i, _)) in group.iter().enumerate() {
+                let path = tmpdir
+                    .path()
+                    .join(format!("{}_sample{}_{}.fq", name, tag, n + 1));
+                writers.push(std::io::BufWriter::new(std::fs::File::create(&path)?));
+                sample_paths.push((path, output_path(*i, n), output_compression[*i]));
+            }
+            let selected_counts = split_tagged_reads(open_reader(&selected)?, &mut writers)?;
+            for mut writer in writers {
+                writer.flush()?;
+            }
+            outputs.extend(sample_paths);
+
+            if n == 0 {
+                for ((i, _), n_selected) in group.iter().zip(selected_counts) {
+                    counts[*i].host = if args.keep_human_reads {
+                        n_selected
+                    } else {
+                        counts[*i].total - n_selected
+                    };
+                }
+            }
+        }
+    }
+    info!("Kraken2 finished. Organising output...");
+
+    let output_paths: Vec<PathBuf> = outputs.iter().map(|(_, out, _)| out.to_owned()).collect();
+    let compression_start = Instant::now();
+    for (input, output, format) in outputs {
+        let progress = output_progress(std::fs::metadata(&input)?.len(), &output);
+        finish_output(
+            &input,
+            &output,
+            format,
+            args.threads.get(),
+            args.keep_tmp,
+            args.write_checksums,
+            &progress,
+        )?;
+        info!("Output file written to: {:?}", &output);
+    }
+    let timings = Timings {
+        total: start.elapsed(),
+        kraken2: kraken2_usage,
+        compression: compression_start.elapsed(),
+    };
+    info!("Time taken: {}", timings);
+
+    for (sample, c) in samples.iter().zip(&counts) {
+        info!(
+            "{}: {} / {} sequences classified as {}",
+            sample.sample, c.host, c.total, args.host
+        );
+        let host_fraction = if c.total == 0 {
+            0.0
+        } else {
+            c.host as f64 / c.total as f64
+        };
+        if let Some(min) = args.expect_human.filter(|min| host_fraction < *min) {
+            warn_low_host_fraction(
+                &format!("sample {}", sample.sample),
+                host_fraction,
+                min,
+                &args.host,
+            );
+        }
+    }
+    if let Some(path) = &args.batch_summary {
+        let mut summary =
+            std::fs::File::create(path).context("Failed to create batch summary file")?;
+        write_summary(&mut summary, &samples, &counts, &args.host)?;
+        info!("Batch summary written to: {:?}", path);
+    }
+    if let Some(path) = &args.multiqc {
+        write_multiqc_json(path, &samples, &counts, &args.host, args.keep_human_reads)?;
+    }
+
+    if !args.keep_tmp {
+        cleanup_tmpdir(tmpdir, args.secure_delete)
+            .context("Failed to securely delete temporary files")?;
     }
 
-    // cleanup the temporary directory, but only issue a warning if it fails
-    if let Err(e) = tmpdir.close() {
-        warn!("Failed to remove temporary output directory: {}", e);
+    if let Some(path) = &args.summary {
+        let summary = Summary {
+            host: args.host.to_string(),
+            keep_host_reads: args.keep_human_reads,
+            outputs: output_paths,
+            secure_delete: args.secure_delete,
+            kraken2_version: kraken2_version.map(str::to_string),
+            timings,
+            ..batch_summary(&samples, &counts)
+        };
+        write_summary_json(&summary, path)?;
     }
 
     info!("Done.");
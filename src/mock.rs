@@ -0,0 +1,102 @@
+//! A stand-in engine (`--engine mock`) that marks a pseudo-random fraction of the reads as host
+//! without classifying them. It needs no external programs or database, so the rest of a run
+//! (reading the inputs, and naming, compressing, and writing the outputs) can be tested anywhere.
+use crate::compression::open_reader;
+use crate::fastx::{fastx_reader, read_id};
+use anyhow::{Context, Result};
+use std::collections::HashSet;
+use std::path::PathBuf;
+
+/// Marks reads as host at random, but reproducibly.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MockClassifier {
+    /// The expected fraction of the reads marked as host.
+    pub fraction: f64,
+    pub seed: u64,
+}
+
+impl Default for MockClassifier {
+    fn default() -> Self {
+        Self {
+            fraction: 0.1,
+            seed: 0,
+        }
+    }
+}
+
+impl MockClassifier {
+    /// Whether the read with (normalised) ID `id` is marked as host. This only depends on the ID
+    /// and the seed, so the mates of a pair agree, and the same reads are marked in every run.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use nohuman::mock::MockClassifier;
+    ///
+    /// let all = MockClassifier { fraction: 1.0, seed: 0 };
+    /// assert!(all.is_host(b"read1"));
+    /// let none = MockClassifier { fraction: 0.0, seed: 0 };
+    /// assert!(!none.is_host(b"read1"));
+    /// ```
+    pub fn is_host(&self, id: &[u8]) -> bool {
+        // FNV-1a, mixed with the seed by the SplitMix64 finaliser
+        let hash = id.iter().fold(0xcbf2_9ce4_8422_2325u64, |hash, &b| {
+            (hash ^ b as u64).wrapping_mul(0x0100_0000_01b3)
+        });
+        let mut z = hash ^ self.seed.wrapping_mul(0x9e37_79b9_7f4a_7c15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^= z >> 31;
+        // the top 53 bits, as a fraction in [0, 1)
+        ((z >> 11) as f64 / (1u64 << 53) as f64) < self.fraction
+    }
+
+    /// The IDs of the reads in `inputs` marked as host, normalised with [`read_id`].
+    pub fn host_read_ids(&self, inputs: &[PathBuf]) -> Result<HashSet<Vec<u8>>> {
+        let mut host_ids = HashSet::new();
+        for input in inputs {
+            let Some(mut reader) = fastx_reader(open_reader(input)?)? else {
+                continue;
+            };
+            while let Some(record) = reader.next() {
+                let record = record.with_context(|| format!("Failed to parse {:?}", input))?;
+                let id = read_id(record.id());
+                if self.is_host(id) {
+                    host_ids.insert(id.to_vec());
+                }
+            }
+        }
+        Ok(host_ids)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mock_classifier() {
+        let tmp = tempfile::tempdir().unwrap();
+        let reads: String = (0..1000)
+            .map(|i| format!("@read{}/1\nACGT\n+\nIIII\n", i))
+            .collect();
+        let path = tmp.path().join("in.fq");
+        std::fs::write(&path, reads).unwrap();
+        let inputs = [path];
+
+        let mock = MockClassifier {
+            fraction: 0.2,
+            seed: 1,
+        };
+        let host_ids = mock.host_read_ids(&inputs).unwrap();
+        assert!((150..250).contains(&host_ids.len()));
+        assert_eq!(
+            host_ids.contains(b"read0".as_slice()),
+            mock.is_host(b"read0")
+        );
+        // the same seed marks the same reads, and another seed marks others
+        assert_eq!(mock.host_read_ids(&inputs).unwrap(), host_ids);
+        let other = MockClassifier { seed: 2, ..mock };
+        assert_ne!(other.host_read_ids(&inputs).unwrap(), host_ids);
+    }
+}
@@ -0,0 +1,394 @@
+//! A built-in classifier, for when kraken2 can't be installed.
+//!
+//! A native database is a sorted set of the minimizers of a host genome (see [`minimizers`]),
+//! stored in a single [`NATIVE_INDEX_FILE`]. A read is a host read if enough of its minimizers are
+//! in the set. This is similar to how kraken2 classifies reads, but with a single "taxon", so it
+//! needs much less memory. It is less sensitive than kraken2 though, as the minimizers are sampled
+//! more sparsely.
+use crate::compression::open_reader;
+use crate::fastx::{fastx_reader, read_id};
+use crate::NohumanError;
+use anyhow::{bail, Context, Result};
+use std::collections::{HashSet, VecDeque};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+/// The name of the index file of a native database.
+pub const NATIVE_INDEX_FILE: &str = "native.idx";
+/// The default k-mer length of a native index.
+pub const DEFAULT_KMER_LEN: u8 = 31;
+/// The default number of consecutive k-mers each minimizer is chosen from.
+pub const DEFAULT_WINDOW_LEN: u8 = 31;
+
+/// Identifies a native index file, and its format version.
+const MAGIC: &[u8; 8] = b"NHNATIV1";
+/// The number of reads classified at once, split between the threads.
+const BATCH_SIZE: usize = 10_000;
+
+/// The minimizers of a host genome.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NativeIndex {
+    kmer_len: u8,
+    window_len: u8,
+    /// Sorted and de-duplicated.
+    minimizers: Vec<u64>,
+}
+
+impl NativeIndex {
+    /// Build an index of the sequences in the (optionally compressed) FASTA/Q `genomes`.
+    pub fn build(genomes: &[PathBuf], kmer_len: u8, window_len: u8) -> Result<Self> {
+        if !(1..=31).contains(&kmer_len) || window_len == 0 {
+            bail!("The k-mer length must be between 1 and 31, and the window length at least 1");
+        }
+        let mut minimizers_of_genomes = Vec::new();
+        for genome in genomes {
+            let Some(mut reader) = fastx_reader(open_reader(genome)?)? else {
+                continue;
+            };
+            while let Some(record) = reader.next() {
+                let record = record.with_context(|| format!("Failed to parse {:?}", genome))?;
+                minimizers(
+                    &record.seq(),
+                    kmer_len,
+                    window_len,
+                    &mut minimizers_of_genomes,
+                );
+            }
+        }
+        if minimizers_of_genomes.is_empty() {
+            bail!("The genome(s) have no sequences to index");
+        }
+        minimizers_of_genomes.sort_unstable();
+        minimizers_of_genomes.dedup();
+        minimizers_of_genomes.shrink_to_fit();
+        Ok(Self {
+            kmer_len,
+            window_len,
+            minimizers: minimizers_of_genomes,
+        })
+    }
+
+    /// The number of minimizers in the index.
+    pub fn len(&self) -> usize {
+        self.minimizers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.minimizers.is_empty()
+    }
+
+    /// Write the index to the file at `path`.
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let mut writer = BufWriter::new(
+            File::create(path).with_context(|| format!("Failed to create {:?}", path))?,
+        );
+        writer.write_all(MAGIC)?;
+        writer.write_all(&[self.kmer_len, self.window_len, 0, 0, 0, 0, 0, 0])?;
+        writer.write_all(&(self.minimizers.len() as u64).to_le_bytes())?;
+        for minimizer in &self.minimizers {
+            writer.write_all(&minimizer.to_le_bytes())?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Read the index in the file at `path`.
+    pub fn read(path: &Path) -> Result<Self> {
+        let mut reader =
+            BufReader::new(File::open(path).with_context(|| format!("Failed to open {:?}", path))?);
+        let mut header = [0u8; 24];
+        reader
+            .read_exact(&mut header)
+            .with_context(|| format!("{:?} is too short to be a native index", path))?;
+        if &header[..8] != MAGIC {
+            bail!(
+                "{:?} is not a native index, or was written by another version of nohuman",
+                path
+            );
+        }
+        let (kmer_len, window_len) = (header[8], header[9]);
+        let n = u64::from_le_bytes(header[16..].try_into().unwrap()) as usize;
+        let mut minimizers = Vec::with_capacity(n);
+        let mut buf = [0u8; 8];
+        for _ in 0..n {
+            reader
+                .read_exact(&mut buf)
+                .with_context(|| format!("{:?} is truncated", path))?;
+            minimizers.push(u64::from_le_bytes(buf));
+        }
+        Ok(Self {
+            kmer_len,
+            window_len,
+            minimizers,
+        })
+    }
+
+    /// The number of `seq`'s minimizers in the index, and its total number of minimizers.
+    /// `buf` is used to collect the minimizers, to save allocating it for every read.
+    fn hits(&self, seq: &[u8], buf: &mut Vec<u64>) -> (usize, usize) {
+        buf.clear();
+        minimizers(seq, self.kmer_len, self.window_len, buf);
+        let hits = buf
+            .iter()
+            .filter(|m| self.minimizers.binary_search(m).is_ok())
+            .count();
+        (hits, buf.len())
+    }
+}
+
+/// The index file of the native database in `path`, or in its 'db' subdirectory.
+pub fn find_native_index(path: &Path) -> Result<PathBuf, NohumanError> {
+    [
+        path.join(NATIVE_INDEX_FILE),
+        path.join("db").join(NATIVE_INDEX_FILE),
+    ]
+    .into_iter()
+    .find(|index| index.is_file())
+    .ok_or_else(|| NohumanError::MissingDb {
+        path: path.to_path_buf(),
+        files: NATIVE_INDEX_FILE.to_string(),
+    })
+}
+
+/// Add the minimizers of `seq` to `out`. A minimizer is the smallest hash of the canonical k-mers
+/// (of length `k`) in a window of `w` consecutive k-mers, so a sequence and its reverse complement
+/// have the same minimizers. Consecutive windows with the same minimizer only add it once, and
+/// k-mers with a base other than A, C, G, or T are skipped.
+pub fn minimizers(seq: &[u8], k: u8, w: u8, out: &mut Vec<u64>) {
+    let (k, w) = (k as usize, w as usize);
+    let mask = (1u64 << (2 * k)) - 1;
+    let (mut forward, mut reverse) = (0u64, 0u64);
+    // the number of valid bases in a row, and the number of k-mers since the last invalid base
+    let (mut run, mut kmers) = (0usize, 0usize);
+    // candidate minimizers of the current window, as (k-mer number, hash), increasing in hash
+    let mut window: VecDeque<(usize, u64)> = VecDeque::with_capacity(w);
+    let mut last = None;
+    for &base in seq {
+        let code = match base {
+            b'A' | b'a' => 0,
+            b'C' | b'c' => 1,
+            b'G' | b'g' => 2,
+            b'T' | b't' => 3,
+            _ => {
+                run = 0;
+                kmers = 0;
+                window.clear();
+                last = None;
+                continue;
+            }
+        };
+        forward = ((forward << 2) | code) & mask;
+        reverse = (reverse >> 2) | ((3 - code) << (2 * (k - 1)));
+        run += 1;
+        if run < k {
+            continue;
+        }
+        let hash = mix(forward.min(reverse));
+        while window.back().is_some_and(|&(_, h)| h > hash) {
+            window.pop_back();
+        }
+        window.push_back((kmers, hash));
+        while window.front().is_some_and(|&(i, _)| i + w <= kmers) {
+            window.pop_front();
+        }
+        kmers += 1;
+        if kmers >= w {
+            let minimizer = window[0].1;
+            if last != Some(minimizer) {
+                out.push(minimizer);
+                last = Some(minimizer);
+            }
+        }
+    }
+}
+
+/// Scramble a k-mer, so minimizers aren't biased towards k-mers low in A/C.
+fn mix(mut x: u64) -> u64 {
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+    x ^ (x >> 31)
+}
+
+/// When a read is a host read.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NativeClassifier {
+    /// The minimum number of the read's minimizers in the index (like kraken2's minimum hit
+    /// groups).
+    pub min_hits: u32,
+    /// The minimum fraction of the read's minimizers in the index (like kraken2's confidence).
+    pub confidence: f32,
+}
+
+impl Default for NativeClassifier {
+    fn default() -> Self {
+        Self {
+            min_hits: 2,
+            confidence: 0.0,
+        }
+    }
+}
+
+impl NativeClassifier {
+    /// Whether a read with `hits` of its `total` minimizers in the index is a host read.
+    pub fn is_host(&self, hits: usize, total: usize) -> bool {
+        total > 0 && hits >= self.min_hits as usize && hits as f32 / total as f32 >= self.confidence
+    }
+
+    /// Classify the reads in `inputs` (two inputs are classified as pairs, with the minimizers of
+    /// both mates counted together) with `threads` threads. Returns the IDs of the host reads,
+    /// normalised with [`read_id`], and the number of reads (or pairs) classified.
+    pub fn host_read_ids(
+        &self,
+        index: &NativeIndex,
+        inputs: &[PathBuf],
+        threads: usize,
+    ) -> Result<(HashSet<Vec<u8>>, u64)> {
+        let mut readers = Vec::new();
+        for input in inputs {
+            match fastx_reader(open_reader(input)?)? {
+                Some(reader) => readers.push(reader),
+                None => return Ok((HashSet::new(), 0)),
+            }
+        }
+
+        let mut host_ids = HashSet::new();
+        let mut total = 0;
+        loop {
+            // each read (or pair) is its ID and its mates' sequences
+            let mut batch: Vec<(Vec<u8>, Vec<Vec<u8>>)> = Vec::with_capacity(BATCH_SIZE);
+            'batch: while batch.len() < BATCH_SIZE {
+                let mut id: Option<Vec<u8>> = None;
+                let mut seqs = Vec::with_capacity(readers.len());
+                for (reader, input) in readers.iter_mut().zip(inputs) {
+                    let Some(record) = reader.next() else {
+                        break 'batch;
+                    };
+                    let record = record.with_context(|| format!("Failed to parse {:?}", input))?;
+                    let mate_id = read_id(record.id()).to_vec();
+                    match &id {
+                        Some(id) if id != &mate_id => bail!(
+                            "The mates of read {} are out of order: found {} in {:?}",
+                            String::from_utf8_lossy(id),
+                            String::from_utf8_lossy(&mate_id),
+                            input
+                        ),
+                        Some(_) => {}
+                        None => id = Some(mate_id),
+                    }
+                    seqs.push(record.seq().into_owned());
+                }
+                batch.push((id.unwrap_or_default(), seqs));
+            }
+            if batch.is_empty() {
+                break;
+            }
+            total += batch.len() as u64;
+
+            let chunk_size = batch.len().div_ceil(threads.max(1));
+            std::thread::scope(|scope| {
+                let handles: Vec<_> = batch
+                    .chunks(chunk_size)
+                    .map(|chunk| {
+                        scope.spawn(move || {
+                            let mut buf = Vec::new();
+                            chunk
+                                .iter()
+                                .filter(|(_, seqs)| {
+                                    let (hits, n) = seqs.iter().fold((0, 0), |(hits, n), seq| {
+                                        let (h, m) = index.hits(seq, &mut buf);
+                                        (hits + h, n + m)
+                                    });
+                                    self.is_host(hits, n)
+                                })
+                                .map(|(id, _)| id.clone())
+                                .collect::<Vec<_>>()
+                        })
+                    })
+                    .collect();
+                for handle in handles {
+                    host_ids.extend(handle.join().expect("classification thread panicked"));
+                }
+            });
+        }
+        Ok((host_ids, total))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn reverse_complement(seq: &[u8]) -> Vec<u8> {
+        seq.iter()
+            .rev()
+            .map(|b| match b {
+                b'A' => b'T',
+                b'C' => b'G',
+                b'G' => b'C',
+                _ => b'A',
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_minimizers() {
+        let seq = b"ACGTTGCATGCATGCCGTAGCTAGCTAGGATCGATCGTAGCTAGTCGAT";
+        let mut forward = Vec::new();
+        minimizers(seq, 5, 4, &mut forward);
+        assert!(!forward.is_empty());
+        // no two consecutive windows add the same minimizer
+        assert!(forward.windows(2).all(|pair| pair[0] != pair[1]));
+
+        let mut reverse = Vec::new();
+        minimizers(&reverse_complement(seq), 5, 4, &mut reverse);
+        forward.sort();
+        reverse.sort();
+        assert_eq!(forward, reverse);
+
+        // too short for a whole window, once the N splits it
+        let mut out = Vec::new();
+        minimizers(b"ACGTACNGTACGT", 5, 4, &mut out);
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn test_native_index() {
+        let tmp = tempfile::tempdir().unwrap();
+        let genome = tmp.path().join("host.fa");
+        let host = "ACGTTGCATGCATGCCGTAGCTAGCTAGGATCGATCGTAGCTAGTCGATTTAGCAGGCATCGACT";
+        fs::write(&genome, format!(">chr1\n{}\n", host)).unwrap();
+        let index = NativeIndex::build(std::slice::from_ref(&genome), 11, 5).unwrap();
+        assert!(!index.is_empty());
+
+        let path = tmp.path().join(NATIVE_INDEX_FILE);
+        index.write(&path).unwrap();
+        assert_eq!(NativeIndex::read(&path).unwrap(), index);
+        assert_eq!(find_native_index(tmp.path()).unwrap(), path);
+        assert!(NativeIndex::read(&genome).is_err());
+
+        let reads = tmp.path().join("reads.fq");
+        let rc = String::from_utf8(reverse_complement(&host.as_bytes()[10..50])).unwrap();
+        let other = "GGGGCCCCAAAATTTTGGGGCCCCAAAATTTTGGGGCCCCAAAATTTT";
+        fs::write(
+            &reads,
+            format!(
+                "@host1\n{}\n+\n{}\n@other\n{}\n+\n{}\n@host2/1 rc\n{}\n+\n{}\n",
+                &host[5..45],
+                "I".repeat(40),
+                other,
+                "I".repeat(other.len()),
+                rc,
+                "I".repeat(rc.len())
+            ),
+        )
+        .unwrap();
+        let (ids, total) = NativeClassifier::default()
+            .host_read_ids(&index, &[reads], 2)
+            .unwrap();
+        assert_eq!(total, 3);
+        let expected: HashSet<Vec<u8>> = [b"host1".to_vec(), b"host2".to_vec()].into();
+        assert_eq!(ids, expected);
+    }
+}
@@ -0,0 +1,2544 @@
+use crate::align::{host_read_ids, Aligner, Minimap2};
+use crate::bam::{fastx_to_bam, OutputFormat};
+use crate::checksum::{file_checksums, write_checksum_files, ChecksumWriter};
+use crate::compression::{decompress, open_reader, CompressionFormat};
+use crate::container::Container;
+use crate::fastx::{
+    check_pairs, count_reads, deinterleave, filter_reads_by_id, interleave, repair_pairs,
+    validate_reads, PAIR_CHECK_IDS, VALIDATION_RECORDS,
+};
+use crate::interrupt::remove_on_interrupt;
+use crate::kraken::{classified_read_ids, read_ids_in_taxa};
+use crate::mock::MockClassifier;
+use crate::native::{find_native_index, NativeClassifier, NativeIndex};
+use crate::progress::{ProgressCallback, ProgressEvent};
+use crate::report::Report;
+use crate::resources::ResourceUsage;
+use crate::{
+    cleanup_tmpdir, is_pipe, is_stdio, keep_tmpdir, use_memory_mapping, validate_db_directory,
+    CommandRunner, Executor, Host, NohumanError, ProcessExecutor, ShredGuard, NULL_DEVICE,
+};
+use anyhow::{bail, Context, Result};
+use indicatif::{HumanBytes, MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
+use log::{debug, info, log_enabled, warn, Level};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ffi::OsStr;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Statistics from a [`DehostPipeline`] run.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct RunStats {
+    /// The number of sequences classified. A pair of reads counts as one sequence.
+    pub total: u64,
+    /// The number of sequences classified as host.
+    pub host: u64,
+    /// The output files written.
+    pub outputs: Vec<PathBuf>,
+    /// With several engines (see [`DehostPipeline::with_consensus`]), the number of sequences
+    /// each flagged as host, keyed by engine name.
+    pub engine_host: BTreeMap<String, u64>,
+    /// The number of reads in each output, counted after it was written.
+    pub output_reads: Vec<u64>,
+    /// The resources used by kraken2, if it was run locally.
+    pub kraken2_usage: Option<ResourceUsage>,
+    /// The time spent writing (and compressing) the outputs.
+    pub compression_time: Duration,
+}
+
+impl RunStats {
+    /// The number of sequences not classified as host.
+    pub fn non_host(&self) -> u64 {
+        self.total - self.host
+    }
+
+    /// The fraction of sequences classified as host, or 0 if there are none.
+    pub fn host_fraction(&self) -> f64 {
+        if self.total == 0 {
+            0.0
+        } else {
+            self.host as f64 / self.total as f64
+        }
+    }
+}
+
+/// Removes (or keeps) host reads from one, two (paired), or three (paired plus singletons) input
+/// files. This is what the `nohuman` binary runs for a single sample.
+///
+/// # Examples
+///
+/// ```no_run
+/// use nohuman::pipeline::DehostPipeline;
+///
+/// let stats = DehostPipeline::new(vec!["in_1.fq".into(), "in_2.fq".into()], "db")
+///     .with_outputs(vec![Some("out_1.fq.gz".into()), Some("out_2.fq.gz".into())])
+///     .with_threads(4)
+///     .run()
+///     .unwrap();
+/// println!("{} of {} read pairs were human", stats.host, stats.total);
+/// ```
+#[derive(Debug, Clone)]
+pub struct DehostPipeline {
+    inputs: Vec<PathBuf>,
+    outputs: Vec<Option<PathBuf>>,
+    database: PathBuf,
+    kraken2: String,
+    container: Option<Container>,
+    timeout: Option<Duration>,
+    kraken2_log: Option<PathBuf>,
+    threads: u32,
+    confidence: f32,
+    keep_host_reads: bool,
+    compression: Option<CompressionFormat>,
+    output_template: Option<OutputTemplate>,
+    host: Host,
+    kraken2_args: Vec<String>,
+    kraken_output: Option<PathBuf>,
+    classifications: Option<PathBuf>,
+    interleaved: bool,
+    secure_delete: bool,
+    server: Option<PathBuf>,
+    low_memory: bool,
+    minimap2: Option<Minimap2>,
+    confirm_with_alignment: bool,
+    native: Option<NativeClassifier>,
+    native_database: Option<PathBuf>,
+    mock: Option<MockClassifier>,
+    consensus: Option<(Vec<Aligner>, usize)>,
+    taxa: Option<HashSet<u64>>,
+    stats_only: bool,
+    removed_ids: Option<PathBuf>,
+    overwrite: bool,
+    keep_tmp: bool,
+    validate_inputs: bool,
+    repair_pairs: bool,
+    verify_outputs: bool,
+    output_format: OutputFormat,
+    write_checksums: bool,
+    krona: Option<PathBuf>,
+    tmp_dir: Option<PathBuf>,
+    progress: Option<ProgressCallback>,
+    executor: Arc<dyn Executor>,
+    /// The resources used by the kraken2 runs so far.
+    kraken2_usage: Arc<Mutex<Option<ResourceUsage>>>,
+}
+
+impl DehostPipeline {
+    /// Create a pipeline for the `inputs` using the kraken2 database at `database`. Inputs can be
+    /// `-` to read from stdin.
+    pub fn new<P: Into<PathBuf>>(inputs: Vec<PathBuf>, database: P) -> Self {
+        Self {
+            inputs,
+            outputs: Vec::new(),
+            database: database.into(),
+            kraken2: "kraken2".to_string(),
+            container: None,
+            timeout: None,
+            kraken2_log: None,
+            threads: 1,
+            confidence: 0.0,
+            keep_host_reads: false,
+            compression: None,
+            output_template: None,
+            host: Host::default(),
+            kraken2_args: Vec::new(),
+            kraken_output: None,
+            classifications: None,
+            interleaved: false,
+            secure_delete: false,
+            server: None,
+            low_memory: false,
+            minimap2: None,
+            confirm_with_alignment: false,
+            native: None,
+            native_database: None,
+            mock: None,
+            consensus: None,
+            taxa: None,
+            stats_only: false,
+            removed_ids: None,
+            overwrite: false,
+            keep_tmp: false,
+            validate_inputs: true,
+            repair_pairs: false,
+            verify_outputs: true,
+            output_format: OutputFormat::default(),
+            write_checksums: false,
+            krona: None,
+            tmp_dir: None,
+            progress: None,
+            executor: Arc::new(ProcessExecutor),
+            kraken2_usage: Arc::default(),
+        }
+    }
+
+    /// The output path for each input. A missing (or `None`) output is named after its input
+    /// with `no<host>` appended. Outputs can be `-` to write to stdout.
+    pub fn with_outputs(mut self, outputs: Vec<Option<PathBuf>>) -> Self {
+        self.outputs = outputs;
+        self
+    }
+
+    pub fn with_threads(mut self, threads: u32) -> Self {
+        self.threads = threads.max(1);
+        self
+    }
+
+    /// The kraken2 confidence score threshold.
+    pub fn with_confidence(mut self, confidence: f32) -> Self {
+        self.confidence = confidence;
+        self
+    }
+
+    /// Keep the host reads instead of removing them.
+    pub fn keep_host_reads(mut self, keep: bool) -> Self {
+        self.keep_host_reads = keep;
+        self
+    }
+
+    /// The compression format of the outputs. By default, this is inferred from the first output
+    /// path, or the first input if it has no output path.
+    pub fn with_compression(mut self, compression: CompressionFormat) -> Self {
+        self.compression = Some(compression);
+        self
+    }
+
+    /// Name the outputs that aren't given explicitly with `template`, rather than appending
+    /// `no<host>` to the input file names.
+    pub fn with_output_template(mut self, template: OutputTemplate) -> Self {
+        self.output_template = Some(template);
+        self
+    }
+
+    pub fn with_host(mut self, host: Host) -> Self {
+        self.host = host;
+        self
+    }
+
+    /// Extra arguments to pass to kraken2 (see [`crate::parse_kraken2_args`]).
+    pub fn with_kraken2_args(mut self, args: Vec<String>) -> Self {
+        self.kraken2_args = args;
+        self
+    }
+
+    /// Only treat the reads kraken2 classifies as one of `taxa` as host reads, rather than every
+    /// classified read. This is needed with a database of more than the host, e.g. kraken2's
+    /// standard database (see [`crate::taxonomy::Taxonomy::clade`]).
+    pub fn with_taxa(mut self, taxa: HashSet<u64>) -> Self {
+        self.taxa = Some(taxa);
+        self
+    }
+
+    /// Write the kraken2 read classification output to `path`.
+    pub fn with_kraken_output<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.kraken_output = Some(path.into());
+        self
+    }
+
+    /// Create the temporary directory in `dir` rather than the current directory.
+    pub fn with_tmp_dir<P: Into<PathBuf>>(mut self, dir: P) -> Self {
+        self.tmp_dir = Some(dir.into());
+        self
+    }
+
+    /// Convert the kraken2 report to Krona's text format (see [`Report::write_krona`]) and write it
+    /// to `path`. kraken2 must be asked to write a report with `--report` in the extra kraken2
+    /// arguments.
+    pub fn with_krona<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.krona = Some(path.into());
+        self
+    }
+
+    /// Filter the inputs using an existing kraken2 read classification output instead of running
+    /// kraken2.
+    pub fn with_classifications<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.classifications = Some(path.into());
+        self
+    }
+
+    /// The (single) input contains interleaved paired reads. They are written to a single
+    /// interleaved output, unless a second output is given.
+    pub fn interleaved(mut self, interleaved: bool) -> Self {
+        self.interleaved = interleaved;
+        self
+    }
+
+    /// Overwrite temporary files before they are deleted (see [`crate::shred_file`]).
+    pub fn secure_delete(mut self, secure_delete: bool) -> Self {
+        self.secure_delete = secure_delete;
+        self
+    }
+
+    /// Only count the host reads, without writing any reads. [`RunStats::outputs`] is empty.
+    pub fn stats_only(mut self, stats_only: bool) -> Self {
+        self.stats_only = stats_only;
+        self
+    }
+
+    /// Write the IDs of the host reads (the reads removed, or kept with
+    /// [`DehostPipeline::keep_host_reads`]) to `path`, one per line in sorted order.
+    pub fn with_removed_ids<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.removed_ids = Some(path.into());
+        self
+    }
+
+    /// Overwrite output files that already exist, rather than failing.
+    pub fn overwrite(mut self, overwrite: bool) -> Self {
+        self.overwrite = overwrite;
+        self
+    }
+
+    /// Keep the temporary files (see [`crate::keep_tmpdir`]), rather than removing them.
+    pub fn keep_tmp(mut self, keep_tmp: bool) -> Self {
+        self.keep_tmp = keep_tmp;
+        self
+    }
+
+    /// Check the inputs are valid FASTA/FASTQ (see [`validate_reads`]) before classifying them.
+    /// This is on by default.
+    pub fn validate_inputs(mut self, validate_inputs: bool) -> Self {
+        self.validate_inputs = validate_inputs;
+        self
+    }
+
+    /// Re-pair paired inputs that are out of sync (see [`repair_pairs`]), instead of failing.
+    /// Reads without a mate are dropped.
+    pub fn repair_pairs(mut self, repair_pairs: bool) -> Self {
+        self.repair_pairs = repair_pairs;
+        self
+    }
+
+    /// Write the outputs in `format`. With BAM, both reads of a pair are written to the first
+    /// output, and the outputs can't be compressed (see [`DehostPipeline::with_compression`]) as
+    /// BAM is compressed already. Default output names end in ".bam" instead of the input's
+    /// extension.
+    pub fn with_output_format(mut self, format: OutputFormat) -> Self {
+        self.output_format = format;
+        self
+    }
+
+    /// Write MD5 and SHA256 checksums of the output files (the reads, and the kraken2 output and
+    /// report if they are written) to `.md5` and `.sha256` files next to them. See
+    /// [`write_checksum_files`].
+    pub fn write_checksums(mut self, write_checksums: bool) -> Self {
+        self.write_checksums = write_checksums;
+        self
+    }
+
+    /// Fail if the reads counted in the outputs don't match the number of sequences kept (see
+    /// [`output_count_mismatch`]). This is on by default. If off, a mismatch is only logged as a
+    /// warning.
+    pub fn verify_outputs(mut self, verify_outputs: bool) -> Self {
+        self.verify_outputs = verify_outputs;
+        self
+    }
+
+    /// Memory-map the database (kraken2's `--memory-mapping`) instead of loading it into memory.
+    /// This is done anyway if the database is larger than the available memory.
+    pub fn low_memory(mut self, low_memory: bool) -> Self {
+        self.low_memory = low_memory;
+        self
+    }
+
+    /// The kraken2 executable to run. Defaults to the `kraken2` on the `PATH`.
+    pub fn with_kraken2<S: Into<String>>(mut self, kraken2: S) -> Self {
+        self.kraken2 = kraken2.into();
+        self
+    }
+
+    /// Run kraken2 in `container` (see [`crate::container`]), rather than directly.
+    pub fn with_container(mut self, container: Container) -> Self {
+        self.container = Some(container);
+        self
+    }
+
+    /// Stop kraken2 if it runs for longer than `timeout`.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Write kraken2's stderr (its database loading and classification statistics) to `path`.
+    pub fn with_kraken2_log<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.kraken2_log = Some(path.into());
+        self
+    }
+
+    /// Find host reads by aligning to a reference with minimap2, instead of classifying them with
+    /// kraken2. The database isn't used, unless the alignments only confirm the kraken2
+    /// classifications (see [`DehostPipeline::confirm_with_alignment`]).
+    pub fn with_minimap2(mut self, minimap2: Minimap2) -> Self {
+        self.minimap2 = Some(minimap2);
+        self
+    }
+
+    /// Only treat reads classified as host (by kraken2, or the existing classifications) as host
+    /// reads if they also align to the [`DehostPipeline::with_minimap2`] reference. The other
+    /// classified reads are rescued, i.e. treated as non-host.
+    pub fn confirm_with_alignment(mut self, confirm: bool) -> Self {
+        self.confirm_with_alignment = confirm;
+        self
+    }
+
+    /// Classify reads with the built-in classifier, instead of kraken2. The database must be a
+    /// native database (see [`crate::native`]).
+    pub fn with_native(mut self, classifier: NativeClassifier) -> Self {
+        self.native = Some(classifier);
+        self
+    }
+
+    /// The native database to use with [`DehostPipeline::with_native`], if the database is a
+    /// kraken2 database (i.e. when both are used by [`DehostPipeline::with_consensus`]).
+    pub fn with_native_database<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.native_database = Some(path.into());
+        self
+    }
+
+    /// Mark a pseudo-random fraction of the reads as host with `classifier`, instead of classifying
+    /// them, to test everything else a run does.
+    pub fn with_mock(mut self, classifier: MockClassifier) -> Self {
+        self.mock = Some(classifier);
+        self
+    }
+
+    /// Call `progress` as the reads are classified and the outputs are written.
+    pub fn with_progress(mut self, progress: ProgressCallback) -> Self {
+        self.progress = Some(progress);
+        self
+    }
+
+    /// Run each of `engines`, and treat a read as a host read if at least `min_votes` of them
+    /// flag it. minimap2 and the native classifier must be set up with
+    /// [`DehostPipeline::with_minimap2`] and [`DehostPipeline::with_native`] if they are used.
+    pub fn with_consensus(mut self, engines: Vec<Aligner>, min_votes: usize) -> Self {
+        self.consensus = Some((engines, min_votes));
+        self
+    }
+
+    /// Run kraken2 with the nohuman server listening on `socket` (see [`crate::server`]).
+    pub fn with_server<P: Into<PathBuf>>(mut self, socket: P) -> Self {
+        self.server = Some(socket.into());
+        self
+    }
+
+    /// Run kraken2 with `executor` (see [`CommandRunner::with_executor`]), e.g. a fake kraken2 in
+    /// tests.
+    pub fn with_executor(mut self, executor: Arc<dyn Executor>) -> Self {
+        self.executor = executor;
+        self
+    }
+
+    /// Run the pipeline. Temporary files are written to a directory in the current directory.
+    pub fn run(&self) -> Result<RunStats> {
+        let input = &self.inputs;
+        match input.len() {
+            0 => bail!("No input files provided"),
+            i if i > 3 => {
+                bail!("Only one, two, or three (R1, R2, and singletons) input files are allowed")
+            }
+            _ => {}
+        }
+        if input.iter().filter(|p| is_stdio(p)).count() > 1 {
+            bail!("Only one input can be read from stdin");
+        }
+        if self.interleaved && input.len() != 1 {
+            bail!("Interleaved reads must be given as a single input file");
+        }
+        if self.krona.is_some() && self.kraken2_report().is_none() {
+            bail!("A Krona file needs a kraken2 report, written with --kraken2-args \"--report <FILE>\"");
+        }
+        *self.kraken2_usage.lock().unwrap() = None;
+
+        // create a temporary output directory in the current directory, unless told otherwise
+        let tmp_root = match &self.tmp_dir {
+            Some(dir) => dir.to_owned(),
+            None => std::env::current_dir()?,
+        };
+        let mut tmpdir = tempfile::Builder::new()
+            .prefix("nohuman")
+            .tempdir_in(&tmp_root)
+            .context("Failed to create temporary directory")?;
+        if self.keep_tmp {
+            keep_tmpdir(&mut tmpdir);
+        }
+        let _shred_guard = ShredGuard::new(self.secure_delete.then(|| tmpdir.path().to_path_buf()));
+        let _interrupt_guard = remove_on_interrupt(tmpdir.path(), self.secure_delete);
+
+        // stdin and pipes can only be read once (and can't be seeked for compression detection),
+        // so they are copied to temporary files that are used in their place
+        let input_files = input
+            .iter()
+            .enumerate()
+            .map(|(i, path)| {
+                if is_stdio(path) {
+                    stdin_to_file(&tmpdir.path().join("stdin.fq"))
+                } else if is_pipe(path) {
+                    pipe_to_file(path, &tmpdir.path().join(format!("pipe_{}.fq", i + 1)))
+                } else {
+                    Ok(path.to_owned())
+                }
+            })
+            .collect::<Result<Vec<_>>>()?;
+        if self.validate_inputs {
+            validate_inputs(input, &input_files)?;
+        }
+        let input_files = if (self.validate_inputs || self.repair_pairs)
+            && !self.interleaved
+            && input_files.len() > 1
+        {
+            check_input_pairs(input, input_files, tmpdir.path(), self.repair_pairs)?
+        } else {
+            input_files
+        };
+        let input_files = if self.interleaved {
+            deinterleave_input(&input_files[0], tmpdir.path())?
+        } else {
+            input_files
+        };
+
+        let output_arg = |i: usize| self.outputs.get(i).cloned().flatten();
+        let bam = self.output_format == OutputFormat::Bam;
+        if bam && self.compression.is_some() {
+            bail!("BAM output is already compressed, so can't be given a compression format");
+        }
+        if bam && input_files.len() > 1 && output_arg(1).is_some() {
+            bail!("Both reads of a pair are written to the first BAM output, so a second output can't be given");
+        }
+        let output_compression = if bam {
+            Ok(CompressionFormat::None)
+        } else if let Some(format) = self.compression {
+            Ok(format)
+        } else if let Some(out1) = output_arg(0).filter(|p| !is_stdio(p)) {
+            CompressionFormat::from_path(out1)
+        } else {
+            CompressionFormat::from_file(&input_files[0])
+        }?;
+
+        let mut outputs = Vec::new();
+        // interleaved input is written to a single output, unless a second output is given. A BAM
+        // output holds both reads of a pair
+        let reinterleave =
+            (self.interleaved || bam) && input_files.len() > 1 && output_arg(1).is_none();
+        let tmpout1 = if reinterleave {
+            tmpdir.path().join("kraken_out_interleaved.fq")
+        } else if input_files.len() == 1 {
+            tmpdir.path().join("kraken_out.fq")
+        } else {
+            tmpdir.path().join("kraken_out_1.fq")
+        };
+        let default_output = |i: usize| {
+            output_arg(i).unwrap_or_else(|| {
+                let path = match &self.output_template {
+                    Some(template) => {
+                        template.output_path(&input[i], i + 1, output_compression, &self.host)
+                    }
+                    None => default_output_path(&input[i], output_compression, &self.host),
+                };
+                if bam {
+                    bam_output_path(&path)
+                } else {
+                    path
+                }
+            })
+        };
+        let out1 = default_output(0);
+        outputs.push((tmpout1, out1));
+        if input_files.len() > 1 && !reinterleave {
+            let out2 = default_output(1);
+            outputs.push((tmpdir.path().join("kraken_out_2.fq"), out2));
+        }
+        if input_files.len() > 2 {
+            let out3 = default_output(2);
+            outputs.push((tmpdir.path().join("kraken_out_singletons.fq"), out3));
+        }
+        let output_paths: Vec<PathBuf> = outputs.iter().map(|(_, out)| out.to_owned()).collect();
+        if output_paths.iter().filter(|p| is_stdio(p)).count() > 1 {
+            bail!("Only one output can be written to stdout");
+        }
+        let unique_outputs: HashSet<&PathBuf> = output_paths.iter().collect();
+        if unique_outputs.len() < output_paths.len() && !self.stats_only {
+            bail!(
+                "The output files must be different, but got {:?}. With --out-template, use {{read}} to tell them apart",
+                output_paths
+            );
+        }
+        if !self.stats_only {
+            check_outputs(input, &output_paths, self.overwrite)?;
+        }
+
+        if self.stats_only {
+            info!("Counting {} reads...", self.host);
+        } else if self.keep_host_reads {
+            info!("Keeping {} reads...", self.host);
+        } else {
+            info!("Removing {} reads...", self.host);
+        }
+
+        self.emit(ProgressEvent::ClassificationStarted);
+        let mut engine_host = BTreeMap::new();
+        let (total, host) = if let Some((engines, min_votes)) = &self.consensus {
+            let tmpdir = tmpdir.path();
+            let mut votes: HashMap<Vec<u8>, usize> = HashMap::new();
+            for engine in engines {
+                let ids = self.host_ids(*engine, &input_files, tmpdir)?;
+                info!(
+                    "{} flagged {} sequences as {}",
+                    engine,
+                    ids.len(),
+                    self.host
+                );
+                engine_host.insert(engine.to_string(), ids.len() as u64);
+                for id in ids {
+                    *votes.entry(id).or_default() += 1;
+                }
+            }
+            let host_ids: HashSet<Vec<u8>> = votes
+                .into_iter()
+                .filter(|(_, n)| n >= min_votes)
+                .map(|(id, _)| id)
+                .collect();
+            info!(
+                "{} sequences were flagged as {} by at least {} of {} engines",
+                host_ids.len(),
+                self.host,
+                min_votes,
+                engines.len()
+            );
+            let counts = self.filter_by_ids(&host_ids, &input_files, tmpdir)?;
+            info!("Consensus finished. Organising output...");
+            counts
+        } else if self.confirm_with_alignment {
+            self.classify_and_confirm(&input_files, tmpdir.path())?
+        } else if let Some(classifications) = &self.classifications {
+            self.filter_with_classifications(classifications, &input_files, tmpdir.path())?
+        } else if let Some(minimap2) = &self.minimap2 {
+            self.align(minimap2, &input_files, tmpdir.path())?
+        } else if let Some(classifier) = &self.native {
+            self.classify_native(classifier, &input_files, tmpdir.path())?
+        } else if let Some(classifier) = &self.mock {
+            let host_ids = classifier.host_read_ids(&input_files)?;
+            let counts = self.filter_by_ids(&host_ids, &input_files, tmpdir.path())?;
+            info!("Mock classification finished. Organising output...");
+            counts
+        } else if self.taxa.is_some() || self.removed_ids.is_some() {
+            let host_ids = self.kraken2_host_ids(&input_files, tmpdir.path())?;
+            self.filter_by_ids(&host_ids, &input_files, tmpdir.path())?
+        } else {
+            self.classify(&input_files, tmpdir.path(), self.kraken_output.as_deref())?
+        };
+        self.emit(ProgressEvent::ClassificationFinished { total, host });
+
+        self.write_kraken2_checksums()?;
+        self.write_krona()?;
+
+        if self.stats_only {
+            if !self.keep_tmp {
+                cleanup_tmpdir(tmpdir, self.secure_delete)
+                    .context("Failed to securely delete temporary files")?;
+            }
+            return Ok(RunStats {
+                total,
+                host,
+                outputs: Vec::new(),
+                engine_host,
+                output_reads: Vec::new(),
+                kraken2_usage: *self.kraken2_usage.lock().unwrap(),
+                compression_time: Duration::ZERO,
+            });
+        }
+
+        let compression_start = Instant::now();
+        if reinterleave {
+            let tmpdir = tmpdir.path();
+            let mut writer = BufWriter::new(File::create(&outputs[0].0)?);
+            interleave(
+                open_reader(tmpdir.join("kraken_out_1.fq"))?,
+                open_reader(tmpdir.join("kraken_out_2.fq"))?,
+                &mut writer,
+            )
+            .context("Failed to interleave output")?;
+            writer.flush()?;
+        }
+
+        // kraken2 has had all the threads, and now they are split between the outputs by size, so
+        // e.g. a small singletons output doesn't hold threads the pairs could use
+        let sizes = outputs
+            .iter()
+            .map(|(tmp, _)| Ok(std::fs::metadata(tmp)?.len()))
+            .collect::<Result<Vec<u64>>>()?;
+        let threads = allocate_threads(self.threads, &sizes);
+        debug!("Compression threads for each output: {:?}", threads);
+        let progress = MultiProgress::new();
+        if !log_enabled!(Level::Info) {
+            progress.set_draw_target(ProgressDrawTarget::hidden());
+        }
+        let bars: Vec<ProgressBar> = outputs
+            .iter()
+            .zip(&sizes)
+            .map(|((_, output), &size)| progress.add(output_progress(size, output)))
+            .collect();
+        // stdout can't be read back, so the reads written to it are counted in the temporary file,
+        // as are the reads written to BAM
+        let count_paths: Vec<PathBuf> = outputs
+            .iter()
+            .map(|(tmp, output)| if is_stdio(output) || bam { tmp } else { output }.to_owned())
+            .collect();
+        let options = OutputOptions {
+            format: self.output_format,
+            compression: output_compression,
+            keep_tmp: self.keep_tmp,
+            checksums: self.write_checksums,
+        };
+
+        // if we have multiple output files and a thread for each, compress them in parallel
+        if outputs.len() > 1 && self.threads as usize >= outputs.len() {
+            let mut handles = Vec::new();
+            for (i, (input, output)) in outputs.into_iter().enumerate() {
+                let interleaved = reinterleave && i == 0;
+                let threads = threads[i];
+                let bar = bars[i].clone();
+                let handle = std::thread::spawn(move || {
+                    info!("Writing output file to: {:?}", &output);
+                    write_output(options, &input, &output, threads, interleaved, &bar)
+                });
+                handles.push(handle);
+            }
+            for handle in handles {
+                handle.join().map_err(|e| {
+                    anyhow::anyhow!("Thread panicked when writing output: {:?}", e)
+                })??;
+            }
+        } else {
+            for (i, (input, output)) in outputs.into_iter().enumerate() {
+                let interleaved = reinterleave && i == 0;
+                write_output(
+                    options,
+                    &input,
+                    &output,
+                    self.threads,
+                    interleaved,
+                    &bars[i],
+                )?;
+                info!("Output file written to: {:?}", &output);
+            }
+        }
+
+        let compression_time = compression_start.elapsed();
+
+        let output_reads = count_output_reads(&count_paths)?;
+        for (path, &reads) in output_paths.iter().zip(&output_reads) {
+            self.emit(ProgressEvent::OutputWritten {
+                path: path.to_owned(),
+                reads,
+            });
+        }
+        let kept = if self.keep_host_reads {
+            host
+        } else {
+            total - host
+        };
+        debug!("Reads in the outputs: {:?}", output_reads);
+        if let Some(mismatch) = output_count_mismatch(&output_reads, kept, reinterleave) {
+            let message = format!(
+                "{} of {} sequences were kept, but {}",
+                kept, total, mismatch
+            );
+            if self.verify_outputs {
+                bail!("{}. Use --no-verify to only warn about this", message);
+            }
+            warn!("{}", message);
+        }
+
+        if !self.keep_tmp {
+            cleanup_tmpdir(tmpdir, self.secure_delete)
+                .context("Failed to securely delete temporary files")?;
+        }
+
+        Ok(RunStats {
+            total,
+            host,
+            outputs: output_paths,
+            engine_host,
+            output_reads,
+            kraken2_usage: *self.kraken2_usage.lock().unwrap(),
+            compression_time,
+        })
+    }
+
+    /// [`DehostPipeline::run`], for async callers (e.g. a GUI or web frontend). The pipeline runs
+    /// on a blocking thread, so it doesn't hold up the executor.
+    pub async fn run_async(self) -> Result<RunStats> {
+        async_std::task::spawn_blocking(move || self.run()).await
+    }
+
+    fn emit(&self, event: ProgressEvent) {
+        if let Some(progress) = &self.progress {
+            progress.emit(event);
+        }
+    }
+
+    /// The report kraken2 is asked to write with `--report` in the extra kraken2 arguments.
+    fn kraken2_report(&self) -> Option<PathBuf> {
+        let mut args = self.kraken2_args.iter();
+        while let Some(arg) = args.next() {
+            if arg == "--report" {
+                return args.next().map(PathBuf::from);
+            }
+            if let Some(path) = arg.strip_prefix("--report=") {
+                return Some(PathBuf::from(path));
+            }
+        }
+        None
+    }
+
+    /// Write checksum files for the kraken2 output and report, if they were written.
+    fn write_kraken2_checksums(&self) -> Result<()> {
+        if !self.write_checksums {
+            return Ok(());
+        }
+        let written = self
+            .kraken_output
+            .iter()
+            .cloned()
+            .chain(self.kraken2_report())
+            .filter(|path| !is_stdio(path) && path.is_file());
+        for path in written {
+            write_checksum_files(&path, &file_checksums(&path)?)?;
+        }
+        Ok(())
+    }
+
+    /// Convert the kraken2 report to Krona's text format, if asked to.
+    fn write_krona(&self) -> Result<()> {
+        let (Some(path), Some(report_path)) = (&self.krona, self.kraken2_report()) else {
+            return Ok(());
+        };
+        let report = Report::from_path(&report_path)
+            .with_context(|| format!("Failed to read kraken2 report {:?}", report_path))?;
+        let file = File::create(path).with_context(|| format!("Failed to create {:?}", path))?;
+        report.write_krona(BufWriter::new(file))?;
+        info!("Krona text written to: {:?}", path);
+        Ok(())
+    }
+
+    /// Classify the inputs with kraken2, writing the selected reads to `tmpdir`, and the
+    /// classifications to `kraken_output` if given. Returns the total and host sequence counts.
+    fn classify(
+        &self,
+        input: &[PathBuf],
+        tmpdir: &Path,
+        kraken_output: Option<&Path>,
+    ) -> Result<(u64, u64)> {
+        if let Some(path) = &self.kraken2_log {
+            // each kraken2 run is appended to the log
+            File::create(path).with_context(|| format!("Failed to create {:?}", path))?;
+        }
+        let kraken = CommandRunner::new(&self.kraken2)
+            .with_container(self.container.clone())
+            .with_timeout(self.timeout)
+            .with_log(self.kraken2_log.clone())
+            .with_host(self.host)
+            .with_server(self.server.clone())
+            .with_executor(self.executor.clone());
+        // reads are selected by taxon afterwards when filtering by taxa
+        let read_selection_flag = if self.taxa.is_some() || self.stats_only {
+            None
+        } else if self.keep_host_reads {
+            Some("--classified-out")
+        } else {
+            Some("--unclassified-out")
+        };
+        let threads = self.threads.to_string();
+        let confidence = self.confidence.to_string();
+        let k2d_dir = validate_db_directory(&self.database)?;
+        // the server memory-maps the database itself
+        let memory_mapping = self.server.is_none()
+            && !self
+                .kraken2_args
+                .iter()
+                .any(|arg| arg == "--memory-mapping")
+            && use_memory_mapping(&k2d_dir, self.low_memory);
+
+        let kraken_input = input
+            .iter()
+            .enumerate()
+            .map(|(i, path)| {
+                readable_input(
+                    path,
+                    tmpdir,
+                    i,
+                    "kraken2",
+                    CompressionFormat::is_kraken2_readable,
+                )
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        // with three inputs, the first two are a pair and the third is singletons. kraken2 can't
+        // classify paired and unpaired reads in one go, so the singletons get their own run
+        let runs: Vec<(&[PathBuf], &str)> = match kraken_input.len() {
+            1 => vec![(&kraken_input[..], "kraken_out.fq")],
+            2 => vec![(&kraken_input[..], "kraken_out#.fq")],
+            _ => vec![
+                (&kraken_input[..2], "kraken_out#.fq"),
+                (&kraken_input[2..], "kraken_out_singletons.fq"),
+            ],
+        };
+
+        let (mut total, mut host) = (0, 0);
+        for (i, (run_input, tmpname)) in runs.into_iter().enumerate() {
+            let outfile = tmpdir.join(tmpname);
+            // the singletons run writes its classifications to a temporary file, which is appended to
+            // the paired classifications afterwards so the user gets a single kraken output file
+            let run_output = match kraken_output {
+                Some(path) if i == 0 => path.to_owned(),
+                Some(_) => tmpdir.join("kraken_singletons.out"),
+                None => PathBuf::from(NULL_DEVICE),
+            };
+
+            // paths are passed as they are, as they may not be UTF-8
+            let mut kraken_cmd: Vec<&OsStr> = vec![
+                OsStr::new("--threads"),
+                OsStr::new(&threads),
+                OsStr::new("--db"),
+                k2d_dir.as_os_str(),
+                OsStr::new("--output"),
+                run_output.as_os_str(),
+                OsStr::new("--confidence"),
+                OsStr::new(&confidence),
+            ];
+            if run_input.len() == 2 {
+                kraken_cmd.push(OsStr::new("--paired"));
+            }
+            if memory_mapping {
+                kraken_cmd.push(OsStr::new("--memory-mapping"));
+            }
+            if let Some(flag) = read_selection_flag {
+                kraken_cmd.extend([OsStr::new(flag), outfile.as_os_str()]);
+            }
+            kraken_cmd.extend(self.kraken2_args.iter().map(OsStr::new));
+            kraken_cmd.extend(run_input.iter().map(|path| path.as_os_str()));
+            debug!("Running kraken2...");
+            debug!("With arguments: {:?}", &kraken_cmd);
+            let (stats, usage) = kraken
+                .run_with_usage(&kraken_cmd)
+                .context("Failed to run kraken2")?;
+            if let Some(usage) = usage {
+                let mut total_usage = self.kraken2_usage.lock().unwrap();
+                *total_usage = Some(total_usage.map_or(usage, |total| total.combine(usage)));
+            }
+            total += stats.total as u64;
+            host += stats.classified as u64;
+
+            if let Some(path) = kraken_output.filter(|_| i > 0) {
+                let mut src = File::open(&run_output)?;
+                let mut dest = std::fs::OpenOptions::new().append(true).open(path)?;
+                std::io::copy(&mut src, &mut dest)
+                    .context("Failed to append singletons kraken2 output")?;
+            }
+        }
+        if self.stats_only {
+            info!("Kraken2 finished.");
+        } else {
+            info!("Kraken2 finished. Organising output...");
+        }
+
+        Ok((total, host))
+    }
+
+    /// Filter the input files using the reads marked as classified in an existing kraken2 output
+    /// file, writing the selected reads to the same temporary files a kraken2 run would. Returns
+    /// the total and host sequence counts.
+    fn filter_with_classifications(
+        &self,
+        kraken_output: &Path,
+        input: &[PathBuf],
+        tmpdir: &Path,
+    ) -> Result<(u64, u64)> {
+        info!("Reading classifications from {:?}...", kraken_output);
+        let classified = self.host_ids_in_output(kraken_output)?;
+        debug!(
+            "{} reads are classified in the kraken2 output",
+            classified.len()
+        );
+        let counts = self.filter_by_ids(&classified, input, tmpdir)?;
+        info!("Filtering finished. Organising output...");
+
+        Ok(counts)
+    }
+
+    /// Classify the inputs with kraken2 (or read the existing classifications), then align the
+    /// reads classified as host to the reference with minimap2. Only the reads that align are
+    /// treated as host reads when writing the selected reads to `tmpdir`. Returns the total and
+    /// host sequence counts.
+    fn classify_and_confirm(&self, input: &[PathBuf], tmpdir: &Path) -> Result<(u64, u64)> {
+        let Some(minimap2) = &self.minimap2 else {
+            bail!("A reference to align to is needed to confirm classifications");
+        };
+        let classified = self.kraken2_host_ids(input, tmpdir)?;
+
+        // only the classified reads are aligned
+        let mut candidates = Vec::new();
+        for (i, path) in input.iter().enumerate() {
+            let candidate = tmpdir.join(format!("classified_{}.fq", i + 1));
+            let mut writer = BufWriter::new(File::create(&candidate)?);
+            filter_reads_by_id(open_reader(path)?, &mut writer, &classified, true)
+                .with_context(|| format!("Failed to extract classified reads from {:?}", path))?;
+            writer.flush()?;
+            candidates.push(candidate);
+        }
+        let aligned = self.aligned_ids(minimap2, &candidates, tmpdir)?;
+        let confirmed: HashSet<Vec<u8>> = classified.intersection(&aligned).cloned().collect();
+        info!(
+            "{} of {} reads classified as {} were confirmed by alignment. {} were rescued",
+            confirmed.len(),
+            classified.len(),
+            self.host,
+            classified.len() - confirmed.len()
+        );
+
+        let counts = self.filter_by_ids(&confirmed, input, tmpdir)?;
+        info!("Confirmation finished. Organising output...");
+
+        Ok(counts)
+    }
+
+    /// The IDs of the reads in the inputs that `engine` flags as host.
+    fn host_ids(
+        &self,
+        engine: Aligner,
+        input: &[PathBuf],
+        tmpdir: &Path,
+    ) -> Result<HashSet<Vec<u8>>> {
+        match engine {
+            Aligner::Kraken2 => self.kraken2_host_ids(input, tmpdir),
+            Aligner::Minimap2 => {
+                let minimap2 = self.minimap2.as_ref().context("No reference to align to")?;
+                self.aligned_ids(minimap2, input, tmpdir)
+            }
+            Aligner::Native => {
+                let classifier = self.native.as_ref().context("No native classifier")?;
+                self.native_host_ids(classifier, input)
+            }
+            Aligner::Mock => {
+                let classifier = self.mock.as_ref().context("No mock classifier")?;
+                classifier.host_read_ids(input)
+            }
+        }
+    }
+
+    /// The IDs of the reads in the inputs that kraken2 classifies (or the existing classifications
+    /// mark) as host.
+    fn kraken2_host_ids(&self, input: &[PathBuf], tmpdir: &Path) -> Result<HashSet<Vec<u8>>> {
+        let kraken_output = match (&self.classifications, &self.kraken_output) {
+            (Some(path), _) => path.to_owned(),
+            (None, Some(path)) => {
+                self.classify(input, tmpdir, Some(path))?;
+                path.to_owned()
+            }
+            (None, None) => {
+                let path = tmpdir.join("kraken.out");
+                self.classify(input, tmpdir, Some(&path))?;
+                path
+            }
+        };
+        self.host_ids_in_output(&kraken_output)
+    }
+
+    /// The IDs of the host reads in the kraken2 output at `path`: the reads classified as one of
+    /// the taxa (see [`DehostPipeline::with_taxa`]), or all classified reads.
+    fn host_ids_in_output(&self, path: &Path) -> Result<HashSet<Vec<u8>>> {
+        let reader = BufReader::new(open_reader(path)?);
+        match &self.taxa {
+            Some(taxa) => read_ids_in_taxa(reader, taxa),
+            None => classified_read_ids(reader),
+        }
+        .context("Failed to read kraken2 output")
+    }
+
+    /// Classify the inputs with the built-in classifier, writing the selected reads to the same
+    /// temporary files a kraken2 run would. Returns the total and host sequence counts.
+    fn classify_native(
+        &self,
+        classifier: &NativeClassifier,
+        input: &[PathBuf],
+        tmpdir: &Path,
+    ) -> Result<(u64, u64)> {
+        let host_ids = self.native_host_ids(classifier, input)?;
+        let counts = self.filter_by_ids(&host_ids, input, tmpdir)?;
+        info!("Classification finished. Organising output...");
+
+        Ok(counts)
+    }
+
+    /// The IDs of the reads in the inputs the built-in classifier classifies as host.
+    fn native_host_ids(
+        &self,
+        classifier: &NativeClassifier,
+        input: &[PathBuf],
+    ) -> Result<HashSet<Vec<u8>>> {
+        let database = self.native_database.as_ref().unwrap_or(&self.database);
+        let path = find_native_index(database)?;
+        info!("Loading the native index {:?}...", path);
+        let index = NativeIndex::read(&path)?;
+        debug!("The index has {} minimizers", index.len());
+
+        // as with kraken2, singletons are classified separately from the pairs
+        let runs: Vec<&[PathBuf]> = match input.len() {
+            1 | 2 => vec![input],
+            _ => vec![&input[..2], &input[2..]],
+        };
+        let mut host_ids = HashSet::new();
+        for run_input in runs {
+            info!("Classifying {:?}...", run_input);
+            let (ids, _) = classifier.host_read_ids(&index, run_input, self.threads as usize)?;
+            host_ids.extend(ids);
+        }
+
+        Ok(host_ids)
+    }
+
+    /// Align the inputs to the reference with minimap2, writing the selected reads to the same
+    /// temporary files a kraken2 run would. Returns the total and host sequence counts.
+    fn align(&self, minimap2: &Minimap2, input: &[PathBuf], tmpdir: &Path) -> Result<(u64, u64)> {
+        let host_ids = self.aligned_ids(minimap2, input, tmpdir)?;
+        let counts = self.filter_by_ids(&host_ids, input, tmpdir)?;
+        info!("minimap2 finished. Organising output...");
+
+        Ok(counts)
+    }
+
+    /// The IDs of the reads in the inputs with an alignment to the reference passing the filter.
+    fn aligned_ids(
+        &self,
+        minimap2: &Minimap2,
+        input: &[PathBuf],
+        tmpdir: &Path,
+    ) -> Result<HashSet<Vec<u8>>> {
+        let minimap2_input = input
+            .iter()
+            .enumerate()
+            .map(|(i, path)| {
+                readable_input(
+                    path,
+                    tmpdir,
+                    i,
+                    "minimap2",
+                    CompressionFormat::is_minimap2_readable,
+                )
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        // as with kraken2, singletons are aligned separately from the pairs
+        let runs: Vec<&[PathBuf]> = match minimap2_input.len() {
+            1 | 2 => vec![&minimap2_input[..]],
+            _ => vec![&minimap2_input[..2], &minimap2_input[2..]],
+        };
+        let mut host_ids = HashSet::new();
+        for (i, run_input) in runs.into_iter().enumerate() {
+            let paf = tmpdir.join(format!("minimap2_{}.paf", i + 1));
+            info!(
+                "Aligning {:?} to {:?} with minimap2...",
+                run_input, minimap2.reference
+            );
+            minimap2
+                .align(run_input, self.threads, &paf)
+                .context("Failed to run minimap2")?;
+            let ids = host_read_ids(BufReader::new(File::open(&paf)?), &minimap2.filter)
+                .context("Failed to read minimap2 alignments")?;
+            host_ids.extend(ids);
+        }
+        debug!("{} reads align to the reference", host_ids.len());
+
+        Ok(host_ids)
+    }
+
+    /// Write the reads of each input whose ID is (or isn't, when removing host reads) in `host_ids`
+    /// to the temporary files a kraken2 run would. Returns the total and host sequence counts.
+    fn filter_by_ids(
+        &self,
+        host_ids: &HashSet<Vec<u8>>,
+        input: &[PathBuf],
+        tmpdir: &Path,
+    ) -> Result<(u64, u64)> {
+        let tmpnames: &[&str] = match input.len() {
+            1 => &["kraken_out.fq"],
+            2 => &["kraken_out_1.fq", "kraken_out_2.fq"],
+            _ => &[
+                "kraken_out_1.fq",
+                "kraken_out_2.fq",
+                "kraken_out_singletons.fq",
+            ],
+        };
+
+        if let Some(path) = &self.removed_ids {
+            write_read_ids(host_ids, path)
+                .with_context(|| format!("Failed to write read IDs to {:?}", path))?;
+        }
+
+        let (mut total, mut host) = (0, 0);
+        for (i, (path, tmpname)) in input.iter().zip(tmpnames).enumerate() {
+            // the reads are only counted for stats
+            let mut writer: Box<dyn Write> = if self.stats_only {
+                Box::new(std::io::sink())
+            } else {
+                Box::new(BufWriter::new(File::create(tmpdir.join(tmpname))?))
+            };
+            let stats = filter_reads_by_id(
+                open_reader(path)?,
+                &mut writer,
+                host_ids,
+                self.keep_host_reads,
+            )
+            .with_context(|| format!("Failed to filter {:?}", path))?;
+            writer.flush()?;
+
+            // only count (and log) once for a pair
+            if i == 1 {
+                continue;
+            }
+            let n_host = if self.keep_host_reads {
+                stats.written
+            } else {
+                stats.total - stats.written
+            };
+            total += stats.total;
+            host += n_host;
+            info!(
+                "{} / {} sequences in {:?} classified as {}",
+                n_host, stats.total, path, self.host
+            );
+        }
+
+        Ok((total, host))
+    }
+}
+
+/// Write `ids` to `path`, one per line in sorted order.
+fn write_read_ids(ids: &HashSet<Vec<u8>>, path: &Path) -> Result<()> {
+    let mut ids: Vec<&Vec<u8>> = ids.iter().collect();
+    ids.sort_unstable();
+    let mut writer = BufWriter::new(File::create(path)?);
+    for id in ids {
+        writer.write_all(id)?;
+        writer.write_all(b"\n")?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Check that each of `input_files` (the files read in place of the `inputs` given) is valid
+/// FASTA/FASTQ, and not truncated. The files are checked in parallel.
+fn validate_inputs(inputs: &[PathBuf], input_files: &[PathBuf]) -> Result<()> {
+    info!("Validating inputs...");
+    std::thread::scope(|scope| {
+        let handles: Vec<_> = input_files
+            .iter()
+            .map(|path| scope.spawn(move || validate_reads(open_reader(path)?, VALIDATION_RECORDS)))
+            .collect();
+        for (handle, input) in handles.into_iter().zip(inputs) {
+            handle
+                .join()
+                .map_err(|e| anyhow::anyhow!("Thread panicked when validating input: {:?}", e))?
+                .with_context(|| {
+                    format!(
+                        "Input {:?} is invalid. Use --skip-validation to skip this check",
+                        input
+                    )
+                })?;
+        }
+        Ok(())
+    })
+}
+
+/// Count the reads in each of `paths` in parallel.
+fn count_output_reads(paths: &[PathBuf]) -> Result<Vec<u64>> {
+    std::thread::scope(|scope| {
+        let handles: Vec<_> = paths
+            .iter()
+            .map(|path| scope.spawn(move || count_reads(open_reader(path)?)))
+            .collect();
+        handles
+            .into_iter()
+            .zip(paths)
+            .map(|(handle, path)| {
+                handle
+                    .join()
+                    .map_err(|e| anyhow::anyhow!("Thread panicked when counting reads: {:?}", e))?
+                    .with_context(|| format!("Failed to count the reads in {:?}", path))
+            })
+            .collect()
+    })
+}
+
+/// Check the number of reads in each output (`output_reads`) against the number of sequences
+/// `kept`, where a pair of reads counts as one sequence. With two or three outputs, the first two
+/// are paired so must have the same number of reads, and the third is singletons. A
+/// `reinterleaved` output holds both reads of each pair. Returns a description of the mismatch,
+/// if there is one.
+pub fn output_count_mismatch(
+    output_reads: &[u64],
+    kept: u64,
+    reinterleaved: bool,
+) -> Option<String> {
+    let sequences = match output_reads {
+        [interleaved] if reinterleaved => {
+            if interleaved % 2 != 0 {
+                return Some(format!(
+                    "the interleaved output has an odd number of reads ({})",
+                    interleaved
+                ));
+            }
+            interleaved / 2
+        }
+        [single] => *single,
+        [r1, r2, rest @ ..] => {
+            if r1 != r2 {
+                return Some(format!("the paired outputs have {} and {} reads", r1, r2));
+            }
+            r1 + rest.iter().sum::<u64>()
+        }
+        [] => 0,
+    };
+    (sequences != kept).then(|| format!("the outputs have {} sequences", sequences))
+}
+
+/// Check that the paired `input_files` (the files read in place of the `inputs` given) are in sync
+/// (see [`check_pairs`]). If they aren't and `repair` is set, they are re-paired into `tmpdir`
+/// (see [`repair_pairs`]), and the re-paired files are returned in their place.
+fn check_input_pairs(
+    inputs: &[PathBuf],
+    input_files: Vec<PathBuf>,
+    tmpdir: &Path,
+    repair: bool,
+) -> Result<Vec<PathBuf>> {
+    let mismatch = check_pairs(
+        open_reader(&input_files[0])?,
+        open_reader(&input_files[1])?,
+        PAIR_CHECK_IDS,
+    )
+    .with_context(|| {
+        format!(
+            "Failed to check {:?} and {:?} are paired",
+            inputs[0], inputs[1]
+        )
+    })?;
+    let Some(mismatch) = mismatch else {
+        return Ok(input_files);
+    };
+    if !repair {
+        bail!(
+            "{:?} and {:?} are not paired - {}. Use --repair-pairs to keep only the reads in both, or --skip-validation to skip this check",
+            inputs[0],
+            inputs[1],
+            mismatch
+        );
+    }
+
+    warn!(
+        "{:?} and {:?} are not paired - {}. Re-pairing them...",
+        inputs[0], inputs[1], mismatch
+    );
+    let paths = vec![tmpdir.join("repaired_1.fq"), tmpdir.join("repaired_2.fq")];
+    let mut writer1 = BufWriter::new(File::create(&paths[0])?);
+    let mut writer2 = BufWriter::new(File::create(&paths[1])?);
+    let stats = repair_pairs(
+        || open_reader(&input_files[0]),
+        || open_reader(&input_files[1]),
+        &mut writer1,
+        &mut writer2,
+        None,
+    )
+    .with_context(|| format!("Failed to re-pair {:?} and {:?}", inputs[0], inputs[1]))?;
+    writer1.flush()?;
+    writer2.flush()?;
+    if stats.orphans1 > 0 || stats.orphans2 > 0 {
+        warn!(
+            "Dropped {} reads from {:?} and {} reads from {:?} that have no mate",
+            stats.orphans1, inputs[0], stats.orphans2, inputs[1]
+        );
+    }
+    info!("Re-paired {} read pairs", stats.pairs);
+    Ok(paths
+        .into_iter()
+        .chain(input_files.into_iter().skip(2))
+        .collect())
+}
+
+/// Split an interleaved input into two files of paired reads in `tmpdir`.
+fn deinterleave_input(path: &Path, tmpdir: &Path) -> Result<Vec<PathBuf>> {
+    let paths = vec![
+        tmpdir.join("interleaved_1.fq"),
+        tmpdir.join("interleaved_2.fq"),
+    ];
+    let mut writer1 = BufWriter::new(File::create(&paths[0])?);
+    let mut writer2 = BufWriter::new(File::create(&paths[1])?);
+    let n_pairs = deinterleave(open_reader(path)?, &mut writer1, &mut writer2)
+        .with_context(|| format!("Failed to deinterleave {:?}", path))?;
+    writer1.flush()?;
+    writer2.flush()?;
+    debug!("Deinterleaved {} read pairs from {:?}", n_pairs, path);
+    Ok(paths)
+}
+
+/// Copy stdin to the file at `path`.
+fn stdin_to_file(path: &Path) -> Result<PathBuf> {
+    info!("Reading input from stdin...");
+    let mut writer = BufWriter::new(File::create(path)?);
+    std::io::copy(&mut std::io::stdin().lock(), &mut writer).context("Failed to read stdin")?;
+    writer.flush()?;
+    Ok(path.to_path_buf())
+}
+
+/// Copy the pipe at `pipe` to the file at `path`, with the extension of its compression format
+/// added so kraken2 recognises it. Returns the path of the copy.
+fn pipe_to_file(pipe: &Path, path: &Path) -> Result<PathBuf> {
+    info!("Reading input from {:?}...", pipe);
+    let file = File::open(pipe).with_context(|| format!("Failed to open {:?}", pipe))?;
+    let (format, mut reader) = CompressionFormat::from_stream(file)?;
+    let path = format.add_extension(path);
+    let mut writer = BufWriter::new(File::create(&path)?);
+    std::io::copy(&mut reader, &mut writer)
+        .with_context(|| format!("Failed to read {:?}", pipe))?;
+    writer.flush()?;
+    Ok(path)
+}
+
+/// Split `threads` between outputs in proportion to their (uncompressed) `sizes`. Every output
+/// gets at least one thread, and the threads left over from rounding go to the outputs that lost
+/// the most to it.
+fn allocate_threads(threads: u32, sizes: &[u64]) -> Vec<u32> {
+    if sizes.is_empty() {
+        return Vec::new();
+    }
+    let n = sizes.len() as u32;
+    let spare = threads.saturating_sub(n) as u128;
+    let mut sizes: Vec<u128> = sizes.iter().map(|&s| s as u128).collect();
+    // empty outputs share the threads evenly
+    if sizes.iter().all(|&s| s == 0) {
+        sizes.fill(1);
+    }
+    let total: u128 = sizes.iter().sum();
+
+    let mut allocation: Vec<u32> = sizes
+        .iter()
+        .map(|&s| 1 + (spare * s / total) as u32)
+        .collect();
+    let mut order: Vec<usize> = (0..sizes.len()).collect();
+    order.sort_by_key(|&i| std::cmp::Reverse(spare * sizes[i] % total));
+    let left = threads.max(n) - allocation.iter().sum::<u32>();
+    for &i in order.iter().take(left as usize) {
+        allocation[i] += 1;
+    }
+    allocation
+}
+
+/// How the outputs of a run are written.
+#[derive(Debug, Clone, Copy)]
+struct OutputOptions {
+    format: OutputFormat,
+    /// The compression of FASTA/Q outputs.
+    compression: CompressionFormat,
+    keep_tmp: bool,
+    /// Whether to write checksum files for the outputs.
+    checksums: bool,
+}
+
+/// Write the temporary output `input` to `output`, which can be `-` for stdout. BAM flags the
+/// reads as pairs if `input` is `interleaved`. `progress` is advanced as `input` is read.
+fn write_output(
+    options: OutputOptions,
+    input: &Path,
+    output: &Path,
+    threads: u32,
+    interleaved: bool,
+    progress: &ProgressBar,
+) -> Result<()> {
+    if options.format == OutputFormat::Bam {
+        let header = format!(
+            "@PG\tID:nohuman\tPN:nohuman\tVN:{}\n",
+            env!("CARGO_PKG_VERSION")
+        );
+        let file = ProgressReader::new(File::open(input)?, progress.clone());
+        let reader = CompressionFormat::from_file(input)?.reader(BufReader::new(file))?;
+        if is_stdio(output) {
+            let writer = BufWriter::new(std::io::stdout().lock());
+            fastx_to_bam(reader, writer, &header, interleaved)?;
+        } else if options.checksums {
+            let file = File::create(output).context("Failed to create output file")?;
+            let (writer, handle) = ChecksumWriter::new(file);
+            fastx_to_bam(reader, BufWriter::new(writer), &header, interleaved)?;
+            write_checksum_files(output, &handle.finish())?;
+        } else {
+            let writer =
+                BufWriter::new(File::create(output).context("Failed to create output file")?);
+            fastx_to_bam(reader, writer, &header, interleaved)?;
+        }
+        progress.finish_and_clear();
+        return Ok(());
+    }
+    finish_output(
+        input,
+        output,
+        options.compression,
+        threads,
+        options.keep_tmp,
+        options.checksums,
+        progress,
+    )
+}
+
+/// How often the progress of writing an output is logged when stderr isn't a terminal.
+const PROGRESS_LOG_INTERVAL: Duration = Duration::from_secs(30);
+
+/// A progress bar for writing `output` from a temporary file of `size` bytes. It is hidden if
+/// stderr isn't a terminal, in which case the progress is logged every 30 seconds instead, or if
+/// info messages aren't logged (e.g. in quiet mode).
+pub fn output_progress(size: u64, output: &Path) -> ProgressBar {
+    let bar = ProgressBar::new(size);
+    if !log_enabled!(Level::Info) {
+        bar.set_draw_target(ProgressDrawTarget::hidden());
+    }
+    bar.set_style(
+        ProgressStyle::default_bar()
+            .template(
+                "[{elapsed_precise}] {msg} [{bar:40.cyan/blue}] {bytes}/{total_bytes} ({eta})",
+            )
+            .unwrap()
+            .progress_chars("#>-"),
+    );
+    let name = if is_stdio(output) {
+        "stdout".into()
+    } else {
+        output.file_name().unwrap_or_default().to_string_lossy()
+    };
+    bar.set_message(name.to_string());
+    bar
+}
+
+/// Reads a temporary output, advancing a progress bar (see [`output_progress`]) by the bytes read.
+struct ProgressReader<R> {
+    reader: R,
+    bar: ProgressBar,
+    last_log: Instant,
+}
+
+impl<R> ProgressReader<R> {
+    fn new(reader: R, bar: ProgressBar) -> Self {
+        Self {
+            reader,
+            bar,
+            last_log: Instant::now(),
+        }
+    }
+}
+
+impl<R: Read> Read for ProgressReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.reader.read(buf)?;
+        self.bar.inc(n as u64);
+        if self.bar.is_hidden() && self.last_log.elapsed() >= PROGRESS_LOG_INTERVAL {
+            let (done, total) = (self.bar.position(), self.bar.length().unwrap_or_default());
+            info!(
+                "Writing {}: {} of {} ({:.0}%)",
+                self.bar.message(),
+                HumanBytes(done),
+                HumanBytes(total),
+                100.0 * done as f64 / total.max(1) as f64
+            );
+            self.last_log = Instant::now();
+        }
+        Ok(n)
+    }
+}
+
+/// Compress the temporary file `tmp` to `output`, which can be `-` for stdout. If it doesn't need
+/// compressing, `tmp` is moved to `output` rather than copied (unless `keep_tmp`), so a large
+/// output isn't written twice. With `checksums`, checksum files are written for `output` (see
+/// [`write_checksum_files`]), hashing it as it is compressed. `progress` (see
+/// [`output_progress`]) is advanced as `tmp` is read.
+pub fn finish_output(
+    tmp: &Path,
+    output: &Path,
+    compression: CompressionFormat,
+    threads: u32,
+    keep_tmp: bool,
+    checksums: bool,
+    progress: &ProgressBar,
+) -> Result<()> {
+    let reader = || -> Result<_> { Ok(ProgressReader::new(File::open(tmp)?, progress.clone())) };
+    if is_stdio(output) {
+        compression.compress_reader_to(reader()?, std::io::stdout(), threads)?;
+    } else if compression.is_compressed() || keep_tmp {
+        let file = File::create(output).context("Failed to create output file")?;
+        if checksums {
+            let (writer, handle) = ChecksumWriter::new(file);
+            compression.compress_reader_to(reader()?, writer, threads)?;
+            write_checksum_files(output, &handle.finish())?;
+        } else {
+            compression.compress_reader_to(reader()?, file, threads)?;
+        }
+    } else {
+        move_file(tmp, output)?;
+        if checksums {
+            write_checksum_files(output, &file_checksums(output)?)?;
+        }
+    }
+    progress.finish_and_clear();
+    Ok(())
+}
+
+/// Move the file `from` to `to`.
+fn move_file(from: &Path, to: &Path) -> Result<()> {
+    // a rename fails if `from` is on a different filesystem, so it is copied instead
+    if std::fs::rename(from, to).is_err() {
+        std::fs::copy(from, to)
+            .with_context(|| format!("Failed to copy {:?} to {:?}", from, to))?;
+        std::fs::remove_file(from)?;
+    }
+    Ok(())
+}
+
+/// kraken2 can read gzip and bzip2 compressed files, and minimap2 only gzip. Inputs in a format
+/// `program` can't read (`is_readable`) are decompressed into `tmpdir` and the path of the
+/// decompressed file is returned instead.
+fn readable_input(
+    path: &Path,
+    tmpdir: &Path,
+    n: usize,
+    program: &str,
+    is_readable: fn(&CompressionFormat) -> bool,
+) -> Result<PathBuf> {
+    let format = CompressionFormat::from_file(path)?;
+    if is_readable(&format) {
+        return Ok(path.to_path_buf());
+    }
+
+    let decompressed = tmpdir.join(format!("input_{}.fq", n + 1));
+    info!(
+        "Decompressing {} input {:?} for {}...",
+        format, path, program
+    );
+    decompress(path, &decompressed)
+        .with_context(|| format!("Failed to decompress input {:?}", path))?;
+    Ok(decompressed)
+}
+
+/// A template for the names of output files, e.g. "{stem}.dehosted.fastq". The tokens are:
+///
+/// - `{stem}`: the input file name without its extensions, e.g. "in_1" for "in_1.fastq.gz"
+/// - `{read}`: the number of the input, i.e. 1, 2, or 3 for singletons
+/// - `{ext}`: the extension of the input, without compression, e.g. "fastq" (or "fq" if it has
+///   none)
+/// - `{comp}`: the compression extension of the output, e.g. ".gz", or empty if uncompressed
+/// - `{suffix}`: `no<host>`, e.g. "nohuman"
+///
+/// If the template doesn't contain `{comp}`, the compression extension is appended.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutputTemplate(String);
+
+impl OutputTemplate {
+    const TOKENS: [&'static str; 5] = ["stem", "read", "ext", "comp", "suffix"];
+
+    /// The output file name for `input`, the `read`th input, in the same directory as `input`.
+    pub fn output_path(
+        &self,
+        input: &Path,
+        read: usize,
+        compression: CompressionFormat,
+        host: &Host,
+    ) -> PathBuf {
+        if is_stdio(input) {
+            return input.to_path_buf();
+        }
+        let (stem, ext) = split_file_name(input);
+        let ext = ext.as_deref().unwrap_or("fq");
+        let parent = input.parent().unwrap_or(Path::new(""));
+        parent.join(self.render(&stem, read, ext, compression, host))
+    }
+
+    /// Fill in the tokens of the template.
+    pub fn render(
+        &self,
+        stem: &str,
+        read: usize,
+        ext: &str,
+        compression: CompressionFormat,
+        host: &Host,
+    ) -> String {
+        let comp = if compression.is_compressed() {
+            format!(".{}", compression)
+        } else {
+            String::new()
+        };
+        let name = self
+            .0
+            .replace("{stem}", stem)
+            .replace("{read}", &read.to_string())
+            .replace("{ext}", ext)
+            .replace("{suffix}", &host.output_suffix());
+        if self.0.contains("{comp}") {
+            name.replace("{comp}", &comp)
+        } else {
+            name + &comp
+        }
+    }
+}
+
+impl std::str::FromStr for OutputTemplate {
+    type Err = NohumanError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || NohumanError::InvalidOutputTemplate(s.to_string());
+        let mut rest = s;
+        while let Some(start) = rest.find(['{', '}']) {
+            if rest[start..].starts_with('}') {
+                return Err(invalid());
+            }
+            let end = rest[start..].find('}').ok_or_else(invalid)? + start;
+            if !Self::TOKENS.contains(&&rest[start + 1..end]) {
+                return Err(invalid());
+            }
+            rest = &rest[end + 1..];
+        }
+        if s.is_empty() || s.ends_with('/') {
+            return Err(invalid());
+        }
+        Ok(Self(s.to_string()))
+    }
+}
+
+/// Check that none of `outputs` is one of `inputs`, and that they don't already exist unless
+/// `overwrite` is set.
+pub fn check_outputs(inputs: &[PathBuf], outputs: &[PathBuf], overwrite: bool) -> Result<()> {
+    let inputs: Vec<PathBuf> = inputs
+        .iter()
+        .filter(|path| !is_stdio(path))
+        .filter_map(|path| path.canonicalize().ok())
+        .collect();
+    for output in outputs.iter().filter(|path| !is_stdio(path)) {
+        let Ok(path) = output.canonicalize() else {
+            continue;
+        };
+        if inputs.contains(&path) {
+            bail!("The output {:?} is also an input", output);
+        }
+        if !overwrite {
+            bail!(
+                "The output {:?} already exists. Use --force to overwrite it",
+                output
+            );
+        }
+    }
+    Ok(())
+}
+
+/// The extensions of sequence files, which are removed from input file names to name outputs.
+const SEQUENCE_EXTENSIONS: [&str; 6] = ["fastq", "fq", "fasta", "fa", "fna", "fas"];
+
+/// Split the file name of `path` into its stem and its sequence file extension (see
+/// [`SEQUENCE_EXTENSIONS`]), ignoring any compression extension. Only known extensions are
+/// removed, so other dots in the name are kept. e.g. "dir/in.R1.fastq.gz" -> ("in.R1", "fastq"),
+/// and "in.R1.gz" -> ("in.R1", None).
+pub(crate) fn split_file_name(path: &Path) -> (String, Option<String>) {
+    let mut name = path
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_string();
+    if CompressionFormat::from_path(path)
+        .unwrap_or_default()
+        .is_compressed()
+    {
+        if let Some(i) = name.rfind('.') {
+            name.truncate(i);
+        }
+    }
+    match name.rsplit_once('.') {
+        Some((stem, ext))
+            if !stem.is_empty() && SEQUENCE_EXTENSIONS.contains(&ext.to_lowercase().as_str()) =>
+        {
+            (stem.to_string(), Some(ext.to_string()))
+        }
+        _ => (name, None),
+    }
+}
+
+/// The path of a BAM output in place of the FASTA/Q output `path`, i.e. with its sequence and
+/// compression extensions replaced by "bam". e.g. "in_1.nohuman.fq" -> "in_1.nohuman.bam".
+fn bam_output_path(path: &Path) -> PathBuf {
+    if is_stdio(path) {
+        return path.to_path_buf();
+    }
+    let (stem, _) = split_file_name(path);
+    path.with_file_name(format!("{}.bam", stem))
+}
+
+/// Get the default output path for an input file. This is the input file name, with any
+/// compression and sequence file extensions removed, and "no<host>" plus the original extension
+/// (or "fq" if it didn't have one) and the output compression extension appended. e.g.
+/// "input_1.fastq.gz" -> "input_1.nohuman.fastq.gz", and "input.R1.gz" -> "input.R1.nohuman.fq.gz".
+fn default_output_path(input: &Path, compression: CompressionFormat, host: &Host) -> PathBuf {
+    suffixed_output_path(input, &host.output_suffix(), compression)
+}
+
+/// Get an output path for an input file by adding `suffix` before its extension, as for the
+/// default output paths (see [`DehostPipeline::with_outputs`]). e.g. "input_1.fastq.gz" with the
+/// suffix "repaired" -> "input_1.repaired.fastq.gz". stdin (`-`) is returned as it is.
+pub fn suffixed_output_path(input: &Path, suffix: &str, compression: CompressionFormat) -> PathBuf {
+    if is_stdio(input) {
+        return input.to_path_buf();
+    }
+    // outputs can't be written next to a process substitution (e.g. /dev/fd/63), so they are
+    // written to the current directory instead
+    let parent = input
+        .parent()
+        .filter(|dir| !dir.starts_with("/dev") && !dir.starts_with("/proc"))
+        .unwrap_or(Path::new(""));
+    let (stem, ext) = split_file_name(input);
+    let fname = format!("{}.{}.{}", stem, suffix, ext.as_deref().unwrap_or("fq"));
+    let fname = parent.join(fname);
+    compression.add_extension(&fname)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_output_path() {
+        assert_eq!(
+            default_output_path(
+                Path::new("dir/in_1.fastq.gz"),
+                CompressionFormat::Gzip,
+                &Host::Human
+            ),
+            PathBuf::from("dir/in_1.nohuman.fastq.gz")
+        );
+        assert_eq!(
+            default_output_path(Path::new("in.fa"), CompressionFormat::None, &Host::Custom),
+            PathBuf::from("in.nohost.fa")
+        );
+        assert_eq!(
+            default_output_path(
+                Path::new("sample.R1.fastq.gz"),
+                CompressionFormat::Gzip,
+                &Host::Human
+            ),
+            PathBuf::from("sample.R1.nohuman.fastq.gz")
+        );
+        // only known extensions are removed
+        assert_eq!(
+            default_output_path(
+                Path::new("sample.R1.zstd"),
+                CompressionFormat::Zstd,
+                &Host::Human
+            ),
+            PathBuf::from("sample.R1.nohuman.fq.zst")
+        );
+        assert_eq!(
+            default_output_path(
+                Path::new("run.2024.FQ"),
+                CompressionFormat::None,
+                &Host::Human
+            ),
+            PathBuf::from("run.2024.nohuman.FQ")
+        );
+        assert_eq!(
+            default_output_path(Path::new("-"), CompressionFormat::Gzip, &Host::Human),
+            PathBuf::from("-")
+        );
+        assert_eq!(
+            suffixed_output_path(
+                Path::new("dir/in_2.fq.bz2"),
+                "repaired",
+                CompressionFormat::Bzip2
+            ),
+            PathBuf::from("dir/in_2.repaired.fq.bz2")
+        );
+    }
+
+    #[test]
+    fn test_output_template() {
+        let template: OutputTemplate = "{stem}.dehosted.fastq".parse().unwrap();
+        assert_eq!(
+            template.output_path(
+                Path::new("dir/in_1.fastq.gz"),
+                1,
+                CompressionFormat::Gzip,
+                &Host::Human
+            ),
+            PathBuf::from("dir/in_1.dehosted.fastq.gz")
+        );
+        let template: OutputTemplate = "clean/{stem}_R{read}.{suffix}.{ext}{comp}".parse().unwrap();
+        assert_eq!(
+            template.output_path(Path::new("s.fq"), 2, CompressionFormat::Zstd, &Host::Custom),
+            PathBuf::from("clean/s_R2.nohost.fq.zst")
+        );
+
+        assert!("{stem".parse::<OutputTemplate>().is_err());
+        assert!("{sample}.fq".parse::<OutputTemplate>().is_err());
+        assert!("stem}.fq".parse::<OutputTemplate>().is_err());
+        assert!("".parse::<OutputTemplate>().is_err());
+    }
+
+    #[test]
+    fn test_check_outputs() {
+        let tmp = tempfile::tempdir().unwrap();
+        let input = tmp.path().join("in.fq");
+        let output = tmp.path().join("out.fq");
+        std::fs::write(&input, "").unwrap();
+        let inputs = vec![input.clone()];
+
+        assert!(check_outputs(&inputs, &[output.clone(), PathBuf::from("-")], false).is_ok());
+        std::fs::write(&output, "").unwrap();
+        assert!(check_outputs(&inputs, std::slice::from_ref(&output), false).is_err());
+        assert!(check_outputs(&inputs, &[output], true).is_ok());
+        // an input is never overwritten
+        let same = tmp.path().join(".").join("in.fq");
+        assert!(check_outputs(&inputs, &[same], true).is_err());
+    }
+
+    #[test]
+    fn test_run_too_many_inputs() {
+        let inputs = vec![PathBuf::from("a.fq"); 4];
+        let result = DehostPipeline::new(inputs, "db").run();
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_run_with_classifications() {
+        let tmp = tempfile::tempdir().unwrap();
+        let input = tmp.path().join("in.fq");
+        let kraken_output = tmp.path().join("kraken.out");
+        let output = tmp.path().join("out.fq");
+        std::fs::write(&input, "@r1\nACGT\n+\nIIII\n@r2\nGGGG\n+\nIIII\n").unwrap();
+        std::fs::write(&kraken_output, "C\tr1\t9606\t4\t9606:1\nU\tr2\t0\t4\t0:1\n").unwrap();
+
+        let stats = DehostPipeline::new(vec![input], tmp.path().join("db"))
+            .with_outputs(vec![Some(output.clone())])
+            .with_classifications(&kraken_output)
+            .run()
+            .unwrap();
+
+        assert_eq!(
+            stats,
+            RunStats {
+                total: 2,
+                host: 1,
+                outputs: vec![output.clone()],
+                engine_host: BTreeMap::new(),
+                output_reads: vec![1],
+                kraken2_usage: None,
+                compression_time: stats.compression_time,
+            }
+        );
+        assert_eq!(stats.non_host(), 1);
+        assert_eq!(stats.host_fraction(), 0.5);
+        assert_eq!(
+            std::fs::read_to_string(&output).unwrap(),
+            "@r2\nGGGG\n+\nIIII\n"
+        );
+    }
+
+    #[test]
+    fn test_finish_output() {
+        let tmp = tempfile::tempdir().unwrap();
+        let reads = "@r1\nACGT\n+\nIIII\n";
+        let input = tmp.path().join("kraken_out_1.fq");
+        let output = tmp.path().join("out.fq");
+        let hidden = ProgressBar::hidden();
+
+        // uncompressed outputs are moved, unless the temporary file is kept
+        std::fs::write(&input, reads).unwrap();
+        finish_output(
+            &input,
+            &output,
+            CompressionFormat::None,
+            1,
+            true,
+            false,
+            &hidden,
+        )
+        .unwrap();
+        assert!(input.exists());
+        finish_output(
+            &input,
+            &output,
+            CompressionFormat::None,
+            1,
+            false,
+            true,
+            &hidden,
+        )
+        .unwrap();
+        assert!(!input.exists());
+        assert_eq!(std::fs::read_to_string(&output).unwrap(), reads);
+
+        std::fs::write(&input, reads).unwrap();
+        let output = tmp.path().join("out.fq.gz");
+        finish_output(
+            &input,
+            &output,
+            CompressionFormat::Gzip,
+            1,
+            false,
+            true,
+            &hidden,
+        )
+        .unwrap();
+        assert!(input.exists());
+        assert_eq!(
+            CompressionFormat::from_file(&output).unwrap(),
+            CompressionFormat::Gzip
+        );
+
+        // checksums of the moved and compressed outputs
+        for (path, name) in [("out.fq.md5", "out.fq"), ("out.fq.gz.sha256", "out.fq.gz")] {
+            let sidecar = std::fs::read_to_string(tmp.path().join(path)).unwrap();
+            assert!(sidecar.ends_with(&format!("  {}\n", name)));
+        }
+        let checksums = crate::checksum::file_checksums(&output).unwrap();
+        assert!(std::fs::read_to_string(tmp.path().join("out.fq.gz.sha256"))
+            .unwrap()
+            .starts_with(&checksums.sha256));
+    }
+
+    #[test]
+    fn test_progress_reader() {
+        let data = vec![b'A'; 10_000];
+        let bar = output_progress(data.len() as u64, Path::new("out.fq.gz"));
+        assert_eq!(bar.message(), "out.fq.gz");
+        assert_eq!(output_progress(0, Path::new("-")).message(), "stdout");
+
+        let mut reader = ProgressReader::new(data.as_slice(), bar.clone());
+        std::io::copy(&mut reader, &mut std::io::sink()).unwrap();
+        assert_eq!(bar.position(), data.len() as u64);
+    }
+
+    #[test]
+    fn test_allocate_threads() {
+        assert_eq!(allocate_threads(8, &[100, 100, 1]), vec![4, 3, 1]);
+        assert_eq!(allocate_threads(4, &[10, 0]), vec![3, 1]);
+        assert_eq!(allocate_threads(8, &[1, 1000]), vec![1, 7]);
+        assert_eq!(allocate_threads(6, &[0, 0]), vec![3, 3]);
+        assert_eq!(allocate_threads(1, &[5, 5]), vec![1, 1]);
+        assert_eq!(allocate_threads(3, &[7]), vec![3]);
+        assert!(allocate_threads(3, &[]).is_empty());
+    }
+
+    #[test]
+    fn test_output_count_mismatch() {
+        assert_eq!(output_count_mismatch(&[3], 3, false), None);
+        assert_eq!(output_count_mismatch(&[4, 4, 2], 6, false), None);
+        assert_eq!(output_count_mismatch(&[6], 3, true), None);
+        assert_eq!(output_count_mismatch(&[], 0, false), None);
+        assert_eq!(
+            output_count_mismatch(&[2], 3, false),
+            Some("the outputs have 2 sequences".to_string())
+        );
+        assert_eq!(
+            output_count_mismatch(&[4, 3], 4, false),
+            Some("the paired outputs have 4 and 3 reads".to_string())
+        );
+        assert!(output_count_mismatch(&[5], 2, true).is_some());
+    }
+
+    #[test]
+    fn test_run_verify_outputs() {
+        let tmp = tempfile::tempdir().unwrap();
+        let in1 = tmp.path().join("in_1.fq");
+        let in2 = tmp.path().join("in_2.fq");
+        let kraken_output = tmp.path().join("kraken.out");
+        std::fs::write(&in1, "@r1/1\nACGT\n+\nIIII\n@r2/1\nGGGG\n+\nIIII\n").unwrap();
+        std::fs::write(&in2, "@r1/2\nCCCC\n+\nIIII\n").unwrap();
+        std::fs::write(&kraken_output, "U\tr1\t0\t4|4\t0:1\n").unwrap();
+        // the pairs aren't checked, so the outputs are out of sync
+        let pipeline = || {
+            DehostPipeline::new(vec![in1.clone(), in2.clone()], tmp.path().join("db"))
+                .with_outputs(vec![
+                    Some(tmp.path().join("out_1.fq")),
+                    Some(tmp.path().join("out_2.fq")),
+                ])
+                .with_classifications(&kraken_output)
+                .validate_inputs(false)
+                .overwrite(true)
+        };
+
+        let err = pipeline().run().unwrap_err();
+        assert!(format!("{:#}", err).contains("the paired outputs have 2 and 1 reads"));
+
+        let stats = pipeline().verify_outputs(false).run().unwrap();
+        assert_eq!(stats.output_reads, vec![2, 1]);
+    }
+
+    #[test]
+    fn test_run_repair_pairs() {
+        let tmp = tempfile::tempdir().unwrap();
+        let in1 = tmp.path().join("in_1.fq");
+        let in2 = tmp.path().join("in_2.fq");
+        let kraken_output = tmp.path().join("kraken.out");
+        let outputs = vec![
+            Some(tmp.path().join("out_1.fq")),
+            Some(tmp.path().join("out_2.fq")),
+        ];
+        std::fs::write(&in1, "@r1/1\nACGT\n+\nIIII\n@r2/1\nGGGG\n+\nIIII\n").unwrap();
+        std::fs::write(&in2, "@r2/2\nCCCC\n+\nIIII\n").unwrap();
+        std::fs::write(&kraken_output, "U\tr2\t0\t4|4\t0:1\n").unwrap();
+        let pipeline = || {
+            DehostPipeline::new(vec![in1.clone(), in2.clone()], tmp.path().join("db"))
+                .with_outputs(outputs.clone())
+                .with_classifications(&kraken_output)
+        };
+
+        let err = pipeline().run().unwrap_err();
+        assert!(format!("{:#}", err).contains("--repair-pairs"));
+
+        let stats = pipeline().repair_pairs(true).run().unwrap();
+        assert_eq!((stats.total, stats.host), (1, 0));
+        assert_eq!(
+            std::fs::read_to_string(outputs[0].as_ref().unwrap()).unwrap(),
+            "@r2/1\nGGGG\n+\nIIII\n"
+        );
+        assert_eq!(
+            std::fs::read_to_string(outputs[1].as_ref().unwrap()).unwrap(),
+            "@r2/2\nCCCC\n+\nIIII\n"
+        );
+    }
+
+    #[test]
+    fn test_run_bam_output() {
+        use flate2::read::MultiGzDecoder;
+        use std::io::Read;
+
+        let tmp = tempfile::tempdir().unwrap();
+        let in1 = tmp.path().join("in_1.fq");
+        let in2 = tmp.path().join("in_2.fq");
+        let kraken_output = tmp.path().join("kraken.out");
+        std::fs::write(&in1, "@r1/1\nACGT\n+\nIIII\n@r2/1\nGGGG\n+\nIIII\n").unwrap();
+        std::fs::write(&in2, "@r1/2\nTTTT\n+\nIIII\n@r2/2\nCCCC\n+\nIIII\n").unwrap();
+        std::fs::write(
+            &kraken_output,
+            "C\tr1\t9606\t4|4\t9606:1\nU\tr2\t0\t4|4\t0:1\n",
+        )
+        .unwrap();
+        let pipeline = || {
+            DehostPipeline::new(vec![in1.clone(), in2.clone()], tmp.path().join("db"))
+                .with_classifications(&kraken_output)
+                .with_output_format(OutputFormat::Bam)
+        };
+
+        let stats = pipeline().run().unwrap();
+        assert_eq!((stats.total, stats.host), (2, 1));
+        assert_eq!(stats.output_reads, vec![2]);
+        let output = tmp.path().join("in_1.nohuman.bam");
+        assert_eq!(stats.outputs, vec![output.clone()]);
+        let mut bam = Vec::new();
+        MultiGzDecoder::new(File::open(&output).unwrap())
+            .read_to_end(&mut bam)
+            .unwrap();
+        assert!(bam.starts_with(b"BAM\x01"));
+        // both mates of r2, flagged as the first and second reads of a pair
+        let r2: Vec<usize> = bam
+            .windows(3)
+            .enumerate()
+            .filter(|(_, w)| w == b"r2\0")
+            .map(|(i, _)| i)
+            .collect();
+        assert_eq!(r2.len(), 2);
+        assert!(!bam.windows(3).any(|w| w == b"r1\0"));
+        // the flag is 18 bytes before the name
+        let flag = |i: usize| u16::from_le_bytes([bam[i - 18], bam[i - 17]]);
+        assert_eq!((flag(r2[0]), flag(r2[1])), (77, 141));
+
+        let err = pipeline()
+            .with_compression(CompressionFormat::Gzip)
+            .run()
+            .unwrap_err();
+        assert!(err.to_string().contains("already compressed"));
+    }
+
+    /// A fake minimap2 in `dir` that writes a PAF with a passing alignment for r1, to the path
+    /// after -o.
+    #[cfg(unix)]
+    fn fake_minimap2(dir: &Path) -> Minimap2 {
+        use std::os::unix::fs::PermissionsExt;
+
+        let minimap2 = dir.join("minimap2");
+        std::fs::write(
+            &minimap2,
+            "#!/bin/sh\nwhile [ \"$1\" != -o ]; do shift; done\n\
+             printf 'r1\\t4\\t0\\t4\\t+\\tchr1\\t100\\t0\\t4\\t4\\t4\\t60\\n' > \"$2\"\n",
+        )
+        .unwrap();
+        std::fs::set_permissions(&minimap2, std::fs::Permissions::from_mode(0o755)).unwrap();
+        Minimap2 {
+            command: minimap2.to_string_lossy().to_string(),
+            filter: crate::align::AlignmentFilter {
+                min_identity: 0.9,
+                min_length: 4,
+            },
+            ..Minimap2::new(dir.join("ref.mmi"))
+        }
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_run_with_minimap2() {
+        let tmp = tempfile::tempdir().unwrap();
+        let input = tmp.path().join("in.fq");
+        let output = tmp.path().join("out.fq");
+        std::fs::write(&input, "@r1\nACGT\n+\nIIII\n@r2\nGGGG\n+\nIIII\n").unwrap();
+
+        let stats = DehostPipeline::new(vec![input], tmp.path().join("db"))
+            .with_outputs(vec![Some(output.clone())])
+            .with_minimap2(fake_minimap2(tmp.path()))
+            .run()
+            .unwrap();
+
+        assert_eq!((stats.total, stats.host), (2, 1));
+        assert_eq!(
+            std::fs::read_to_string(&output).unwrap(),
+            "@r2\nGGGG\n+\nIIII\n"
+        );
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_run_confirm_with_alignment() {
+        let tmp = tempfile::tempdir().unwrap();
+        let input = tmp.path().join("in.fq");
+        let kraken_output = tmp.path().join("kraken.out");
+        let output = tmp.path().join("out.fq");
+        std::fs::write(
+            &input,
+            "@r1\nACGT\n+\nIIII\n@r2\nGGGG\n+\nIIII\n@r3\nTTTT\n+\nIIII\n",
+        )
+        .unwrap();
+        // r1 and r2 are classified, but only r1 aligns, so r2 is rescued
+        std::fs::write(
+            &kraken_output,
+            "C\tr1\t9606\t4\t9606:1\nC\tr2\t9606\t4\t9606:1\nU\tr3\t0\t4\t0:1\n",
+        )
+        .unwrap();
+
+        let stats = DehostPipeline::new(vec![input], tmp.path().join("db"))
+            .with_outputs(vec![Some(output.clone())])
+            .with_classifications(&kraken_output)
+            .with_minimap2(fake_minimap2(tmp.path()))
+            .confirm_with_alignment(true)
+            .run()
+            .unwrap();
+
+        assert_eq!((stats.total, stats.host), (3, 1));
+        assert_eq!(
+            std::fs::read_to_string(&output).unwrap(),
+            "@r2\nGGGG\n+\nIIII\n@r3\nTTTT\n+\nIIII\n"
+        );
+    }
+
+    #[test]
+    fn test_run_with_taxa() {
+        let tmp = tempfile::tempdir().unwrap();
+        let input = tmp.path().join("in.fq");
+        let kraken_output = tmp.path().join("kraken.out");
+        let output = tmp.path().join("out.fq");
+        std::fs::write(
+            &input,
+            "@r1\nACGT\n+\nIIII\n@r2\nGGGG\n+\nIIII\n@r3\nTTTT\n+\nIIII\n",
+        )
+        .unwrap();
+        // r2 is classified, but as E. coli
+        std::fs::write(
+            &kraken_output,
+            "C\tr1\t9606\t4\t9606:1\nC\tr2\t562\t4\t562:1\nU\tr3\t0\t4\t0:1\n",
+        )
+        .unwrap();
+
+        let stats = DehostPipeline::new(vec![input], tmp.path().join("db"))
+            .with_outputs(vec![Some(output.clone())])
+            .with_classifications(&kraken_output)
+            .with_taxa(HashSet::from([9606]))
+            .run()
+            .unwrap();
+
+        assert_eq!((stats.total, stats.host), (3, 1));
+        assert_eq!(
+            std::fs::read_to_string(&output).unwrap(),
+            "@r2\nGGGG\n+\nIIII\n@r3\nTTTT\n+\nIIII\n"
+        );
+    }
+
+    #[test]
+    fn test_run_removed_ids() {
+        let tmp = tempfile::tempdir().unwrap();
+        let input = tmp.path().join("in.fq");
+        let kraken_output = tmp.path().join("kraken.out");
+        let removed_ids = tmp.path().join("removed.txt");
+        std::fs::write(
+            &input,
+            "@r3\nACGT\n+\nIIII\n@r2\nGGGG\n+\nIIII\n@r1\nTTTT\n+\nIIII\n",
+        )
+        .unwrap();
+        std::fs::write(
+            &kraken_output,
+            "C\tr3\t9606\t4\t9606:1\nU\tr2\t0\t4\t0:1\nC\tr1\t9606\t4\t9606:1\n",
+        )
+        .unwrap();
+
+        let stats = DehostPipeline::new(vec![input], tmp.path().join("db"))
+            .with_outputs(vec![Some(tmp.path().join("out.fq"))])
+            .with_classifications(&kraken_output)
+            .with_removed_ids(&removed_ids)
+            .keep_host_reads(true)
+            .run()
+            .unwrap();
+
+        assert_eq!(stats.host, 2);
+        assert_eq!(std::fs::read_to_string(&removed_ids).unwrap(), "r1\nr3\n");
+    }
+
+    #[test]
+    fn test_run_stats_only() {
+        let tmp = tempfile::tempdir().unwrap();
+        let input = tmp.path().join("in.fq");
+        let kraken_output = tmp.path().join("kraken.out");
+        let output = tmp.path().join("out.fq");
+        std::fs::write(&input, "@r1\nACGT\n+\nIIII\n@r2\nGGGG\n+\nIIII\n").unwrap();
+        std::fs::write(&kraken_output, "C\tr1\t9606\t4\t9606:1\nU\tr2\t0\t4\t0:1\n").unwrap();
+
+        let stats = DehostPipeline::new(vec![input], tmp.path().join("db"))
+            .with_outputs(vec![Some(output.clone())])
+            .with_classifications(&kraken_output)
+            .stats_only(true)
+            .run()
+            .unwrap();
+
+        assert_eq!((stats.total, stats.host), (2, 1));
+        assert!(stats.outputs.is_empty());
+        assert!(!output.exists());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_run_consensus() {
+        let tmp = tempfile::tempdir().unwrap();
+        let input = tmp.path().join("in.fq");
+        let kraken_output = tmp.path().join("kraken.out");
+        let output = tmp.path().join("out.fq");
+        std::fs::write(
+            &input,
+            "@r1\nACGT\n+\nIIII\n@r2\nGGGG\n+\nIIII\n@r3\nTTTT\n+\nIIII\n",
+        )
+        .unwrap();
+        // kraken2 flags r1 and r2, minimap2 only r1
+        std::fs::write(
+            &kraken_output,
+            "C\tr1\t9606\t4\t9606:1\nC\tr2\t9606\t4\t9606:1\nU\tr3\t0\t4\t0:1\n",
+        )
+        .unwrap();
+        let engines = vec![Aligner::Kraken2, Aligner::Minimap2];
+        let pipeline = DehostPipeline::new(vec![input], tmp.path().join("db"))
+            .with_outputs(vec![Some(output.clone())])
+            .with_classifications(&kraken_output)
+            .with_minimap2(fake_minimap2(tmp.path()));
+
+        let stats = pipeline
+            .clone()
+            .with_consensus(engines.clone(), 2)
+            .run()
+            .unwrap();
+        assert_eq!((stats.total, stats.host), (3, 1));
+        assert_eq!(stats.engine_host["kraken2"], 2);
+        assert_eq!(stats.engine_host["minimap2"], 1);
+        assert_eq!(
+            std::fs::read_to_string(&output).unwrap(),
+            "@r2\nGGGG\n+\nIIII\n@r3\nTTTT\n+\nIIII\n"
+        );
+
+        let stats = pipeline
+            .overwrite(true)
+            .with_consensus(engines, 1)
+            .run()
+            .unwrap();
+        assert_eq!((stats.total, stats.host), (3, 2));
+        assert_eq!(
+            std::fs::read_to_string(&output).unwrap(),
+            "@r3\nTTTT\n+\nIIII\n"
+        );
+    }
+
+    #[test]
+    fn test_run_mock() {
+        let tmp = tempfile::tempdir().unwrap();
+        let input = tmp.path().join("in.fq");
+        let output = tmp.path().join("out.fq.gz");
+        std::fs::write(&input, "@r1\nACGT\n+\nIIII\n@r2\nGGGG\n+\nIIII\n").unwrap();
+        // no kraken2 or database is needed
+        let stats = DehostPipeline::new(vec![input], tmp.path().join("db"))
+            .with_kraken2("no-such-kraken2".to_string())
+            .with_outputs(vec![Some(output.clone())])
+            .with_mock(MockClassifier {
+                fraction: 1.0,
+                seed: 0,
+            })
+            .run()
+            .unwrap();
+        assert_eq!((stats.total, stats.host), (2, 2));
+        assert_eq!(
+            CompressionFormat::from_file(&output).unwrap(),
+            CompressionFormat::Gzip
+        );
+    }
+
+    #[test]
+    fn test_run_async_progress() {
+        let tmp = tempfile::tempdir().unwrap();
+        let input = tmp.path().join("in.fq");
+        let output = tmp.path().join("out.fq");
+        std::fs::write(&input, "@r1\nACGT\n+\nIIII\n@r2\nGGGG\n+\nIIII\n").unwrap();
+        let (sender, receiver) = async_std::channel::unbounded();
+        let pipeline = DehostPipeline::new(vec![input], tmp.path().join("db"))
+            .with_outputs(vec![Some(output.clone())])
+            .with_mock(MockClassifier {
+                fraction: 0.0,
+                seed: 0,
+            })
+            .with_progress(ProgressCallback::channel(sender));
+        let stats = async_std::task::block_on(pipeline.run_async()).unwrap();
+        assert_eq!((stats.total, stats.host), (2, 0));
+
+        let events: Vec<_> = std::iter::from_fn(|| receiver.try_recv().ok()).collect();
+        assert_eq!(
+            events,
+            [
+                ProgressEvent::ClassificationStarted,
+                ProgressEvent::ClassificationFinished { total: 2, host: 0 },
+                ProgressEvent::OutputWritten {
+                    path: output,
+                    reads: 2
+                },
+            ]
+        );
+    }
+
+    /// A kraken2 that writes the reads without "human" in their name to its --unclassified-out.
+    #[derive(Debug, Default)]
+    struct FakeKraken2 {
+        args: Mutex<Vec<std::ffi::OsString>>,
+    }
+
+    impl Executor for FakeKraken2 {
+        fn execute(
+            &self,
+            _: &CommandRunner,
+            args: &[&OsStr],
+        ) -> std::result::Result<crate::Execution, NohumanError> {
+            *self.args.lock().unwrap() = args.iter().map(|&arg| arg.to_owned()).collect();
+            let out = args.iter().position(|&arg| arg == "--unclassified-out");
+            let reads = std::fs::read_to_string(args[args.len() - 1])?;
+            let records: Vec<&str> = reads.lines().collect();
+            let kept: String = records
+                .chunks(4)
+                .filter(|record| !record[0].contains("human"))
+                .map(|record| record.join("\n") + "\n")
+                .collect();
+            std::fs::write(args[out.unwrap() + 1], &kept)?;
+            let total = records.len() / 4;
+            let unclassified = kept.lines().count() / 4;
+            Ok(crate::Execution {
+                success: true,
+                stderr: format!(
+                    "{} sequences processed\n{} sequences classified\n{} sequences unclassified\n",
+                    total,
+                    total - unclassified,
+                    unclassified
+                ),
+                ..Default::default()
+            })
+        }
+    }
+
+    #[test]
+    fn test_run_with_executor() {
+        let tmp = tempfile::tempdir().unwrap();
+        let db = tmp.path().join("db");
+        std::fs::create_dir(&db).unwrap();
+        for file in crate::database::INDEX_FILES {
+            std::fs::write(db.join(file), "").unwrap();
+        }
+        // a path with a space that isn't UTF-8 is passed to kraken2 as it is
+        #[cfg(unix)]
+        let input = tmp
+            .path()
+            .join(std::os::unix::ffi::OsStrExt::from_bytes(b"in \xff.fq") as &OsStr);
+        #[cfg(not(unix))]
+        let input = tmp.path().join("in .fq");
+        let output = tmp.path().join("out.fq");
+        std::fs::write(&input, "@human1\nACGT\n+\nIIII\n@r2\nGGGG\n+\nIIII\n").unwrap();
+
+        let fake = Arc::new(FakeKraken2::default());
+        let stats = DehostPipeline::new(vec![input.clone()], &db)
+            .with_outputs(vec![Some(output.clone())])
+            .with_threads(2)
+            .with_executor(fake.clone())
+            .run()
+            .unwrap();
+        assert_eq!((stats.total, stats.host), (2, 1));
+        assert_eq!(
+            std::fs::read_to_string(&output).unwrap(),
+            "@r2\nGGGG\n+\nIIII\n"
+        );
+        let args = fake.args.lock().unwrap();
+        assert_eq!(
+            args[..4],
+            [
+                OsStr::new("--threads"),
+                OsStr::new("2"),
+                OsStr::new("--db"),
+                db.as_os_str()
+            ]
+        );
+        assert_eq!(args.last(), Some(&input.into_os_string()));
+    }
+
+    #[test]
+    fn test_run_pipe_input() {
+        let tmp = tempfile::tempdir().unwrap();
+        // e.g. <(zcat in.fq.gz), which is only readable once and can't be seeked
+        let pipe = tmp.path().join("in.fq.gz");
+        let status = std::process::Command::new("mkfifo")
+            .arg(&pipe)
+            .status()
+            .unwrap();
+        assert!(status.success());
+        assert!(is_pipe(&pipe));
+        let writer = {
+            let pipe = pipe.clone();
+            std::thread::spawn(move || {
+                let mut writer = flate2::write::GzEncoder::new(
+                    File::create(pipe).unwrap(),
+                    flate2::Compression::default(),
+                );
+                writer
+                    .write_all(b"@r1\nACGT\n+\nIIII\n@r2\nGGGG\n+\nIIII\n")
+                    .unwrap();
+                writer.finish().unwrap();
+            })
+        };
+        let output = tmp.path().join("out.fq");
+        let stats = DehostPipeline::new(vec![pipe], tmp.path().join("db"))
+            .with_outputs(vec![Some(output.clone())])
+            .with_mock(MockClassifier {
+                fraction: 0.0,
+                seed: 0,
+            })
+            .run()
+            .unwrap();
+        writer.join().unwrap();
+        assert_eq!((stats.total, stats.host), (2, 0));
+        assert_eq!(stats.output_reads, [2]);
+    }
+}
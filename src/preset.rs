@@ -0,0 +1,120 @@
+//! Bundles of options tuned for a read technology, or for how aggressively host reads are removed.
+//!
+//! Long reads need different thresholds than short reads: they have many more k-mers (so more
+//! chances of a spurious hit), and nanopore reads have lower identity to the host reference.
+use anyhow::{bail, Result};
+use std::num::NonZeroU32;
+
+/// A preset (`--preset`).
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum Preset {
+    /// Illumina short reads.
+    Illumina,
+    /// Oxford Nanopore long reads.
+    Ont,
+    /// PacBio HiFi long reads.
+    Pacbio,
+    /// Remove any read with evidence of being a host read, e.g. for data sharing.
+    Strict,
+    /// Only remove reads that are clearly host reads, keeping more of the non-host reads.
+    Lenient,
+}
+
+/// The options a [`Preset`] sets.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct PresetOptions {
+    /// The kraken2 minimum confidence score.
+    pub confidence: f32,
+    /// The kraken2 minimum number of hit groups.
+    pub min_hit_groups: NonZeroU32,
+    /// The minimap2 preset (-x), if the preset is for a read technology.
+    pub minimap2_preset: Option<&'static str>,
+    /// The minimum identity of an alignment that makes a read a host read.
+    pub min_identity: f64,
+}
+
+impl Preset {
+    /// The options the preset sets.
+    pub fn options(self) -> PresetOptions {
+        let options = |confidence, min_hit_groups, minimap2_preset, min_identity| PresetOptions {
+            confidence,
+            min_hit_groups: NonZeroU32::new(min_hit_groups).unwrap(),
+            minimap2_preset,
+            min_identity,
+        };
+        match self {
+            Self::Illumina => options(0.0, 2, Some("sr"), 0.9),
+            Self::Ont => options(0.0, 3, Some("map-ont"), 0.8),
+            Self::Pacbio => options(0.0, 3, Some("map-hifi"), 0.9),
+            Self::Strict => options(0.0, 1, None, 0.8),
+            Self::Lenient => options(0.1, 3, None, 0.95),
+        }
+    }
+}
+
+impl std::str::FromStr for Preset {
+    type Err = anyhow::Error;
+
+    /// Parse a preset. `s` is case-insensitive.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use nohuman::preset::Preset;
+    ///
+    /// assert_eq!("ONT".parse::<Preset>().unwrap(), Preset::Ont);
+    /// assert!("hifi".parse::<Preset>().is_err());
+    /// ```
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "illumina" => Ok(Self::Illumina),
+            "ont" => Ok(Self::Ont),
+            "pacbio" => Ok(Self::Pacbio),
+            "strict" => Ok(Self::Strict),
+            "lenient" => Ok(Self::Lenient),
+            _ => bail!(
+                "Invalid preset: {} (expected illumina, ont, pacbio, strict, or lenient)",
+                s
+            ),
+        }
+    }
+}
+
+impl std::fmt::Display for Preset {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Illumina => write!(f, "illumina"),
+            Self::Ont => write!(f, "ont"),
+            Self::Pacbio => write!(f, "pacbio"),
+            Self::Strict => write!(f, "strict"),
+            Self::Lenient => write!(f, "lenient"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_preset_options() {
+        let presets = [
+            Preset::Illumina,
+            Preset::Ont,
+            Preset::Pacbio,
+            Preset::Strict,
+            Preset::Lenient,
+        ];
+        for preset in presets {
+            assert_eq!(preset.to_string().parse::<Preset>().unwrap(), preset);
+            let options = preset.options();
+            assert!((0.0..=1.0).contains(&options.confidence));
+            assert!((0.0..=1.0).contains(&options.min_identity));
+        }
+        // lenient removes fewer reads than strict
+        let (strict, lenient) = (Preset::Strict.options(), Preset::Lenient.options());
+        assert!(lenient.confidence > strict.confidence);
+        assert!(lenient.min_hit_groups > strict.min_hit_groups);
+        assert_eq!(Preset::Ont.options().minimap2_preset, Some("map-ont"));
+    }
+}
@@ -0,0 +1,71 @@
+//! Progress events from downloading a database and running the pipeline, for frontends (e.g. a
+//! GUI or web service) that show their own progress rather than nohuman's progress bars.
+use async_std::channel::Sender;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/// A step in a download or pipeline run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ProgressEvent {
+    /// Started downloading `url`, which is `total` bytes, if the server said.
+    DownloadStarted { url: String, total: Option<u64> },
+    /// `bytes` more bytes have been downloaded.
+    Downloaded { bytes: u64 },
+    /// Started extracting the downloaded database.
+    Extracting,
+    /// Started classifying the reads (e.g. running kraken2).
+    ClassificationStarted,
+    /// Classified `total` sequences, of which `host` are host sequences.
+    ClassificationFinished { total: u64, host: u64 },
+    /// Wrote `reads` reads to the output `path`.
+    OutputWritten { path: PathBuf, reads: u64 },
+}
+
+/// Called with each [`ProgressEvent`]. It may be called from several threads at once.
+#[derive(Clone)]
+pub struct ProgressCallback(Arc<dyn Fn(ProgressEvent) + Send + Sync>);
+
+impl ProgressCallback {
+    pub fn new<F: Fn(ProgressEvent) + Send + Sync + 'static>(callback: F) -> Self {
+        Self(Arc::new(callback))
+    }
+
+    /// Send the events to a channel. Events are dropped if the channel is full or closed, so a
+    /// slow (or finished) receiver never holds up the download or run.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use nohuman::progress::{ProgressCallback, ProgressEvent};
+    ///
+    /// let (sender, receiver) = async_std::channel::unbounded();
+    /// let progress = ProgressCallback::channel(sender);
+    /// progress.emit(ProgressEvent::Extracting);
+    /// assert_eq!(receiver.try_recv(), Ok(ProgressEvent::Extracting));
+    /// ```
+    pub fn channel(sender: Sender<ProgressEvent>) -> Self {
+        Self::new(move |event| {
+            let _ = sender.try_send(event);
+        })
+    }
+
+    pub fn emit(&self, event: ProgressEvent) {
+        (self.0)(event)
+    }
+}
+
+impl std::fmt::Debug for ProgressCallback {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("ProgressCallback")
+    }
+}
+
+/// Callbacks are equal if they are the same callback (i.e. clones of each other).
+impl PartialEq for ProgressCallback {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for ProgressCallback {}
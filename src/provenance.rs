@@ -0,0 +1,191 @@
+//! An audit record of a run: what was run, on which machine, with which database, on which
+//! inputs, and what it produced. Clinical labs need this trail for accreditation.
+use crate::checksum::{file_checksums, Checksums};
+use crate::database::{DatabaseMetadata, FileChecksum};
+use crate::pipeline::split_file_name;
+use crate::{is_stdio, Host};
+use anyhow::Result;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::SystemTime;
+
+/// The provenance of a nohuman run.
+#[derive(Debug, Serialize)]
+pub struct Provenance {
+    /// The command line nohuman was run with, including the program name.
+    pub command_line: Vec<String>,
+    pub nohuman_version: String,
+    /// The version of kraken2 used, if it could be determined.
+    pub kraken2_version: Option<String>,
+    pub hostname: Option<String>,
+    /// When the run started and ended, in RFC 3339 format.
+    pub start_time: String,
+    pub end_time: String,
+    /// The database used, if one was.
+    pub database: Option<DatabaseRecord>,
+    pub inputs: Vec<FileRecord>,
+    pub outputs: Vec<FileRecord>,
+    /// The number of sequences classified. A pair of reads counts as one sequence.
+    pub sequences: u64,
+    /// The number of sequences classified as host.
+    pub host_sequences: u64,
+    /// The number of reads in each output.
+    pub output_reads: Vec<u64>,
+}
+
+/// A file read or written by a run, with its checksums. Standard input and output have no
+/// checksums.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FileRecord {
+    pub path: PathBuf,
+    pub md5: Option<String>,
+    pub sha256: Option<String>,
+}
+
+impl FileRecord {
+    /// Record the file at `path`, computing its checksums.
+    pub fn new(path: &Path) -> Result<Self> {
+        let checksums = if is_stdio(path) {
+            None
+        } else {
+            Some(file_checksums(path)?)
+        };
+        Ok(Self {
+            path: path.to_path_buf(),
+            md5: checksums.as_ref().map(|c| c.md5.clone()),
+            sha256: checksums.map(|Checksums { sha256, .. }| sha256),
+        })
+    }
+}
+
+/// The database a run used. The version and checksums are those recorded when the database was
+/// installed (see [`DatabaseMetadata`]), so are missing for a database nohuman didn't install.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DatabaseRecord {
+    pub path: PathBuf,
+    pub version: Option<String>,
+    /// The SHA256 of the tarball the database was installed from.
+    pub sha256: Option<String>,
+    /// The checksums of the database files, keyed by their path in the database.
+    pub files: BTreeMap<String, FileChecksum>,
+}
+
+impl DatabaseRecord {
+    /// Record the database at `path`.
+    pub fn new(path: &Path) -> Self {
+        let metadata = DatabaseMetadata::read(path).ok();
+        Self {
+            path: path.to_path_buf(),
+            version: metadata.as_ref().map(|m| m.version.clone()),
+            sha256: metadata.as_ref().and_then(|m| m.sha256.clone()),
+            files: metadata.map(|m| m.files).unwrap_or_default(),
+        }
+    }
+}
+
+impl Provenance {
+    /// Write the provenance as pretty-printed JSON.
+    pub fn to_writer<W: Write>(&self, writer: W) -> Result<()> {
+        serde_json::to_writer_pretty(writer, self)?;
+        Ok(())
+    }
+}
+
+/// Format `time` as an RFC 3339 timestamp.
+pub fn timestamp(time: SystemTime) -> String {
+    humantime::format_rfc3339_seconds(time).to_string()
+}
+
+/// The name of the machine, from `hostname`.
+pub fn hostname() -> Option<String> {
+    let output = Command::new("hostname").output().ok()?;
+    let name = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    (output.status.success() && !name.is_empty()).then_some(name)
+}
+
+/// The path of the provenance record of a run with the first `output` and `input`. It is the
+/// output without its extensions, plus ".provenance.json", e.g. "in_1.nohuman.fq.gz" ->
+/// "in_1.nohuman.provenance.json". If the output is stdout (or there is none), it is named
+/// after the input as the output would be, and if that is stdin too, it is
+/// `no<host>.provenance.json`.
+pub fn provenance_path(output: Option<&Path>, input: &Path, host: &Host) -> PathBuf {
+    let (path, stem) = match output.filter(|output| !is_stdio(output)) {
+        Some(output) if output.extension().is_some_and(|ext| ext == "bam") => (
+            output,
+            output
+                .file_stem()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .to_string(),
+        ),
+        Some(output) => (output, split_file_name(output).0),
+        None if !is_stdio(input) => (
+            input,
+            format!("{}.{}", split_file_name(input).0, host.output_suffix()),
+        ),
+        None => return PathBuf::from(format!("{}.provenance.json", host.output_suffix())),
+    };
+    path.with_file_name(format!("{}.provenance.json", stem))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_provenance_path() {
+        let host = Host::Human;
+        let path = |output: Option<&str>, input: &str| {
+            provenance_path(output.map(Path::new), Path::new(input), &host)
+        };
+        assert_eq!(
+            path(Some("out/in_1.nohuman.fq.gz"), "in_1.fq.gz"),
+            PathBuf::from("out/in_1.nohuman.provenance.json")
+        );
+        assert_eq!(
+            path(Some("in.nohuman.bam"), "in.fq"),
+            PathBuf::from("in.nohuman.provenance.json")
+        );
+        assert_eq!(
+            path(Some("-"), "reads/in.fastq"),
+            PathBuf::from("reads/in.nohuman.provenance.json")
+        );
+        assert_eq!(path(None, "-"), PathBuf::from("nohuman.provenance.json"));
+    }
+
+    #[test]
+    fn test_provenance_to_writer() {
+        let tmp = tempfile::tempdir().unwrap();
+        let input = tmp.path().join("in.fq");
+        std::fs::write(&input, "@r1\nACGT\n+\nIIII\n").unwrap();
+        let provenance = Provenance {
+            command_line: vec!["nohuman".to_string(), "in.fq".to_string()],
+            nohuman_version: env!("CARGO_PKG_VERSION").to_string(),
+            kraken2_version: None,
+            hostname: hostname(),
+            start_time: timestamp(SystemTime::UNIX_EPOCH),
+            end_time: timestamp(SystemTime::UNIX_EPOCH),
+            database: Some(DatabaseRecord::new(tmp.path())),
+            inputs: vec![FileRecord::new(&input).unwrap()],
+            outputs: vec![FileRecord::new(Path::new("-")).unwrap()],
+            sequences: 1,
+            host_sequences: 0,
+            output_reads: vec![1],
+        };
+        let mut out = Vec::new();
+        provenance.to_writer(&mut out).unwrap();
+
+        let json: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(json["start_time"], "1970-01-01T00:00:00Z");
+        assert_eq!(
+            json["inputs"][0]["md5"],
+            format!("{:x}", md5::compute("@r1\nACGT\n+\nIIII\n"))
+        );
+        assert!(json["outputs"][0]["sha256"].is_null());
+        assert!(json["database"]["version"].is_null());
+        assert_eq!(json["sequences"], 1);
+    }
+}
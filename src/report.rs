@@ -0,0 +1,234 @@
+use crate::compression::open_reader;
+use anyhow::{bail, Context, Result};
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+
+/// The NCBI taxonomy ID of Homo sapiens.
+pub const HUMAN_TAXID: u64 = 9606;
+
+/// A single line of a kraken2 report (`--report`).
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReportLine {
+    /// Percentage of reads in the clade rooted at this taxon.
+    pub pct: f64,
+    /// Number of reads in the clade rooted at this taxon.
+    pub clade_reads: u64,
+    /// Number of reads assigned directly to this taxon.
+    pub taxon_reads: u64,
+    /// Rank code, e.g. "U" (unclassified), "R" (root), "S" (species), or "S1".
+    pub rank: String,
+    pub taxid: u64,
+    /// Scientific name of the taxon, with the indentation removed.
+    pub name: String,
+    /// Depth of the taxon in the tree, from the indentation of the name (two spaces per level).
+    /// The root (and unclassified) are at depth 0.
+    pub depth: usize,
+}
+
+impl std::str::FromStr for ReportLine {
+    type Err = anyhow::Error;
+
+    /// Parse a line of a kraken2 report. Reports written with `--report-minimizer-data` (which
+    /// have two extra minimizer columns before the rank) are also supported.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use nohuman::report::ReportLine;
+    ///
+    /// let line: ReportLine = " 12.50\t25\t25\tS\t9606\t                    Homo sapiens"
+    ///     .parse()
+    ///     .unwrap();
+    /// assert_eq!(line.taxid, 9606);
+    /// assert_eq!(line.name, "Homo sapiens");
+    /// assert_eq!(line.depth, 10);
+    /// ```
+    fn from_str(s: &str) -> Result<Self> {
+        let fields: Vec<&str> = s.trim_end_matches(['\r', '\n']).split('\t').collect();
+        // the minimizer columns sit between the taxon reads and the rank
+        let (rank, taxid, name) = match fields.len() {
+            6 => (fields[3], fields[4], fields[5]),
+            8 => (fields[5], fields[6], fields[7]),
+            n => bail!(
+                "Expected 6 or 8 columns in kraken2 report line, found {}",
+                n
+            ),
+        };
+
+        Ok(Self {
+            pct: fields[0].trim().parse().context("Invalid percentage")?,
+            clade_reads: fields[1]
+                .trim()
+                .parse()
+                .context("Invalid clade read count")?,
+            taxon_reads: fields[2]
+                .trim()
+                .parse()
+                .context("Invalid taxon read count")?,
+            rank: rank.trim().to_string(),
+            taxid: taxid.trim().parse().context("Invalid taxonomy ID")?,
+            name: name.trim().to_string(),
+            depth: (name.len() - name.trim_start_matches(' ').len()) / 2,
+        })
+    }
+}
+
+/// A parsed kraken2 report.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Report {
+    pub lines: Vec<ReportLine>,
+}
+
+impl Report {
+    /// Parse a kraken2 report from `reader`.
+    pub fn from_reader<R: BufRead>(reader: R) -> Result<Self> {
+        let mut lines = Vec::new();
+        for (i, line) in reader.lines().enumerate() {
+            let line = line.context("Failed to read kraken2 report")?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let line = line
+                .parse()
+                .with_context(|| format!("Failed to parse line {} of kraken2 report", i + 1))?;
+            lines.push(line);
+        }
+        Ok(Self { lines })
+    }
+
+    /// Parse the (possibly compressed) kraken2 report at `path`.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::from_reader(BufReader::new(open_reader(path)?))
+    }
+
+    /// Get the report line for `taxid`.
+    pub fn get(&self, taxid: u64) -> Option<&ReportLine> {
+        self.lines.iter().find(|line| line.taxid == taxid)
+    }
+
+    /// The total number of reads, classified or not.
+    pub fn total_reads(&self) -> u64 {
+        self.lines
+            .iter()
+            .filter(|line| line.rank == "U" || line.rank == "R")
+            .map(|line| line.clade_reads)
+            .sum()
+    }
+
+    /// The number of reads in the clade rooted at `taxid`. This is 0 if the taxon isn't in the
+    /// report.
+    pub fn clade_reads(&self, taxid: u64) -> u64 {
+        self.get(taxid).map_or(0, |line| line.clade_reads)
+    }
+
+    /// The fraction of all reads that are in the clade rooted at `taxid`.
+    pub fn clade_fraction(&self, taxid: u64) -> f64 {
+        let total = self.total_reads();
+        if total == 0 {
+            0.0
+        } else {
+            self.clade_reads(taxid) as f64 / total as f64
+        }
+    }
+
+    /// The fraction of all reads that are classified as human (or below).
+    pub fn human_fraction(&self) -> f64 {
+        self.clade_fraction(HUMAN_TAXID)
+    }
+
+    /// Write the report in Krona's text format, for `ktImportText`: a line for each taxon with
+    /// reads assigned directly to it, with the read count followed by the names of the taxa from
+    /// the root down to it. The root itself is left out of the lineages (as Krona has its own), so
+    /// reads assigned to the root have no names.
+    pub fn write_krona<W: Write>(&self, mut writer: W) -> Result<()> {
+        let mut lineage: Vec<&ReportLine> = Vec::new();
+        for line in &self.lines {
+            lineage.truncate(line.depth);
+            lineage.push(line);
+            if line.taxon_reads == 0 {
+                continue;
+            }
+            write!(writer, "{}", line.taxon_reads)?;
+            for taxon in lineage.iter().filter(|taxon| taxon.rank != "R") {
+                write!(writer, "\t{}", taxon.name)?;
+            }
+            writeln!(writer)?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    const REPORT: &str = " 60.00\t6\t6\tU\t0\tunclassified
+ 40.00\t4\t0\tR\t1\troot
+ 40.00\t4\t0\tR1\t131567\t  cellular organisms
+ 30.00\t3\t3\tS\t9606\t                    Homo sapiens
+ 10.00\t1\t1\tS\t10090\t                    Mus musculus
+";
+
+    #[test]
+    fn test_parse_report() {
+        let report = Report::from_reader(Cursor::new(REPORT)).unwrap();
+        assert_eq!(report.lines.len(), 5);
+        assert_eq!(
+            report.lines[3],
+            ReportLine {
+                pct: 30.0,
+                clade_reads: 3,
+                taxon_reads: 3,
+                rank: "S".to_string(),
+                taxid: 9606,
+                name: "Homo sapiens".to_string(),
+                depth: 10,
+            }
+        );
+        assert_eq!(report.total_reads(), 10);
+        assert_eq!(report.clade_reads(10090), 1);
+        assert_eq!(report.clade_reads(1234), 0);
+        assert!((report.human_fraction() - 0.3).abs() < f64::EPSILON);
+    }
+
+    #[test]
+    fn test_parse_report_line_with_minimizer_data() {
+        let line: ReportLine = "  2.00\t2\t1\t3\t3\tG\t9605\t  Homo".parse().unwrap();
+        assert_eq!(line.rank, "G");
+        assert_eq!(line.taxid, 9605);
+        assert_eq!(line.taxon_reads, 1);
+        assert_eq!(line.depth, 1);
+    }
+
+    #[test]
+    fn test_write_krona() {
+        let report = " 50.00\t5\t5\tU\t0\tunclassified
+ 50.00\t5\t1\tR\t1\troot
+ 40.00\t4\t0\tR1\t131567\t  cellular organisms
+ 30.00\t3\t2\tG\t9605\t    Homo
+ 10.00\t1\t1\tS\t9606\t      Homo sapiens
+ 10.00\t1\t1\tS\t10090\t    Mus musculus
+";
+        let report = Report::from_reader(Cursor::new(report)).unwrap();
+        let mut krona = Vec::new();
+        report.write_krona(&mut krona).unwrap();
+        assert_eq!(
+            String::from_utf8(krona).unwrap(),
+            "5\tunclassified
+1
+2\tcellular organisms\tHomo
+1\tcellular organisms\tHomo\tHomo sapiens
+1\tcellular organisms\tMus musculus
+"
+        );
+    }
+
+    #[test]
+    fn test_parse_report_malformed() {
+        assert!(Report::from_reader(Cursor::new("foo\tbar\n")).is_err());
+        let empty = Report::from_reader(Cursor::new("")).unwrap();
+        assert_eq!(empty.human_fraction(), 0.0);
+    }
+}
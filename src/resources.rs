@@ -0,0 +1,168 @@
+//! The time and memory used by the programs nohuman runs (i.e. kraken2), so a slow run can be
+//! explained.
+use indicatif::HumanBytes;
+use serde::{Serialize, Serializer};
+use std::io;
+#[cfg(unix)]
+use std::os::unix::process::ExitStatusExt;
+use std::process::{Child, ExitStatus};
+use std::time::Duration;
+
+/// The resources used by a program.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct ResourceUsage {
+    /// The time from starting the program to it exiting.
+    #[serde(rename = "wall_seconds", serialize_with = "seconds")]
+    pub wall_time: Duration,
+    /// The user plus system CPU time of the program, and any processes it waited for.
+    #[serde(rename = "cpu_seconds", serialize_with = "seconds")]
+    pub cpu_time: Duration,
+    /// The peak resident set size (memory use), in bytes.
+    #[serde(rename = "max_rss_bytes")]
+    pub max_rss: u64,
+}
+
+impl ResourceUsage {
+    /// The usage of two runs: the times add up, and the peak memory is the larger.
+    pub fn combine(self, other: Self) -> Self {
+        Self {
+            wall_time: self.wall_time + other.wall_time,
+            cpu_time: self.cpu_time + other.cpu_time,
+            max_rss: self.max_rss.max(other.max_rss),
+        }
+    }
+
+    #[cfg(unix)]
+    fn from_rusage(wall_time: Duration, rusage: &libc::rusage) -> Self {
+        let timeval = |tv: libc::timeval| {
+            Duration::from_secs(tv.tv_sec as u64) + Duration::from_micros(tv.tv_usec as u64)
+        };
+        // Linux reports the peak RSS in kilobytes, and macOS in bytes
+        let rss_unit = if cfg!(target_os = "macos") { 1 } else { 1024 };
+        Self {
+            wall_time,
+            cpu_time: timeval(rusage.ru_utime) + timeval(rusage.ru_stime),
+            max_rss: (rusage.ru_maxrss as u64).saturating_mul(rss_unit),
+        }
+    }
+}
+
+impl std::fmt::Display for ResourceUsage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} ({} CPU time) and {} of memory",
+            format_seconds(self.wall_time),
+            format_seconds(self.cpu_time),
+            HumanBytes(self.max_rss)
+        )
+    }
+}
+
+/// Format `duration` to the nearest second, e.g. "1m 5s".
+pub fn format_seconds(duration: Duration) -> String {
+    let seconds = Duration::from_secs(duration.as_secs_f64().round() as u64);
+    humantime::format_duration(seconds).to_string()
+}
+
+/// Serialize a duration as a number of seconds.
+pub(crate) fn seconds<S: Serializer>(
+    duration: &Duration,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_f64(duration.as_secs_f64())
+}
+
+/// Wait for `child` to exit, returning its exit status and the resources it used, with
+/// `wall_time` as its running time. If `block` is false, `None` is returned if it is still
+/// running.
+///
+/// Unlike [`Child::wait`], this reaps the child with `wait4`, which gives the resource usage of
+/// that one process (rather than all children, as `getrusage` does). As the [`Child`] isn't told
+/// that it has exited, it mustn't be waited for again.
+#[cfg(unix)]
+pub fn wait_with_usage(
+    child: &mut Child,
+    block: bool,
+    wall_time: impl Fn() -> Duration,
+) -> io::Result<Option<(ExitStatus, Option<ResourceUsage>)>> {
+    let options = if block { 0 } else { libc::WNOHANG };
+    let mut status = 0;
+    // SAFETY: an all-zero rusage is valid, and it is only read after wait4 fills it in
+    let mut rusage: libc::rusage = unsafe { std::mem::zeroed() };
+    loop {
+        // SAFETY: the pointers are to valid, writable values for the duration of the call
+        let pid =
+            unsafe { libc::wait4(child.id() as libc::pid_t, &mut status, options, &mut rusage) };
+        match pid {
+            0 => return Ok(None),
+            -1 => {
+                let error = io::Error::last_os_error();
+                if error.kind() != io::ErrorKind::Interrupted {
+                    return Err(error);
+                }
+            }
+            _ => {
+                let usage = ResourceUsage::from_rusage(wall_time(), &rusage);
+                return Ok(Some((ExitStatus::from_raw(status), Some(usage))));
+            }
+        }
+    }
+}
+
+/// Wait for `child` to exit, returning its exit status. The resources it used aren't known
+/// without `wait4`, so they are `None`.
+#[cfg(not(unix))]
+pub fn wait_with_usage(
+    child: &mut Child,
+    block: bool,
+    _wall_time: impl Fn() -> Duration,
+) -> io::Result<Option<(ExitStatus, Option<ResourceUsage>)>> {
+    let status = if block {
+        Some(child.wait()?)
+    } else {
+        child.try_wait()?
+    };
+    Ok(status.map(|status| (status, None)))
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::process::Command;
+    use std::time::Instant;
+
+    #[test]
+    #[allow(clippy::zombie_processes)] // the child is reaped by wait_with_usage
+    fn test_wait_with_usage() {
+        let start = Instant::now();
+        let mut child = Command::new("sh")
+            .args([
+                "-c",
+                "i=0; while [ $i -lt 20000 ]; do i=$((i+1)); done; exit 3",
+            ])
+            .spawn()
+            .unwrap();
+        let (status, usage) = wait_with_usage(&mut child, true, || start.elapsed())
+            .unwrap()
+            .unwrap();
+        let usage = usage.unwrap();
+        assert_eq!(status.code(), Some(3));
+        assert!(usage.cpu_time > Duration::ZERO);
+        assert!(usage.max_rss > 1024);
+        assert!(usage.wall_time > Duration::ZERO);
+
+        let total = usage.combine(ResourceUsage {
+            wall_time: Duration::from_secs(1),
+            cpu_time: Duration::from_secs(2),
+            max_rss: 0,
+        });
+        assert_eq!(total.wall_time, usage.wall_time + Duration::from_secs(1));
+        assert_eq!(total.max_rss, usage.max_rss);
+
+        let json = serde_json::to_value(total).unwrap();
+        assert_eq!(json["max_rss_bytes"], usage.max_rss);
+        assert!(json["cpu_seconds"].as_f64().unwrap() > 2.0);
+        assert_eq!(format_seconds(Duration::from_millis(65_600)), "1m 6s");
+    }
+}
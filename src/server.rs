@@ -0,0 +1,304 @@
+//! A server that keeps a database in memory, so nohuman runs can classify reads without loading
+//! the database each time.
+//!
+//! The server maps the database's index files into memory (locking them there if it is allowed
+//! to) and runs kraken2 with `--memory-mapping` for each request, so kraken2 uses the copy of the
+//! database already in memory instead of reading it from disk. Requests are sent over a Unix
+//! socket as a line of JSON, and answered in the same way. Unix sockets (and locking memory) are
+//! only available on Unix, so there is no server elsewhere, and [`submit`] fails.
+#[cfg(unix)]
+use crate::database::INDEX_FILES;
+#[cfg(unix)]
+use crate::{validate_db_directory, CommandRunner};
+#[cfg(unix)]
+use anyhow::{bail, Context, Result};
+#[cfg(unix)]
+use log::{debug, info, warn};
+#[cfg(unix)]
+use memmap2::{Advice, Mmap};
+use serde::{Deserialize, Serialize};
+use std::ffi::OsStr;
+#[cfg(unix)]
+use std::fs::{self, File};
+use std::io;
+#[cfg(unix)]
+use std::io::{BufRead, BufReader, Write};
+#[cfg(unix)]
+use std::os::unix::fs::PermissionsExt;
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+#[cfg(unix)]
+use std::os::unix::process::ExitStatusExt;
+use std::path::{Path, PathBuf};
+#[cfg(unix)]
+use std::thread;
+use std::time::Duration;
+
+/// How often the database is read to keep it in memory, if it can't be locked there.
+#[cfg(unix)]
+const TOUCH_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// A request to run kraken2 with `args`, in the directory `cwd`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Request {
+    pub args: Vec<String>,
+    pub cwd: PathBuf,
+}
+
+/// The result of a [`Request`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Response {
+    /// Whether kraken2 succeeded.
+    pub success: bool,
+    /// kraken2's stderr, or why the request failed.
+    pub stderr: String,
+    /// The signal that killed kraken2, if any.
+    #[serde(default)]
+    pub signal: Option<i32>,
+}
+
+#[cfg(unix)]
+impl Response {
+    fn error(message: String) -> Self {
+        Self {
+            success: false,
+            stderr: message,
+            signal: None,
+        }
+    }
+}
+
+/// Send the kraken2 `args` to the server listening on `socket`, and wait for the result, for at
+/// most `timeout` if given. The server isn't told to stop kraken2 if the wait times out.
+#[cfg(unix)]
+pub fn submit(socket: &Path, args: &[&OsStr], timeout: Option<Duration>) -> io::Result<Response> {
+    let args = args
+        .iter()
+        .map(|arg| {
+            arg.to_str().map(str::to_string).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("{:?} isn't UTF-8, so can't be sent to the server", arg),
+                )
+            })
+        })
+        .collect::<io::Result<_>>()?;
+    let request = Request {
+        args,
+        cwd: std::env::current_dir()?,
+    };
+    let mut stream = UnixStream::connect(socket)?;
+    stream.set_read_timeout(timeout)?;
+    serde_json::to_writer(&mut stream, &request)?;
+    stream.write_all(b"\n")?;
+    stream.flush()?;
+
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line)?;
+    serde_json::from_str(&line).map_err(io::Error::other)
+}
+
+/// Requests can't be sent without Unix sockets, so this always fails.
+#[cfg(not(unix))]
+pub fn submit(
+    _socket: &Path,
+    _args: &[&OsStr],
+    _timeout: Option<Duration>,
+) -> io::Result<Response> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "The nohuman server needs Unix sockets, which this platform doesn't have",
+    ))
+}
+
+/// A server holding the database in memory.
+#[cfg(unix)]
+pub struct Server {
+    /// The directory of the database's index files.
+    database: PathBuf,
+    kraken: CommandRunner,
+    maps: Vec<Mmap>,
+    locked: bool,
+}
+
+#[cfg(unix)]
+impl Server {
+    /// Load the database at `database` into memory.
+    pub fn new(database: &Path) -> Result<Self> {
+        let database = validate_db_directory(database)?.canonicalize()?;
+        let mut maps = Vec::new();
+        let mut locked = true;
+        for name in INDEX_FILES {
+            let path = database.join(name);
+            let file = File::open(&path).with_context(|| format!("Failed to open {:?}", path))?;
+            // SAFETY: the database files are not expected to change while the server is running.
+            // If they are, kraken2 reads the changed files anyway.
+            let map = unsafe { Mmap::map(&file) }
+                .with_context(|| format!("Failed to map {:?} into memory", path))?;
+            if let Err(e) = map.lock() {
+                debug!("Failed to lock {:?} in memory: {}", path, e);
+                locked = false;
+                let _ = map.advise(Advice::WillNeed);
+            }
+            maps.push(map);
+        }
+        let server = Self {
+            database,
+            kraken: CommandRunner::new("kraken2"),
+            maps,
+            locked,
+        };
+        if !server.locked {
+            warn!(
+                "The database couldn't be locked in memory (see `ulimit -l`), so it is re-read every {} minutes to keep it cached",
+                TOUCH_INTERVAL.as_secs() / 60
+            );
+            server.touch();
+        }
+        Ok(server)
+    }
+
+    /// How to run kraken2. Defaults to the `kraken2` on the `PATH`.
+    pub fn with_kraken2(mut self, kraken: CommandRunner) -> Self {
+        self.kraken = kraken;
+        self
+    }
+
+    /// Read a byte of every page of the database, so the operating system keeps it in memory.
+    fn touch(&self) -> u8 {
+        let mut sum = 0u8;
+        for map in &self.maps {
+            for byte in map.iter().step_by(4096) {
+                sum = sum.wrapping_add(*byte);
+            }
+        }
+        sum
+    }
+
+    /// Listen for requests on `socket` until the process is stopped. Only the user running the
+    /// server can connect to it.
+    pub fn run(&self, socket: &Path) -> Result<()> {
+        if socket.exists() {
+            if UnixStream::connect(socket).is_ok() {
+                bail!("A server is already running at {:?}", socket);
+            }
+            // left behind by a server that was stopped
+            fs::remove_file(socket)?;
+        }
+        if let Some(parent) = socket.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let listener = UnixListener::bind(socket)
+            .with_context(|| format!("Failed to listen on {:?}", socket))?;
+        fs::set_permissions(socket, fs::Permissions::from_mode(0o600))?;
+        info!(
+            "Serving database {:?} at {:?}. Run nohuman with --server to use it",
+            self.database, socket
+        );
+
+        thread::scope(|scope| {
+            if !self.locked {
+                scope.spawn(|| loop {
+                    thread::sleep(TOUCH_INTERVAL);
+                    std::hint::black_box(self.touch());
+                });
+            }
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => {
+                        scope.spawn(move || {
+                            if let Err(e) = self.handle(stream) {
+                                warn!("Failed to handle request: {:#}", e);
+                            }
+                        });
+                    }
+                    Err(e) => warn!("Failed to accept connection: {}", e),
+                }
+            }
+        });
+        Ok(())
+    }
+
+    fn handle(&self, stream: UnixStream) -> Result<()> {
+        let mut line = String::new();
+        BufReader::new(&stream).read_line(&mut line)?;
+        let request: Request = serde_json::from_str(&line).context("Invalid request")?;
+        debug!("Request: {:?}", request);
+
+        let response = self
+            .check_database(&request)
+            .and_then(|()| self.classify(&request))
+            .unwrap_or_else(|e| Response::error(format!("{:#}", e)));
+        serde_json::to_writer(&stream, &response)?;
+        (&stream).write_all(b"\n")?;
+        Ok(())
+    }
+
+    /// Check the request is for the database the server has loaded.
+    fn check_database(&self, request: &Request) -> Result<()> {
+        let requested = request
+            .args
+            .iter()
+            .position(|arg| arg == "--db")
+            .and_then(|i| request.args.get(i + 1))
+            .context("No database (--db) given")?;
+        let requested = validate_db_directory(&request.cwd.join(requested))?.canonicalize()?;
+        if requested != self.database {
+            bail!(
+                "The server has database {:?} loaded, but {:?} was requested",
+                self.database,
+                requested
+            );
+        }
+        Ok(())
+    }
+
+    fn classify(&self, request: &Request) -> Result<Response> {
+        let mut args: Vec<&str> = request.args.iter().map(String::as_str).collect();
+        if !args.contains(&"--memory-mapping") {
+            args.push("--memory-mapping");
+        }
+        let output = self
+            .kraken
+            .command_in(&args, &request.cwd)
+            .output()
+            .with_context(|| format!("Failed to run {}", self.kraken.command))?;
+        Ok(Response {
+            success: output.status.success(),
+            stderr: String::from_utf8_lossy(&output.stderr).to_string(),
+            signal: output.status.signal(),
+        })
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_database() {
+        let tmp = tempfile::tempdir().unwrap();
+        let db = tmp.path().join("db");
+        let other = tmp.path().join("other");
+        for dir in [&db, &other] {
+            fs::create_dir(dir).unwrap();
+            for name in INDEX_FILES {
+                fs::write(dir.join(name), name).unwrap();
+            }
+        }
+        let server = Server::new(&db).unwrap();
+        let request = |db: &str| Request {
+            args: vec!["--db".to_string(), db.to_string(), "in.fq".to_string()],
+            cwd: tmp.path().to_path_buf(),
+        };
+
+        assert!(server.check_database(&request("db")).is_ok());
+        assert!(server.check_database(&request("other")).is_err());
+        assert!(server
+            .check_database(&Request {
+                args: vec![],
+                cwd: tmp.path().to_path_buf()
+            })
+            .is_err());
+    }
+}
@@ -0,0 +1,167 @@
+//! Verifying [minisign](https://jedisct1.github.io/minisign/) signatures of the database
+//! manifest and tarballs.
+use crate::download::DownloadError;
+use log::warn;
+use minisign_verify::{PublicKey, Signature};
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+/// The extension of (detached) signature files.
+pub const SIGNATURE_EXTENSION: &str = "minisig";
+
+/// The signature file URL, or path, for `url`.
+pub fn signature_url(url: &str) -> String {
+    format!("{}.{}", url, SIGNATURE_EXTENSION)
+}
+
+/// Which key signatures must be made with, and whether to allow files with no signature.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SignaturePolicy {
+    /// The trusted public key, base64 encoded.
+    public_key: String,
+    /// Only warn if a file has no signature. Invalid signatures are always an error.
+    pub allow_unsigned: bool,
+}
+
+impl SignaturePolicy {
+    /// Create a policy trusting `key`, which is either a base64-encoded minisign public key or
+    /// the path to a public key file.
+    pub fn new(key: &str, allow_unsigned: bool) -> Result<Self, DownloadError> {
+        let public_key = if Path::new(key).is_file() {
+            let content = std::fs::read_to_string(key)?;
+            // key files have an untrusted comment line before the key
+            content
+                .lines()
+                .map(str::trim)
+                .rfind(|line| !line.is_empty())
+                .unwrap_or_default()
+                .to_string()
+        } else {
+            key.trim().to_string()
+        };
+        PublicKey::from_base64(&public_key)
+            .map_err(|_| DownloadError::InvalidPublicKey(key.to_string()))?;
+        Ok(Self {
+            public_key,
+            allow_unsigned,
+        })
+    }
+
+    fn public_key(&self) -> PublicKey {
+        // the key is checked when the policy is created
+        PublicKey::from_base64(&self.public_key).expect("public key is valid")
+    }
+
+    /// Handle `what` not having a signature - an error unless unsigned files are allowed.
+    fn unsigned(&self, what: &str) -> Result<(), DownloadError> {
+        if self.allow_unsigned {
+            warn!("No signature found for {}. Continuing anyway", what);
+            Ok(())
+        } else {
+            Err(DownloadError::SignatureMissing(what.to_string()))
+        }
+    }
+
+    /// Check `signature` is a valid signature of `data`, which is described by `what`.
+    /// `signature` is `None` if there isn't one.
+    pub fn verify_bytes(
+        &self,
+        data: &[u8],
+        signature: Option<&str>,
+        what: &str,
+    ) -> Result<(), DownloadError> {
+        let Some(signature) = signature else {
+            return self.unsigned(what);
+        };
+        let signature = Signature::decode(signature)
+            .map_err(|_| DownloadError::InvalidSignature(what.to_string()))?;
+        self.public_key()
+            .verify(data, &signature, false)
+            .map_err(|_| DownloadError::InvalidSignature(what.to_string()))
+    }
+
+    /// Check `signature` is a valid signature of the file at `path`, which is described by
+    /// `what`. The file is read in chunks, so it can be large. `signature` is `None` if there
+    /// isn't one.
+    pub fn verify_file(
+        &self,
+        path: &Path,
+        signature: Option<&str>,
+        what: &str,
+    ) -> Result<(), DownloadError> {
+        let Some(signature) = signature else {
+            return self.unsigned(what);
+        };
+        let invalid = |_| DownloadError::InvalidSignature(what.to_string());
+        let signature = Signature::decode(signature).map_err(invalid)?;
+        let public_key = self.public_key();
+        let mut verifier = public_key.verify_stream(&signature).map_err(invalid)?;
+
+        let mut file = File::open(path)?;
+        let mut buffer = vec![0; 64 * 1024];
+        loop {
+            let n = file.read(&mut buffer)?;
+            if n == 0 {
+                break;
+            }
+            verifier.update(&buffer[..n]);
+        }
+        verifier.finalize().map_err(invalid)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // test vector from the minisign-verify crate
+    const PUBLIC_KEY: &str = "RWQf6LRCGA9i53mlYecO4IzT51TGPpvWucNSCh1CBM0QTaLn73Y7GFO3";
+    const SIGNATURE: &str = "untrusted comment: signature from minisign secret key
+RUQf6LRCGA9i559r3g7V1qNyJDApGip8MfqcadIgT9CuhV3EMhHoN1mGTkUidF/z7SrlQgXdy8ofjb7bNJJylDOocrCo8KLzZwo=
+trusted comment: timestamp:1556193335\tfile:test
+y/rUw2y8/hOUYjZU71eHp/Wo1KZ40fGy2VJEDl34XMJM+TX48Ss/17u3IvIfbVR1FkZZSNCisQbuQY+bHwhEBg==";
+
+    #[test]
+    fn test_verify_signature() {
+        let policy = SignaturePolicy::new(PUBLIC_KEY, false).unwrap();
+        assert!(policy
+            .verify_bytes(b"test", Some(SIGNATURE), "test")
+            .is_ok());
+        assert!(matches!(
+            policy.verify_bytes(b"Test", Some(SIGNATURE), "test"),
+            Err(DownloadError::InvalidSignature(_))
+        ));
+        assert!(matches!(
+            policy.verify_bytes(b"test", None, "test"),
+            Err(DownloadError::SignatureMissing(_))
+        ));
+
+        let tmp = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(tmp.path(), "test").unwrap();
+        assert!(policy
+            .verify_file(tmp.path(), Some(SIGNATURE), "test")
+            .is_ok());
+    }
+
+    #[test]
+    fn test_signature_policy_allow_unsigned() {
+        let key_file = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(
+            key_file.path(),
+            format!("untrusted comment: minisign public key\n{}\n", PUBLIC_KEY),
+        )
+        .unwrap();
+        let policy = SignaturePolicy::new(key_file.path().to_str().unwrap(), true).unwrap();
+        assert!(policy.verify_bytes(b"test", None, "test").is_ok());
+        // an invalid signature is still an error
+        assert!(policy
+            .verify_bytes(b"Test", Some(SIGNATURE), "test")
+            .is_err());
+
+        assert!(matches!(
+            SignaturePolicy::new("not a key", false),
+            Err(DownloadError::InvalidPublicKey(_))
+        ));
+    }
+}
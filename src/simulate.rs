@@ -0,0 +1,231 @@
+//! Simulating a mix of host and contaminant reads with known origins (`nohuman simulate`), so an
+//! installation can be checked end to end with `nohuman eval`.
+use crate::fastx::{fastx_reader, write_record};
+use crate::pipeline::split_file_name;
+use anyhow::{bail, Context, Result};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+/// The number of times a read is sampled again because it overlaps an ambiguous base (e.g. the
+/// runs of N in a genome assembly) before it is kept anyway.
+const MAX_RESAMPLES: usize = 100;
+/// The bases substituted in for sequencing errors.
+const BASES: [u8; 4] = *b"ACGT";
+
+/// The sequences reads are sampled from.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Reference {
+    /// The ID and (uppercase) sequence of each sequence long enough to sample a read from.
+    sequences: Vec<(Vec<u8>, Vec<u8>)>,
+    /// The number of reads that can start in each sequence and the ones before it, so sequences
+    /// are sampled in proportion to their length.
+    cumulative_starts: Vec<u64>,
+}
+
+impl Reference {
+    /// Read a FASTA/Q reference, keeping the sequences at least `read_length` long.
+    pub fn from_reader<R: Read + Send>(reader: R, read_length: usize) -> Result<Self> {
+        let mut reference = Self::default();
+        let Some(mut parser) = fastx_reader(reader)? else {
+            bail!("The reference is empty");
+        };
+        let mut starts = 0;
+        while let Some(record) = parser.next() {
+            let record = record.context("Failed to parse the reference")?;
+            let seq = record.seq();
+            if seq.len() < read_length {
+                continue;
+            }
+            starts += (seq.len() - read_length + 1) as u64;
+            let id = record.id().split(|b| b.is_ascii_whitespace()).next();
+            reference
+                .sequences
+                .push((id.unwrap_or_default().to_vec(), seq.to_ascii_uppercase()));
+            reference.cumulative_starts.push(starts);
+        }
+        if reference.sequences.is_empty() {
+            bail!(
+                "The reference has no sequences at least {} bp long",
+                read_length
+            );
+        }
+        Ok(reference)
+    }
+
+    /// Sample a read of `read_length` from a random position and strand, returning its sequence
+    /// and a description of where it came from, e.g. "chr1:100-250:+".
+    fn sample(&self, rng: &mut fastrand::Rng, read_length: usize) -> (Vec<u8>, String) {
+        let total = *self.cumulative_starts.last().unwrap();
+        let mut attempt = 0;
+        loop {
+            let start = rng.u64(..total);
+            let i = self.cumulative_starts.partition_point(|&n| n <= start);
+            let offset = start - i.checked_sub(1).map_or(0, |j| self.cumulative_starts[j]);
+            let (id, seq) = &self.sequences[i];
+            let offset = offset as usize;
+            let window = &seq[offset..offset + read_length];
+            attempt += 1;
+            if window.iter().any(|b| !BASES.contains(b)) && attempt < MAX_RESAMPLES {
+                continue;
+            }
+            let forward = rng.bool();
+            let read = if forward {
+                window.to_vec()
+            } else {
+                reverse_complement(window)
+            };
+            let origin = format!(
+                "{}:{}-{}:{}",
+                String::from_utf8_lossy(id),
+                offset + 1,
+                offset + read_length,
+                if forward { '+' } else { '-' }
+            );
+            return (read, origin);
+        }
+    }
+}
+
+fn reverse_complement(seq: &[u8]) -> Vec<u8> {
+    seq.iter()
+        .rev()
+        .map(|b| match b {
+            b'A' => b'T',
+            b'C' => b'G',
+            b'G' => b'C',
+            b'T' => b'A',
+            _ => b'N',
+        })
+        .collect()
+}
+
+/// The options for simulating reads.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SimulationOptions {
+    /// The number of reads.
+    pub reads: u64,
+    /// The fraction of the reads sampled from the host reference.
+    pub host_fraction: f64,
+    pub read_length: usize,
+    /// The probability of each base being substituted, as a sequencing error.
+    pub error_rate: f64,
+    pub seed: u64,
+}
+
+impl SimulationOptions {
+    /// The number of host reads, i.e. the host fraction of the reads, rounded.
+    pub fn host_reads(&self) -> u64 {
+        (self.reads as f64 * self.host_fraction).round() as u64
+    }
+}
+
+/// Simulate single-end FASTQ reads sampled from the `host` and `contaminant` references, in a
+/// random order, writing them to `reads` and the origin of each to `truth`. The truth set has a
+/// line per read, with its ID, a tab, and "host" or "contaminant", as read by
+/// [`crate::eval::Truth::from_reader`]. The same `options` (including the seed) give the same
+/// reads.
+pub fn simulate<W: Write, T: Write>(
+    host: &Reference,
+    contaminant: &Reference,
+    options: &SimulationOptions,
+    reads: &mut W,
+    truth: &mut T,
+) -> Result<()> {
+    let mut rng = fastrand::Rng::with_seed(options.seed);
+    let mut is_host: Vec<bool> = (0..options.reads)
+        .map(|i| i < options.host_reads())
+        .collect();
+    rng.shuffle(&mut is_host);
+    // the quality score matching the error rate, capped at Q41
+    let phred = (-10.0 * options.error_rate.max(1e-5).log10())
+        .round()
+        .min(41.0) as u8;
+    let qual = vec![33 + phred; options.read_length];
+    for (i, is_host) in is_host.into_iter().enumerate() {
+        let (reference, label) = if is_host {
+            (host, "host")
+        } else {
+            (contaminant, "contaminant")
+        };
+        let (mut seq, origin) = reference.sample(&mut rng, options.read_length);
+        for base in seq.iter_mut() {
+            if rng.f64() < options.error_rate {
+                let other: Vec<u8> = BASES.into_iter().filter(|b| b != base).collect();
+                *base = other[rng.usize(..other.len())];
+            }
+        }
+        let id = format!("sim{}", i + 1);
+        let header = format!("{} origin={}", id, origin);
+        write_record(reads, header.as_bytes(), &seq, Some(&qual))?;
+        writeln!(truth, "{}\t{}", id, label)?;
+    }
+    Ok(())
+}
+
+/// The default path of the truth set for the simulated `reads`, e.g. "sim.fq.gz" ->
+/// "sim.truth.tsv".
+pub fn truth_path(reads: &Path) -> PathBuf {
+    let (stem, _) = split_file_name(reads);
+    reads.with_file_name(format!("{}.truth.tsv", stem))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eval::Truth;
+
+    #[test]
+    fn test_simulate() {
+        let host = Reference::from_reader(
+            ">chr1 human\nNNNNACGTACGTAAAACCCC\n>chrM\nAC\n".as_bytes(),
+            8,
+        )
+        .unwrap();
+        assert_eq!(host.sequences.len(), 1);
+        assert_eq!(host.sequences[0].0, b"chr1");
+        let contaminant =
+            Reference::from_reader(">phage\nggggttttggggtttt\n".as_bytes(), 8).unwrap();
+        assert!(Reference::from_reader(">short\nACGT\n".as_bytes(), 8).is_err());
+
+        let options = SimulationOptions {
+            reads: 20,
+            host_fraction: 0.25,
+            read_length: 8,
+            error_rate: 0.0,
+            seed: 1,
+        };
+        let (mut reads, mut truth) = (Vec::new(), Vec::new());
+        simulate(&host, &contaminant, &options, &mut reads, &mut truth).unwrap();
+        let truth = Truth::from_reader(truth.as_slice()).unwrap();
+        assert_eq!(truth.labelled, Some(20));
+        assert_eq!(truth.host.len(), 5);
+
+        let reads = String::from_utf8(reads).unwrap();
+        let lines: Vec<_> = reads.lines().collect();
+        assert_eq!(lines.len(), 80);
+        for record in lines.chunks(4) {
+            let id = record[0][1..].split(' ').next().unwrap();
+            let seq = record[1];
+            assert_eq!(seq.len(), 8);
+            assert_eq!(record[3], "J".repeat(8));
+            if truth.host.contains(id.as_bytes()) {
+                // the reads avoid the Ns at the start of chr1
+                assert!(!seq.contains('N'));
+                assert!(record[0].contains("origin=chr1:"));
+            } else {
+                assert!(
+                    seq.chars().all(|b| "GT".contains(b)) || seq.chars().all(|b| "AC".contains(b))
+                );
+            }
+        }
+
+        // the same seed gives the same reads
+        let mut again = Vec::new();
+        simulate(&host, &contaminant, &options, &mut again, &mut Vec::new()).unwrap();
+        assert_eq!(again, reads.as_bytes());
+        assert_eq!(
+            truth_path(Path::new("dir/sim.fq.gz")),
+            Path::new("dir/sim.truth.tsv")
+        );
+    }
+}
@@ -0,0 +1,141 @@
+use crate::resources::{format_seconds, seconds, ResourceUsage};
+use anyhow::Result;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// A machine-readable summary of a nohuman run.
+#[derive(Debug, Serialize)]
+pub struct Summary {
+    pub nohuman_version: String,
+    /// The host whose reads were removed (or kept).
+    pub host: String,
+    /// Whether host reads were kept instead of removed.
+    pub keep_host_reads: bool,
+    pub inputs: Vec<PathBuf>,
+    pub outputs: Vec<PathBuf>,
+    /// Whether the temporary files were securely deleted (overwritten before removal).
+    pub secure_delete: bool,
+    /// The version of kraken2 used, if it could be determined. `None` if kraken2 wasn't run
+    /// locally (e.g. with `--server` or `--from-kraken-output`).
+    pub kraken2_version: Option<String>,
+    /// The number of sequences classified. A pair of reads counts as one sequence.
+    pub sequences: u64,
+    /// The number of sequences classified as host.
+    pub host_sequences: u64,
+    /// When several engines are combined (e.g. `--engine kraken2+minimap2`), the number of
+    /// sequences each flagged as host. `host_sequences` is the consensus.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub engine_host_sequences: BTreeMap<String, u64>,
+    /// The number of reads in each output, counted after it was written. Paired outputs have a
+    /// read for each sequence.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub output_reads: Vec<u64>,
+    /// Where the run spent its time.
+    pub timings: Timings,
+}
+
+/// A breakdown of where a run spent its time, so a slow run can be explained.
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub struct Timings {
+    /// The time the whole run took.
+    #[serde(rename = "total_seconds", serialize_with = "seconds")]
+    pub total: Duration,
+    /// The resources used by kraken2. `None` if it wasn't run, or not run locally (e.g. with
+    /// `--server`).
+    pub kraken2: Option<ResourceUsage>,
+    /// The time spent writing (and compressing) the outputs.
+    #[serde(rename = "compression_seconds", serialize_with = "seconds")]
+    pub compression: Duration,
+}
+
+impl std::fmt::Display for Timings {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} in total", format_seconds(self.total))?;
+        if let Some(kraken2) = &self.kraken2 {
+            write!(f, "; kraken2 took {}", kraken2)?;
+        }
+        write!(
+            f,
+            "; writing the outputs took {}",
+            format_seconds(self.compression)
+        )
+    }
+}
+
+impl Default for Summary {
+    fn default() -> Self {
+        Self {
+            nohuman_version: env!("CARGO_PKG_VERSION").to_string(),
+            host: String::new(),
+            keep_host_reads: false,
+            inputs: Vec::new(),
+            outputs: Vec::new(),
+            secure_delete: false,
+            kraken2_version: None,
+            sequences: 0,
+            host_sequences: 0,
+            engine_host_sequences: BTreeMap::new(),
+            output_reads: Vec::new(),
+            timings: Timings::default(),
+        }
+    }
+}
+
+impl Summary {
+    /// Write the summary as pretty-printed JSON.
+    pub fn to_writer<W: Write>(&self, writer: W) -> Result<()> {
+        serde_json::to_writer_pretty(writer, self)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_summary_to_writer() {
+        let summary = Summary {
+            host: "human".to_string(),
+            inputs: vec![PathBuf::from("in.fq")],
+            outputs: vec![PathBuf::from("in.nohuman.fq")],
+            secure_delete: true,
+            kraken2_version: Some("2.1.3".to_string()),
+            sequences: 10,
+            host_sequences: 3,
+            output_reads: vec![7],
+            timings: Timings {
+                total: Duration::from_millis(2500),
+                kraken2: Some(ResourceUsage {
+                    wall_time: Duration::from_secs(2),
+                    cpu_time: Duration::from_secs(6),
+                    max_rss: 1 << 30,
+                }),
+                compression: Duration::from_millis(250),
+            },
+            ..Default::default()
+        };
+        assert_eq!(
+            summary.timings.to_string(),
+            "3s in total; kraken2 took 2s (6s CPU time) and 1.00 GiB of memory; writing the outputs took 0s"
+        );
+        let mut out = Vec::new();
+        summary.to_writer(&mut out).unwrap();
+
+        let json: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(json["nohuman_version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(json["host"], "human");
+        assert_eq!(json["inputs"][0], "in.fq");
+        assert_eq!(json["secure_delete"], true);
+        assert_eq!(json["kraken2_version"], "2.1.3");
+        assert_eq!(json["host_sequences"], 3);
+        assert!(json.get("engine_host_sequences").is_none());
+        assert_eq!(json["output_reads"][0], 7);
+        assert_eq!(json["timings"]["total_seconds"], 2.5);
+        assert_eq!(json["timings"]["kraken2"]["cpu_seconds"], 6.0);
+        assert_eq!(json["timings"]["compression_seconds"], 0.25);
+    }
+}
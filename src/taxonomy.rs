@@ -0,0 +1,209 @@
+//! Reading the taxonomy of a kraken2 database (`taxo.k2d`), to work out which taxa are in the
+//! clade of a host.
+//!
+//! With a host-only database, every classified read is a host read. With a database of many
+//! species (e.g. kraken2's standard database), reads have to be filtered by the taxon they are
+//! classified as instead.
+use anyhow::{bail, Context, Result};
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
+use std::path::Path;
+
+/// The name of the taxonomy file in a kraken2 database.
+pub const TAXONOMY_FILE: &str = "taxo.k2d";
+
+const MAGIC: &[u8; 8] = b"K2TAXDAT";
+/// The number of 64-bit fields in each node of the taxonomy file.
+const NODE_FIELDS: usize = 7;
+
+/// A node of the taxonomy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Node {
+    /// The index of the parent node. The root is its own parent.
+    parent: usize,
+    /// The NCBI taxonomy ID.
+    taxid: u64,
+}
+
+/// The taxonomy of a kraken2 database.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Taxonomy {
+    nodes: Vec<Node>,
+    index: HashMap<u64, usize>,
+}
+
+impl Taxonomy {
+    /// Read the taxonomy of the kraken2 database `db` (the directory containing `taxo.k2d`).
+    pub fn read(db: &Path) -> Result<Self> {
+        let path = db.join(TAXONOMY_FILE);
+        let mut reader = BufReader::new(
+            File::open(&path).with_context(|| format!("Failed to open {:?}", path))?,
+        );
+        let mut magic = [0; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            bail!("{:?} isn't a kraken2 taxonomy", path);
+        }
+        let node_count = read_u64(&mut reader)? as usize;
+        // the lengths of the names and ranks, which follow the nodes
+        read_u64(&mut reader)?;
+        read_u64(&mut reader)?;
+
+        let mut nodes = Vec::with_capacity(node_count);
+        let mut index = HashMap::with_capacity(node_count);
+        let mut fields = [0; NODE_FIELDS];
+        for i in 0..node_count {
+            for field in fields.iter_mut() {
+                *field =
+                    read_u64(&mut reader).with_context(|| format!("{:?} is truncated", path))?;
+            }
+            let (parent, taxid) = (fields[0] as usize, fields[5]);
+            if parent >= node_count {
+                bail!("Taxon {} in {:?} has an invalid parent", taxid, path);
+            }
+            // node 0 is a placeholder, so isn't a real taxon
+            if i > 0 {
+                index.insert(taxid, i);
+            }
+            nodes.push(Node { parent, taxid });
+        }
+        Ok(Self { nodes, index })
+    }
+
+    /// Whether `taxid` is in the taxonomy.
+    pub fn contains(&self, taxid: u64) -> bool {
+        self.index.contains_key(&taxid)
+    }
+
+    /// The taxa in the clades rooted at `taxids` (including the taxa themselves). Taxa that aren't
+    /// in the taxonomy are included as they are.
+    pub fn clade(&self, taxids: &[u64]) -> HashSet<u64> {
+        let roots: HashSet<usize> = taxids
+            .iter()
+            .filter_map(|t| self.index.get(t))
+            .copied()
+            .collect();
+        let mut clade: HashSet<u64> = taxids.iter().copied().collect();
+        for (&taxid, &i) in &self.index {
+            if self.ancestors(i).any(|a| roots.contains(&a)) {
+                clade.insert(taxid);
+            }
+        }
+        clade
+    }
+
+    /// Whether every taxon is an ancestor or a descendant of `taxid`, i.e. whether a read
+    /// classified by the database is always in the clade of `taxid` (or unspecific).
+    pub fn is_lineage_of(&self, taxid: u64) -> bool {
+        let Some(&target) = self.index.get(&taxid) else {
+            return false;
+        };
+        let lineage: HashSet<usize> = self.ancestors(target).collect();
+        self.index
+            .values()
+            .all(|&i| lineage.contains(&i) || self.ancestors(i).any(|a| a == target))
+    }
+
+    /// The indices of node `i` and its ancestors, up to the root.
+    fn ancestors(&self, i: usize) -> impl Iterator<Item = usize> + '_ {
+        let mut next = Some(i);
+        std::iter::from_fn(move || {
+            let current = next?;
+            let parent = self.nodes[current].parent;
+            next = (parent != current && parent != 0).then_some(parent);
+            Some(current)
+        })
+    }
+}
+
+/// Parse a list of taxonomy IDs, one per line. Blank lines and lines starting with `#` are
+/// ignored.
+pub fn read_taxid_list<R: BufRead>(reader: R) -> Result<Vec<u64>> {
+    let mut taxids = Vec::new();
+    for (i, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let taxid = line
+            .parse()
+            .with_context(|| format!("Invalid taxonomy ID {:?} on line {}", line, i + 1))?;
+        taxids.push(taxid);
+    }
+    Ok(taxids)
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> std::io::Result<u64> {
+    let mut buf = [0; 8];
+    reader.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    /// Write a taxonomy of `(parent index, taxid)` nodes to `dir`. The first node is kraken2's
+    /// placeholder.
+    fn write_taxonomy(dir: &Path, nodes: &[(u64, u64)]) {
+        let mut file = File::create(dir.join(TAXONOMY_FILE)).unwrap();
+        file.write_all(MAGIC).unwrap();
+        for n in [nodes.len() as u64, 0, 0] {
+            file.write_all(&n.to_le_bytes()).unwrap();
+        }
+        for &(parent, taxid) in nodes {
+            for field in [parent, 0, 0, 0, 0, taxid, 0] {
+                file.write_all(&field.to_le_bytes()).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn test_read_taxonomy() {
+        let tmp = tempfile::tempdir().unwrap();
+        // root (1), Homo (9605), Homo sapiens (9606), Mus musculus (10090)
+        write_taxonomy(
+            tmp.path(),
+            &[(0, 0), (1, 1), (1, 9605), (2, 9606), (1, 10090)],
+        );
+
+        let taxonomy = Taxonomy::read(tmp.path()).unwrap();
+        assert!(taxonomy.contains(9606));
+        assert!(!taxonomy.contains(0));
+        assert_eq!(taxonomy.clade(&[9605]), HashSet::from([9605, 9606]));
+        assert_eq!(taxonomy.clade(&[7]), HashSet::from([7]));
+        assert!(!taxonomy.is_lineage_of(9606));
+
+        assert!(Taxonomy::read(&tmp.path().join("missing")).is_err());
+        std::fs::write(tmp.path().join(TAXONOMY_FILE), "K2TAXDAT").unwrap();
+        assert!(Taxonomy::read(tmp.path()).is_err());
+    }
+
+    #[test]
+    fn test_read_taxid_list() {
+        let list = "# primates\n9443\n\n 9606 \n";
+        assert_eq!(
+            read_taxid_list(std::io::Cursor::new(list)).unwrap(),
+            vec![9443, 9606]
+        );
+        assert!(read_taxid_list(std::io::Cursor::new("9606\nHomo sapiens\n")).is_err());
+    }
+
+    #[test]
+    fn test_is_lineage_of() {
+        let tmp = tempfile::tempdir().unwrap();
+        // root (1), Homo (9605), Homo sapiens (9606), a subspecies (63221)
+        write_taxonomy(
+            tmp.path(),
+            &[(0, 0), (1, 1), (1, 9605), (2, 9606), (3, 63221)],
+        );
+
+        let taxonomy = Taxonomy::read(tmp.path()).unwrap();
+        assert!(taxonomy.is_lineage_of(9606));
+        assert_eq!(taxonomy.clade(&[9606]), HashSet::from([9606, 63221]));
+        assert!(!taxonomy.is_lineage_of(10090));
+    }
+}